@@ -0,0 +1,202 @@
+// --agent: what kind of client sent a request, going by its User-Agent
+// header, and what's done about each kind: turn it away, limit its request
+// rate, or give it plain text listings which are easier to read in a
+// terminal or a script than our pages.
+
+use crate::tenant::RateLimit;
+
+// crawlers known to crawl aggressively and to ignore robots.txt, lowercase
+const BAD_BOTS: [&str; 10] = [
+    "ahrefsbot",
+    "barkrowler",
+    "blexbot",
+    "bytespider",
+    "dotbot",
+    "megaindex",
+    "mj12bot",
+    "petalbot",
+    "semrushbot",
+    "serpstatbot",
+];
+// the products of command line clients and HTTP libraries, lowercase
+const CLI_TOOLS: [&str; 10] = [
+    "curl",
+    "wget",
+    "httpie",
+    "aria2",
+    "python-requests",
+    "python-urllib",
+    "go-http-client",
+    "libwww-perl",
+    "powershell",
+    "okhttp",
+];
+// in the names of crawlers which don't say "bot", lowercase
+const BOT_WORDS: [&str; 5] = ["bot", "crawl", "spider", "slurp", "facebookexternalhit"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Class {
+    Browser,
+    Bot,
+    BadBot,
+    Cli,
+    // the rest, and clients without a User-Agent
+    Other,
+}
+
+const CLASSES: [Class; 5] = [
+    Class::Browser,
+    Class::Bot,
+    Class::BadBot,
+    Class::Cli,
+    Class::Other,
+];
+
+impl Class {
+    pub fn name(self) -> &'static str {
+        match self {
+            Class::Browser => "browser",
+            Class::Bot => "bot",
+            Class::BadBot => "bad-bot",
+            Class::Cli => "cli",
+            Class::Other => "other",
+        }
+    }
+}
+
+pub fn classify(user_agent: Option<&str>) -> Class {
+    let Some(user_agent) = user_agent else {
+        return Class::Other;
+    };
+    let lowercase = user_agent.to_ascii_lowercase();
+    // bots also say they are browsers, e.g. "Mozilla/5.0 (compatible;
+    // Googlebot/2.1; ...)"
+    if BAD_BOTS.iter().any(|bot| lowercase.contains(bot)) {
+        return Class::BadBot;
+    }
+    if BOT_WORDS.iter().any(|word| lowercase.contains(word)) {
+        return Class::Bot;
+    }
+    let product = lowercase.split('/').next().unwrap_or_default();
+    if CLI_TOOLS.contains(&product) {
+        return Class::Cli;
+    }
+    if product == "mozilla" || product == "opera" {
+        return Class::Browser;
+    }
+    Class::Other
+}
+
+#[derive(Default)]
+pub struct Policy {
+    // 403 for all its requests
+    pub deny: bool,
+    pub rate: Option<RateLimit>,
+    // listings as text/plain, a name per line
+    pub plain: bool,
+}
+
+impl Policy {
+    // e.g. "rate=30/min, plain", None when nothing is done
+    pub fn describe(&self) -> Option<String> {
+        let mut res = Vec::new();
+        if self.deny {
+            res.push("deny".to_owned());
+        }
+        if let Some(rate) = &self.rate {
+            res.push(format!("rate={}/min", rate.rate));
+        }
+        if self.plain {
+            res.push("plain".to_owned());
+        }
+        (!res.is_empty()).then(|| res.join(", "))
+    }
+}
+
+// The policy of each class, none by default
+#[derive(Default)]
+pub struct Policies([Policy; 5]);
+
+impl Policies {
+    pub fn get(&self, class: Class) -> &Policy {
+        &self.0[class as usize]
+    }
+
+    // "class=option[,option]...", with the options "deny", "rate=requests per
+    // minute" and "plain"
+    pub fn parse(&mut self, value: &str) -> Result<(), String> {
+        let Some((name, options)) = value.split_once('=') else {
+            return Err(
+                "'--agent' value must be 'class=option[,option]...', e.g. 'bot=rate=30'".to_owned(),
+            );
+        };
+        let Some(class) = CLASSES.into_iter().find(|class| class.name() == name) else {
+            return Err(format!(
+                "'--agent' class must be browser, bot, bad-bot, cli or other: {name}"
+            ));
+        };
+        let policy = &mut self.0[class as usize];
+        for option in options.split(',') {
+            match option.split_once('=') {
+                None if option == "deny" => policy.deny = true,
+                None if option == "plain" => policy.plain = true,
+                Some(("rate", rate)) => match rate.parse() {
+                    Ok(rate) if rate > 0 => policy.rate = Some(RateLimit::new(rate)),
+                    _ => {
+                        return Err(format!(
+                            "'--agent' rate must be requests per minute: {rate}"
+                        ));
+                    }
+                },
+                _ => return Err(format!("unknown '--agent' option: {option}")),
+            }
+        }
+        Ok(())
+    }
+
+    // The classes with a policy, and what it is
+    pub fn describe(&self) -> Vec<String> {
+        CLASSES
+            .into_iter()
+            .filter_map(|class| {
+                let policy = self.get(class).describe()?;
+                Some(format!("{} {policy}", class.name()))
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_classify() {
+    let chrome = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) \
+                  Chrome/120.0.0.0 Safari/537.36";
+    assert_eq!(classify(Some(chrome)), Class::Browser);
+    let googlebot = "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+    assert_eq!(classify(Some(googlebot)), Class::Bot);
+    let ahrefs = "Mozilla/5.0 (compatible; AhrefsBot/7.0; +http://ahrefs.com/robot/)";
+    assert_eq!(classify(Some(ahrefs)), Class::BadBot);
+    assert_eq!(classify(Some("curl/8.5.0")), Class::Cli);
+    assert_eq!(classify(Some("Wget/1.21.4")), Class::Cli);
+    assert_eq!(classify(Some("python-requests/2.31.0")), Class::Cli);
+    assert_eq!(classify(Some("MyApp/1.0")), Class::Other);
+    assert_eq!(classify(None), Class::Other);
+}
+
+#[test]
+fn test_policies() {
+    let mut policies = Policies::default();
+    policies.parse("bad-bot=deny").unwrap();
+    policies.parse("cli=plain,rate=30").unwrap();
+    assert!(policies.get(Class::BadBot).deny);
+    assert!(policies.get(Class::Cli).plain);
+    assert_eq!(policies.get(Class::Cli).rate.as_ref().unwrap().rate, 30);
+    assert!(policies.get(Class::Browser).describe().is_none());
+    assert_eq!(
+        policies.describe(),
+        ["bad-bot deny", "cli rate=30/min, plain"]
+    );
+    assert!(policies.parse("robot=deny").is_err());
+    assert!(policies.parse("bot=rate=0").is_err());
+    assert!(policies.parse("bot=block").is_err());
+    assert!(policies.parse("bot").is_err());
+}
@@ -0,0 +1,288 @@
+// HTTP Digest authentication (RFC 7616) for --auth, below the paths given to
+// --digest, where passwords don't go over plain HTTP as they do with Basic.
+// SHA-256 and MD5 are offered, with qop=auth only. Nonces carry the time they
+// were made at, signed, so they needn't be kept until they're used; then the
+// highest nonce count each came with is, so requests can't be replayed.
+
+use crate::{
+    Request, crypto,
+    crypto::{Digest as _, hmac, md5::Md5, sha2::Sha256},
+    date, is_below,
+};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+// after which clients are asked to retry with a new one, with stale=true
+const NONCE_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Sha256 => "SHA-256",
+        }
+    }
+
+    fn hash(self, data: &str) -> String {
+        crypto::hex(&match self {
+            Algorithm::Md5 => Md5::digest(data.as_bytes()),
+            Algorithm::Sha256 => Sha256::digest(data.as_bytes()),
+        })
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Verdict {
+    Authorized,
+    // right credentials, with a nonce which expired
+    Stale,
+    Unauthorized,
+}
+
+pub struct Digest {
+    // normalized
+    paths: Vec<String>,
+    user: String,
+    password: String,
+    secret: [u8; 32],
+    // nonce -> the highest nonce count it was used with
+    counts: Mutex<HashMap<String, u64>>,
+}
+
+impl Digest {
+    pub fn new(paths: Vec<String>, user: String, password: String) -> std::io::Result<Self> {
+        Ok(Digest {
+            paths,
+            user,
+            password,
+            secret: crypto::random_bytes()?,
+            counts: Mutex::default(),
+        })
+    }
+
+    // Whether requests for `path` get Digest rather than Basic
+    pub fn applies(&self, path: &str) -> bool {
+        self.paths.iter().any(|prefix| is_below(path, prefix))
+    }
+
+    pub fn describe(&self) -> String {
+        let paths: Vec<_> = self.paths.iter().map(|path| format!("/{path}")).collect();
+        paths.join(" ")
+    }
+
+    // The WWW-Authenticate values, the preferred algorithm first
+    pub fn challenges(&self, realm: &str, stale: bool) -> Vec<String> {
+        let nonce = self.nonce(date::now());
+        [Algorithm::Sha256, Algorithm::Md5]
+            .iter()
+            .map(|algorithm| {
+                let mut challenge = format!(
+                    "Digest realm=\"{realm}\", qop=\"auth\", algorithm={}, nonce=\"{nonce}\"",
+                    algorithm.name()
+                );
+                if stale {
+                    challenge.push_str(", stale=true");
+                }
+                challenge
+            })
+            .collect()
+    }
+
+    pub fn verify(&self, request: &Request, realm: &str) -> Verdict {
+        let Some(params) = request
+            .header("Authorization")
+            .and_then(|value| value.trim().strip_prefix("Digest "))
+            .map(parse_params)
+        else {
+            return Verdict::Unauthorized;
+        };
+        let param = |name: &str| params.get(name).map(String::as_str).unwrap_or_default();
+        let algorithm = match param("algorithm") {
+            "" | "MD5" => Algorithm::Md5,
+            "SHA-256" => Algorithm::Sha256,
+            _ => return Verdict::Unauthorized,
+        };
+        let nonce = param("nonce");
+        let Some(issued) = self.issued(nonce) else {
+            return Verdict::Unauthorized;
+        };
+        let Ok(count) = u64::from_str_radix(param("nc"), 16) else {
+            return Verdict::Unauthorized;
+        };
+        if param("qop") != "auth"
+            || param("realm") != realm
+            || param("uri") != request.path
+            || !crypto::constant_time_eq(param("username").as_bytes(), self.user.as_bytes())
+        {
+            return Verdict::Unauthorized;
+        }
+        let expected = response(
+            algorithm,
+            [&self.user, realm, &self.password],
+            [nonce, param("nc"), param("cnonce")],
+            &request.method,
+            &request.path,
+        );
+        if !crypto::constant_time_eq(param("response").as_bytes(), expected.as_bytes()) {
+            return Verdict::Unauthorized;
+        }
+        let now = date::now();
+        if now.saturating_sub(issued) > NONCE_LIFETIME.as_secs() {
+            return Verdict::Stale;
+        }
+
+        let mut counts = self.counts.lock().unwrap_or_else(|err| err.into_inner());
+        counts.retain(|nonce, _| {
+            self.issued(nonce)
+                .is_some_and(|issued| now.saturating_sub(issued) <= NONCE_LIFETIME.as_secs())
+        });
+        let last = counts.entry(nonce.to_owned()).or_default();
+        // a replay, or requests sent in parallel arriving out of order
+        if count <= *last {
+            return Verdict::Unauthorized;
+        }
+        *last = count;
+        Verdict::Authorized
+    }
+
+    // The time in hex, then its signature
+    fn nonce(&self, time: u64) -> String {
+        let signature = hmac::hmac_sha256(&self.secret, &time.to_be_bytes());
+        format!("{time:016x}{}", crypto::hex(&signature[..16]))
+    }
+
+    // When `nonce` was made, if it's one of ours
+    fn issued(&self, nonce: &str) -> Option<u64> {
+        let time = u64::from_str_radix(nonce.get(..16)?, 16).ok()?;
+        crypto::constant_time_eq(nonce.as_bytes(), self.nonce(time).as_bytes()).then_some(time)
+    }
+}
+
+// The request-digest of section 3.4.1, with qop=auth
+fn response(
+    algorithm: Algorithm,
+    [user, realm, password]: [&str; 3],
+    [nonce, count, cnonce]: [&str; 3],
+    method: &str,
+    uri: &str,
+) -> String {
+    let ha1 = algorithm.hash(&format!("{user}:{realm}:{password}"));
+    let ha2 = algorithm.hash(&format!("{method}:{uri}"));
+    algorithm.hash(&format!("{ha1}:{nonce}:{count}:{cnonce}:auth:{ha2}"))
+}
+
+// `name=value, name="quoted, value"` into a map, names lowercase
+fn parse_params(input: &str) -> HashMap<String, String> {
+    let mut res = HashMap::new();
+    let mut rest = input.trim_start();
+    while let Some((name, after)) = rest.split_once('=') {
+        let name = name.trim().to_ascii_lowercase();
+        let after = after.trim_start();
+        let mut value = String::new();
+        if let Some(quoted) = after.strip_prefix('"') {
+            let mut chars = quoted.char_indices();
+            rest = "";
+            while let Some((idx, c)) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        rest = &quoted[idx + 1..];
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            value.push_str(after[..end].trim());
+            rest = &after[end..];
+        }
+        res.insert(name, value);
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+    res
+}
+
+#[test]
+fn test_response() {
+    // the examples of RFC 7616 section 3.9.1
+    let credentials = ["Mufasa", "http-auth@example.org", "Circle of Life"];
+    let nonce = [
+        "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v",
+        "00000001",
+        "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+    ];
+    let uri = "/dir/index.html";
+    assert_eq!(
+        response(Algorithm::Md5, credentials, nonce, "GET", uri),
+        "8ca523f5e9506fed4657c9700eebdbec"
+    );
+    assert_eq!(
+        response(Algorithm::Sha256, credentials, nonce, "GET", uri),
+        "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1"
+    );
+
+    let params = parse_params("username=\"a\\\"b\", qop=auth,nc=00000001 , realm=\"x, y\"");
+    assert_eq!(params["username"], "a\"b");
+    assert_eq!(params["qop"], "auth");
+    assert_eq!(params["nc"], "00000001");
+    assert_eq!(params["realm"], "x, y");
+}
+
+#[test]
+fn test_verify() {
+    let digest = Digest::new(vec!["private".to_owned()], "a".to_owned(), "b".to_owned()).unwrap();
+    assert!(digest.applies("private/x") && !digest.applies("public"));
+    let request = |nonce: &str, count: &str| {
+        let digest = response(
+            Algorithm::Sha256,
+            ["a", "realm", "b"],
+            [nonce, count, "c"],
+            "GET",
+            "/private/x",
+        );
+        let header = format!(
+            "Digest username=\"a\", realm=\"realm\", uri=\"/private/x\", algorithm=SHA-256, \
+             nonce=\"{nonce}\", nc={count}, cnonce=\"c\", qop=auth, response=\"{digest}\""
+        );
+        Request::parse(
+            format!("GET /private/x HTTP/1.1\r\nAuthorization: {header}\r\n\r\n").as_bytes(),
+        )
+        .unwrap()
+    };
+
+    let nonce = digest.nonce(date::now());
+    assert_eq!(
+        digest.verify(&request(&nonce, "00000001"), "realm"),
+        Verdict::Authorized
+    );
+    assert_eq!(
+        digest.verify(&request(&nonce, "00000002"), "realm"),
+        Verdict::Authorized
+    );
+    // replayed
+    assert_eq!(
+        digest.verify(&request(&nonce, "00000002"), "realm"),
+        Verdict::Unauthorized
+    );
+    assert_eq!(
+        digest.verify(&request(&nonce, "00000003"), "other"),
+        Verdict::Unauthorized
+    );
+    let forged = format!("{}{}", &nonce[..16], "0".repeat(32));
+    assert_eq!(
+        digest.verify(&request(&forged, "00000001"), "realm"),
+        Verdict::Unauthorized
+    );
+    let old = digest.nonce(date::now() - 3600);
+    assert_eq!(
+        digest.verify(&request(&old, "00000001"), "realm"),
+        Verdict::Stale
+    );
+    assert!(digest.challenges("realm", true)[0].ends_with("stale=true"));
+}
@@ -0,0 +1,161 @@
+// Apache htpasswd files, one "user:hash" per line. bcrypt, MD5 ($apr1$ and
+// $1$), SHA-1 ({SHA}) and SHA-2 ($5$ and $6$) hashes are supported, DES
+// crypt and plain text passwords are not.
+
+use crate::{
+    base64,
+    crypto::{self, Digest, bcrypt, crypt, sha1::Sha1, sha2::Sha256},
+    log,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+
+pub struct Htpasswd {
+    path: PathBuf,
+    users: RwLock<HashMap<String, String>>,
+    // Hashes like bcrypt are slow on purpose, and browsers send the
+    // credentials with every request: the SHA-256 of the last password that
+    // was accepted for each user is kept here.
+    verified: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl Htpasswd {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        // reloads don't depend on the current directory
+        let path = std::fs::canonicalize(path)?;
+        let users = parse(&std::fs::read_to_string(&path)?);
+        Ok(Htpasswd {
+            path,
+            users: RwLock::new(users),
+            verified: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn reload(&self) -> Result<(), Box<dyn Error>> {
+        let users = parse(&std::fs::read_to_string(&self.path)?);
+        log::info(&format!(
+            "loaded {} users from {}",
+            users.len(),
+            self.path.display()
+        ));
+        *self.users.write().unwrap_or_else(|err| err.into_inner()) = users;
+        self.verified
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clear();
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        let users = self.users.read().unwrap_or_else(|err| err.into_inner());
+        format!("htpasswd {} ({} users)", self.path.display(), users.len())
+    }
+
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let fingerprint = Sha256::digest(password.as_bytes());
+        if self
+            .verified
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(user)
+            .is_some_and(|known| crypto::constant_time_eq(known, &fingerprint))
+        {
+            return true;
+        }
+
+        // without holding the locks: the other requests don't wait on a slow
+        // hash
+        let Some(hash) = self.hash(user) else {
+            return false;
+        };
+        if !verify_hash(password, &hash) {
+            return false;
+        }
+        let mut verified = self.verified.lock().unwrap_or_else(|err| err.into_inner());
+        // unless a reload changed it meanwhile
+        if self.hash(user).as_ref() == Some(&hash) {
+            verified.insert(user.to_owned(), fingerprint);
+        }
+        true
+    }
+
+    fn hash(&self, user: &str) -> Option<String> {
+        let users = self.users.read().unwrap_or_else(|err| err.into_inner());
+        users.get(user).cloned()
+    }
+}
+
+fn parse(content: &str) -> HashMap<String, String> {
+    let mut users = HashMap::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((user, hash)) = line.split_once(':') else {
+            log::warning(&format!("ignoring invalid htpasswd line: {line}"));
+            continue;
+        };
+        if !is_supported(hash) {
+            log::warning(&format!(
+                "ignoring user '{user}': unsupported password hash format"
+            ));
+            continue;
+        }
+        users.insert(user.to_owned(), hash.to_owned());
+    }
+
+    users
+}
+
+fn is_supported(hash: &str) -> bool {
+    [
+        "$2a$", "$2b$", "$2y$", "$apr1$", "$1$", "$5$", "$6$", "{SHA}",
+    ]
+    .iter()
+    .any(|prefix| hash.starts_with(prefix))
+}
+
+fn verify_hash(password: &str, hash: &str) -> bool {
+    let computed = if hash.starts_with("$2") {
+        return bcrypt::verify(password, hash);
+    } else if let Some(rest) = hash.strip_prefix("{SHA}") {
+        return base64::decode(rest).is_some_and(|digest| {
+            crypto::constant_time_eq(&digest, &Sha1::digest(password.as_bytes()))
+        });
+    } else if let Some(rest) = hash.strip_prefix("$apr1$") {
+        crypt::md5_crypt(password.as_bytes(), "$apr1$", rest)
+    } else if let Some(rest) = hash.strip_prefix("$1$") {
+        crypt::md5_crypt(password.as_bytes(), "$1$", rest)
+    } else if let Some(rest) = hash.strip_prefix("$5$") {
+        crypt::sha256_crypt(password.as_bytes(), rest)
+    } else if let Some(rest) = hash.strip_prefix("$6$") {
+        crypt::sha512_crypt(password.as_bytes(), rest)
+    } else {
+        return false;
+    };
+    crypto::constant_time_eq(computed.as_bytes(), hash.as_bytes())
+}
+
+#[test]
+fn test_verify_hash() {
+    let users = parse(
+        "# comment
+alice:$apr1$abcdefgh$h9FWgUz3n9YxylKLlR5SQ/
+bob:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=
+carol:$2b$05$abcdefghijklmnopqrstuuOQiyCxlgf/oeuTqixKmWdcYUh4Hjl0a
+dave:$5$saltsalt$0IyaXrmV7.sGNS6tirgqHLqX/G.FBvgkYA.lpPdS5sA
+eve:plaintext
+",
+    );
+    assert_eq!(users.len(), 4);
+    for user in ["alice", "bob", "carol", "dave"] {
+        assert!(verify_hash("secret", &users[user]), "{user}");
+        assert!(!verify_hash("wrong", &users[user]), "{user}");
+    }
+}
@@ -0,0 +1,235 @@
+// Validation of the JSON Web Tokens (RFC 7519) API clients send as
+// `Authorization: Bearer <token>`, signed either with a shared secret (HS256)
+// or by one of the RSA keys published at a JWKS URL (RS256).
+
+use super::oidc::claim_matches;
+use crate::{
+    base64, client,
+    crypto::{self, hmac, rsa},
+    json,
+};
+use std::{
+    error::Error,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const JWKS_LIFETIME: Duration = Duration::from_secs(60 * 60);
+// tokens with unknown key ids trigger a refetch, but not more often than this
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+// tolerated clock difference with the issuer, in seconds
+const LEEWAY: f64 = 60.0;
+
+struct RsaKey {
+    id: Option<String>,
+    modulus: Vec<u8>,
+    exponent: Vec<u8>,
+}
+
+struct KeySet {
+    keys: Vec<RsaKey>,
+    fetched: Instant,
+}
+
+pub struct Jwt {
+    secret: Option<Vec<u8>>,
+    jwks_url: Option<String>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    jwks: Mutex<Option<KeySet>>,
+}
+
+impl Jwt {
+    pub fn new(
+        secret: Option<String>,
+        jwks_url: Option<String>,
+        issuer: Option<String>,
+        audience: Option<String>,
+    ) -> Result<Self, String> {
+        if let Some(url) = &jwks_url
+            && !url.starts_with("http://")
+        {
+            return Err(format!("unsupported JWKS URL: {url} (only http:// URLs)"));
+        }
+        Ok(Jwt {
+            secret: secret.map(String::into_bytes),
+            jwks_url,
+            issuer,
+            audience,
+            jwks: Mutex::new(None),
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        let mut res = match &self.jwks_url {
+            Some(url) => format!("RS256 keys from {url}"),
+            None => "HS256 secret".to_owned(),
+        };
+        if let Some(issuer) = &self.issuer {
+            res.push_str(&format!(", iss {issuer}"));
+        }
+        if let Some(audience) = &self.audience {
+            res.push_str(&format!(", aud {audience}"));
+        }
+        res
+    }
+
+    // The claims of `token`, once its signature and times are checked
+    pub fn verify(&self, token: &str) -> Result<json::Value, Box<dyn Error>> {
+        let (signed, signature) = token.rsplit_once('.').ok_or("malformed token")?;
+        let (header, payload) = signed.split_once('.').ok_or("malformed token")?;
+        let decode = |part: &str| -> Result<json::Value, Box<dyn Error>> {
+            let bytes = base64::decode_url_safe(part).ok_or("malformed token")?;
+            Ok(json::parse(&String::from_utf8(bytes)?)?)
+        };
+        let header = decode(header)?;
+        let signature = base64::decode_url_safe(signature).ok_or("malformed token")?;
+
+        // the algorithm has to match the configured key, "none" never does
+        let valid = match header.get("alg").and_then(json::Value::as_str) {
+            Some("HS256") if let Some(secret) = &self.secret => {
+                crypto::constant_time_eq(&hmac::hmac_sha256(secret, signed.as_bytes()), &signature)
+            }
+            Some("RS256") if let Some(url) = &self.jwks_url => {
+                let key_id = header.get("kid").and_then(json::Value::as_str);
+                self.verify_rs256(url, key_id, signed.as_bytes(), &signature)?
+            }
+            alg => return Err(format!("unsupported algorithm {alg:?}").into()),
+        };
+        if !valid {
+            return Err("invalid signature".into());
+        }
+
+        let claims = decode(payload)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64();
+        let time = |name: &str| claims.get(name).and_then(json::Value::as_f64);
+        if !time("exp").is_some_and(|exp| exp + LEEWAY > now) {
+            return Err("token expired".into());
+        }
+        if time("nbf").is_some_and(|nbf| nbf - LEEWAY > now) {
+            return Err("token not valid yet".into());
+        }
+        if let Some(issuer) = &self.issuer
+            && claims.get("iss").and_then(json::Value::as_str) != Some(issuer)
+        {
+            return Err("unexpected issuer".into());
+        }
+        if let Some(audience) = &self.audience
+            && !claim_matches(claims.get("aud"), audience)
+        {
+            return Err("token wasn't issued for us".into());
+        }
+
+        Ok(claims)
+    }
+
+    fn verify_rs256(
+        &self,
+        url: &str,
+        key_id: Option<&str>,
+        signed: &[u8],
+        signature: &[u8],
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut jwks = self.jwks.lock().unwrap_or_else(|err| err.into_inner());
+        let matches = |key: &RsaKey| key_id.is_none() || key.id.as_deref() == key_id;
+
+        let refresh = match jwks.as_ref() {
+            None => true,
+            Some(set) => {
+                set.fetched.elapsed() > JWKS_LIFETIME
+                    || (!set.keys.iter().any(matches) && set.fetched.elapsed() > JWKS_MIN_REFRESH)
+            }
+        };
+        if refresh {
+            *jwks = Some(KeySet {
+                keys: fetch_jwks(url)?,
+                fetched: Instant::now(),
+            });
+        }
+
+        Ok(jwks
+            .iter()
+            .flat_map(|set| &set.keys)
+            .filter(|key| matches(key))
+            .any(|key| rsa::verify_sha256(&key.modulus, &key.exponent, signed, signature)))
+    }
+}
+
+// Keeps the RSA signing keys of a JWK Set (RFC 7517)
+fn fetch_jwks(url: &str) -> Result<Vec<RsaKey>, Box<dyn Error>> {
+    let document = json::parse(&client::fetch(url, "GET", &[], "")?)?;
+    let keys = document
+        .get("keys")
+        .and_then(json::Value::as_array)
+        .ok_or_else(|| format!("no keys in {url}"))?;
+
+    Ok(keys
+        .iter()
+        .filter(|key| key.get("kty").and_then(json::Value::as_str) == Some("RSA"))
+        .filter(|key| {
+            key.get("use")
+                .is_none_or(|usage| usage.as_str() == Some("sig"))
+        })
+        .filter_map(|key| {
+            let field = |name: &str| base64::decode_url_safe(key.get(name)?.as_str()?);
+            Some(RsaKey {
+                id: key
+                    .get("kid")
+                    .and_then(json::Value::as_str)
+                    .map(str::to_owned),
+                modulus: field("n")?,
+                exponent: field("e")?,
+            })
+        })
+        .collect())
+}
+
+#[test]
+fn test_verify() {
+    let claims = "eyJpc3MiOiJodHRwOi8vaWRwIiwiYXVkIjoiYXBpIiwiZXhwIjo0MTAyNDQ0ODAwLCJzdWIiOiJ1MSJ9";
+    let jwt = Jwt::new(
+        Some("secret".to_owned()),
+        None,
+        Some("http://idp".to_owned()),
+        Some("api".to_owned()),
+    )
+    .unwrap();
+    let hs256 = format!(
+        "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.{claims}.CqRCEMZZuMZloFkIz-Zw3I65Go7nSRZS9Pqy1QyGeUI"
+    );
+    assert!(jwt.verify(&hs256).is_ok());
+    assert!(jwt.verify(&hs256.replace(".Cq", ".Dq")).is_err());
+    // alg: none
+    assert!(
+        jwt.verify(&format!("eyJhbGciOiJub25lIn0.{claims}."))
+            .is_err()
+    );
+
+    let rs256 = format!(
+        "eyJhbGciOiJSUzI1NiIsImtpZCI6ImsxIiwidHlwIjoiSldUIn0.{claims}.\
+         tpUzdhR24Dd3WgPjH57YrAF87rcAUffFI-Is2CThmpQPZGcXk7ZbxzY3HItcb53HRBGoBZzpOn41rDh7IPxpBk2CPr5D\
+         K-6j_5cBbZC-KiOiqetct8M9ehGpHFECwhSVIoccVdg4QXsEDBlBTrHNvzzTzzg8J6EVF5EPavU-Gc4"
+    );
+    let modulus = base64::decode_url_safe(
+        "xkD_Gb2Zk2ndBhVibABbyyFJknXm0KIXjffkIjhsQEPtKWv_RLu0Ka8FouPuKYrk1qrscOM5A_HS07bUawevsB1aO330\
+         8ONUapGxaHrTAcJj8tbarbuZN2W9wJ888YQvvGHbPnDVvql-eAunXyU1hT9LVL92hP1jW_CWm4CLtD0",
+    )
+    .unwrap();
+    let jwt = Jwt {
+        secret: None,
+        jwks_url: Some("http://idp/jwks".to_owned()),
+        jwks: Mutex::new(Some(KeySet {
+            keys: vec![RsaKey {
+                id: Some("k1".to_owned()),
+                modulus,
+                exponent: vec![1, 0, 1],
+            }],
+            fetched: Instant::now(),
+        })),
+        ..jwt
+    };
+    assert!(jwt.verify(&rs256).is_ok());
+    assert!(jwt.verify(&rs256.replace(".tp", ".tq")).is_err());
+    // no secret configured anymore
+    assert!(jwt.verify(&hs256).is_err());
+}
@@ -0,0 +1,295 @@
+// Checks credentials by binding to an LDAP server (OpenLDAP, Active
+// Directory...) as the user, then optionally checks that the user belongs to
+// a group. Only plain ldap:// is supported, the messages are BER encoded by
+// hand.
+
+use crate::log;
+use std::{
+    error::Error,
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// BER tags
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const ENUMERATED: u8 = 0x0a;
+const BOOLEAN: u8 = 0x01;
+const SEQUENCE: u8 = 0x30;
+const BIND_REQUEST: u8 = 0x60;
+const BIND_RESPONSE: u8 = 0x61;
+const UNBIND_REQUEST: u8 = 0x42;
+const SEARCH_REQUEST: u8 = 0x63;
+const SEARCH_RESULT_ENTRY: u8 = 0x64;
+const SEARCH_RESULT_DONE: u8 = 0x65;
+const SIMPLE_AUTH: u8 = 0x80;
+const FILTER_OR: u8 = 0xa1;
+const FILTER_EQUALITY: u8 = 0xa3;
+
+pub struct Ldap {
+    address: String,
+    // "uid={user},ou=people,dc=example,dc=org" or "{user}@example.org"
+    user_dn: String,
+    group: Option<String>,
+}
+
+impl Ldap {
+    pub fn new(url: &str, user_dn: String, group: Option<String>) -> Result<Self, String> {
+        let Some(host) = url.strip_prefix("ldap://") else {
+            return Err(format!("unsupported LDAP URL (only ldap:// is): {url}"));
+        };
+        let host = host.trim_end_matches('/');
+        let address = if host.contains(':') {
+            host.to_owned()
+        } else {
+            format!("{host}:389")
+        };
+        if !user_dn.contains("{user}") {
+            return Err("the LDAP user DN must contain '{user}'".to_owned());
+        }
+        Ok(Ldap {
+            address,
+            user_dn,
+            group,
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        let mut res = format!("ldap://{} as {}", self.address, self.user_dn);
+        if let Some(group) = &self.group {
+            res.push_str(&format!(", member of {group}"));
+        }
+        res
+    }
+
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        // an empty password is an anonymous bind, which always succeeds
+        if user.is_empty() || password.is_empty() {
+            return false;
+        }
+        match self.bind_and_check(user, password) {
+            Ok(res) => res,
+            Err(err) => {
+                log::error(&format!("LDAP error: {err}"));
+                false
+            }
+        }
+    }
+
+    fn bind_and_check(&self, user: &str, password: &str) -> Result<bool, Box<dyn Error>> {
+        let address = self
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or("LDAP server not found")?;
+        let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let dn = self.user_dn.replace("{user}", &escape_dn(user));
+        stream.write_all(&bind_request(1, &dn, password))?;
+        let (tag, response) = read_message(&mut stream, 1)?;
+        if tag != BIND_RESPONSE {
+            return Err(format!("unexpected LDAP response {tag:#x}").into());
+        }
+        if result_code(&response)? != 0 {
+            return Ok(false);
+        }
+
+        let res = match &self.group {
+            None => true,
+            Some(group) => {
+                stream.write_all(&group_search_request(2, group, &dn, user))?;
+                let mut found = false;
+                loop {
+                    match read_message(&mut stream, 2)? {
+                        (SEARCH_RESULT_ENTRY, _) => found = true,
+                        (SEARCH_RESULT_DONE, _) => break,
+                        _ => (), // referrals
+                    }
+                }
+                found
+            }
+        };
+
+        let _ = stream.write_all(&message(3, &tlv(UNBIND_REQUEST, &[])));
+        Ok(res)
+    }
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut res = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        res.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|byte| *byte == 0)
+            .collect();
+        res.push(0x80 | bytes.len() as u8);
+        res.extend(bytes);
+    }
+    res.extend_from_slice(content);
+    res
+}
+
+fn integer(tag: u8, value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // minimal two's complement encoding
+    let start = bytes[..3]
+        .iter()
+        .zip(&bytes[1..])
+        .take_while(|(byte, next)| **byte == 0 && **next < 0x80)
+        .count();
+    tlv(tag, &bytes[start..])
+}
+
+fn message(id: u32, operation: &[u8]) -> Vec<u8> {
+    tlv(
+        SEQUENCE,
+        &[integer(INTEGER, id), operation.to_vec()].concat(),
+    )
+}
+
+fn bind_request(id: u32, dn: &str, password: &str) -> Vec<u8> {
+    let bind = [
+        integer(INTEGER, 3),
+        tlv(OCTET_STRING, dn.as_bytes()),
+        tlv(SIMPLE_AUTH, password.as_bytes()),
+    ]
+    .concat();
+    message(id, &tlv(BIND_REQUEST, &bind))
+}
+
+// Looks for the group entry itself, filtered on the usual membership
+// attributes of groupOfNames, groupOfUniqueNames and posixGroup
+fn group_search_request(id: u32, group: &str, dn: &str, user: &str) -> Vec<u8> {
+    let equality = |attribute: &str, value: &str| {
+        tlv(
+            FILTER_EQUALITY,
+            &[
+                tlv(OCTET_STRING, attribute.as_bytes()),
+                tlv(OCTET_STRING, value.as_bytes()),
+            ]
+            .concat(),
+        )
+    };
+    let filter = tlv(
+        FILTER_OR,
+        &[
+            equality("member", dn),
+            equality("uniqueMember", dn),
+            equality("memberUid", user),
+        ]
+        .concat(),
+    );
+    let search = [
+        tlv(OCTET_STRING, group.as_bytes()),
+        integer(ENUMERATED, 0), // base object
+        integer(ENUMERATED, 0), // never dereference aliases
+        integer(INTEGER, 1),    // size limit
+        integer(INTEGER, 5),    // time limit
+        tlv(BOOLEAN, &[0xff]),  // types only
+        filter,
+        // "1.1" means no attributes
+        tlv(SEQUENCE, &tlv(OCTET_STRING, b"1.1")),
+    ]
+    .concat();
+    message(id, &tlv(SEARCH_REQUEST, &search))
+}
+
+fn read_tlv(stream: &mut impl Read) -> Result<(u8, Vec<u8>), Box<dyn Error>> {
+    let mut header = [0; 2];
+    stream.read_exact(&mut header)?;
+    let len = if header[1] < 0x80 {
+        usize::from(header[1])
+    } else {
+        let count = usize::from(header[1] & 0x7f);
+        if count == 0 || count > 4 {
+            return Err("unsupported BER length".into());
+        }
+        let mut bytes = [0; 4];
+        stream.read_exact(&mut bytes[4 - count..])?;
+        u32::from_be_bytes(bytes) as usize
+    };
+    if len > 1024 * 1024 {
+        return Err("LDAP message too big".into());
+    }
+    let mut content = vec![0; len];
+    stream.read_exact(&mut content)?;
+    Ok((header[0], content))
+}
+
+// Reads an LDAPMessage and returns the tag and content of its operation
+fn read_message(stream: &mut impl Read, id: u32) -> Result<(u8, Vec<u8>), Box<dyn Error>> {
+    let (tag, content) = read_tlv(stream)?;
+    if tag != SEQUENCE {
+        return Err("invalid LDAP message".into());
+    }
+    let mut content = &content[..];
+    let (tag, message_id) = read_tlv(&mut content)?;
+    if tag != INTEGER
+        || message_id
+            .iter()
+            .fold(0, |acc, byte| (acc << 8) | u32::from(*byte))
+            != id
+    {
+        return Err("unexpected LDAP message id".into());
+    }
+    read_tlv(&mut content)
+}
+
+fn result_code(ldap_result: &[u8]) -> Result<u8, Box<dyn Error>> {
+    match read_tlv(&mut &ldap_result[..])? {
+        (ENUMERATED, code) if code.len() == 1 => Ok(code[0]),
+        _ => Err("invalid LDAP result".into()),
+    }
+}
+
+// RFC 4514 escaping, for the user name inside the DN
+fn escape_dn(value: &str) -> String {
+    let mut res = String::new();
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=' => {
+                res.push('\\');
+                res.push(c);
+            }
+            '#' | ' ' if i == 0 => {
+                res.push('\\');
+                res.push(c);
+            }
+            '\0' => res.push_str("\\00"),
+            c => res.push(c),
+        }
+    }
+    if res.ends_with(' ') {
+        res.pop();
+        res.push_str("\\ ");
+    }
+    res
+}
+
+#[test]
+fn test_ber() {
+    assert_eq!(
+        bind_request(1, "cn=admin", "pw"),
+        [
+            &[
+                0x30, 0x16, 0x02, 0x01, 0x01, 0x60, 0x11, 0x02, 0x01, 0x03, 0x04, 0x08
+            ][..],
+            b"cn=admin",
+            &[0x80, 0x02],
+            b"pw"
+        ]
+        .concat()
+    );
+    assert_eq!(integer(INTEGER, 128), [0x02, 0x02, 0x00, 0x80]);
+    assert_eq!(tlv(OCTET_STRING, &[0; 200])[..3], [0x04, 0x81, 200]);
+    assert_eq!(escape_dn(" a,b "), "\\ a\\,b\\ ");
+}
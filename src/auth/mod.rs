@@ -0,0 +1,92 @@
+// Where the credentials sent with HTTP Basic authentication are checked.
+
+mod digest;
+mod htpasswd;
+mod jwt;
+mod ldap;
+mod oidc;
+#[cfg(feature = "pam")]
+mod pam;
+
+use crate::{base64, crypto};
+use std::error::Error;
+
+pub use digest::{Digest, Verdict};
+pub use htpasswd::Htpasswd;
+pub use jwt::Jwt;
+pub use ldap::Ldap;
+pub use oidc::{Oidc, Outcome};
+#[cfg(feature = "pam")]
+pub use pam::Pam;
+
+pub enum Credentials {
+    User {
+        user: String,
+        password: String,
+    },
+    Htpasswd(Htpasswd),
+    Ldap(Ldap),
+    #[cfg(feature = "pam")]
+    Pam(Pam),
+}
+
+impl Credentials {
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        match self {
+            Credentials::User {
+                user: expected_user,
+                password: expected_password,
+            } => {
+                // compare both even when the user is wrong, to not leak it
+                let user_ok = crypto::constant_time_eq(user.as_bytes(), expected_user.as_bytes());
+                let password_ok =
+                    crypto::constant_time_eq(password.as_bytes(), expected_password.as_bytes());
+                user_ok && password_ok
+            }
+            Credentials::Htpasswd(htpasswd) => htpasswd.verify(user, password),
+            Credentials::Ldap(ldap) => ldap.verify(user, password),
+            #[cfg(feature = "pam")]
+            Credentials::Pam(pam) => pam.verify(user, password),
+        }
+    }
+
+    // What's checked, leaving secrets out
+    pub fn describe(&self) -> String {
+        match self {
+            Credentials::User { user, .. } => format!("user {user}"),
+            Credentials::Htpasswd(htpasswd) => htpasswd.describe(),
+            Credentials::Ldap(ldap) => ldap.describe(),
+            #[cfg(feature = "pam")]
+            Credentials::Pam(pam) => pam.describe(),
+        }
+    }
+
+    // Re-reads credentials stored in files, keeping the old ones on errors
+    pub fn reload(&self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Credentials::Htpasswd(htpasswd) => htpasswd.reload(),
+            _ => Ok(()),
+        }
+    }
+}
+
+// "Basic dXNlcjpwYXNzd29yZA==" -> ("user", "password")
+pub fn parse_basic(header: &str) -> Option<(String, String)> {
+    let (scheme, credentials) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let credentials = String::from_utf8(base64::decode(credentials.trim())?).ok()?;
+    let (user, password) = credentials.split_once(':')?;
+    Some((user.to_owned(), password.to_owned()))
+}
+
+#[test]
+fn test_parse_basic() {
+    assert_eq!(
+        parse_basic("Basic dXNlcjpwYXNzOndvcmQ="),
+        Some(("user".to_owned(), "pass:word".to_owned()))
+    );
+    assert_eq!(parse_basic("Bearer dXNlcjpwYXNzOndvcmQ="), None);
+    assert_eq!(parse_basic("Basic dXNlcg=="), None);
+}
@@ -0,0 +1,407 @@
+// OpenID Connect relying party: browsers without a session are sent to the
+// identity provider, which sends them back to the callback with a code we
+// trade for an ID token. Its claims decide whether a session is opened.
+//
+// std has no TLS, so the provider has to be reachable over plain http://
+// (e.g. on localhost or through a TLS terminating proxy).
+
+use super::Jwt;
+use crate::{
+    Request, base64, client, crypto, json, log, normalize_path,
+    session::{self, Sessions},
+    url_decode, url_encode,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub const CALLBACK_PATH: &str = "/_oidc/callback";
+pub const LOGOUT_PATH: &str = "/_oidc/logout";
+const STATE_COOKIE: &str = "oidc_state";
+const SCOPES: &str = "openid email profile";
+// how long users have to log in at the provider
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+// logins under way, the oldest being dropped beyond: anyone can start one
+const MAX_PENDING: usize = 10_000;
+
+pub enum Outcome {
+    Authorized,
+    Redirect {
+        location: String,
+        cookie: Option<String>,
+    },
+    Unauthorized,
+    Forbidden,
+}
+
+struct Metadata {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    // checks ID tokens against the keys of the jwks_uri, or the client secret
+    keys: Arc<Jwt>,
+}
+
+struct PendingLogin {
+    nonce: String,
+    redirect_uri: String,
+    return_to: String,
+    started: Instant,
+}
+
+pub struct Oidc {
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    // of this server, for the redirect URI: "http" or "https"
+    scheme: &'static str,
+    // claim=value pairs all of which the ID token must contain
+    required_claims: Vec<(String, String)>,
+    // fetched on first use, so the provider doesn't need to be up at startup
+    metadata: Mutex<Option<Metadata>>,
+    pending: Mutex<HashMap<String, PendingLogin>>,
+    sessions: Sessions,
+}
+
+impl Oidc {
+    pub fn new(
+        issuer: &str,
+        client_id: String,
+        client_secret: String,
+        scheme: &'static str,
+        required_claims: Vec<(String, String)>,
+    ) -> Result<Self, String> {
+        if !issuer.starts_with("http://") {
+            return Err(format!(
+                "unsupported OIDC issuer: {issuer} (only http:// URLs)"
+            ));
+        }
+        Ok(Oidc {
+            issuer: issuer.trim_end_matches('/').to_owned(),
+            client_id,
+            client_secret,
+            scheme,
+            required_claims,
+            metadata: Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+            sessions: Sessions::default(),
+        })
+    }
+
+    pub fn authorize(&self, request: &Request) -> Outcome {
+        let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+        match path {
+            CALLBACK_PATH => self.callback(request, query).unwrap_or_else(|err| {
+                log::error(&format!("OIDC login failed: {err}"));
+                Outcome::Forbidden
+            }),
+            LOGOUT_PATH => Outcome::Redirect {
+                location: "/".to_owned(),
+                cookie: Some(
+                    self.sessions
+                        .remove(request.cookie(session::COOKIE).unwrap_or_default()),
+                ),
+            },
+            _ if request
+                .cookie(session::COOKIE)
+                .is_some_and(|id| self.sessions.claims(id).is_some()) =>
+            {
+                Outcome::Authorized
+            }
+            // only browsers can follow the login pages
+            _ if request.method == "GET"
+                && request
+                    .header("Accept")
+                    .is_some_and(|accept| accept.contains("text/html")) =>
+            {
+                self.login(request).unwrap_or_else(|err| {
+                    log::error(&format!("OIDC login failed: {err}"));
+                    Outcome::Unauthorized
+                })
+            }
+            _ => Outcome::Unauthorized,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut res = format!("{} as client {}", self.issuer, self.client_id);
+        for (claim, value) in &self.required_claims {
+            res.push_str(&format!(", {claim}={value}"));
+        }
+        res
+    }
+
+    pub fn csrf_token(&self, request: &Request) -> Option<String> {
+        self.sessions.csrf_token(request.cookie(session::COOKIE)?)
+    }
+
+    fn login(&self, request: &Request) -> Result<Outcome, Box<dyn Error>> {
+        let authorization_endpoint =
+            self.metadata(|metadata| metadata.authorization_endpoint.clone())?;
+        let host = request.header("Host").ok_or("no Host header")?;
+        let redirect_uri = format!("{}://{host}{CALLBACK_PATH}", self.scheme);
+        let state = crypto::random_token()?;
+        let nonce = crypto::random_token()?;
+
+        let location = format!(
+            "{authorization_endpoint}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={state}&nonce={nonce}",
+            if authorization_endpoint.contains('?') {
+                '&'
+            } else {
+                '?'
+            },
+            url_encode(&self.client_id),
+            url_encode(&redirect_uri),
+            url_encode(SCOPES),
+        );
+
+        let mut pending = self.pending.lock().unwrap_or_else(|err| err.into_inner());
+        pending.retain(|_, login| login.started.elapsed() < LOGIN_TIMEOUT);
+        if pending.len() >= MAX_PENDING
+            && let Some(oldest) = pending
+                .iter()
+                .min_by_key(|(_, login)| login.started)
+                .map(|(state, _)| state.clone())
+        {
+            pending.remove(&oldest);
+        }
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                nonce,
+                redirect_uri,
+                return_to: return_to(&request.path),
+                started: Instant::now(),
+            },
+        );
+
+        // ties the callback to the browser which started the login
+        let cookie = format!(
+            "{STATE_COOKIE}={state}; Path={CALLBACK_PATH}; HttpOnly; SameSite=Lax; Max-Age={}",
+            LOGIN_TIMEOUT.as_secs()
+        );
+        Ok(Outcome::Redirect {
+            location,
+            cookie: Some(cookie),
+        })
+    }
+
+    fn callback(&self, request: &Request, query: &str) -> Result<Outcome, Box<dyn Error>> {
+        let params: HashMap<_, _> = query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .filter_map(|(key, value)| Some((url_decode(key)?, url_decode(value)?)))
+            .collect();
+        if let Some(error) = params.get("error") {
+            return Err(format!("provider answered '{error}'").into());
+        }
+        let state = params.get("state").ok_or("no state")?;
+        if request.cookie(STATE_COOKIE) != Some(state.as_str()) {
+            return Err("state doesn't match the browser's".into());
+        }
+        let login = self
+            .pending
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(state)
+            .filter(|login| login.started.elapsed() < LOGIN_TIMEOUT)
+            .ok_or("unknown or expired state")?;
+        let code = params.get("code").ok_or("no code")?;
+
+        let token_endpoint = self.metadata(|metadata| metadata.token_endpoint.clone())?;
+        let client = format!(
+            "{}:{}",
+            url_encode(&self.client_id),
+            url_encode(&self.client_secret)
+        );
+        let authorization = format!("Basic {}", base64::encode(client.as_bytes()));
+        let body = format!(
+            "grant_type=authorization_code&code={}&redirect_uri={}",
+            url_encode(code),
+            url_encode(&login.redirect_uri)
+        );
+        let response = client::fetch(
+            &token_endpoint,
+            "POST",
+            &[
+                ("Authorization", &authorization),
+                ("Content-Type", "application/x-www-form-urlencoded"),
+                ("Accept", "application/json"),
+            ],
+            &body,
+        )?;
+        let response = json::parse(&response)?;
+        let id_token = response
+            .get("id_token")
+            .and_then(json::Value::as_str)
+            .ok_or("no id_token in token response")?;
+        let claims = self.validate_id_token(id_token, &login.nonce)?;
+
+        if let Some((claim, value)) = self
+            .required_claims
+            .iter()
+            .find(|(claim, value)| !claim_matches(claims.get(claim), value))
+        {
+            let subject = claims
+                .get("sub")
+                .and_then(json::Value::as_str)
+                .unwrap_or_default();
+            log::warning(&format!(
+                "OIDC user '{subject}' denied: {claim} is not {value}"
+            ));
+            return Ok(Outcome::Forbidden);
+        }
+
+        Ok(Outcome::Redirect {
+            location: login.return_to,
+            cookie: Some(self.sessions.create(claims)?),
+        })
+    }
+
+    // The token endpoint is plain http://, so the signature is checked like
+    // that of bearer tokens, with the issuer and us as audience
+    fn validate_id_token(&self, token: &str, nonce: &str) -> Result<json::Value, Box<dyn Error>> {
+        let keys = self.metadata(|metadata| Arc::clone(&metadata.keys))?;
+        let claims = keys
+            .verify(token)
+            .map_err(|err| format!("invalid ID token: {err}"))?;
+        if claims.get("nonce").and_then(json::Value::as_str) != Some(nonce) {
+            return Err("nonce doesn't match".into());
+        }
+        Ok(claims)
+    }
+
+    fn metadata<T>(&self, field: impl Fn(&Metadata) -> T) -> Result<T, Box<dyn Error>> {
+        let mut metadata = self.metadata.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(metadata) = metadata.as_ref() {
+            return Ok(field(metadata));
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.issuer);
+        let document = json::parse(&client::fetch(&url, "GET", &[], "")?)?;
+        let endpoint = |name: &str| {
+            document
+                .get(name)
+                .and_then(json::Value::as_str)
+                .map(str::to_owned)
+                .ok_or_else(|| format!("no {name} in {url}"))
+        };
+        let keys = Jwt::new(
+            Some(self.client_secret.clone()),
+            Some(endpoint("jwks_uri")?),
+            Some(self.issuer.clone()),
+            Some(self.client_id.clone()),
+        )?;
+        let fetched = Metadata {
+            authorization_endpoint: endpoint("authorization_endpoint")?,
+            token_endpoint: endpoint("token_endpoint")?,
+            keys: Arc::new(keys),
+        };
+        let res = field(&fetched);
+        *metadata = Some(fetched);
+        Ok(res)
+    }
+}
+
+// Where the callback sends browsers back to: the path they asked for, on this
+// server, e.g. not //evil.example/x
+fn return_to(target: &str) -> String {
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, format!("?{query}")),
+        None => (target, String::new()),
+    };
+    // browsers take backslashes for slashes
+    let path = normalize_path(path.replace('\\', "/"));
+    format!("/{path}{query}")
+}
+
+// Claims are strings, or arrays of strings for audiences and groups
+pub(super) fn claim_matches(claim: Option<&json::Value>, expected: &str) -> bool {
+    match claim {
+        Some(json::Value::String(value)) => value == expected,
+        Some(json::Value::Bool(value)) => value.to_string() == expected,
+        Some(json::Value::Number(value)) => value.to_string() == expected,
+        Some(json::Value::Array(values)) => values
+            .iter()
+            .any(|value| claim_matches(Some(value), expected)),
+        _ => false,
+    }
+}
+
+#[test]
+fn test_claim_matches() {
+    let claims =
+        json::parse(r#"{"aud": ["a", "b"], "email_verified": true, "hd": "example.org"}"#).unwrap();
+    assert!(claim_matches(claims.get("aud"), "b"));
+    assert!(!claim_matches(claims.get("aud"), "c"));
+    assert!(claim_matches(claims.get("email_verified"), "true"));
+    assert!(claim_matches(claims.get("hd"), "example.org"));
+    assert!(!claim_matches(claims.get("missing"), ""));
+}
+
+#[test]
+fn test_return_to() {
+    assert_eq!(return_to("/docs/a.html?x=1"), "/docs/a.html?x=1");
+    assert_eq!(return_to("//evil.example/x"), "/evil.example/x");
+    assert_eq!(return_to("/\\evil.example/x"), "/evil.example/x");
+    assert_eq!(return_to("/a/../../b"), "/b");
+    assert_eq!(return_to("/"), "/");
+}
+
+#[test]
+fn test_validate_id_token() {
+    let oidc = Oidc::new(
+        "http://idp/",
+        "app".to_owned(),
+        "secret".to_owned(),
+        "http",
+        Vec::new(),
+    )
+    .unwrap();
+    *oidc.metadata.lock().unwrap() = Some(Metadata {
+        authorization_endpoint: "http://idp/authorize".to_owned(),
+        token_endpoint: "http://idp/token".to_owned(),
+        keys: Arc::new(
+            Jwt::new(
+                Some("secret".to_owned()),
+                None,
+                Some("http://idp".to_owned()),
+                Some("app".to_owned()),
+            )
+            .unwrap(),
+        ),
+    });
+    let sign = |key: &[u8], claims: &str| {
+        let url_safe = |bytes: &[u8]| {
+            base64::encode(bytes)
+                .trim_end_matches('=')
+                .replace('+', "-")
+                .replace('/', "_")
+        };
+        let signed = format!(
+            "{}.{}",
+            url_safe(br#"{"alg":"HS256"}"#),
+            url_safe(claims.as_bytes())
+        );
+        let signature = crypto::hmac::hmac_sha256(key, signed.as_bytes());
+        format!("{signed}.{}", url_safe(&signature))
+    };
+    let claims = r#"{"iss":"http://idp","aud":"app","exp":4102444800,"nonce":"n1","sub":"u1"}"#;
+    assert!(
+        oidc.validate_id_token(&sign(b"secret", claims), "n1")
+            .is_ok()
+    );
+    assert!(
+        oidc.validate_id_token(&sign(b"secret", claims), "n2")
+            .is_err()
+    );
+    // forged on the way from the token endpoint
+    assert!(
+        oidc.validate_id_token(&sign(b"guess", claims), "n1")
+            .is_err()
+    );
+    let unsigned = sign(b"secret", claims).replace("eyJhbGciOiJIUzI1NiJ9", "eyJhbGciOiJub25lIn0");
+    assert!(oidc.validate_id_token(&unsigned, "n1").is_err());
+}
@@ -0,0 +1,124 @@
+// Checks credentials with PAM, so system accounts (or whatever the PAM
+// service is configured with) can log in. Only built with the `pam` feature,
+// as it links to libpam.
+
+use std::{
+    ffi::{CString, c_char, c_int, c_void},
+    ptr,
+};
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_BUF_ERR: c_int = 5;
+const PAM_CONV_ERR: c_int = 19;
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+const PAM_SILENT: c_int = 0x8000;
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+#[repr(C)]
+struct PamConv {
+    conv: extern "C" fn(c_int, *mut *const PamMessage, *mut *mut PamResponse, *mut c_void) -> c_int,
+    appdata_ptr: *mut c_void,
+}
+
+#[link(name = "pam")]
+unsafe extern "C" {
+    fn pam_start(
+        service_name: *const c_char,
+        user: *const c_char,
+        pam_conversation: *const PamConv,
+        pamh: *mut *mut c_void,
+    ) -> c_int;
+    fn pam_authenticate(pamh: *mut c_void, flags: c_int) -> c_int;
+    fn pam_acct_mgmt(pamh: *mut c_void, flags: c_int) -> c_int;
+    fn pam_end(pamh: *mut c_void, pam_status: c_int) -> c_int;
+}
+
+unsafe extern "C" {
+    // PAM frees the responses, they must come from the C allocator
+    fn calloc(count: usize, size: usize) -> *mut c_void;
+    fn strdup(s: *const c_char) -> *mut c_char;
+}
+
+// Answers every prompt with the password, which is what `appdata` points to
+extern "C" fn conversation(
+    num_msg: c_int,
+    msg: *mut *const PamMessage,
+    resp: *mut *mut PamResponse,
+    appdata: *mut c_void,
+) -> c_int {
+    let Ok(count) = usize::try_from(num_msg) else {
+        return PAM_CONV_ERR;
+    };
+    // SAFETY: PAM hands us `num_msg` messages and expects `num_msg`
+    // responses allocated with malloc, `appdata` is the CString we passed to
+    // pam_start and outlives the PAM transaction.
+    unsafe {
+        let responses = calloc(count, size_of::<PamResponse>()) as *mut PamResponse;
+        if responses.is_null() {
+            return PAM_BUF_ERR;
+        }
+        let password = appdata as *const c_char;
+        for i in 0..count {
+            let message = &**msg.add(i);
+            if matches!(message.msg_style, PAM_PROMPT_ECHO_OFF | PAM_PROMPT_ECHO_ON) {
+                (*responses.add(i)).resp = strdup(password);
+            }
+        }
+        *resp = responses;
+    }
+    PAM_SUCCESS
+}
+
+pub struct Pam {
+    service: CString,
+}
+
+impl Pam {
+    pub fn new(service: &str) -> Result<Self, std::ffi::NulError> {
+        Ok(Pam {
+            service: CString::new(service)?,
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        format!("pam service {}", self.service.to_string_lossy())
+    }
+
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let (Ok(user), Ok(password)) = (CString::new(user), CString::new(password)) else {
+            return false;
+        };
+        let conv = PamConv {
+            conv: conversation,
+            appdata_ptr: password.as_ptr() as *mut c_void,
+        };
+
+        let mut handle = ptr::null_mut();
+        // SAFETY: every pointer handed to PAM lives until pam_end()
+        unsafe {
+            let mut status = pam_start(self.service.as_ptr(), user.as_ptr(), &conv, &mut handle);
+            if status != PAM_SUCCESS {
+                return false;
+            }
+            status = pam_authenticate(handle, PAM_SILENT);
+            if status == PAM_SUCCESS {
+                // is the account allowed to log in right now (expired, locked...)?
+                status = pam_acct_mgmt(handle, PAM_SILENT);
+            }
+            pam_end(handle, status);
+            status == PAM_SUCCESS
+        }
+    }
+}
@@ -0,0 +1,63 @@
+// Standard base64 (RFC 4648 section 4) as used by HTTP Basic auth, and its
+// URL safe variant (section 5) found in JSON Web Tokens.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(input: &[u8]) -> String {
+    let mut res = String::with_capacity(input.len().div_ceil(3) * 4);
+
+    for chunk in input.chunks(3) {
+        let mut group = [0; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = (u32::from(group[0]) << 16) | (u32::from(group[1]) << 8) | u32::from(group[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(ALPHABET[(bits >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+
+    res
+}
+
+pub fn decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=').as_bytes();
+    let mut res = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0_u32;
+    let mut bits = 0;
+
+    for byte in input {
+        let value = ALPHABET.iter().position(|c| c == byte)? as u32;
+        buffer = (buffer << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            res.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(res)
+}
+
+pub fn decode_url_safe(input: &str) -> Option<Vec<u8>> {
+    if input.contains(['+', '/']) {
+        return None;
+    }
+    decode(&input.replace('-', "+").replace('_', "/"))
+}
+
+#[test]
+fn test_base64() {
+    assert_eq!(decode("dXNlcjpwYXNzd29yZA==").unwrap(), b"user:password");
+    assert_eq!(decode("YQ").unwrap(), b"a");
+    assert_eq!(decode("").unwrap(), b"");
+    assert!(decode("not base64!").is_none());
+    assert_eq!(decode_url_safe("-_8").unwrap(), [0xfb, 0xff]);
+    for input in [&b""[..], b"a", b"ab", b"abc", b"user:password"] {
+        assert_eq!(decode(&encode(input)).unwrap(), input);
+    }
+    assert_eq!(encode(b"ab"), "YWI=");
+}
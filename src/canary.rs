@@ -0,0 +1,116 @@
+// --canary: a share of the visitors of the main site gets its pages from
+// another directory below the served one, e.g. the next build of the site,
+// which is only served that way. Visitors are drawn once and keep their
+// variant through a cookie; it's in the log of each of their requests.
+
+use crate::{Request, crypto, is_below, normalize_path};
+use std::cell::Cell;
+
+pub const COOKIE: &str = "canary";
+// long enough for a visitor to see the same site throughout a rollout
+const MAX_AGE: u64 = 30 * 24 * 3600;
+
+thread_local! {
+    // of the request being answered, for the log
+    static VARIANT: Cell<Option<Variant>> = const { Cell::new(None) };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    Stable,
+    Canary,
+}
+
+impl Variant {
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Stable => "stable",
+            Variant::Canary => "canary",
+        }
+    }
+}
+
+pub struct Canary {
+    // normalized, below the served directory
+    pub root: String,
+    // of new visitors, from 0 to 100
+    pub percent: u8,
+}
+
+// "dir=percent"
+pub fn parse(value: &str) -> Result<Canary, String> {
+    let Some((root, percent)) = value.rsplit_once('=') else {
+        return Err("'--canary' value must be 'dir=percent', e.g. 'next=10'".to_owned());
+    };
+    let root = normalize_path(root.to_owned());
+    if root.is_empty() || root == "." {
+        return Err("'--canary' directory must be below the served directory".to_owned());
+    }
+    let percent = percent.trim_end_matches('%');
+    match percent.parse() {
+        Ok(percent) if percent <= 100 => Ok(Canary { root, percent }),
+        _ => Err(format!("'--canary' percent must be 0 to 100: {percent}")),
+    }
+}
+
+impl Canary {
+    // The variant of the visitor, and the Set-Cookie header of new ones
+    pub fn pick(&self, request: &Request) -> (Variant, Option<String>) {
+        match request.cookie(COOKIE) {
+            Some("1") => return (Variant::Canary, None),
+            Some("0") => return (Variant::Stable, None),
+            _ => (),
+        }
+        let draw = crypto::random_bytes::<2>().map_or(u16::MAX, u16::from_be_bytes);
+        let variant = match u32::from(draw) * 100 < u32::from(self.percent) << 16 {
+            true => Variant::Canary,
+            false => Variant::Stable,
+        };
+        let value = (variant == Variant::Canary) as u8;
+        let cookie = format!("{COOKIE}={value}; Path=/; Max-Age={MAX_AGE}; HttpOnly; SameSite=Lax");
+        (variant, Some(cookie))
+    }
+
+    // `path` (normalized) as below the canary's directory
+    pub fn map(&self, path: &str) -> String {
+        match path {
+            "" | "." => self.root.clone(),
+            path => format!("{}/{path}", self.root),
+        }
+    }
+}
+
+// Whether `path` (normalized) is in the canary's directory, which isn't served
+// as it is
+pub fn owns(canary: Option<&Canary>, path: &str) -> bool {
+    canary.is_some_and(|canary| is_below(path, &canary.root))
+}
+
+pub fn set_variant(variant: Option<Variant>) {
+    VARIANT.set(variant);
+}
+
+pub fn take_variant() -> Option<Variant> {
+    VARIANT.take()
+}
+
+#[test]
+fn test_canary() {
+    let canary = parse("builds/next/=100").unwrap();
+    assert_eq!(canary.root, "builds/next");
+    assert_eq!(canary.map("."), "builds/next");
+    assert_eq!(canary.map("css/a.css"), "builds/next/css/a.css");
+    assert!(owns(Some(&canary), "builds/next/index.html"));
+    assert!(!owns(Some(&canary), "builds/nextx"));
+    assert!(parse("next=101").is_err());
+    assert!(parse(".=10").is_err());
+
+    let request = Request::parse(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let (variant, cookie) = canary.pick(&request);
+    assert_eq!(variant, Variant::Canary);
+    assert!(cookie.unwrap().starts_with("canary=1;"));
+    let never = parse("next=0").unwrap();
+    assert_eq!(never.pick(&request).0, Variant::Stable);
+    let request = Request::parse(b"GET / HTTP/1.1\r\nCookie: a=b; canary=1\r\n\r\n").unwrap();
+    assert_eq!(never.pick(&request), (Variant::Canary, None));
+}
@@ -0,0 +1,143 @@
+// SHA-256 of the files we serve, for the Content-Digest (RFC 9530) and ETag
+// headers, the .sha256 files next to them and fingerprinted names. Hashing reads the whole file,
+// so results are kept until the file's size or modification time changes.
+
+use crate::{
+    coalesce::Coalescer,
+    crypto::{Digest, hex, sha2::Sha256},
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+pub const SIDECAR_EXTENSION: &str = ".sha256";
+
+// what a digest was computed from, to notice changes
+struct Entry {
+    size: u64,
+    modified: SystemTime,
+    digest: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct Checksums {
+    cache: Mutex<HashMap<PathBuf, Entry>>,
+    // files being hashed, by their size and modification time too
+    hashing: Coalescer<(PathBuf, u64, SystemTime), Vec<u8>>,
+}
+
+impl Checksums {
+    pub fn sha256(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let (size, modified) = (metadata.len(), metadata.modified()?);
+        let cached = self
+            .cache
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(path)
+            .filter(|entry| entry.size == size && entry.modified == modified)
+            .map(|entry| entry.digest.clone());
+        if let Some(digest) = cached {
+            return Ok(digest);
+        }
+
+        let key = (path.to_path_buf(), size, modified);
+        self.hashing.run(key, || {
+            let mut hasher = Sha256::default();
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                let read = file.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+            }
+            let digest = hasher.finish();
+            self.cache
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .insert(
+                    path.to_path_buf(),
+                    Entry {
+                        size,
+                        modified,
+                        digest: digest.clone(),
+                    },
+                );
+            Ok(digest)
+        })
+    }
+}
+
+// The same for a file whichever way it's served
+pub fn etag(digest: &[u8]) -> String {
+    format!("\"{}\"", hex(digest))
+}
+
+// What sha256sum prints, so `sha256sum -c` can check the download
+pub fn sidecar(digest: &[u8], name: &str) -> String {
+    format!("{}  {name}\n", hex(digest))
+}
+
+// "js/app.0123abcd.js" -> ("js/app.js", "0123abcd")
+pub fn strip_fingerprint(path: &str) -> Option<(String, String)> {
+    let (directory, name) = match path.rsplit_once('/') {
+        Some((directory, name)) => (Some(directory), name),
+        None => (None, path),
+    };
+    let (rest, extension) = name.rsplit_once('.')?;
+    let (stem, fingerprint) = rest.rsplit_once('.')?;
+    if stem.is_empty()
+        || !(8..=64).contains(&fingerprint.len())
+        || !fingerprint.bytes().all(|byte| byte.is_ascii_hexdigit())
+    {
+        return None;
+    }
+    let name = format!("{stem}.{extension}");
+    let path = match directory {
+        Some(directory) => format!("{directory}/{name}"),
+        None => name,
+    };
+    Some((path, fingerprint.to_ascii_lowercase()))
+}
+
+#[test]
+fn test_checksums() {
+    let path = std::env::temp_dir().join(format!("checksum-test-{}", std::process::id()));
+    let path = path.as_path();
+    std::fs::write(path, "abc").unwrap();
+    let checksums = Checksums::default();
+    let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    assert_eq!(hex(&checksums.sha256(path).unwrap()), abc);
+    assert_eq!(hex(&checksums.sha256(path).unwrap()), abc);
+    std::fs::write(path, "abcd").unwrap();
+    assert_ne!(hex(&checksums.sha256(path).unwrap()), abc);
+    assert_eq!(
+        sidecar(&Sha256::digest(b"abc"), "a b"),
+        format!("{abc}  a b\n")
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_strip_fingerprint() {
+    assert_eq!(strip_fingerprint("js/app.0123ABCD.min.js"), None);
+    assert_eq!(
+        strip_fingerprint("js/app.min.0123ABCD.js"),
+        Some(("js/app.min.js".to_owned(), "0123abcd".to_owned()))
+    );
+    assert_eq!(
+        strip_fingerprint("app.0123abcd.css"),
+        Some(("app.css".to_owned(), "0123abcd".to_owned()))
+    );
+    assert_eq!(strip_fingerprint("app.0123abc.js"), None);
+    assert_eq!(strip_fingerprint("app.0123abcg.js"), None);
+    assert_eq!(strip_fingerprint(".0123abcd.js"), None);
+    assert_eq!(strip_fingerprint("0123abcd.js"), None);
+}
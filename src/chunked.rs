@@ -0,0 +1,165 @@
+// Transfer-Encoding: chunked (RFC 9112 section 7.1), for responses which
+// start going out before their length is known, and so can only tell what
+// depends on all of it in trailer fields after the last chunk. HTTP/1.0
+// clients get the payload as it is, which the connection closing ends.
+
+use crate::{
+    base64,
+    crypto::{Digest, sha2::Sha256},
+    response,
+};
+use std::{
+    io::{self, Write},
+    time::Instant,
+};
+
+// small writes are gathered so every chunk isn't a few bytes on the wire
+const CHUNK_SIZE: usize = 8 * 1024;
+
+// The trailer fields of a response, which its Trailer header announces
+// (RFC 9110 section 6.5)
+#[derive(Clone, Copy, Default)]
+pub struct Trailers {
+    // Content-Digest, the SHA-256 of the payload
+    pub digest: bool,
+    // Server-Timing, how long producing the payload took
+    pub timing: bool,
+}
+
+impl Trailers {
+    // The value of the Trailer header, None when there are none
+    pub fn names(&self) -> Option<String> {
+        let names: Vec<_> = [
+            (self.digest, "Content-Digest"),
+            (self.timing, "Server-Timing"),
+        ]
+        .into_iter()
+        .filter_map(|(on, name)| on.then_some(name))
+        .collect();
+        (!names.is_empty()).then(|| names.join(", "))
+    }
+}
+
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    // payload bytes, without the framing
+    written: u64,
+    digest: Option<Sha256>,
+    // for Server-Timing
+    started: Option<Instant>,
+    // false for HTTP/1.0 clients, which get neither chunks nor trailers
+    framed: bool,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    // `trailers` must have been announced in the head
+    pub fn new(inner: W, trailers: Trailers) -> Self {
+        ChunkedWriter {
+            inner,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            written: 0,
+            digest: trailers.digest.then(Sha256::default),
+            started: trailers.timing.then(Instant::now),
+            framed: !response::http_10(),
+        }
+    }
+
+    // The last chunk tells the client the response is complete, the trailer
+    // fields follow it. Returns the length of the payload.
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.write_chunk()?;
+        if !self.framed {
+            self.inner.flush()?;
+            return Ok(self.written);
+        }
+        let mut end = String::from("0\r\n");
+        if let Some(digest) = self.digest.take() {
+            let digest = base64::encode(&digest.finish());
+            end.push_str(&format!("Content-Digest: sha-256=:{digest}:\r\n"));
+        }
+        if let Some(started) = self.started {
+            let millis = started.elapsed().as_secs_f64() * 1000.0;
+            end.push_str(&format!("Server-Timing: body;dur={millis:.1}\r\n"));
+        }
+        end.push_str("\r\n");
+        self.inner.write_all(end.as_bytes())?;
+        self.inner.flush()?;
+        Ok(self.written)
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        if let Some(digest) = &mut self.digest {
+            digest.update(&self.buffer);
+        }
+        self.written += self.buffer.len() as u64;
+        if !self.framed {
+            self.inner.write_all(&self.buffer)?;
+            self.buffer.clear();
+            return Ok(());
+        }
+        let mut chunk = format!("{:x}\r\n", self.buffer.len()).into_bytes();
+        chunk.append(&mut self.buffer);
+        chunk.extend_from_slice(b"\r\n");
+        self.inner.write_all(&chunk)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_chunked_writer() {
+    let mut out = Vec::new();
+    let mut writer = ChunkedWriter::new(&mut out, Trailers::default());
+    writer.write_all(b"hello ").unwrap();
+    writer.flush().unwrap();
+    writer.write_all(b"world, this is chunked").unwrap();
+    assert_eq!(writer.finish().unwrap(), 28);
+    assert_eq!(
+        out,
+        b"6\r\nhello \r\n16\r\nworld, this is chunked\r\n0\r\n\r\n"
+    );
+
+    let mut out = Vec::new();
+    let mut writer = ChunkedWriter::new(&mut out, Trailers::default());
+    writer.write_all(&[b'a'; CHUNK_SIZE + 1]).unwrap();
+    writer.finish().unwrap();
+    assert!(out.starts_with(b"2001\r\naaa"));
+    assert!(out.ends_with(b"a\r\n0\r\n\r\n"));
+
+    let trailers = Trailers {
+        digest: true,
+        timing: true,
+    };
+    assert_eq!(
+        trailers.names().as_deref(),
+        Some("Content-Digest, Server-Timing")
+    );
+    assert_eq!(Trailers::default().names(), None);
+    let mut out = Vec::new();
+    let mut writer = ChunkedWriter::new(&mut out, trailers);
+    writer.write_all(b"hello").unwrap();
+    writer.finish().unwrap();
+    let out = String::from_utf8(out).unwrap();
+    // sha256("hello")
+    assert!(out.starts_with(
+        "5\r\nhello\r\n0\r\nContent-Digest: sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:\r\nServer-Timing: body;dur="
+    ));
+    assert!(out.ends_with("\r\n\r\n"));
+}
@@ -0,0 +1,101 @@
+// --ip-allow and --ip-deny: which clients may connect, by address ranges in
+// CIDR notation (e.g. 192.168.0.0/16 or 2001:db8::/32). The connections of
+// the others are closed as soon as they're accepted, without a response.
+
+use std::{fmt, net::IpAddr};
+
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    // "10.0.0.0/8", or an address alone for just that one
+    pub fn parse(value: &str) -> Option<Self> {
+        let (address, prefix) = value.split_once('/').unwrap_or((value, ""));
+        let network: IpAddr = address.parse().ok()?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            "" => max,
+            prefix => prefix.parse().ok().filter(|prefix| *prefix <= max)?,
+        };
+        Some(Cidr { network, prefix })
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        // IPv4 clients of a dual stack socket come as ::ffff:a.b.c.d
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+#[derive(Default)]
+pub struct Rules {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl Rules {
+    // Clients of an allowed range may, then those of a denied one may not,
+    // nor any other when there are only allowed ranges
+    pub fn permits(&self, address: IpAddr) -> bool {
+        if self.allow.iter().any(|range| range.contains(address)) {
+            return true;
+        }
+        if self.deny.is_empty() {
+            return self.allow.is_empty();
+        }
+        !self.deny.iter().any(|range| range.contains(address))
+    }
+}
+
+#[test]
+fn test_rules() {
+    let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+    let range = Cidr::parse("192.168.0.0/16").unwrap();
+    assert!(range.contains(ip("192.168.1.2")));
+    assert!(range.contains(ip("::ffff:192.168.1.2")));
+    assert!(!range.contains(ip("192.169.0.1")));
+    assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(ip("8.8.8.8")));
+    assert!(
+        Cidr::parse("2001:db8::/32")
+            .unwrap()
+            .contains(ip("2001:db8::1"))
+    );
+    assert!(Cidr::parse("10.0.0.1").unwrap().contains(ip("10.0.0.1")));
+    assert!(!Cidr::parse("10.0.0.1").unwrap().contains(ip("10.0.0.2")));
+    assert!(Cidr::parse("10.0.0.0/33").is_none());
+    assert!(Cidr::parse("example.com").is_none());
+    assert_eq!(Cidr::parse("::1").unwrap().to_string(), "::1/128");
+
+    let mut rules = Rules::default();
+    assert!(rules.permits(ip("8.8.8.8")));
+    rules.allow.push(range);
+    assert!(!rules.permits(ip("8.8.8.8")));
+    rules.deny.push(Cidr::parse("0.0.0.0/0").unwrap());
+    assert!(rules.permits(ip("192.168.1.2")));
+    assert!(!rules.permits(ip("8.8.8.8")));
+    // IPv6 clients aren't denied by an IPv4 range
+    assert!(rules.permits(ip("2001:db8::1")));
+    rules.allow.clear();
+    assert!(!rules.permits(ip("8.8.8.8")));
+}
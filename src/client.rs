@@ -0,0 +1,313 @@
+// A small HTTP/1.1 client for our outbound calls (identity providers, JWK
+// Sets, UPnP gateways): one request per connection, which the response may
+// delimit with Content-Length, chunked coding or by closing it.
+//
+// std has no TLS, so only http:// URLs are supported.
+
+use crate::{status::StatusCode, tunnel};
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::SocketAddr,
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+// nothing we fetch comes close, it only protects us from broken servers
+const MAX_HEAD_SIZE: u64 = 64 * 1024;
+const MAX_BODY_SIZE: u64 = 8 * 1024 * 1024;
+// of chunk sizes, their extensions and trailer fields
+const MAX_CHUNK_LINE: u64 = 8 * 1024;
+
+pub struct Url {
+    // with the port, if any
+    pub host: String,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Self, Box<dyn Error>> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("unsupported URL: {url} (only http:// URLs)"))?;
+        let (host, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(format!("no host in URL: {url}").into());
+        }
+        Ok(Url {
+            host: host.to_owned(),
+            path: path.to_owned(),
+        })
+    }
+
+    pub fn address(&self) -> String {
+        // IPv6 literals are [::1] or [::1]:80
+        match self.host.rsplit_once(':') {
+            Some((_, port)) if !port.ends_with(']') => self.host.clone(),
+            _ => format!("{}:80", self.host),
+        }
+    }
+}
+
+pub struct Response {
+    pub status_line: String,
+    pub status: Option<StatusCode>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    // our end of the connection
+    pub local_addr: Option<SocketAddr>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Through --upstream-proxy, if there's one
+pub fn request(
+    url: &Url,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    timeout: Duration,
+) -> Result<Response, Box<dyn Error>> {
+    request_via(url, &tunnel::Via::Default, method, headers, body, timeout)
+}
+
+pub fn request_via(
+    url: &Url,
+    via: &tunnel::Via,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    timeout: Duration,
+) -> Result<Response, Box<dyn Error>> {
+    let mut stream = tunnel::connect(&url.address(), via, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut head = format!(
+        "{method} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+        url.path, url.host
+    );
+    for (key, value) in headers {
+        head.push_str(&format!("{key}: {value}\r\n"));
+    }
+    if !body.is_empty() || method == "POST" || method == "PUT" {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+    let mut request = head.into_bytes();
+    request.extend_from_slice(body);
+    stream.write_all(&request)?;
+
+    let mut response = read_response(&mut BufReader::new(&stream), method)?;
+    response.local_addr = stream.local_addr().ok();
+    Ok(response)
+}
+
+// The body of a 200 response to a GET, or of a POST with a form, as text
+pub fn fetch(
+    url: &str,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> Result<String, Box<dyn Error>> {
+    let response = request(&Url::parse(url)?, method, headers, body.as_bytes(), TIMEOUT)?;
+    if response.status != Some(StatusCode::Ok) {
+        return Err(format!("{url} answered '{}'", response.status_line).into());
+    }
+    Ok(String::from_utf8(response.body)?)
+}
+
+pub fn read_response(reader: &mut impl BufRead, method: &str) -> Result<Response, Box<dyn Error>> {
+    let mut response = read_head(reader)?;
+    // RFC 9112 section 6.3
+    let code = response.status.map(StatusCode::code).unwrap_or_default();
+    if method == "HEAD" || code < 200 || code == 204 || code == 304 {
+        return Ok(response);
+    }
+    let mut body = reader.take(MAX_BODY_SIZE + 1);
+    if response
+        .header("Transfer-Encoding")
+        .is_some_and(|codings| codings.to_ascii_lowercase().ends_with("chunked"))
+    {
+        response.body = read_chunked(&mut body, MAX_BODY_SIZE)?;
+    } else if let Some(length) = response.header("Content-Length") {
+        let length: u64 = length.parse()?;
+        if length > MAX_BODY_SIZE {
+            return Err("response too large".into());
+        }
+        response.body = vec![0; length as usize];
+        body.read_exact(&mut response.body)?;
+    } else {
+        body.read_to_end(&mut response.body)?;
+    }
+    if response.body.len() as u64 > MAX_BODY_SIZE {
+        return Err("response too large".into());
+    }
+    Ok(response)
+}
+
+// The status line and headers of a response, after the interim ones, which
+// leaves `reader` at the start of the body
+pub fn read_head(reader: &mut impl BufRead) -> Result<Response, Box<dyn Error>> {
+    let mut head = reader.take(MAX_HEAD_SIZE);
+    let mut read_line = || -> Result<String, Box<dyn Error>> {
+        let mut line = String::new();
+        if head.read_line(&mut line)? == 0 {
+            return Err("connection closed in the response head".into());
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+    };
+
+    let (status_line, status, headers) = loop {
+        let status_line = read_line()?;
+        let status = StatusCode::from_status_line(&status_line);
+        let mut headers: Vec<(String, String)> = Vec::new();
+        loop {
+            let line = read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            match line.split_once(':') {
+                Some((key, value)) => {
+                    headers.push((key.trim().to_owned(), value.trim().to_owned()))
+                }
+                None => return Err(format!("invalid response header: {line}").into()),
+            }
+        }
+        // interim responses, e.g. 103 Early Hints, come before the one
+        let code = status_line.split(' ').nth(1).unwrap_or_default();
+        if code.len() == 3 && code.starts_with('1') && code != "101" {
+            continue;
+        }
+        break (status_line, status, headers);
+    };
+
+    Ok(Response {
+        status_line,
+        status,
+        headers,
+        body: Vec::new(),
+        local_addr: None,
+    })
+}
+
+// RFC 9112 section 7.1, trailers are read and ignored. Bodies of requests
+// are read with it too: a malformed one is an InvalidData error, and one
+// larger than `max` bytes a FileTooLarge one.
+pub fn read_chunked(reader: &mut impl BufRead, max: u64) -> io::Result<Vec<u8>> {
+    let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, message);
+    let mut res = Vec::new();
+    let mut line = String::new();
+    loop {
+        read_chunk_line(reader, &mut line)?;
+        let size = line.trim_end().split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| invalid("invalid chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        if size > max - res.len() as u64 {
+            return Err(io::Error::new(ErrorKind::FileTooLarge, "body too large"));
+        }
+        let start = res.len();
+        res.resize(start + size as usize, 0);
+        reader.read_exact(&mut res[start..])?;
+        read_chunk_line(reader, &mut line)?;
+        if line.trim_end() != "" {
+            return Err(invalid("invalid chunk end"));
+        }
+    }
+    loop {
+        if read_chunk_line(reader, &mut line)? == 0 || line.trim_end().is_empty() {
+            return Ok(res);
+        }
+    }
+}
+
+fn read_chunk_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    line.clear();
+    let read = reader.take(MAX_CHUNK_LINE).read_line(line)?;
+    if read as u64 == MAX_CHUNK_LINE {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "chunk line too long",
+        ));
+    }
+    Ok(read)
+}
+
+#[test]
+fn test_read_response() {
+    let response = read_response(
+        &mut &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5;x=y\r\nhello\r\n6\r\n world\r\n0\r\nTrailer: 1\r\n\r\n"[..],
+        "GET",
+    )
+    .unwrap();
+    assert_eq!(response.status, Some(StatusCode::Ok));
+    assert_eq!(response.body, b"hello world");
+
+    let response = read_response(
+        &mut &b"HTTP/1.1 404 Not Found\r\ncontent-length: 3\r\n\r\nabcdef"[..],
+        "GET",
+    )
+    .unwrap();
+    assert_eq!(response.status, Some(StatusCode::NotFound));
+    assert_eq!(response.header("Content-Length"), Some("3"));
+    assert_eq!(response.body, b"abc");
+
+    let response = read_response(
+        &mut &b"HTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n"
+            [..],
+        "GET",
+    )
+    .unwrap();
+    assert_eq!(response.status, Some(StatusCode::NoContent));
+    assert_eq!(response.header("Link"), None);
+
+    let response = read_response(&mut &b"HTTP/1.0 200 OK\r\n\r\nuntil the end"[..], "GET").unwrap();
+    assert_eq!(response.body, b"until the end");
+
+    let response = read_response(
+        &mut &b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"[..],
+        "HEAD",
+    )
+    .unwrap();
+    assert!(response.body.is_empty());
+
+    assert!(
+        read_response(
+            &mut &b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nab"[..],
+            "GET"
+        )
+        .is_err()
+    );
+    assert!(
+        read_response(
+            &mut &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"[..],
+            "GET"
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn test_url() {
+    let url = Url::parse("http://idp:8080/realms/a?x=1").unwrap();
+    assert_eq!(
+        (url.host.as_str(), url.path.as_str()),
+        ("idp:8080", "/realms/a?x=1")
+    );
+    assert_eq!(url.address(), "idp:8080");
+    assert_eq!(Url::parse("http://idp").unwrap().address(), "idp:80");
+    assert_eq!(Url::parse("http://[::1]/").unwrap().address(), "[::1]:80");
+    assert!(Url::parse("https://idp/").is_err());
+}
@@ -0,0 +1,171 @@
+// Concurrent requests for what isn't cached yet, like the minified content or
+// checksum of a file after it changed or the cache was emptied, share the
+// work: the first one does it while the others wait for its result, rather
+// than all reading the file and computing the same thing at once.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io,
+    sync::{Arc, Condvar, Mutex},
+};
+
+enum State<V> {
+    Running,
+    // errors can't be cloned, their kind and message can
+    Done(Result<V, (io::ErrorKind, String)>),
+    // the thread doing the work panicked
+    Abandoned,
+}
+
+struct Flight<V> {
+    state: Mutex<State<V>>,
+    done: Condvar,
+}
+
+pub struct Coalescer<K, V> {
+    flights: Mutex<HashMap<K, Arc<Flight<V>>>>,
+}
+
+impl<K, V> Default for Coalescer<K, V> {
+    fn default() -> Self {
+        Coalescer {
+            flights: Mutex::default(),
+        }
+    }
+}
+
+// Ends the flight of the thread doing the work, even if it panics
+struct Leader<'a, K: Eq + Hash, V> {
+    coalescer: &'a Coalescer<K, V>,
+    key: Option<K>,
+    flight: Arc<Flight<V>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Coalescer<K, V> {
+    // The result of `work`, done by this thread unless another one is already
+    // doing it for `key`
+    pub fn run(&self, key: K, work: impl FnOnce() -> io::Result<V>) -> io::Result<V> {
+        let mut flights = self.flights.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(flight) = flights.get(&key).cloned() {
+            drop(flights);
+            return flight.wait();
+        }
+        let flight = Arc::new(Flight {
+            state: Mutex::new(State::Running),
+            done: Condvar::new(),
+        });
+        flights.insert(key.clone(), flight.clone());
+        drop(flights);
+
+        let mut leader = Leader {
+            coalescer: self,
+            key: Some(key),
+            flight,
+        };
+        let res = work();
+        let shared = match &res {
+            Ok(value) => Ok(value.clone()),
+            Err(err) => Err((err.kind(), err.to_string())),
+        };
+        leader.finish(State::Done(shared));
+        res
+    }
+}
+
+impl<V: Clone> Flight<V> {
+    fn wait(&self) -> io::Result<V> {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        loop {
+            match &*state {
+                State::Running => {
+                    state = self.done.wait(state).unwrap_or_else(|err| err.into_inner())
+                }
+                State::Done(Ok(value)) => return Ok(value.clone()),
+                State::Done(Err((kind, message))) => {
+                    return Err(io::Error::new(*kind, message.clone()));
+                }
+                State::Abandoned => return Err(io::Error::other("shared work failed")),
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Leader<'_, K, V> {
+    fn finish(&mut self, end: State<V>) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        // later requests do the work again, with what's cached by then
+        self.coalescer
+            .flights
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(&key);
+        *self
+            .flight
+            .state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = end;
+        self.flight.done.notify_all();
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for Leader<'_, K, V> {
+    fn drop(&mut self) {
+        self.finish(State::Abandoned);
+    }
+}
+
+#[test]
+fn test_coalescer() {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    let coalescer = &Coalescer::<&str, u32>::default();
+    let calls = &AtomicUsize::new(0);
+    let (release, released) = mpsc::channel::<()>();
+    thread::scope(|scope| {
+        let leader = scope.spawn(move || {
+            coalescer.run("a", || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                released.recv().unwrap();
+                Ok(42)
+            })
+        });
+        // the leader is working
+        while calls.load(Ordering::SeqCst) == 0 {
+            thread::yield_now();
+        }
+        let waiters: Vec<_> = (0..4)
+            .map(|_| {
+                scope.spawn(|| {
+                    coalescer.run("a", || {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        Ok(0)
+                    })
+                })
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(50));
+        release.send(()).unwrap();
+        assert_eq!(leader.join().unwrap().unwrap(), 42);
+        for waiter in waiters {
+            assert_eq!(waiter.join().unwrap().unwrap(), 42);
+        }
+    });
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // done again once finished, and errors are passed on
+    let err = coalescer
+        .run("a", || Err(io::Error::from(io::ErrorKind::NotFound)))
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert_eq!(coalescer.run("a", || Ok(1)).unwrap(), 1);
+}
@@ -0,0 +1,132 @@
+// Conditional requests (RFC 9110 section 13) on files. Every file has an ETag,
+// the SHA-256 of --checksums or else one made of its inode, size and
+// modification time, and a Last-Modified date. Downloads answer If-None-Match
+// and If-Modified-Since with 304 and check If-Range, uploads If-Match and
+// If-None-Match, against the same ETag. Gzipped downloads have it with
+// "-gzip" added.
+
+use crate::{Request, checksum, date};
+use std::{fs::Metadata, time::UNIX_EPOCH};
+
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: Option<String>,
+    // unix seconds, as Last-Modified has it
+    modified: Option<u64>,
+}
+
+impl Validators {
+    // `digest` is the SHA-256 of the file, with --checksums
+    pub fn new(metadata: &Metadata, digest: Option<&[u8]>) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+        let etag = match digest {
+            Some(digest) => checksum::etag(digest),
+            None => format!(
+                "\"{:x}-{:x}-{:x}\"",
+                inode(metadata),
+                metadata.len(),
+                modified.unwrap_or_default().as_nanos()
+            ),
+        };
+        let modified = modified.map(|modified| modified.as_secs());
+        Validators {
+            etag,
+            last_modified: modified.map(date::http_date),
+            modified,
+        }
+    }
+
+    // If-None-Match, or without it If-Modified-Since (section 13.2.2)
+    pub fn not_modified(&self, request: &Request) -> bool {
+        match request.header("If-None-Match") {
+            Some(tags) => etag_matches(tags, &self.etag),
+            None => request
+                .header("If-Modified-Since")
+                .and_then(date::parse_http_date)
+                .zip(self.modified)
+                .is_some_and(|(since, modified)| modified <= since),
+        }
+    }
+
+    // A client resuming with If-Range wants the rest of the version it has,
+    // or the whole file if it changed
+    pub fn range_applies(&self, request: &Request) -> bool {
+        request.header("If-Range").is_none_or(|validator| {
+            let validator = validator.trim();
+            validator == self.etag || self.last_modified.as_deref() == Some(validator)
+        })
+    }
+
+    // Compressed responses are another representation, with an ETag of their
+    // own (section 8.8.3)
+    pub fn set_coding(&mut self, coding: &str) {
+        if let Some(tag) = self.etag.strip_suffix('"') {
+            self.etag = format!("{tag}-{coding}\"");
+        }
+    }
+
+    pub fn headers(&self) -> Vec<(&'static str, &str)> {
+        let mut res = vec![("ETag", self.etag.as_str())];
+        if let Some(last_modified) = &self.last_modified {
+            res.push(("Last-Modified", last_modified));
+        }
+        res
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+#[cfg(not(unix))]
+fn inode(_: &Metadata) -> u64 {
+    0
+}
+
+// Whether an If-None-Match header lists `etag`, with the weak comparison of
+// RFC 9110 section 13.1.2
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+#[test]
+fn test_validators() {
+    let path = std::env::temp_dir().join(format!("conditional-test-{}", std::process::id()));
+    std::fs::write(&path, "abc").unwrap();
+    let metadata = std::fs::metadata(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let validators = Validators::new(&metadata, None);
+    assert!(validators.etag.starts_with('"') && validators.etag.contains("-3-"));
+    assert_eq!(Validators::new(&metadata, None).etag, validators.etag);
+    assert_eq!(
+        Validators::new(&metadata, Some(&[0xab, 0xcd])).etag,
+        "\"abcd\""
+    );
+    let mut gzipped = Validators::new(&metadata, Some(&[0xab, 0xcd]));
+    gzipped.set_coding("gzip");
+    assert_eq!(gzipped.etag, "\"abcd-gzip\"");
+    let last_modified = validators.last_modified.clone().unwrap();
+    assert_eq!(
+        validators.headers(),
+        [
+            ("ETag", validators.etag.as_str()),
+            ("Last-Modified", last_modified.as_str())
+        ]
+    );
+}
+
+#[test]
+fn test_etag_matches() {
+    assert!(etag_matches("\"a\"", "\"a\""));
+    assert!(etag_matches("\"b\", W/\"a\"", "\"a\""));
+    assert!(etag_matches("*", "\"a\""));
+    assert!(!etag_matches("\"ab\"", "\"a\""));
+}
@@ -0,0 +1,362 @@
+// Where options come from, each layer over the previous ones: the defaults,
+// the --config file, the WEBSERVER_<OPTION> variables, then the command line.
+// The upper layers are turned into arguments, ahead of the command line's, for
+// parse_args to apply in order: the last value of an option wins and repeated
+// options add up.
+
+use crate::toml;
+use std::{collections::HashMap, fs};
+
+// The options which can come from WEBSERVER_<NAME> variables, and from <name>
+// keys of the config file
+const OPTIONS: [(&str, &str); 99] = [
+    ("CONFIG", "--config"),
+    ("PORT", "-p"),
+    ("BIND", "-b"),
+    ("EXPOSE", "--expose"),
+    ("DIR", "-d"),
+    ("THREADS", "--threads"),
+    ("MAX_CONNS", "--max-conns"),
+    ("KEEP_ALIVE", "--keep-alive"),
+    ("REQUEST_TIMEOUT", "--request-timeout"),
+    ("READ_TIMEOUT", "--read-timeout"),
+    ("WRITE_TIMEOUT", "--write-timeout"),
+    ("MAX_BODY", "--max-body"),
+    ("REPORT", "--report"),
+    ("CERT", "--cert"),
+    ("KEY", "--key"),
+    ("ALT_SVC", "--alt-svc"),
+    ("ALT_SVC_MAX_AGE", "--alt-svc-max-age"),
+    ("DOCTOR", "--doctor"),
+    ("UPNP", "--upnp"),
+    ("STRICT_HTTP", "--strict-http"),
+    ("SERVER_TOKEN", "--server-token"),
+    ("MODE", "--mode"),
+    ("PUT_CONFLICT", "--put-conflict"),
+    ("MAX_UPLOAD", "--max-upload"),
+    ("CREATE_DIRS", "--create-dirs"),
+    ("TRASH", "--trash"),
+    ("TRASH_RETENTION", "--trash-retention"),
+    ("WEBHOOK", "--webhook"),
+    ("WEBHOOK_SECRET", "--webhook-secret"),
+    ("HOOK", "--hook"),
+    ("HOOK_ON", "--hook-on"),
+    ("HOOK_THRESHOLD", "--hook-threshold"),
+    ("EARLY_HINT", "--early-hint"),
+    ("MIME", "--mime"),
+    ("NO_COMPRESS", "--no-compress"),
+    ("HEADER", "--header"),
+    ("MAINTENANCE_FILE", "--maintenance-file"),
+    ("MAINTENANCE_ALLOW", "--maintenance-allow"),
+    ("MAINTENANCE_RETRY_AFTER", "--maintenance-retry-after"),
+    ("AUTH", "--auth"),
+    ("DIGEST", "--digest"),
+    ("AUTH_PATH", "--auth-path"),
+    ("HTPASSWD", "--htpasswd"),
+    ("PAM", "--pam"),
+    ("LDAP", "--ldap"),
+    ("LDAP_USER_DN", "--ldap-user-dn"),
+    ("LDAP_GROUP", "--ldap-group"),
+    ("OIDC_ISSUER", "--oidc-issuer"),
+    ("OIDC_CLIENT_ID", "--oidc-client-id"),
+    ("OIDC_CLIENT_SECRET", "--oidc-client-secret"),
+    ("OIDC_REQUIRE", "--oidc-require"),
+    ("JWT_SECRET", "--jwt-secret"),
+    ("JWT_JWKS", "--jwt-jwks"),
+    ("JWT_ISSUER", "--jwt-issuer"),
+    ("JWT_AUDIENCE", "--jwt-audience"),
+    ("TOKEN", "--token"),
+    ("QUOTA", "--quota"),
+    ("HIDE_DOTFILES", "--hide-dotfiles"),
+    ("GIT", "--git"),
+    ("GIT_REF", "--git-ref"),
+    ("DENY", "--deny"),
+    ("GEOIP", "--geoip"),
+    ("GEO_ALLOW", "--geo-allow"),
+    ("GEO_DENY", "--geo-deny"),
+    ("IP_ALLOW", "--ip-allow"),
+    ("IP_DENY", "--ip-deny"),
+    ("RATE_LIMIT", "--rate-limit"),
+    ("RATE_BURST", "--rate-burst"),
+    ("AGENT", "--agent"),
+    ("TENANT", "--tenant"),
+    ("CANARY", "--canary"),
+    ("VHOST", "--vhost"),
+    ("VHOST_DEFAULT", "--vhost-default"),
+    ("PROXY", "--proxy"),
+    ("UPSTREAM_PROXY", "--upstream-proxy"),
+    ("MAP", "--map"),
+    ("FASTCGI", "--fastcgi"),
+    ("LOG_TARGET", "--log-target"),
+    ("LOG_TIME", "--log-time"),
+    ("ACCESS_LOG", "--access-log"),
+    ("ACCESS_LOG_FORMAT", "--access-log-format"),
+    ("LOG_EXCLUDE", "--log-exclude"),
+    ("LOG_SAMPLE", "--log-sample"),
+    ("LOG_TIMINGS", "--log-timings"),
+    ("METRICS", "--metrics"),
+    ("CHECKSUMS", "--checksums"),
+    ("FINGERPRINTS", "--fingerprints"),
+    ("MINIFY", "--minify"),
+    ("INJECT_HTML", "--inject-html"),
+    ("FILTER", "--filter"),
+    ("SPA", "--spa"),
+    ("ZIP", "--zip"),
+    ("MEDIA", "--media"),
+    ("VIEWER", "--viewer"),
+    ("WEBDAV", "--webdav"),
+    ("SEARCH", "--search"),
+    ("SEARCH_CONTENTS", "--search-contents"),
+    ("SEARCH_REFRESH", "--search-refresh"),
+    ("DEBUG_ROUTES", "--debug-routes"),
+];
+const ENV_PREFIX: &str = "WEBSERVER_";
+
+// options without a value, on with 1, true or yes
+const SWITCHES: [&str; 20] = [
+    "--expose",
+    "--doctor",
+    "--upnp",
+    "--strict-http",
+    "--create-dirs",
+    "--hide-dotfiles",
+    "--git",
+    "--log-timings",
+    "--metrics",
+    "--checksums",
+    "--fingerprints",
+    "--minify",
+    "--spa",
+    "--zip",
+    "--media",
+    "--viewer",
+    "--webdav",
+    "--search",
+    "--search-contents",
+    "--debug-routes",
+];
+
+// options which can be repeated, from whitespace separated lists (lines for
+// --header, whose values have spaces)
+const LISTS: [&str; 24] = [
+    "--oidc-require",
+    "--digest",
+    "--auth-path",
+    "--token",
+    "--quota",
+    "--deny",
+    "--tenant",
+    "--vhost",
+    "--proxy",
+    "--map",
+    "--log-exclude",
+    "--early-hint",
+    "--webhook",
+    "--alt-svc",
+    "--maintenance-allow",
+    "--mime",
+    "--no-compress",
+    "--header",
+    "--geoip",
+    "--geo-allow",
+    "--geo-deny",
+    "--ip-allow",
+    "--ip-deny",
+    "--agent",
+];
+
+// The arguments of every layer, `args` being the command line's
+pub fn args(
+    args: impl IntoIterator<Item = String>,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<Vec<String>, String> {
+    let mut args: Vec<String> = env_args(vars)?.into_iter().chain(args).collect();
+    let mut file = None;
+    while let Some(index) = args.iter().position(|arg| arg == "--config") {
+        if index + 1 == args.len() {
+            return Err("'--config' needs a value".to_owned());
+        }
+        // the last one wins, as with other options
+        file = Some(args.remove(index + 1));
+        args.remove(index);
+    }
+    match file {
+        Some(file) => Ok(file_args(&file)?.into_iter().chain(args).collect()),
+        None => Ok(args),
+    }
+}
+
+// The arguments the WEBSERVER_* variables stand for, in the order of OPTIONS
+// rather than the environment's
+fn env_args(vars: impl Iterator<Item = (String, String)>) -> Result<Vec<String>, String> {
+    let vars: HashMap<_, _> = vars
+        .filter(|(key, _)| key.starts_with(ENV_PREFIX))
+        .collect();
+    if let Some(key) = vars.keys().find(|key| {
+        !OPTIONS
+            .iter()
+            .any(|(name, _)| key[ENV_PREFIX.len()..] == **name)
+    }) {
+        return Err(format!("unknown environment variable: {key}"));
+    }
+
+    let mut res = Vec::new();
+    for (name, option) in OPTIONS {
+        let key = format!("{ENV_PREFIX}{name}");
+        let Some(value) = vars.get(&key) else {
+            continue;
+        };
+        if SWITCHES.contains(&option) {
+            match value.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" => res.push(option.to_owned()),
+                "" | "0" | "false" | "no" => (),
+                _ => return Err(format!("'{key}' must be 1, true, yes, 0, false or no")),
+            }
+        } else if option == "--header" {
+            for item in value.lines().filter(|line| !line.trim().is_empty()) {
+                res.extend([option.to_owned(), item.to_owned()]);
+            }
+        } else if LISTS.contains(&option) {
+            for item in value.split_whitespace() {
+                res.extend([option.to_owned(), item.to_owned()]);
+            }
+        } else {
+            res.extend([option.to_owned(), value.clone()]);
+        }
+    }
+    Ok(res)
+}
+
+// The arguments the keys of a TOML file stand for, in its order: `port =
+// 8080`, `upnp = true`, `deny = [".git"]`, and the [mime], [headers] and
+// [agents] tables for --mime, --header and --agent
+fn file_args(path: &str) -> Result<Vec<String>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("failed to read config file '{path}': {err}"))?;
+    let entries = toml::parse(&text)
+        .map_err(|(line, err)| format!("config file '{path}', line {line}: {err}"))?;
+
+    let mut res = Vec::new();
+    for toml::Entry { table, key, value } in entries {
+        let invalid = |expected: &str| format!("config file '{path}': '{key}' must be {expected}");
+        let string = |value: toml::Value| match value {
+            toml::Value::String(value) => Ok(value),
+            toml::Value::Integer(value) => Ok(value.to_string()),
+            _ => Err(invalid("a string or a number")),
+        };
+        match table.as_str() {
+            "mime" => res.extend(["--mime".to_owned(), format!("{key}={}", string(value)?)]),
+            "headers" => res.extend(["--header".to_owned(), format!("{key}: {}", string(value)?)]),
+            "agents" => res.extend(["--agent".to_owned(), format!("{key}={}", string(value)?)]),
+            "" => {
+                let Some((_, option)) = OPTIONS
+                    .iter()
+                    .find(|(name, _)| key.replace('-', "_").eq_ignore_ascii_case(name))
+                    .filter(|(_, option)| *option != "--config")
+                else {
+                    return Err(format!("config file '{path}': unknown key '{key}'"));
+                };
+                match value {
+                    toml::Value::Bool(on) if SWITCHES.contains(option) => {
+                        if on {
+                            res.push(option.to_string());
+                        }
+                    }
+                    _ if SWITCHES.contains(option) => return Err(invalid("true or false")),
+                    toml::Value::Array(values) if LISTS.contains(option) => {
+                        for value in values {
+                            res.extend([option.to_string(), string(value)?]);
+                        }
+                    }
+                    value => res.extend([option.to_string(), string(value)?]),
+                }
+            }
+            _ => return Err(format!("config file '{path}': unknown table [{table}]")),
+        }
+    }
+    Ok(res)
+}
+
+#[test]
+fn test_env_args() {
+    let vars = [
+        ("HOME", "/root"),
+        ("WEBSERVER_PORT", "9000"),
+        ("WEBSERVER_UPNP", "yes"),
+        ("WEBSERVER_STRICT_HTTP", "0"),
+        ("WEBSERVER_DENY", ".git  node_modules"),
+        ("WEBSERVER_DIR", "/srv"),
+    ];
+    let args = env_args(
+        vars.into_iter()
+            .map(|(key, value)| (key.to_owned(), value.to_owned())),
+    )
+    .unwrap();
+    assert_eq!(
+        args,
+        [
+            "-p",
+            "9000",
+            "-d",
+            "/srv",
+            "--upnp",
+            "--deny",
+            ".git",
+            "--deny",
+            "node_modules"
+        ]
+    );
+}
+
+#[test]
+fn test_args() {
+    let file = std::env::temp_dir().join(format!("config-test-{}.toml", std::process::id()));
+    fs::write(
+        &file,
+        "port = 9000
+dir = \"/srv\"
+upnp = true
+strict-http = false
+deny = [\".git\", \"node_modules\"]
+
+[mime]
+md = \"text/markdown\"
+
+[headers]
+X-Frame-Options = \"DENY\"
+
+[agents]
+bad-bot = \"deny\"
+",
+    )
+    .unwrap();
+    let vars = [("WEBSERVER_CONFIG", file.display().to_string())];
+    let args = args(
+        ["-p", "9001"].map(str::to_owned),
+        vars.into_iter().map(|(key, value)| (key.to_owned(), value)),
+    )
+    .unwrap();
+    assert_eq!(
+        args,
+        [
+            "-p",
+            "9000",
+            "-d",
+            "/srv",
+            "--upnp",
+            "--deny",
+            ".git",
+            "--deny",
+            "node_modules",
+            "--mime",
+            "md=text/markdown",
+            "--header",
+            "X-Frame-Options: DENY",
+            "--agent",
+            "bad-bot=deny",
+            "-p",
+            "9001"
+        ]
+    );
+    fs::remove_file(&file).unwrap();
+}
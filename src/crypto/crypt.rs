@@ -0,0 +1,242 @@
+// The crypt(3) schemes based on MD5 ($1$ and Apache's $apr1$) and SHA-2 ($5$
+// and $6$). They all return the full hash string, to be compared with the
+// stored one.
+
+use super::{
+    Digest,
+    md5::Md5,
+    sha2::{Sha256, Sha512},
+};
+
+const ITOA64: &[u8; 64] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+const SHA256_ORDER: [(usize, usize, usize); 10] = [
+    (0, 10, 20),
+    (21, 1, 11),
+    (12, 22, 2),
+    (3, 13, 23),
+    (24, 4, 14),
+    (15, 25, 5),
+    (6, 16, 26),
+    (27, 7, 17),
+    (18, 28, 8),
+    (9, 19, 29),
+];
+
+const SHA512_ORDER: [(usize, usize, usize); 21] = [
+    (0, 21, 42),
+    (22, 43, 1),
+    (44, 2, 23),
+    (3, 24, 45),
+    (25, 46, 4),
+    (47, 5, 26),
+    (6, 27, 48),
+    (28, 49, 7),
+    (50, 8, 29),
+    (9, 30, 51),
+    (31, 52, 10),
+    (53, 11, 32),
+    (12, 33, 54),
+    (34, 55, 13),
+    (56, 14, 35),
+    (15, 36, 57),
+    (37, 58, 16),
+    (59, 17, 38),
+    (18, 39, 60),
+    (40, 61, 19),
+    (62, 20, 41),
+];
+
+fn to64(res: &mut String, mut value: u32, len: usize) {
+    for _ in 0..len {
+        res.push(ITOA64[(value & 0x3f) as usize] as char);
+        value >>= 6;
+    }
+}
+
+fn group(b2: u8, b1: u8, b0: u8) -> u32 {
+    (u32::from(b2) << 16) | (u32::from(b1) << 8) | u32::from(b0)
+}
+
+// `magic` is "$1$" or "$apr1$", `salt` is what follows it in the hash
+pub fn md5_crypt(password: &[u8], magic: &str, salt: &str) -> String {
+    let salt = salt.split('$').next().unwrap_or_default();
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+
+    let mut alternate = Md5::default();
+    alternate.update(password);
+    alternate.update(salt);
+    alternate.update(password);
+    let alternate = alternate.finish();
+
+    let mut ctx = Md5::default();
+    ctx.update(password);
+    ctx.update(magic.as_bytes());
+    ctx.update(salt);
+    let repeated: Vec<u8> = alternate
+        .iter()
+        .cycle()
+        .take(password.len())
+        .copied()
+        .collect();
+    ctx.update(&repeated);
+    let mut len = password.len();
+    while len != 0 {
+        if len & 1 != 0 {
+            ctx.update(&[0]);
+        } else {
+            ctx.update(&password[..1]);
+        }
+        len >>= 1;
+    }
+    let mut digest = ctx.finish();
+
+    for i in 0..1000 {
+        let mut ctx = Md5::default();
+        ctx.update(if i & 1 != 0 { password } else { &digest });
+        if i % 3 != 0 {
+            ctx.update(salt);
+        }
+        if i % 7 != 0 {
+            ctx.update(password);
+        }
+        ctx.update(if i & 1 != 0 { &digest } else { password });
+        digest = ctx.finish();
+    }
+
+    let mut res = format!("{magic}{}$", String::from_utf8_lossy(salt));
+    let d = &digest;
+    to64(&mut res, group(d[0], d[6], d[12]), 4);
+    to64(&mut res, group(d[1], d[7], d[13]), 4);
+    to64(&mut res, group(d[2], d[8], d[14]), 4);
+    to64(&mut res, group(d[3], d[9], d[15]), 4);
+    to64(&mut res, group(d[4], d[10], d[5]), 4);
+    to64(&mut res, u32::from(d[11]), 2);
+    res
+}
+
+// `setting` is what follows "$5$": an optional "rounds=N$" and the salt
+pub fn sha256_crypt(password: &[u8], setting: &str) -> String {
+    let (mut res, digest) = sha_crypt::<Sha256>(password, "$5$", setting);
+    for (b2, b1, b0) in SHA256_ORDER {
+        to64(&mut res, group(digest[b2], digest[b1], digest[b0]), 4);
+    }
+    to64(&mut res, group(0, digest[31], digest[30]), 3);
+    res
+}
+
+// `setting` is what follows "$6$": an optional "rounds=N$" and the salt
+pub fn sha512_crypt(password: &[u8], setting: &str) -> String {
+    let (mut res, digest) = sha_crypt::<Sha512>(password, "$6$", setting);
+    for (b2, b1, b0) in SHA512_ORDER {
+        to64(&mut res, group(digest[b2], digest[b1], digest[b0]), 4);
+    }
+    to64(&mut res, u32::from(digest[63]), 2);
+    res
+}
+
+// Returns the hash prefix (up to the last '$') and the raw digest
+fn sha_crypt<D: Digest>(password: &[u8], magic: &str, setting: &str) -> (String, Vec<u8>) {
+    let (rounds, salt) = match setting.strip_prefix("rounds=") {
+        Some(rest) => {
+            let (rounds, salt) = rest.split_once('$').unwrap_or((rest, ""));
+            let rounds = rounds
+                .parse::<u32>()
+                .unwrap_or(5000)
+                .clamp(1000, 999_999_999);
+            (Some(rounds), salt)
+        }
+        None => (None, setting),
+    };
+    let salt = salt.split('$').next().unwrap_or_default();
+    let salt = &salt.as_bytes()[..salt.len().min(16)];
+
+    let mut alternate = D::default();
+    alternate.update(password);
+    alternate.update(salt);
+    alternate.update(password);
+    let alternate = alternate.finish();
+
+    let mut ctx = D::default();
+    ctx.update(password);
+    ctx.update(salt);
+    let repeated: Vec<u8> = alternate
+        .iter()
+        .cycle()
+        .take(password.len())
+        .copied()
+        .collect();
+    ctx.update(&repeated);
+    let mut len = password.len();
+    while len > 0 {
+        if len & 1 != 0 {
+            ctx.update(&alternate);
+        } else {
+            ctx.update(password);
+        }
+        len >>= 1;
+    }
+    let mut digest = ctx.finish();
+
+    let mut ctx = D::default();
+    for _ in 0..password.len() {
+        ctx.update(password);
+    }
+    let p_bytes: Vec<u8> = ctx
+        .finish()
+        .into_iter()
+        .cycle()
+        .take(password.len())
+        .collect();
+
+    let mut ctx = D::default();
+    for _ in 0..16 + usize::from(digest[0]) {
+        ctx.update(salt);
+    }
+    let s_bytes: Vec<u8> = ctx.finish().into_iter().cycle().take(salt.len()).collect();
+
+    for i in 0..rounds.unwrap_or(5000) {
+        let mut ctx = D::default();
+        ctx.update(if i & 1 != 0 { &p_bytes } else { &digest });
+        if i % 3 != 0 {
+            ctx.update(&s_bytes);
+        }
+        if i % 7 != 0 {
+            ctx.update(&p_bytes);
+        }
+        ctx.update(if i & 1 != 0 { &digest } else { &p_bytes });
+        digest = ctx.finish();
+    }
+
+    let mut res = magic.to_owned();
+    if let Some(rounds) = rounds {
+        res.push_str(&format!("rounds={rounds}$"));
+    }
+    res.push_str(&String::from_utf8_lossy(salt));
+    res.push('$');
+    (res, digest)
+}
+
+#[test]
+fn test_crypt() {
+    assert_eq!(
+        md5_crypt(b"secret", "$apr1$", "abcdefgh$h9FWgUz3n9YxylKLlR5SQ/"),
+        "$apr1$abcdefgh$h9FWgUz3n9YxylKLlR5SQ/"
+    );
+    assert_eq!(
+        md5_crypt(b"secret", "$1$", "abcdefgh"),
+        "$1$abcdefgh$cHJi5PXp/ki/ktXzqlk6I1"
+    );
+    assert_eq!(
+        sha256_crypt(b"Hello world!", "rounds=10000$saltstringsaltstring"),
+        "$5$rounds=10000$saltstringsaltst$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA"
+    );
+    assert_eq!(
+        sha256_crypt(b"secret", "saltsalt"),
+        "$5$saltsalt$0IyaXrmV7.sGNS6tirgqHLqX/G.FBvgkYA.lpPdS5sA"
+    );
+    assert_eq!(
+        sha512_crypt(b"secret", "saltsalt"),
+        "$6$saltsalt$TVLlQcbpFVof5W3Yz4DTP6gRstiNuHwwTt6GLc1E5n0U0aDehy0S5knV8wiOQSpT0Y77vwPZN.Pq.H91p5hVO1"
+    );
+}
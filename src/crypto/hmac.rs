@@ -0,0 +1,42 @@
+// HMAC (RFC 2104) over SHA-256, for HS256 signed tokens.
+
+use super::{Digest, sha2::Sha256};
+
+const BLOCK_SIZE: usize = 64;
+
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = [0; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        let key = Sha256::digest(key);
+        block[..key.len()].copy_from_slice(&key);
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::default();
+    inner.update(&block.map(|byte| byte ^ 0x36));
+    inner.update(message);
+
+    let mut outer = Sha256::default();
+    outer.update(&block.map(|byte| byte ^ 0x5c));
+    outer.update(&inner.finish());
+    outer.finish()
+}
+
+#[test]
+fn test_hmac_sha256() {
+    use super::hex;
+
+    // RFC 4231 test cases 2 and 6
+    assert_eq!(
+        hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+    assert_eq!(
+        hex(&hmac_sha256(
+            &[0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First"
+        )),
+        "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+    );
+}
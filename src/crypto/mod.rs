@@ -0,0 +1,91 @@
+// Hash functions, password hashing schemes and signatures, std has none of
+// them.
+
+pub mod bcrypt;
+pub mod crypt;
+pub mod hmac;
+pub mod md5;
+pub mod rsa;
+pub mod sha1;
+pub mod sha2;
+
+pub trait Digest: Default {
+    fn update(&mut self, data: &[u8]);
+    fn finish(self) -> Vec<u8>;
+
+    fn digest(data: &[u8]) -> Vec<u8> {
+        let mut hasher = Self::default();
+        hasher.update(data);
+        hasher.finish()
+    }
+}
+
+// Splits the input into N bytes blocks and does the Merkle–Damgård padding
+// shared by MD5 and the SHA family.
+struct BlockBuffer<const N: usize> {
+    buffer: [u8; N],
+    filled: usize,
+    length: u128,
+}
+
+impl<const N: usize> Default for BlockBuffer<N> {
+    fn default() -> Self {
+        BlockBuffer {
+            buffer: [0; N],
+            filled: 0,
+            length: 0,
+        }
+    }
+}
+
+impl<const N: usize> BlockBuffer<N> {
+    fn update(&mut self, mut data: &[u8], mut compress: impl FnMut(&[u8; N])) {
+        self.length += data.len() as u128;
+        while !data.is_empty() {
+            let take = (N - self.filled).min(data.len());
+            self.buffer[self.filled..self.filled + take].copy_from_slice(&data[..take]);
+            self.filled += take;
+            data = &data[take..];
+            if self.filled == N {
+                compress(&self.buffer);
+                self.filled = 0;
+            }
+        }
+    }
+
+    // 0x80, zeros, then the message length in bits on `length_size` bytes
+    fn finish(mut self, big_endian: bool, length_size: usize, compress: impl FnMut(&[u8; N])) {
+        let bits = self.length * 8;
+        let zeros = (2 * N - self.filled - 1 - length_size) % N;
+        let mut padding = vec![0x80];
+        padding.resize(1 + zeros, 0);
+        if big_endian {
+            padding.extend_from_slice(&bits.to_be_bytes()[16 - length_size..]);
+        } else {
+            padding.extend_from_slice(&bits.to_le_bytes()[..length_size]);
+        }
+        self.update(&padding, compress);
+    }
+}
+
+// A hex encoded token nobody can guess, for session ids and the like
+pub fn random_token() -> std::io::Result<String> {
+    Ok(hex(&random_bytes::<32>()?))
+}
+
+pub fn random_bytes<const N: usize>() -> std::io::Result<[u8; N]> {
+    use std::io::Read;
+
+    let mut bytes = [0; N];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+// Compares secrets without leaking how many bytes matched through timing
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
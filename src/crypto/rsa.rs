@@ -0,0 +1,151 @@
+// RSASSA-PKCS1-v1_5 signature verification with SHA-256 (RFC 8017), for
+// RS256 signed tokens. Only public keys are involved, so nothing here has to
+// run in constant time.
+
+use super::{Digest, sha2::Sha256};
+use std::cmp::Ordering;
+
+// DER encoding of the DigestInfo prefix for SHA-256
+const SHA256_PREFIX: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+
+pub fn verify_sha256(modulus: &[u8], exponent: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let modulus = trim(modulus);
+    let n = from_be_bytes(modulus);
+    let s = from_be_bytes(signature);
+    // room for the padding and at least 8 bytes of 0xff
+    if modulus.len() < SHA256_PREFIX.len() + 32 + 11
+        || signature.len() != modulus.len()
+        || compare(&s, &n) != Ordering::Less
+    {
+        return false;
+    }
+
+    // EM = 0x00 || 0x01 || 0xff... || 0x00 || DigestInfo
+    let encoded = to_be_bytes(&mod_pow(&s, &from_be_bytes(exponent), &n), modulus.len());
+    let mut expected = vec![0x00, 0x01];
+    expected.resize(modulus.len() - SHA256_PREFIX.len() - 32 - 1, 0xff);
+    expected.push(0x00);
+    expected.extend_from_slice(&SHA256_PREFIX);
+    expected.extend_from_slice(&Sha256::digest(message));
+    encoded == expected
+}
+
+fn trim(bytes: &[u8]) -> &[u8] {
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    &bytes[zeros..]
+}
+
+// Numbers are little endian vectors of 32 bits limbs, without leading zeros
+fn from_be_bytes(bytes: &[u8]) -> Vec<u32> {
+    let mut res: Vec<u32> = bytes
+        .rchunks(4)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .fold(0, |acc, byte| (acc << 8) | u32::from(*byte))
+        })
+        .collect();
+    normalize(&mut res);
+    res
+}
+
+fn to_be_bytes(number: &[u32], len: usize) -> Vec<u8> {
+    let mut res: Vec<u8> = number
+        .iter()
+        .rev()
+        .flat_map(|limb| limb.to_be_bytes())
+        .collect();
+    let extra = res.len().saturating_sub(len);
+    res.drain(..extra);
+    let mut padded = vec![0; len - res.len()];
+    padded.append(&mut res);
+    padded
+}
+
+fn normalize(number: &mut Vec<u32>) {
+    while number.last() == Some(&0) {
+        number.pop();
+    }
+}
+
+fn compare(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn mul(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = vec![0; a.len() + b.len()];
+    for (i, a) in a.iter().enumerate() {
+        let mut carry = 0;
+        for (j, b) in b.iter().enumerate() {
+            let value = u64::from(*a) * u64::from(*b) + u64::from(res[i + j]) + carry;
+            res[i + j] = value as u32;
+            carry = value >> 32;
+        }
+        res[i + b.len()] = carry as u32;
+    }
+    normalize(&mut res);
+    res
+}
+
+// a -= b, with a >= b
+fn sub_assign(a: &mut Vec<u32>, b: &[u32]) {
+    let mut borrow = 0;
+    for (i, limb) in a.iter_mut().enumerate() {
+        let (value, overflow1) = limb.overflowing_sub(b.get(i).copied().unwrap_or(0));
+        let (value, overflow2) = value.overflowing_sub(borrow);
+        *limb = value;
+        borrow = u32::from(overflow1 || overflow2);
+    }
+    normalize(a);
+}
+
+// Bit by bit long division, slow but plenty for a handful of operations
+fn rem(a: &[u32], n: &[u32]) -> Vec<u32> {
+    let mut res = Vec::with_capacity(n.len() + 1);
+    for bit in (0..a.len() * 32).rev() {
+        // res = res << 1 | bit
+        let mut carry = (a[bit / 32] >> (bit % 32)) & 1;
+        for limb in res.iter_mut() {
+            let next = *limb >> 31;
+            *limb = (*limb << 1) | carry;
+            carry = next;
+        }
+        if carry != 0 {
+            res.push(carry);
+        }
+        if compare(&res, n) != Ordering::Less {
+            sub_assign(&mut res, n);
+        }
+    }
+    res
+}
+
+fn mod_pow(base: &[u32], exponent: &[u32], n: &[u32]) -> Vec<u32> {
+    let mut res = vec![1];
+    for bit in (0..exponent.len() * 32).rev() {
+        res = rem(&mul(&res, &res), n);
+        if (exponent[bit / 32] >> (bit % 32)) & 1 == 1 {
+            res = rem(&mul(&res, base), n);
+        }
+    }
+    res
+}
+
+#[test]
+fn test_mod_pow() {
+    let n = from_be_bytes(&[0x01, 0x00, 0x00, 0x00, 0x0f]);
+    let res = mod_pow(
+        &from_be_bytes(&[0x12, 0x34, 0x56, 0x78, 0x9a]),
+        &[65537],
+        &n,
+    );
+    // 0x123456789a ^ 65537 mod 0x10000000f
+    assert_eq!(to_be_bytes(&res, 5), 0x8dd3_bfab_u64.to_be_bytes()[3..]);
+    assert_eq!(rem(&[5], &[7]), [5]);
+    assert!(rem(&[14], &[7]).is_empty());
+}
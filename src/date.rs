@@ -0,0 +1,510 @@
+// Dates, as std has no formatter: IMF-fixdate for HTTP headers (RFC 9110
+// section 5.6.7), ISO 8601 for logs, the Common Log Format's, and how long
+// ago for listings. Local time is that of TZ, else /etc/localtime: a TZif
+// file of the system's time zone database (RFC 8536), with the POSIX TZ rule
+// at its end for the years past its transitions, or such a rule as TZ.
+
+use std::{
+    fs,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+static LOCAL: OnceLock<Zone> = OnceLock::new();
+
+// For --log-time
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Clock {
+    Utc,
+    Local,
+}
+
+impl Clock {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "utc" => Some(Clock::Utc),
+            "local" => Some(Clock::Local),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Clock::Utc => "utc",
+            Clock::Local => "local",
+        }
+    }
+}
+
+// UTC offsets in seconds, UTC itself by default
+#[derive(Default)]
+struct Zone {
+    // (from, offset), sorted
+    transitions: Vec<(i64, i64)>,
+    // before the first transition
+    initial: i64,
+    // after the last one
+    rule: Option<Rule>,
+}
+
+// A POSIX TZ rule, e.g. "CET-1CEST,M3.5.0,M10.5.0/3"
+struct Rule {
+    standard: i64,
+    // the offset, and when it starts and ends
+    daylight: Option<(i64, Change, Change)>,
+}
+
+// Mm.w.d/time: the d-th day of the week (0 for Sunday) of the w-th week of
+// month m (5 for its last), at time
+struct Change {
+    month: u64,
+    week: u64,
+    weekday: u64,
+    time: i64,
+}
+
+impl Zone {
+    fn offset(&self, timestamp: i64) -> i64 {
+        let passed = self
+            .transitions
+            .partition_point(|(from, _)| *from <= timestamp);
+        if passed == self.transitions.len()
+            && let Some(rule) = &self.rule
+        {
+            return rule.offset(timestamp);
+        }
+        match passed {
+            0 => self.initial,
+            passed => self.transitions[passed - 1].1,
+        }
+    }
+}
+
+impl Rule {
+    fn offset(&self, timestamp: i64) -> i64 {
+        let Some((daylight, start, end)) = &self.daylight else {
+            return self.standard;
+        };
+        let local = (timestamp + self.standard).max(0) as u64;
+        let (year, _, _) = civil_from_days(local / 86400);
+        // given in the local time in effect before them
+        let start = start.at(year) - self.standard;
+        let end = end.at(year) - daylight;
+        let in_daylight = if start < end {
+            (start..end).contains(&timestamp)
+        } else {
+            // in the southern hemisphere, across the new year
+            timestamp >= start || timestamp < end
+        };
+        match in_daylight {
+            true => *daylight,
+            false => self.standard,
+        }
+    }
+}
+
+impl Change {
+    // In local seconds since 1970
+    fn at(&self, year: u64) -> i64 {
+        let first = days_from_civil(year, self.month, 1);
+        let next = match self.month {
+            12 => days_from_civil(year + 1, 1, 1),
+            month => days_from_civil(year, month + 1, 1),
+        };
+        // 1970-01-01 was a Thursday
+        let mut day = first + (self.weekday + 7 - (first + 4) % 7) % 7 + 7 * (self.week - 1);
+        while day >= next {
+            day -= 7;
+        }
+        day as i64 * 86400 + self.time
+    }
+}
+
+// Seconds since 1970
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+// IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn http_date(timestamp: u64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+    let days = timestamp / 86400;
+    let seconds = timestamp % 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {day:02} {} {year} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        MONTHS[month as usize - 1],
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// The Common Log Format's, in UTC, e.g. "06/Nov/1994:08:49:37 +0000"
+pub fn log_date(timestamp: u64) -> String {
+    let seconds = timestamp % 86400;
+    let (year, month, day) = civil_from_days(timestamp / 86400);
+    format!(
+        "{day:02}/{}/{year}:{:02}:{:02}:{:02} +0000",
+        MONTHS[month as usize - 1],
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// e.g. "1994-11-06T08:49:37Z", or "1994-11-06T09:49:37+01:00" in local time
+pub fn iso8601(timestamp: u64, clock: Clock) -> String {
+    let offset = match clock {
+        Clock::Utc => 0,
+        Clock::Local => local().offset(timestamp as i64),
+    };
+    format_iso8601(timestamp, offset, clock)
+}
+
+fn format_iso8601(timestamp: u64, offset: i64, clock: Clock) -> String {
+    let local = (timestamp as i64 + offset).max(0) as u64;
+    let seconds = local % 86400;
+    let (year, month, day) = civil_from_days(local / 86400);
+    let zone = match (clock, offset) {
+        (Clock::Utc, _) => "Z".to_owned(),
+        (Clock::Local, offset) => {
+            let sign = if offset < 0 { '-' } else { '+' };
+            let minutes = offset.abs() / 60;
+            format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+        }
+    };
+    format!(
+        "{year}-{month:02}-{day:02}T{:02}:{:02}:{:02}{zone}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// How long before `now`, e.g. "3 hours ago"
+pub fn ago(timestamp: u64, now: u64) -> String {
+    const DAY: u64 = 86400;
+
+    let seconds = now.saturating_sub(timestamp);
+    let (count, unit) = match seconds {
+        0..60 => return "just now".to_owned(),
+        60..3600 => (seconds / 60, "minute"),
+        3600..DAY => (seconds / 3600, "hour"),
+        DAY..2_592_000 => (seconds / DAY, "day"),
+        2_592_000..31_536_000 => (seconds / 2_592_000, "month"),
+        _ => (seconds / 31_536_000, "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{count} {unit}{plural} ago")
+}
+
+// The timestamp of an IMF-fixdate. The obsolete formats, which clients only
+// send back when servers did, give None.
+pub fn parse_http_date(value: &str) -> Option<u64> {
+    let (_, date) = value.trim().split_once(", ")?;
+    let number = |digits: &str, len: usize| {
+        (digits.len() == len && digits.bytes().all(|byte| byte.is_ascii_digit()))
+            .then(|| digits.parse::<u64>().ok())
+            .flatten()
+    };
+    let [day, month, year, time, "GMT"] = date.split(' ').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let [hours, minutes, seconds] = time.split(':').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let (day, year) = (number(day, 2)?, number(year, 4)?);
+    let month = MONTHS.iter().position(|name| *name == month)? as u64 + 1;
+    let (hours, minutes, seconds) = (number(hours, 2)?, number(minutes, 2)?, number(seconds, 2)?);
+    if year < 1970 || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+// Howard Hinnant's algorithms between days since 1970 and (year, month, day)
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year % 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+// Read once, UTC when there's none
+fn local() -> &'static Zone {
+    LOCAL.get_or_init(|| {
+        let load = |path: &str| fs::read(path).ok().and_then(|data| parse_tzif(&data));
+        let zone = match std::env::var("TZ") {
+            Ok(tz) => {
+                let tz = tz.strip_prefix(':').unwrap_or(&tz);
+                match tz.starts_with('/') {
+                    true => load(tz),
+                    false => load(&format!("{ZONEINFO}/{tz}")).or_else(|| {
+                        parse_rule(tz).map(|rule| Zone {
+                            rule: Some(rule),
+                            ..Zone::default()
+                        })
+                    }),
+                }
+            }
+            Err(_) => load("/etc/localtime"),
+        };
+        zone.unwrap_or_default()
+    })
+}
+
+fn parse_tzif(data: &[u8]) -> Option<Zone> {
+    // isutcnt, isstdcnt, leapcnt, timecnt, typecnt and charcnt
+    let counts = |data: &[u8]| -> Option<[usize; 6]> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let mut res = [0; 6];
+        for (i, count) in res.iter_mut().enumerate() {
+            *count = signed(data.get(20 + i * 4..24 + i * 4)?) as u32 as usize;
+        }
+        Some(res)
+    };
+    let version = *data.get(4)?;
+    let [isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt] = counts(data)?;
+    // version 1 data, with 32-bit times, comes before that of later ones
+    let (data, time_size) = match version {
+        0 | b'1' => (data, 4),
+        _ => {
+            let skipped = timecnt * 5 + typecnt * 6 + charcnt + leapcnt * 8 + isstdcnt + isutcnt;
+            (data.get(44 + skipped..)?, 8)
+        }
+    };
+    let [isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt] = counts(data)?;
+
+    let mut at = 44;
+    let mut take = |len: usize| {
+        let res = data.get(at..at + len);
+        at += len;
+        res
+    };
+    let times = take(timecnt * time_size)?;
+    let indices = take(timecnt)?;
+    let types = take(typecnt * 6)?;
+    take(charcnt + leapcnt * (time_size + 4) + isstdcnt + isutcnt)?;
+    // "\n<rule>\n"
+    let footer = data.get(at..).unwrap_or_default();
+
+    let offset = |index: usize| types.get(index * 6..index * 6 + 4).map(signed);
+    let transitions = times
+        .chunks(time_size)
+        .zip(indices)
+        .map(|(time, index)| Some((signed(time), offset(usize::from(*index))?)))
+        .collect::<Option<Vec<_>>>()?;
+    let rule = std::str::from_utf8(footer)
+        .ok()
+        .and_then(|footer| parse_rule(footer.trim_matches('\n')));
+    Some(Zone {
+        transitions,
+        initial: offset(0)?,
+        rule: rule.filter(|_| version != 0 && version != b'1'),
+    })
+}
+
+// Big-endian two's complement
+fn signed(bytes: &[u8]) -> i64 {
+    let value = bytes
+        .iter()
+        .fold(0_u64, |value, byte| value << 8 | u64::from(*byte));
+    let shift = 64 - 8 * bytes.len() as u32;
+    ((value << shift) as i64) >> shift
+}
+
+// e.g. "UTC0", "CET-1CEST,M3.5.0,M10.5.0/3" or "<+1030>-10:30<+11>-11,...":
+// offsets are west of Greenwich, daylight time is an hour ahead by default
+fn parse_rule(value: &str) -> Option<Rule> {
+    let mut rest = value;
+    skip_name(&mut rest)?;
+    let standard = -parse_offset(&mut rest)?;
+    if rest.is_empty() {
+        return Some(Rule {
+            standard,
+            daylight: None,
+        });
+    }
+    skip_name(&mut rest)?;
+    let daylight = match rest.starts_with(',') {
+        true => standard + 3600,
+        false => -parse_offset(&mut rest)?,
+    };
+    let (start, end) = rest.strip_prefix(',')?.split_once(',')?;
+    Some(Rule {
+        standard,
+        daylight: Some((daylight, parse_change(start)?, parse_change(end)?)),
+    })
+}
+
+fn skip_name(rest: &mut &str) -> Option<()> {
+    let len = match rest.strip_prefix('<') {
+        Some(quoted) => quoted.find('>')? + 2,
+        None => rest
+            .find(|char: char| !char.is_ascii_alphabetic())
+            .unwrap_or(rest.len()),
+    };
+    if len < 3 {
+        return None;
+    }
+    *rest = &rest[len..];
+    Some(())
+}
+
+fn parse_offset(rest: &mut &str) -> Option<i64> {
+    let len = rest
+        .find(|char: char| !(char.is_ascii_digit() || "+-:".contains(char)))
+        .unwrap_or(rest.len());
+    let (offset, after) = rest.split_at(len);
+    *rest = after;
+    parse_time(offset)
+}
+
+// [+-]hh[:mm[:ss]], in seconds
+fn parse_time(value: &str) -> Option<i64> {
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(value) => (-1, value),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut res = 0;
+    let mut unit = 3600;
+    for part in value.split(':') {
+        if unit == 0 || part.is_empty() || !part.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        res += part.parse::<i64>().ok()? * unit;
+        unit /= 60;
+    }
+    Some(sign * res)
+}
+
+fn parse_change(value: &str) -> Option<Change> {
+    let (date, time) = value.split_once('/').unwrap_or((value, "2"));
+    let parts: Vec<u64> = date
+        .strip_prefix('M')?
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let [month @ 1..=12, week @ 1..=5, weekday @ 0..=6] = parts[..] else {
+        return None;
+    };
+    Some(Change {
+        month,
+        week,
+        weekday,
+        time: parse_time(time)?,
+    })
+}
+
+#[test]
+fn test_http_date() {
+    assert_eq!(http_date(784111777), "Sun, 06 Nov 1994 08:49:37 GMT");
+    assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+    assert_eq!(http_date(951782400), "Tue, 29 Feb 2000 00:00:00 GMT");
+    assert_eq!(log_date(784111777), "06/Nov/1994:08:49:37 +0000");
+    for timestamp in [0, 784111777, 951782400, 1700000000] {
+        assert_eq!(parse_http_date(&http_date(timestamp)), Some(timestamp));
+    }
+    for value in [
+        "Sunday, 06-Nov-94 08:49:37 GMT",
+        "Sun Nov  6 08:49:37 1994",
+        "Sun, 06 Nov 1994 08:49:37 UTC",
+        "Sun, 6 Nov 1994 08:49:37 GMT",
+        "Sun, 06 Nov 1969 08:49:37 GMT",
+        "Sun, 06 Nov 1994 24:49:37 GMT",
+        "Sun, 06 Nov 1994 +8:49:37 GMT",
+    ] {
+        assert_eq!(parse_http_date(value), None, "{value}");
+    }
+}
+
+#[test]
+fn test_iso8601() {
+    assert_eq!(iso8601(784111777, Clock::Utc), "1994-11-06T08:49:37Z");
+    assert_eq!(
+        format_iso8601(784111777, 3600, Clock::Local),
+        "1994-11-06T09:49:37+01:00"
+    );
+    assert_eq!(
+        format_iso8601(784111777, -9000, Clock::Local),
+        "1994-11-06T06:19:37-02:30"
+    );
+    assert_eq!(ago(1000, 1030), "just now");
+    assert_eq!(ago(0, 7200), "2 hours ago");
+    assert_eq!(ago(0, 86400), "1 day ago");
+    assert_eq!(ago(0, 400 * 86400), "1 year ago");
+    assert_eq!(ago(2000, 1000), "just now");
+}
+
+#[test]
+fn test_rules() {
+    let paris = parse_rule("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+    // 2024-03-31 00:59:59 and 01:00:00 UTC, then 2024-10-27 00:59:59 and 01:00
+    assert_eq!(paris.offset(1711846799), 3600);
+    assert_eq!(paris.offset(1711846800), 7200);
+    assert_eq!(paris.offset(1729990799), 7200);
+    assert_eq!(paris.offset(1729990800), 3600);
+    // daylight time across the new year, in January and July
+    let sydney = parse_rule("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+    assert_eq!(sydney.offset(1705000000), 11 * 3600);
+    assert_eq!(sydney.offset(1720000000), 10 * 3600);
+    let kolkata = parse_rule("<+0530>-5:30").unwrap();
+    assert_eq!(kolkata.offset(0), 19800);
+    let new_york = parse_rule("EST5EDT,M3.2.0,M11.1.0").unwrap();
+    assert_eq!(new_york.offset(1720000000), -4 * 3600);
+    assert_eq!(parse_rule("UTC0").map(|rule| rule.standard), Some(0));
+
+    // a version 2 TZif file: an empty version 1 block, then a transition
+    // to +01:00 at 1000 from UTC, and the rule for after it
+    let counts = |timecnt: u32, typecnt: u32, charcnt: u32| {
+        let mut res = b"TZif2".to_vec();
+        res.extend([0; 15]);
+        for count in [0, 0, 0, timecnt, typecnt, charcnt] {
+            res.extend(count.to_be_bytes());
+        }
+        res
+    };
+    let mut file = counts(0, 1, 4);
+    file.extend([0; 6]);
+    file.extend(b"UTC\0");
+    file.extend(counts(1, 2, 8));
+    file.extend(1000_i64.to_be_bytes());
+    file.push(1);
+    file.extend([0, 0, 0, 0, 0, 0]);
+    file.extend(3600_i32.to_be_bytes());
+    file.extend([0, 4]);
+    file.extend(b"UTC\0CET\0");
+    file.extend(b"\nCET-1CEST,M3.5.0,M10.5.0/3\n");
+    let zone = parse_tzif(&file).unwrap();
+    assert_eq!(zone.offset(999), 0);
+    assert_eq!(zone.offset(1000), 3600);
+    assert_eq!(zone.offset(1711846800), 7200);
+}
@@ -0,0 +1,126 @@
+// --request-timeout: a cap on the time a request takes to be answered, from
+// its head being read to the last byte of the response, so clients reading
+// slowly, or stuck uploads, don't hold up a worker forever. Socket timeouts
+// can't do that, as a client reading a byte now and then resets them: a
+// watchdog thread shuts down the connections of requests past their deadline
+// instead, which makes the worker answering them fail at its next read or
+// write and move on to another connection.
+
+use crate::{format_duration, log, stream::Connection};
+use std::{
+    collections::HashMap,
+    net::Shutdown,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// how late a request can be cut off past its deadline, at most
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+struct Entry {
+    deadline: Instant,
+    connection: Connection,
+    // e.g. "GET /a.txt from 127.0.0.1", for the log
+    what: String,
+}
+
+pub struct Watchdog {
+    pub timeout: Duration,
+    requests: Mutex<HashMap<u64, Entry>>,
+    next_id: AtomicU64,
+}
+
+// A request being watched, until it's dropped
+pub struct Watch<'a> {
+    watchdog: &'a Watchdog,
+    id: u64,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration) -> Self {
+        Watchdog {
+            timeout,
+            requests: Mutex::default(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    pub fn watch(&self, connection: Connection, what: String) -> Watch<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let entry = Entry {
+            deadline: Instant::now() + self.timeout,
+            connection,
+            what,
+        };
+        self.lock().insert(id, entry);
+        Watch { watchdog: self, id }
+    }
+
+    // Cuts off the requests past their deadline, for ever
+    pub fn run(&self) {
+        loop {
+            self.check(Instant::now());
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    // Cuts off the requests past their deadline at `now`, and returns how many
+    fn check(&self, now: Instant) -> usize {
+        let mut requests = self.lock();
+        let expired: Vec<u64> = requests
+            .iter()
+            .filter(|(_, entry)| entry.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            let Some(entry) = requests.remove(id) else {
+                continue;
+            };
+            log::warning(&format!(
+                "{} took longer than {}, connection closed",
+                entry.what,
+                format_duration(self.timeout)
+            ));
+            let _ = entry.connection.shutdown(Shutdown::Both);
+        }
+        expired.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Entry>> {
+        self.requests.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Drop for Watch<'_> {
+    fn drop(&mut self) {
+        self.watchdog.lock().remove(&self.id);
+    }
+}
+
+#[test]
+fn test_watchdog() {
+    use std::{
+        io::Read,
+        net::{TcpListener, TcpStream},
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let watchdog = Watchdog::new(Duration::from_secs(30));
+
+    let watch = watchdog.watch(Box::new(server.try_clone().unwrap()), "GET /".to_owned());
+    assert_eq!(watchdog.check(Instant::now()), 0);
+    drop(watch);
+    // answered in time
+    assert_eq!(watchdog.check(Instant::now() + Duration::from_secs(60)), 0);
+
+    let _watch = watchdog.watch(Box::new(server), "GET /".to_owned());
+    assert_eq!(watchdog.check(Instant::now() + Duration::from_secs(60)), 1);
+    let mut buf = [0; 1];
+    assert_eq!(client.read(&mut buf).unwrap(), 0);
+}
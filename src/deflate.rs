@@ -0,0 +1,460 @@
+// DEFLATE compression (RFC 1951) and the gzip format around it (RFC 1952),
+// for responses. Matches are found with hash chains, one byte of lazy
+// matching, and each block gets Huffman codes fitted to its symbols.
+
+use crate::{
+    inflate::{CODE_LENGTH_ORDER, DISTANCE_BASE, DISTANCE_EXTRA, LENGTH_BASE, LENGTH_EXTRA},
+    zip::crc32,
+};
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// how many earlier positions are tried for a match, more is slower for
+// little gain
+const MAX_CHAIN: usize = 64;
+// matches at least this long are taken without looking one byte further
+const LAZY_LIMIT: usize = 32;
+// a 3 byte match further away costs about as much as the literals
+const MAX_SHORT_DISTANCE: usize = 4096;
+const HASH_BITS: u32 = 15;
+// each block gets its own codes
+const BLOCK_SYMBOLS: usize = 64 * 1024;
+const END_OF_BLOCK: usize = 256;
+
+#[derive(Clone, Copy)]
+enum Symbol {
+    Literal(u8),
+    Match { length: usize, distance: usize },
+}
+
+pub fn gzip(input: &[u8]) -> Vec<u8> {
+    // no name nor time, unknown OS
+    let mut res = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    res.extend(deflate(input));
+    res.extend(crc32(input).to_le_bytes());
+    // the size modulo 2^32
+    res.extend((input.len() as u32).to_le_bytes());
+    res
+}
+
+pub fn deflate(input: &[u8]) -> Vec<u8> {
+    let symbols = find_matches(input);
+    let mut writer = BitWriter::default();
+    if symbols.is_empty() {
+        write_block(&mut writer, &[], true);
+    }
+    let mut blocks = symbols.chunks(BLOCK_SYMBOLS).peekable();
+    while let Some(block) = blocks.next() {
+        write_block(&mut writer, block, blocks.peek().is_none());
+    }
+    writer.finish()
+}
+
+// Positions of the earlier bytes starting with the same 3 bytes, newest
+// first, through the window
+struct Chains {
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+const NONE: usize = usize::MAX;
+
+impl Chains {
+    fn hash(input: &[u8], pos: usize) -> usize {
+        let bytes = u32::from(input[pos]) << 16
+            | u32::from(input[pos + 1]) << 8
+            | u32::from(input[pos + 2]);
+        (bytes.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, input: &[u8], pos: usize) {
+        if pos + MIN_MATCH <= input.len() {
+            let hash = Self::hash(input, pos);
+            self.prev[pos % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = pos;
+        }
+    }
+
+    // The length and distance of the longest match for `pos`, whose earlier
+    // positions have all been inserted
+    fn longest_match(&self, input: &[u8], pos: usize) -> (usize, usize) {
+        if pos + MIN_MATCH > input.len() {
+            return (0, 0);
+        }
+        let max = (input.len() - pos).min(MAX_MATCH);
+        let mut best = (0, 0);
+        let mut candidate = self.head[Self::hash(input, pos)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == NONE || pos - candidate > WINDOW_SIZE {
+                break;
+            }
+            if input[candidate + best.0] == input[pos + best.0] {
+                let length = input[candidate..candidate + max]
+                    .iter()
+                    .zip(&input[pos..pos + max])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best.0 {
+                    best = (length, pos - candidate);
+                    if length == max {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[candidate % WINDOW_SIZE];
+            // the slot was reused by a newer position
+            if next == NONE || next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        if best.0 < MIN_MATCH || (best.0 == MIN_MATCH && best.1 > MAX_SHORT_DISTANCE) {
+            return (0, 0);
+        }
+        best
+    }
+}
+
+fn find_matches(input: &[u8]) -> Vec<Symbol> {
+    let mut chains = Chains {
+        head: vec![NONE; 1 << HASH_BITS],
+        prev: vec![NONE; WINDOW_SIZE],
+    };
+    let mut symbols = Vec::new();
+    // positions below are in the chains
+    let mut inserted = 0;
+    let mut insert_until = |chains: &mut Chains, end: usize| {
+        while inserted < end {
+            chains.insert(input, inserted);
+            inserted += 1;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < input.len() {
+        insert_until(&mut chains, pos);
+        let (mut length, mut distance) = chains.longest_match(input, pos);
+        // a longer match one byte later is worth a literal
+        if (MIN_MATCH..LAZY_LIMIT).contains(&length) && pos + 1 < input.len() {
+            insert_until(&mut chains, pos + 1);
+            let (next_length, next_distance) = chains.longest_match(input, pos + 1);
+            if next_length > length {
+                symbols.push(Symbol::Literal(input[pos]));
+                pos += 1;
+                (length, distance) = (next_length, next_distance);
+            }
+        }
+        if length >= MIN_MATCH {
+            symbols.push(Symbol::Match { length, distance });
+            pos += length;
+        } else {
+            symbols.push(Symbol::Literal(input[pos]));
+            pos += 1;
+        }
+    }
+    symbols
+}
+
+// Bits go least significant first, Huffman codes most significant first
+#[derive(Default)]
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.buffer |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - u32::from(length));
+        self.bits(reversed.into(), length.into());
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.output.push(self.buffer as u8);
+        }
+        self.output
+    }
+}
+
+// The index of the base a length or distance falls in
+fn base_index(bases: &[u16], value: usize) -> usize {
+    bases.partition_point(|base| usize::from(*base) <= value) - 1
+}
+
+// A block with dynamic Huffman codes (section 3.2.7)
+fn write_block(writer: &mut BitWriter, symbols: &[Symbol], last: bool) {
+    let mut literal_counts = [0; 286];
+    let mut distance_counts = [0; 30];
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(byte) => literal_counts[usize::from(byte)] += 1,
+            Symbol::Match { length, distance } => {
+                literal_counts[257 + base_index(&LENGTH_BASE, length)] += 1;
+                distance_counts[base_index(&DISTANCE_BASE, distance)] += 1;
+            }
+        }
+    }
+    literal_counts[END_OF_BLOCK] += 1;
+    let literal_lengths = code_lengths(&literal_counts, 15);
+    let distance_lengths = code_lengths(&distance_counts, 15);
+    let literal_codes = canonical_codes(&literal_lengths);
+    let distance_codes = canonical_codes(&distance_lengths);
+
+    // both lists of code lengths, trailing zeros left out, run-length encoded
+    let used = |lengths: &[u8], min: usize| {
+        lengths
+            .iter()
+            .rposition(|length| *length > 0)
+            .map_or(min, |last| (last + 1).max(min))
+    };
+    let literals = used(&literal_lengths, 257);
+    let distances = used(&distance_lengths, 1);
+    let runs =
+        run_lengths(&[&literal_lengths[..literals], &distance_lengths[..distances]].concat());
+    let mut run_counts = [0; 19];
+    for (symbol, _) in &runs {
+        run_counts[usize::from(*symbol)] += 1;
+    }
+    let run_lengths = code_lengths(&run_counts, 7);
+    let run_codes = canonical_codes(&run_lengths);
+    let run_code_count = CODE_LENGTH_ORDER
+        .iter()
+        .rposition(|symbol| run_lengths[*symbol] > 0)
+        .map_or(4, |last| (last + 1).max(4));
+
+    writer.bits(last.into(), 1);
+    writer.bits(2, 2);
+    writer.bits((literals - 257) as u32, 5);
+    writer.bits((distances - 1) as u32, 5);
+    writer.bits((run_code_count - 4) as u32, 4);
+    for symbol in &CODE_LENGTH_ORDER[..run_code_count] {
+        writer.bits(run_lengths[*symbol].into(), 3);
+    }
+    for (symbol, extra) in runs {
+        let symbol = usize::from(symbol);
+        writer.code(run_codes[symbol], run_lengths[symbol]);
+        match symbol {
+            16 => writer.bits(extra.into(), 2),
+            17 => writer.bits(extra.into(), 3),
+            18 => writer.bits(extra.into(), 7),
+            _ => (),
+        }
+    }
+
+    for symbol in symbols {
+        match *symbol {
+            Symbol::Literal(byte) => {
+                let byte = usize::from(byte);
+                writer.code(literal_codes[byte], literal_lengths[byte]);
+            }
+            Symbol::Match { length, distance } => {
+                let idx = base_index(&LENGTH_BASE, length);
+                writer.code(literal_codes[257 + idx], literal_lengths[257 + idx]);
+                let extra = (length - usize::from(LENGTH_BASE[idx])) as u32;
+                writer.bits(extra, LENGTH_EXTRA[idx].into());
+                let idx = base_index(&DISTANCE_BASE, distance);
+                writer.code(distance_codes[idx], distance_lengths[idx]);
+                let extra = (distance - usize::from(DISTANCE_BASE[idx])) as u32;
+                writer.bits(extra, DISTANCE_EXTRA[idx].into());
+            }
+        }
+    }
+    writer.code(literal_codes[END_OF_BLOCK], literal_lengths[END_OF_BLOCK]);
+}
+
+// Code lengths as symbols 0-15, with 16 repeating the previous one 3-6
+// times, 17 and 18 repeating zero 3-10 and 11-138 times, and their extra
+// bits
+fn run_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut res = Vec::new();
+    let mut idx = 0;
+    while idx < lengths.len() {
+        let length = lengths[idx];
+        let run = lengths[idx..]
+            .iter()
+            .take_while(|other| **other == length)
+            .count();
+        if length == 0 && run >= 3 {
+            let run = run.min(138);
+            if run >= 11 {
+                res.push((18, (run - 11) as u8));
+            } else {
+                res.push((17, (run - 3) as u8));
+            }
+            idx += run;
+        } else if length != 0 && run >= 4 {
+            let repeats = (run - 1).min(6);
+            res.push((length, 0));
+            res.push((16, (repeats - 3) as u8));
+            idx += 1 + repeats;
+        } else {
+            res.push((length, 0));
+            idx += 1;
+        }
+    }
+    res
+}
+
+// Huffman code lengths for symbols seen `counts` times, of at most `limit`
+// bits, making a complete code as zlib expects
+fn code_lengths(counts: &[u32], limit: u8) -> Vec<u8> {
+    let mut used: Vec<_> = (0..counts.len()).filter(|idx| counts[*idx] > 0).collect();
+    // a code has at least two symbols
+    for idx in 0..counts.len() {
+        if used.len() >= 2 {
+            break;
+        }
+        if !used.contains(&idx) {
+            used.push(idx);
+        }
+    }
+
+    // merge the two lightest nodes until one is left, leaves come first
+    let mut parents = vec![NONE; used.len()];
+    let mut heap: BinaryHeap<_> = used
+        .iter()
+        .enumerate()
+        .map(|(node, symbol)| Reverse((u64::from(counts[*symbol].max(1)), node)))
+        .collect();
+    while let (Some(Reverse((a_weight, a))), Some(Reverse((b_weight, b)))) =
+        (heap.pop(), heap.pop())
+    {
+        let node = parents.len();
+        parents.push(NONE);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(Reverse((a_weight + b_weight, node)));
+    }
+    let mut lengths = vec![0; counts.len()];
+    for (leaf, symbol) in used.iter().enumerate() {
+        let mut node = leaf;
+        while parents[node] != NONE {
+            node = parents[node];
+            lengths[*symbol] += 1;
+        }
+    }
+    limit_lengths(&mut lengths, limit);
+    lengths
+}
+
+// Brings codes longer than `limit` to it, then lengthens the longest of the
+// others until the code fits (Kraft's inequality), and shortens codes while
+// that leaves it complete
+fn limit_lengths(lengths: &mut [u8], limit: u8) {
+    let full = 1_u64 << limit;
+    let kraft = |lengths: &[u8]| -> u64 {
+        lengths
+            .iter()
+            .filter(|length| **length > 0)
+            .map(|length| 1 << (limit - length))
+            .sum()
+    };
+    for length in lengths.iter_mut() {
+        *length = (*length).min(limit);
+    }
+    let mut sum = kraft(lengths);
+    while sum > full {
+        let Some(idx) = (0..lengths.len())
+            .filter(|idx| lengths[*idx] > 0 && lengths[*idx] < limit)
+            .max_by_key(|idx| lengths[*idx])
+        else {
+            break;
+        };
+        sum -= 1 << (limit - lengths[idx] - 1);
+        lengths[idx] += 1;
+    }
+    while sum < full {
+        let Some(idx) = (0..lengths.len())
+            .filter(|idx| lengths[*idx] > 1 && sum + (1 << (limit - lengths[*idx])) <= full)
+            .max_by_key(|idx| lengths[*idx])
+        else {
+            break;
+        };
+        sum += 1 << (limit - lengths[idx]);
+        lengths[idx] -= 1;
+    }
+}
+
+// Section 3.2.2
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0_u16; 16];
+    for length in lengths.iter().filter(|length| **length > 0) {
+        counts[usize::from(*length)] += 1;
+    }
+    let mut next = [0_u16; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + counts[bits - 1]) << 1;
+        next[bits] = code;
+    }
+    lengths
+        .iter()
+        .map(|length| {
+            let length = usize::from(*length);
+            if length == 0 {
+                return 0;
+            }
+            next[length] += 1;
+            next[length] - 1
+        })
+        .collect()
+}
+
+#[test]
+fn test_deflate() {
+    use crate::inflate::inflate;
+
+    let text = "<p>The quick brown fox jumps over the lazy dog.</p>\n".repeat(500);
+    let mut noise = Vec::new();
+    let mut state = 1_u32;
+    for _ in 0..100_000 {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        noise.push((state >> 16) as u8);
+    }
+    let skewed: Vec<u8> = (0..200_000_u32)
+        .map(|idx| (idx % 7 * idx % 13) as u8)
+        .collect();
+    for input in [
+        b"".as_slice(),
+        b"a",
+        b"aaaaaaaaaa",
+        text.as_bytes(),
+        &noise,
+        &skewed,
+    ] {
+        let compressed = deflate(input);
+        assert_eq!(inflate(&compressed, input.len()).unwrap(), input);
+    }
+    assert!(deflate(text.as_bytes()).len() < text.len() / 20);
+
+    let gzipped = gzip(b"abc");
+    assert_eq!(gzipped[..3], [0x1f, 0x8b, 8]);
+    assert_eq!(
+        gzipped[gzipped.len() - 8..],
+        [0xc2, 0x41, 0x24, 0x35, 3, 0, 0, 0]
+    );
+}
+
+#[test]
+fn test_code_lengths() {
+    // far too skewed for 7 bits
+    let counts: Vec<u32> = (0..19).map(|idx| 1 << idx).collect();
+    let lengths = code_lengths(&counts, 7);
+    assert!(lengths.iter().all(|length| (1..=7).contains(length)));
+    let kraft: u32 = lengths.iter().map(|length| 1 << (7 - length)).sum();
+    assert_eq!(kraft, 1 << 7);
+    assert_eq!(code_lengths(&[0, 5, 0], 15), [1, 1, 0]);
+}
@@ -0,0 +1,366 @@
+// --doctor: checks what the server needs from where it runs, before it
+// starts: the served directory, the port, the certificate, the limit of open
+// files, the settings and the clock, with what to do about what's wrong.
+
+use crate::{Config, Mode, release};
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::ErrorKind,
+    net::{TcpListener, UdpSocket},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const NTP_SERVER: &str = "pool.ntp.org:123";
+const NTP_TIMEOUT: Duration = Duration::from_secs(2);
+// seconds from 1900, where NTP time starts, to 1970
+const NTP_EPOCH: f64 = 2_208_988_800.0;
+// tokens, sessions and If-Modified-Since go wrong past that
+const MAX_CLOCK_SKEW: f64 = 30.0;
+// time enough to renew a certificate
+#[cfg(feature = "tls")]
+const CERT_RENEWAL: i64 = 14 * 86400;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    Ok,
+    Warning,
+    Error,
+}
+
+pub struct Finding {
+    pub level: Level,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = match self.level {
+            Level::Ok => "ok",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        };
+        write!(f, "{level:<9}{}", self.message)
+    }
+}
+
+fn finding(level: Level, message: impl Into<String>) -> Finding {
+    Finding {
+        level,
+        message: message.into(),
+    }
+}
+
+// Run from the served directory; `problems` and `warnings` are those of the
+// settings, from where the server was started
+pub fn run(config: &Config, problems: &[String], warnings: &[String]) -> Vec<Finding> {
+    let mut res = Vec::new();
+    check_directory(config, &mut res);
+    check_port(config, &mut res);
+    #[cfg(feature = "tls")]
+    check_certificate(config, &mut res);
+    check_open_files(config, &mut res);
+    check_settings(config, problems, warnings, &mut res);
+    check_clock(config, &mut res);
+    res
+}
+
+fn check_directory(config: &Config, res: &mut Vec<Finding>) {
+    // where -d leads, as Server::new resolved it
+    let served = release::current();
+    let directory = served.display().to_string();
+    let entries = match fs::read_dir(&served) {
+        Ok(entries) => entries,
+        Err(err) => {
+            res.push(finding(
+                Level::Error,
+                format!("'{directory}' can't be listed ({err}): give the user running the server read and execute permission on it"),
+            ));
+            return;
+        }
+    };
+    // only the top, the whole tree could take long
+    let mut total = 0;
+    let mut unreadable = Vec::new();
+    for entry in entries.flatten() {
+        total += 1;
+        let path = entry.path();
+        let readable = if path.is_dir() {
+            fs::read_dir(&path).is_ok()
+        } else {
+            fs::File::open(&path).is_ok()
+        };
+        if !readable {
+            unreadable.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    match unreadable.first() {
+        Some(example) => res.push(finding(
+            Level::Warning,
+            format!(
+                "{} of the {total} entries of '{directory}' can't be read, e.g. '{example}': they are listed but fail to download, fix their permissions or hide them with --deny",
+                unreadable.len()
+            ),
+        )),
+        None => res.push(finding(
+            Level::Ok,
+            format!("'{directory}' and its {total} entries are readable"),
+        )),
+    }
+
+    if config.mode != Mode::ReadOnly && config.git_ref.is_none() {
+        let probe = served.join(format!(".doctor-{}", std::process::id()));
+        match OpenOptions::new().write(true).create_new(true).open(&probe) {
+            Ok(_) => {
+                let _ = fs::remove_file(&probe);
+                res.push(finding(Level::Ok, "uploads can be written"));
+            }
+            Err(err) => res.push(finding(
+                Level::Error,
+                format!("uploads can't be written to '{directory}' ({err}): give the user running the server write permission on it, or use --mode read-only"),
+            )),
+        }
+    }
+}
+
+fn check_port(config: &Config, res: &mut Vec<Finding>) {
+    let address = format!("{}:{}", config.address, config.port);
+    let message = match TcpListener::bind(&address) {
+        Ok(_) => {
+            res.push(finding(Level::Ok, format!("{address} is free")));
+            return;
+        }
+        Err(err) => match err.kind() {
+            ErrorKind::AddrInUse => format!(
+                "{address} is taken by another program: stop it, or pick another port with -p"
+            ),
+            ErrorKind::PermissionDenied => format!(
+                "binding to {address} isn't allowed: ports below 1024 need root or CAP_NET_BIND_SERVICE, pick e.g. -p 8080"
+            ),
+            ErrorKind::AddrNotAvailable => format!(
+                "{} isn't an address of this computer: see -b, or --expose for every interface",
+                config.address
+            ),
+            _ => format!("can't bind to {address}: {err}"),
+        },
+    };
+    res.push(finding(Level::Error, message));
+}
+
+#[cfg(feature = "tls")]
+fn check_certificate(config: &Config, res: &mut Vec<Finding>) {
+    let Some(tls) = &config.tls else {
+        return;
+    };
+    let cert = tls.cert();
+    match tls.expires_in() {
+        Some(secs) if secs <= 0 => res.push(finding(
+            Level::Error,
+            format!("certificate '{cert}' has expired: browsers refuse it, renew it"),
+        )),
+        Some(secs) if secs < CERT_RENEWAL => res.push(finding(
+            Level::Warning,
+            format!(
+                "certificate '{cert}' expires in {} days: renew it",
+                secs / 86400
+            ),
+        )),
+        Some(secs) => res.push(finding(
+            Level::Ok,
+            format!(
+                "certificate '{cert}' is valid for {} more days",
+                secs / 86400
+            ),
+        )),
+        None => res.push(finding(
+            Level::Warning,
+            format!("the expiry of certificate '{cert}' couldn't be read"),
+        )),
+    }
+}
+
+fn check_open_files(config: &Config, res: &mut Vec<Finding>) {
+    let Some((soft, hard)) = open_files_limit() else {
+        return;
+    };
+    // each busy or waiting connection, the file it sends or receives, and
+    // some for the listener, logs and background tasks
+    let needed = config.threads as u64 * 4 + 64;
+    if soft >= needed {
+        res.push(finding(
+            Level::Ok,
+            format!(
+                "up to {soft} files can be open, enough for -j {}",
+                config.threads
+            ),
+        ));
+    } else if hard >= needed {
+        res.push(finding(
+            Level::Warning,
+            format!(
+                "only {soft} files can be open, -j {} may need {needed}: raise it with `ulimit -n {needed}` before starting",
+                config.threads
+            ),
+        ));
+    } else {
+        res.push(finding(
+            Level::Warning,
+            format!(
+                "only {soft} files can be open (at most {hard}), -j {} may need {needed}: raise the hard limit (LimitNOFILE= for systemd, /etc/security/limits.conf) or lower -j",
+                config.threads
+            ),
+        ));
+    }
+}
+
+// The soft and hard limits of open files, from /proc on Linux, None elsewhere
+fn open_files_limit() -> Option<(u64, u64)> {
+    let limits = fs::read_to_string("/proc/self/limits").ok()?;
+    let line = limits
+        .lines()
+        .find(|line| line.starts_with("Max open files"))?;
+    let mut values = line["Max open files".len()..]
+        .split_whitespace()
+        .map(|value| match value {
+            "unlimited" => Some(u64::MAX),
+            _ => value.parse().ok(),
+        });
+    Some((values.next()??, values.next()??))
+}
+
+fn check_settings(
+    config: &Config,
+    problems: &[String],
+    warnings: &[String],
+    res: &mut Vec<Finding>,
+) {
+    let count = res.len();
+    res.extend(
+        problems
+            .iter()
+            .map(|problem| finding(Level::Error, problem.as_str())),
+    );
+    res.extend(
+        warnings
+            .iter()
+            .map(|warning| finding(Level::Warning, warning.as_str())),
+    );
+    // rules about paths which aren't served at all
+    for token in &config.tokens {
+        if config.hidden.matches(&token.prefix) {
+            res.push(finding(
+                Level::Warning,
+                format!("token path '/{}' is hidden (--hide-dotfiles, --deny or --git): nothing below it is served", token.prefix),
+            ));
+        }
+    }
+    for quota in &config.quotas {
+        if !quota.prefix.is_empty() && config.hidden.matches(&quota.prefix) {
+            res.push(finding(
+                Level::Warning,
+                format!("quota path '/{}' is hidden (--hide-dotfiles, --deny or --git): uploads there are refused anyway", quota.prefix),
+            ));
+        }
+    }
+    if res.len() == count {
+        res.push(finding(Level::Ok, "the settings are consistent"));
+    }
+}
+
+fn check_clock(config: &Config, res: &mut Vec<Finding>) {
+    let offset = match clock_offset(NTP_SERVER) {
+        Ok(offset) => offset,
+        Err(err) => {
+            res.push(finding(
+                Level::Warning,
+                format!("the clock couldn't be checked against {NTP_SERVER}: {err}"),
+            ));
+            return;
+        }
+    };
+    if offset.abs() <= MAX_CLOCK_SKEW {
+        res.push(finding(
+            Level::Ok,
+            format!("the clock is {:.1}s off {NTP_SERVER}", offset.abs()),
+        ));
+        return;
+    }
+    let direction = if offset > 0.0 { "behind" } else { "ahead of" };
+    let consequence = if config.jwt.is_some() || config.oidc.is_some() {
+        "tokens and sessions will be refused or expire early"
+    } else {
+        "caches will revalidate badly and the trash expires at the wrong time"
+    };
+    res.push(finding(
+        Level::Warning,
+        format!(
+            "the clock is {:.0}s {direction} {NTP_SERVER}, {consequence}: enable time synchronization (e.g. timedatectl set-ntp true)",
+            offset.abs()
+        ),
+    ));
+}
+
+// How far `server` is ahead of this clock, in seconds, with SNTP (RFC 4330)
+fn clock_offset(server: &str) -> std::io::Result<f64> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(NTP_TIMEOUT))?;
+    socket.connect(server)?;
+    // version 4, client mode
+    let mut request = [0; 48];
+    request[0] = 0x23;
+    let sent = unix_now();
+    socket.send(&request)?;
+    let mut response = [0; 48];
+    let len = socket.recv(&mut response)?;
+    let received = unix_now();
+    if len < 48 || response[0] & 0x7 != 4 {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "not an NTP response",
+        ));
+    }
+    let timestamp = |offset: usize| {
+        let seconds = u32::from_be_bytes(response[offset..offset + 4].try_into().unwrap());
+        let fraction = u32::from_be_bytes(response[offset + 4..offset + 8].try_into().unwrap());
+        f64::from(seconds) + f64::from(fraction) / 2_f64.powi(32) - NTP_EPOCH
+    };
+    // when the server received the request and sent the response
+    let (server_received, server_sent) = (timestamp(32), timestamp(40));
+    Ok(((server_received - sent) + (server_sent - received)) / 2.0)
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+#[test]
+fn test_open_files_limit() {
+    // wherever /proc is there, it has the line
+    if std::path::Path::new("/proc/self/limits").exists() {
+        let (soft, hard) = open_files_limit().unwrap();
+        assert!(soft <= hard);
+    }
+}
+
+#[test]
+fn test_clock_offset() {
+    // a server 100s ahead
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+        let mut request = [0; 48];
+        let (_, client) = server.recv_from(&mut request).unwrap();
+        let now = unix_now() + NTP_EPOCH + 100.0;
+        let mut response = [0; 48];
+        response[0] = 0x24;
+        for offset in [32, 40] {
+            response[offset..offset + 4].copy_from_slice(&(now as u32).to_be_bytes());
+        }
+        server.send_to(&response, client).unwrap();
+    });
+    let offset = clock_offset(&address).unwrap();
+    assert!((offset - 100.0).abs() < 2.0, "{offset}");
+}
@@ -0,0 +1,159 @@
+// A small EPUB reader for the viewer page. The book is a zip file: its
+// META-INF/container.xml names the package document, whose spine lists the
+// chapters in reading order. Chapters are shown one at a time in the frame,
+// their images and stylesheets replaced with blob URLs of the zip members.
+const frame = document.getElementById("book");
+const chapters = document.getElementById("chapters");
+const previous = document.getElementById("previous");
+const next = document.getElementById("next");
+const error = document.getElementById("error");
+let book = null;
+let spine = [];
+let current = -1;
+
+// The members of the zip file in `buffer`, as a function reading one by name
+function unzip(buffer) {
+  const view = new DataView(buffer);
+  // the end of central directory record, before a comment of up to 64 KiB
+  let end = buffer.byteLength - 22;
+  while (end >= 0 && view.getUint32(end, true) !== 0x06054b50) {
+    end--;
+  }
+  if (end < 0) {
+    throw new Error("not a zip file");
+  }
+  const count = view.getUint16(end + 10, true);
+  let offset = view.getUint32(end + 16, true);
+  const members = new Map();
+  const decoder = new TextDecoder();
+  for (let i = 0; i < count; i++) {
+    if (view.getUint32(offset, true) !== 0x02014b50) {
+      throw new Error("broken zip file");
+    }
+    const nameLength = view.getUint16(offset + 28, true);
+    const name = decoder.decode(new Uint8Array(buffer, offset + 46, nameLength));
+    members.set(name, {
+      method: view.getUint16(offset + 10, true),
+      size: view.getUint32(offset + 20, true),
+      header: view.getUint32(offset + 42, true),
+    });
+    offset += 46 + nameLength + view.getUint16(offset + 30, true) + view.getUint16(offset + 32, true);
+  }
+
+  return async (name) => {
+    const member = members.get(name);
+    if (!member) {
+      throw new Error(`${name} is missing from the book`);
+    }
+    const start = member.header + 30 + view.getUint16(member.header + 26, true)
+      + view.getUint16(member.header + 28, true);
+    const data = new Blob([new Uint8Array(buffer, start, member.size)]);
+    switch (member.method) {
+      case 0:
+        return data;
+      case 8:
+        return new Response(data.stream().pipeThrough(new DecompressionStream("deflate-raw"))).blob();
+      default:
+        throw new Error(`${name} is compressed with an unknown method`);
+    }
+  };
+}
+
+// `href` relative to the member `base`, as a member name
+function resolve(base, href) {
+  const url = new URL(href, `http://book/${base}`);
+  return decodeURIComponent(url.pathname.slice(1));
+}
+
+async function readXml(name) {
+  const text = await (await book(name)).text();
+  return new DOMParser().parseFromString(text, "application/xml");
+}
+
+async function openBook(url) {
+  const response = await fetch(url);
+  if (!response.ok) {
+    throw new Error(`${response.status} ${response.statusText}`);
+  }
+  book = unzip(await response.arrayBuffer());
+  const container = await readXml("META-INF/container.xml");
+  const opf = container.querySelector("rootfile")?.getAttribute("full-path");
+  if (!opf) {
+    throw new Error("the book has no package document");
+  }
+  const pkg = await readXml(opf);
+  const items = new Map();
+  for (const item of pkg.querySelectorAll("manifest > item")) {
+    items.set(item.getAttribute("id"), resolve(opf, item.getAttribute("href")));
+  }
+  spine = [...pkg.querySelectorAll("spine > itemref")]
+    .map((itemref) => items.get(itemref.getAttribute("idref")))
+    .filter((name) => name);
+  const title = pkg.querySelector("metadata > title")?.textContent;
+  if (title) {
+    document.title = title;
+  }
+  spine.forEach((name, index) => chapters.add(new Option(`${index + 1}. ${name}`, index)));
+  await show(0);
+}
+
+async function show(index) {
+  if (index < 0 || index >= spine.length) {
+    return;
+  }
+  current = index;
+  chapters.value = index;
+  previous.disabled = index === 0;
+  next.disabled = index === spine.length - 1;
+  const name = spine[index];
+  const page = await readXml(name);
+  const attributes = [["img", "src"], ["link", "href"], ["image", "href"], ["image", "xlink:href"]];
+  for (const [tag, attribute] of attributes) {
+    for (const element of page.getElementsByTagName(tag)) {
+      const value = element.getAttribute(attribute);
+      if (value && !/^[a-z]+:/i.test(value)) {
+        const blob = await book(resolve(name, value)).catch(() => null);
+        if (blob) {
+          const type = tag === "link" ? "text/css" : blob.type;
+          element.setAttribute(attribute, URL.createObjectURL(new Blob([blob], { type })));
+        }
+      }
+    }
+  }
+  const xhtml = new XMLSerializer().serializeToString(page);
+  frame.src = URL.createObjectURL(new Blob([xhtml], { type: "application/xhtml+xml" }));
+}
+
+// links between chapters open them here
+frame.addEventListener("load", () => {
+  frame.contentDocument?.addEventListener("click", (event) => {
+    const link = event.target.closest?.("a[href]");
+    // anchors within the chapter work as they are
+    const href = link?.getAttribute("href")?.split("#")[0];
+    if (!href || /^[a-z]+:/i.test(href)) {
+      return;
+    }
+    event.preventDefault();
+    const index = spine.indexOf(resolve(spine[current], href));
+    if (index >= 0) {
+      show(index).catch(fail);
+    }
+  });
+});
+
+function fail(err) {
+  error.textContent = `This book can't be shown: ${err.message}`;
+  error.hidden = false;
+}
+
+previous.addEventListener("click", () => show(current - 1).catch(fail));
+next.addEventListener("click", () => show(current + 1).catch(fail));
+chapters.addEventListener("change", () => show(Number(chapters.value)).catch(fail));
+document.addEventListener("keydown", (event) => {
+  if (event.key === "ArrowLeft") {
+    show(current - 1).catch(fail);
+  } else if (event.key === "ArrowRight") {
+    show(current + 1).catch(fail);
+  }
+});
+openBook(frame.dataset.src).catch(fail);
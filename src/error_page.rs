@@ -0,0 +1,98 @@
+// The bodies of 403, 404, 500 and 503 responses, which browsers would
+// otherwise show as a blank page: the site's own 404.html (or 403.html,
+// 500.html, 503.html) at its root when there is one, else a page of ours.
+
+use crate::{root, status::StatusCode};
+use std::{cell::RefCell, fs};
+
+// pages of the site beyond that are left alone, ours is sent instead
+const MAX_PAGE_SIZE: u64 = 1024 * 1024;
+
+thread_local! {
+    // the directory of the site asked, see --tenant, in that of --vhost or --map
+    static ROOT: RefCell<String> = RefCell::new(".".to_owned());
+}
+
+pub fn set_root(root: &str) {
+    ROOT.with_borrow_mut(|current| root.clone_into(current));
+}
+
+// For --spa too, whose page is there
+pub fn root() -> String {
+    ROOT.with_borrow(String::clone)
+}
+
+// The HTML page for `status`, None for statuses without one
+pub fn page(status: StatusCode) -> Option<Vec<u8>> {
+    if !matches!(
+        status,
+        StatusCode::Forbidden
+            | StatusCode::NotFound
+            | StatusCode::InternalServerError
+            | StatusCode::ServiceUnavailable
+    ) {
+        return None;
+    }
+    let custom = ROOT.with_borrow(|root| root::path(root).join(format!("{}.html", status.code())));
+    let custom = fs::metadata(&custom)
+        .ok()
+        .filter(|metadata| metadata.is_file() && metadata.len() <= MAX_PAGE_SIZE)
+        .and_then(|_| fs::read(&custom).ok());
+    Some(custom.unwrap_or_else(|| builtin(status).into_bytes()))
+}
+
+fn builtin(status: StatusCode) -> String {
+    let explanation = match status {
+        StatusCode::Forbidden => "You aren't allowed to do this here.",
+        StatusCode::NotFound => "There is nothing at this address.",
+        StatusCode::ServiceUnavailable => "The site is briefly unavailable, please come back soon.",
+        _ => "Something went wrong on our side, please try again later.",
+    };
+    format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+  <meta charset=\"utf-8\">
+  <title>{status}</title>
+  <style>
+  body {{
+    background-color: Canvas;
+    color: CanvasText;
+    color-scheme: light dark;
+    font-family: sans-serif;
+    margin: 4em auto;
+    max-width: 40em;
+    text-align: center;
+  }}
+  h1 {{
+    font-size: 4em;
+    margin-bottom: 0;
+  }}
+  </style>
+</head>
+<h1>{}</h1>
+<p>{}</p>
+<p>{explanation}</p>
+<p><a href=\"/\">Home</a></p>
+</html>
+",
+        status.code(),
+        status.reason()
+    )
+}
+
+#[test]
+fn test_page() {
+    let root = std::env::temp_dir().join(format!("error-page-test-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    set_root(&root.display().to_string());
+    let text = |status| String::from_utf8(page(status).unwrap()).unwrap();
+    assert!(text(StatusCode::NotFound).contains("<h1>404</h1>"));
+    fs::write(root.join("404.html"), "gone").unwrap();
+    assert_eq!(text(StatusCode::NotFound), "gone");
+    assert!(text(StatusCode::Forbidden).contains("<h1>403</h1>"));
+    assert!(text(StatusCode::InternalServerError).contains("Internal Server Error"));
+    assert!(text(StatusCode::ServiceUnavailable).contains("<h1>503</h1>"));
+    assert!(page(StatusCode::MethodNotAllowed).is_none());
+    fs::remove_dir_all(&root).unwrap();
+}
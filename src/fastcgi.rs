@@ -0,0 +1,483 @@
+// --fastcgi: requests for .php scripts are run by a FastCGI server such as
+// PHP-FPM, at a TCP address or a unix socket, e.g. 127.0.0.1:9000 or
+// unix:/run/php/php-fpm.sock. The script gets the usual CGI variables (RFC
+// 3875) and the body of the request, and what it writes on its standard
+// output, a CGI response, is sent back as it comes.
+
+use crate::{
+    Request, chunked::ChunkedWriter, chunked::Trailers, log, response, root, send_status,
+    status::StatusCode, stream::Connection,
+};
+use std::{
+    error::Error,
+    io::{self, BufReader, Read, Take, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    time::Duration,
+};
+
+const EXTENSION: &str = ".php";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// scripts may take a while to answer
+const TIMEOUT: Duration = Duration::from_secs(60);
+// what comes before the body, past which the script's response is refused
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+// the most a record may carry
+const MAX_CONTENT_SIZE: usize = 0xffff;
+
+// record types, see the FastCGI specification, section 8
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+const RESPONDER: u16 = 1;
+// one request per connection, which the server closes once it's answered
+const REQUEST_ID: u16 = 1;
+
+pub enum Address {
+    // host:port
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+// "host:port" or "unix:path"
+pub fn parse(value: &str) -> Result<Address, String> {
+    if let Some(path) = value.strip_prefix("unix:") {
+        #[cfg(unix)]
+        if !path.is_empty() {
+            return Ok(Address::Unix(PathBuf::from(path)));
+        }
+        return Err(format!("'--fastcgi' needs a unix socket path: {path}"));
+    }
+    match value.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            Ok(Address::Tcp(value.to_owned()))
+        }
+        _ => Err(format!(
+            "'--fastcgi' value must be 'host:port' or 'unix:path', e.g. '127.0.0.1:9000': {value}"
+        )),
+    }
+}
+
+impl Address {
+    pub fn describe(&self) -> String {
+        match self {
+            Address::Tcp(address) => address.clone(),
+            #[cfg(unix)]
+            Address::Unix(path) => format!("unix:{}", path.display()),
+        }
+    }
+
+    fn connect(&self) -> io::Result<Box<dyn Socket>> {
+        match self {
+            Address::Tcp(address) => {
+                let address = address
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+                let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                let stream = std::os::unix::net::UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+trait Socket: Read + Write {}
+
+impl<T: Read + Write> Socket for T {}
+
+// The script `path` (normalized) asks for, and the path info after it, e.g.
+// "blog/index.php" and "/posts/1" for blog/index.php/posts/1, or the
+// index.php of a directory asked as one (`slash`) without another index page
+pub fn script(path: &str, slash: bool) -> Option<(String, String)> {
+    let mut end = 0;
+    for component in path.split('/') {
+        end += component.len();
+        if component.ends_with(EXTENSION) && root::path(&path[..end]).is_file() {
+            return Some((path[..end].to_owned(), path[end..].to_owned()));
+        }
+        end += 1;
+    }
+    let index = |name| match path {
+        "." => name,
+        _ => format!("{path}/{name}"),
+    };
+    (slash
+        && root::path(path).is_dir()
+        && !["index.html", "index.htm"]
+            .into_iter()
+            .any(|name| root::path(&index(name.to_owned())).is_file())
+        && root::path(&index("index.php".to_owned())).is_file())
+    .then(|| (index("index.php".to_owned()), String::new()))
+}
+
+// Runs `script` for `request` and its body, then sends back its response
+pub fn forward(
+    address: &Address,
+    request: &Request,
+    script: &str,
+    path_info: &str,
+    body: &mut Take<&mut BufReader<Connection>>,
+    tcp_stream: &mut Connection,
+    scheme: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut upstream = match address.connect() {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            log::warning(&format!("fastcgi {}: {err}", address.describe()));
+            return send_status(tcp_stream, StatusCode::BadGateway, &[]);
+        }
+    };
+    let params = params(request, script, path_info, body.limit(), tcp_stream, scheme)?;
+
+    let mut begin = RESPONDER.to_be_bytes().to_vec();
+    // not keeping the connection, and reserved bytes
+    begin.extend([0; 6]);
+    write_record(&mut upstream, BEGIN_REQUEST, &begin)?;
+    let mut encoded = Vec::new();
+    for (name, value) in &params {
+        encode_param(&mut encoded, name, value);
+    }
+    for content in encoded.chunks(MAX_CONTENT_SIZE) {
+        write_record(&mut upstream, PARAMS, content)?;
+    }
+    write_record(&mut upstream, PARAMS, &[])?;
+    let mut buffer = vec![0; 16 * 1024];
+    while let read = body.read(&mut buffer)?
+        && read != 0
+    {
+        write_record(&mut upstream, STDIN, &buffer[..read])?;
+    }
+    write_record(&mut upstream, STDIN, &[])?;
+    upstream.flush()?;
+
+    let mut upstream = BufReader::new(upstream);
+    let mut head = Vec::new();
+    let mut response: Option<Response> = None;
+    loop {
+        let (kind, content) = match read_record(&mut upstream) {
+            Ok(record) => record,
+            Err(err) if response.is_none() => {
+                log::warning(&format!("fastcgi {}: {err}", address.describe()));
+                return send_status(tcp_stream, gateway_status(&err), &[]);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        match kind {
+            STDOUT if !content.is_empty() => match &mut response {
+                Some(response) => response.write(&content)?,
+                None => {
+                    head.extend_from_slice(&content);
+                    let Some(parsed) = parse_head(&head) else {
+                        if head.len() > MAX_HEAD_SIZE {
+                            log::warning(&format!(
+                                "fastcgi {}: response head too large",
+                                address.describe()
+                            ));
+                            return send_status(tcp_stream, StatusCode::BadGateway, &[]);
+                        }
+                        continue;
+                    };
+                    let mut started =
+                        Response::start(request, tcp_stream, parsed.status, &parsed.headers)?;
+                    started.write(&head[parsed.body..])?;
+                    response = Some(started);
+                }
+            },
+            STDERR => {
+                let message = String::from_utf8_lossy(&content);
+                for line in message.lines().filter(|line| !line.trim().is_empty()) {
+                    log::warning(&format!("fastcgi {script}: {line}"));
+                }
+            }
+            END_REQUEST => break,
+            _ => {}
+        }
+    }
+    match response {
+        Some(response) => response.finish()?,
+        None => {
+            log::warning(&format!(
+                "fastcgi {}: {script} ended without a response",
+                address.describe()
+            ));
+            send_status(tcp_stream, StatusCode::BadGateway, &[])?;
+        }
+    }
+    Ok(())
+}
+
+fn gateway_status(err: &io::Error) -> StatusCode {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => StatusCode::GatewayTimeout,
+        _ => StatusCode::BadGateway,
+    }
+}
+
+// The CGI variables of the request, and its headers as HTTP_ ones
+fn params(
+    request: &Request,
+    script: &str,
+    path_info: &str,
+    content_length: u64,
+    tcp_stream: &Connection,
+    scheme: &str,
+) -> io::Result<Vec<(String, String)>> {
+    let query = request
+        .path
+        .split_once('?')
+        .map(|(_, query)| query)
+        .unwrap_or_default();
+    let document_root = std::path::absolute(root::path("."))?;
+    let script_filename = std::path::absolute(root::path(script))?;
+    let host = request.header("Host").unwrap_or_default();
+    let (server_name, server_port) = match host.rsplit_once(':') {
+        Some((name, port)) if port.parse::<u16>().is_ok() => (name, port.to_owned()),
+        _ => (
+            host,
+            if scheme == "https" { "443" } else { "80" }.to_owned(),
+        ),
+    };
+    let peer = tcp_stream.peer_addr()?;
+    let mut res: Vec<(String, String)> = [
+        ("GATEWAY_INTERFACE", "CGI/1.1".to_owned()),
+        ("SERVER_SOFTWARE", response::default_server()),
+        ("SERVER_PROTOCOL", request.version.clone()),
+        ("SERVER_NAME", server_name.to_owned()),
+        ("SERVER_PORT", server_port),
+        ("REQUEST_SCHEME", scheme.to_owned()),
+        ("REQUEST_METHOD", request.method.clone()),
+        ("REQUEST_URI", request.path.clone()),
+        ("QUERY_STRING", query.to_owned()),
+        ("DOCUMENT_ROOT", document_root.display().to_string()),
+        ("SCRIPT_NAME", format!("/{script}")),
+        ("SCRIPT_FILENAME", script_filename.display().to_string()),
+        ("PATH_INFO", path_info.to_owned()),
+        ("REMOTE_ADDR", peer.ip().to_string()),
+        ("REMOTE_PORT", peer.port().to_string()),
+        // php-cgi only runs scripts with it, as it would behind a redirect
+        ("REDIRECT_STATUS", "200".to_owned()),
+    ]
+    .map(|(name, value)| (name.to_owned(), value))
+    .to_vec();
+    if scheme == "https" {
+        res.push(("HTTPS".to_owned(), "on".to_owned()));
+    }
+    if content_length > 0 {
+        res.push(("CONTENT_LENGTH".to_owned(), content_length.to_string()));
+    }
+    if let Some(content_type) = request.header("Content-Type") {
+        res.push(("CONTENT_TYPE".to_owned(), content_type.to_owned()));
+    }
+    for (name, value) in &request.headers {
+        // HTTP_PROXY would be taken for the proxy of the script's own
+        // requests (httpoxy)
+        if matches!(name.as_str(), "content-type" | "content-length" | "proxy") {
+            continue;
+        }
+        let name = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+        res.push((name, value.clone()));
+    }
+    Ok(res)
+}
+
+fn write_record(upstream: &mut impl Write, kind: u8, content: &[u8]) -> io::Result<()> {
+    let length = content.len() as u16;
+    let mut header = [1, kind, 0, 0, 0, 0, 0, 0];
+    header[2..4].copy_from_slice(&REQUEST_ID.to_be_bytes());
+    header[4..6].copy_from_slice(&length.to_be_bytes());
+    upstream.write_all(&header)?;
+    upstream.write_all(content)
+}
+
+// The type and content of the next record
+fn read_record(upstream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 8];
+    upstream.read_exact(&mut header)?;
+    if header[0] != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported FastCGI version",
+        ));
+    }
+    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let padding = header[6] as usize;
+    let mut content = vec![0; length + padding];
+    upstream.read_exact(&mut content)?;
+    content.truncate(length);
+    Ok((header[1], content))
+}
+
+// Lengths take a byte below 128, else four with the high bit set
+fn encode_param(res: &mut Vec<u8>, name: &str, value: &str) {
+    for length in [name.len(), value.len()] {
+        match length {
+            0..128 => res.push(length as u8),
+            _ => res.extend((length as u32 | 1 << 31).to_be_bytes()),
+        }
+    }
+    res.extend_from_slice(name.as_bytes());
+    res.extend_from_slice(value.as_bytes());
+}
+
+struct Head {
+    status: StatusCode,
+    headers: Vec<(String, String)>,
+    // where the body starts in the output
+    body: usize,
+}
+
+// The head of a CGI response, once it's complete (RFC 3875 section 6.2)
+fn parse_head(output: &[u8]) -> Option<Head> {
+    let (end, body) = [&b"\r\n\r\n"[..], b"\n\n"]
+        .into_iter()
+        .filter_map(|separator| {
+            let end = output
+                .windows(separator.len())
+                .position(|window| window == separator)?;
+            Some((end, end + separator.len()))
+        })
+        .min()?;
+    let head = String::from_utf8_lossy(&output[..end]);
+    let mut status = None;
+    let mut headers = Vec::new();
+    for line in head.lines() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("Status") {
+            status = value
+                .split(' ')
+                .next()
+                .and_then(|code| code.parse().ok())
+                .and_then(StatusCode::from_code_or_class);
+        } else if !["Connection", "Content-Length", "Transfer-Encoding"]
+            .iter()
+            .any(|hop| name.eq_ignore_ascii_case(hop))
+        {
+            headers.push((name.to_owned(), value.to_owned()));
+        }
+    }
+    // a local redirect isn't supported, a Location is sent as it is
+    let redirect = headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("Location"));
+    let status = status.unwrap_or(match redirect {
+        true => StatusCode::Found,
+        false => StatusCode::Ok,
+    });
+    Some(Head {
+        status,
+        headers,
+        body,
+    })
+}
+
+// A response being sent, chunked as its length isn't known
+struct Response<'a> {
+    body: Option<ChunkedWriter<&'a mut Connection>>,
+}
+
+impl<'a> Response<'a> {
+    fn start(
+        request: &Request,
+        tcp_stream: &'a mut Connection,
+        status: StatusCode,
+        headers: &[(String, String)],
+    ) -> io::Result<Self> {
+        let code = status.code();
+        let has_body = request.method != "HEAD" && code >= 200 && code != 204 && code != 304;
+        let mut headers: Vec<_> = headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        if has_body {
+            headers.push(("Transfer-Encoding", "chunked"));
+        }
+        response::write_head(tcp_stream, status, &headers)?;
+        let body = has_body.then(|| ChunkedWriter::new(tcp_stream, Trailers::default()));
+        Ok(Response { body })
+    }
+
+    fn write(&mut self, content: &[u8]) -> io::Result<()> {
+        match &mut self.body {
+            Some(body) => body.write_all(content),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        if let Some(body) = self.body {
+            response::count_body(body.finish()?);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert!(matches!(parse("127.0.0.1:9000"), Ok(Address::Tcp(_))));
+    assert!(matches!(parse("[::1]:9000"), Ok(Address::Tcp(_))));
+    #[cfg(unix)]
+    assert!(matches!(
+        parse("unix:/run/php/php-fpm.sock"),
+        Ok(Address::Unix(_))
+    ));
+    assert!(parse("unix:").is_err());
+    assert!(parse("127.0.0.1").is_err());
+    assert!(parse(":9000").is_err());
+}
+
+#[test]
+fn test_records() {
+    let mut encoded = Vec::new();
+    encode_param(&mut encoded, "A", "b");
+    encode_param(&mut encoded, "LONG", &"x".repeat(200));
+    assert_eq!(&encoded[..4], [1, 1, b'A', b'b']);
+    assert_eq!(&encoded[4..9], [4, 0x80, 0, 0, 200]);
+
+    let mut written = Vec::new();
+    write_record(&mut written, STDOUT, b"hello").unwrap();
+    // with padding, which readers skip
+    written.extend([1, STDERR, 0, 1, 0, 2, 3, 0, b'n', b'o', 0, 0, 0]);
+    let mut reader = &written[..];
+    assert_eq!(
+        read_record(&mut reader).unwrap(),
+        (STDOUT, b"hello".to_vec())
+    );
+    assert_eq!(read_record(&mut reader).unwrap(), (STDERR, b"no".to_vec()));
+    assert!(read_record(&mut reader).is_err());
+}
+
+#[test]
+fn test_parse_head() {
+    let output = b"Content-Type: text/html\r\nStatus: 404 Not Found\r\n\r\n<p>gone";
+    let head = parse_head(output).unwrap();
+    assert_eq!(head.status, StatusCode::NotFound);
+    assert_eq!(
+        head.headers,
+        [("Content-Type".to_owned(), "text/html".to_owned())]
+    );
+    assert_eq!(&output[head.body..], b"<p>gone");
+
+    let output = b"Location: /login\nContent-Length: 0\n\n";
+    let head = parse_head(output).unwrap();
+    assert_eq!(head.status, StatusCode::Found);
+    assert_eq!(head.headers.len(), 1);
+    assert_eq!(head.body, output.len());
+
+    assert!(parse_head(b"Content-Type: text/html\r\n").is_none());
+}
@@ -0,0 +1,162 @@
+// Response filters, which may rewrite the files of some types before they're
+// sent, e.g. to add an analytics snippet to pages, or refuse them: programs
+// embedding the server add theirs with Config::add_filter, and --filter runs
+// a command with the file on its standard input and what to send instead on
+// its standard output. Files which go through a filter are read whole, and
+// sent as gzip or minified as the others are, but never precompressed.
+
+use crate::{hook, root};
+use std::{
+    io::{self, Read, Write},
+    process::Stdio,
+    thread,
+    time::{Duration, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub trait Filter: Send + Sync {
+    // Whether files of `mime_type`, e.g. "text/html; charset=utf-8", go
+    // through this filter
+    fn applies(&self, mime_type: &str) -> bool;
+
+    // What to send instead of `body`, the content of `path` (normalized).
+    // Errors are answered with a 500.
+    fn filter(&self, path: &str, mime_type: &str, body: Vec<u8>) -> io::Result<Vec<u8>>;
+
+    // For the settings logged at startup
+    fn describe(&self) -> String {
+        "added by the program".to_owned()
+    }
+}
+
+// --filter, e.g. "text/html=sed s/x/y/"
+pub struct Command {
+    // a media type without parameters, or type/*
+    mime_type: String,
+    command: String,
+}
+
+impl Command {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.split_once('=') {
+            Some((mime_type, command)) if mime_type.contains('/') && !command.is_empty() => {
+                Ok(Command {
+                    mime_type: mime_type.trim().to_ascii_lowercase(),
+                    command: command.to_owned(),
+                })
+            }
+            _ => Err(
+                "'--filter' value must be 'type=command', e.g. 'text/html=./inject.sh'".to_owned(),
+            ),
+        }
+    }
+}
+
+impl Filter for Command {
+    fn describe(&self) -> String {
+        format!("{} {}", self.mime_type, self.command)
+    }
+
+    fn applies(&self, mime_type: &str) -> bool {
+        let essence = mime_type.split(';').next().unwrap_or_default().trim();
+        match self.mime_type.strip_suffix("/*") {
+            Some(top) => essence.split('/').next() == Some(top),
+            None => essence.eq_ignore_ascii_case(&self.mime_type),
+        }
+    }
+
+    fn filter(&self, path: &str, mime_type: &str, body: Vec<u8>) -> io::Result<Vec<u8>> {
+        // in the release the request is answered from, see release::enter
+        let mut child = hook::shell(&self.command)
+            .current_dir(root::served("."))
+            .env("FILTER_PATH", format!("/{path}"))
+            .env("FILTER_TYPE", mime_type)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        // written while the output is read, as neither may fit in a pipe
+        let mut stdin = child.stdin.take();
+        let writer = thread::spawn(move || {
+            if let Some(stdin) = &mut stdin {
+                // commands needn't read all of it
+                let _ = stdin.write_all(&body);
+            }
+        });
+        let mut stdout = child.stdout.take();
+        let reader = thread::spawn(move || {
+            let mut output = Vec::new();
+            stdout
+                .as_mut()
+                .map_or(Ok(0), |stdout| stdout.read_to_end(&mut output))
+                .map(|_| output)
+        });
+
+        let deadline = Instant::now() + TIMEOUT;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("filter '{}' took longer than {TIMEOUT:?}", self.command),
+                ));
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let _ = writer.join();
+        let output = reader
+            .join()
+            .map_err(|_| io::Error::other("reading the filter's output failed"))??;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "filter '{}' failed: {status}",
+                self.command
+            )));
+        }
+        Ok(output)
+    }
+}
+
+// `body` through the filters which apply to `mime_type`, in order
+pub fn apply(
+    filters: &[Box<dyn Filter>],
+    path: &str,
+    mime_type: &str,
+    mut body: Vec<u8>,
+) -> io::Result<Vec<u8>> {
+    for filter in filters.iter().filter(|filter| filter.applies(mime_type)) {
+        body = filter.filter(path, mime_type, body)?;
+    }
+    Ok(body)
+}
+
+#[test]
+fn test_command() {
+    let filter =
+        Command::parse("text/html=sed 's|</body>|<script src=/a.js></script></body>|'").unwrap();
+    assert!(filter.applies("text/html; charset=utf-8"));
+    assert!(!filter.applies("text/css"));
+    assert!(Command::parse("text/*=cat").unwrap().applies("text/css"));
+    assert!(Command::parse("html=cat").is_err());
+    assert!(Command::parse("text/html=").is_err());
+
+    let filters: Vec<Box<dyn Filter>> = vec![Box::new(filter)];
+    let page = apply(&filters, "a.html", "text/html", b"<body></body>".to_vec()).unwrap();
+    assert_eq!(page, b"<body><script src=/a.js></script></body>");
+    let css = apply(&filters, "a.css", "text/css", b"a{}".to_vec()).unwrap();
+    assert_eq!(css, b"a{}");
+
+    let failing: Vec<Box<dyn Filter>> = vec![Box::new(Command::parse("text/*=exit 1").unwrap())];
+    assert!(apply(&failing, "a.txt", "text/plain", Vec::new()).is_err());
+    let env: Vec<Box<dyn Filter>> = vec![Box::new(
+        Command::parse("text/*=echo $FILTER_PATH").unwrap(),
+    )];
+    assert_eq!(
+        apply(&env, "docs/a.txt", "text/plain", Vec::new()).unwrap(),
+        b"/docs/a.txt\n"
+    );
+}
@@ -0,0 +1,400 @@
+// --geoip: the country and autonomous system of clients, from MaxMind DB
+// files (GeoLite2-Country, GeoLite2-ASN, DB-IP Lite and the like), for the
+// log, metrics and --geo-allow/--geo-deny. Only the parts of the format such
+// lookups need are read: the search tree and the data section it points to.
+
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    net::IpAddr,
+};
+
+// at the end of the file, before the metadata
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+const METADATA_MAX_SIZE: usize = 128 * 1024;
+// between the search tree and the data section
+const DATA_SEPARATOR: usize = 16;
+// records nest a few levels at most, this keeps the stack safe
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Double(f64),
+    Bytes(Vec<u8>),
+    Uint(u128),
+    Int(i32),
+    Map(Vec<(String, Value)>),
+    Array(Vec<Value>),
+    Bool(bool),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+pub struct Database {
+    pub path: String,
+    bytes: Vec<u8>,
+    node_count: usize,
+    // bits per record, two records per node
+    record_size: usize,
+    ip_version: u16,
+    // where the data section starts
+    data: usize,
+}
+
+impl Database {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let invalid = |what: &str| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("'{path}' is not a MaxMind DB: {what}"),
+            )
+        };
+        let bytes = fs::read(path)?;
+        let tail = bytes.len().saturating_sub(METADATA_MAX_SIZE);
+        let Some(marker) = bytes[tail..]
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+        else {
+            return Err(invalid("no metadata"));
+        };
+        let start = tail + marker + METADATA_MARKER.len();
+        let metadata = Decoder(&bytes[start..])
+            .value(0, 0)
+            .map_err(|_| invalid("bad metadata"))?
+            .0;
+        let number = |key: &str| match metadata.get(key) {
+            Some(Value::Uint(number)) => usize::try_from(*number).ok(),
+            _ => None,
+        };
+        let (Some(node_count), Some(record_size), Some(ip_version)) = (
+            number("node_count"),
+            number("record_size"),
+            number("ip_version"),
+        ) else {
+            return Err(invalid("incomplete metadata"));
+        };
+        if ![24, 28, 32].contains(&record_size) || ![4, 6].contains(&ip_version) {
+            return Err(invalid("unsupported record size or IP version"));
+        }
+        let data = node_count
+            .checked_mul(record_size / 4)
+            .and_then(|tree| tree.checked_add(DATA_SEPARATOR))
+            .filter(|data| *data <= start)
+            .ok_or_else(|| invalid("search tree past the end"))?;
+        Ok(Database {
+            path: path.to_owned(),
+            bytes,
+            node_count,
+            record_size,
+            ip_version: ip_version as u16,
+            data,
+        })
+    }
+
+    // The record of `ip`, None when the database doesn't know it
+    pub fn lookup(&self, ip: IpAddr) -> Option<Value> {
+        let ip = match ip {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+            ip => ip,
+        };
+        let bits = match (ip, self.ip_version) {
+            (IpAddr::V4(ip), 4) => {
+                let mut bits = [0; 16];
+                bits[..4].copy_from_slice(&ip.octets());
+                bits
+            }
+            // IPv4 addresses are below ::/96 in IPv6 databases
+            (IpAddr::V4(ip), _) => ip.to_ipv6_compatible().octets(),
+            (IpAddr::V6(ip), 6) => ip.octets(),
+            (IpAddr::V6(_), _) => return None,
+        };
+        let length = if self.ip_version == 4 { 32 } else { 128 };
+        let mut node = 0;
+        for index in 0..length {
+            if node >= self.node_count {
+                break;
+            }
+            let bit = bits[index / 8] >> (7 - index % 8) & 1;
+            node = self.record(node, bit)?;
+        }
+        // relative to the data section
+        let offset = node.checked_sub(self.node_count + DATA_SEPARATOR)?;
+        Decoder(&self.bytes[self.data..])
+            .value(offset, 0)
+            .ok()
+            .map(|(value, _)| value)
+    }
+
+    // The left (0) or right (1) record of `node`
+    fn record(&self, node: usize, bit: u8) -> Option<usize> {
+        let size = self.record_size / 4;
+        let bytes = self.bytes.get(node * size..(node + 1) * size)?;
+        let be = |bytes: &[u8]| bytes.iter().fold(0, |acc, byte| acc << 8 | *byte as usize);
+        Some(match (self.record_size, bit) {
+            (24, 0) => be(&bytes[..3]),
+            (24, _) => be(&bytes[3..]),
+            (28, 0) => (bytes[3] as usize & 0xf0) << 20 | be(&bytes[..3]),
+            (28, _) => (bytes[3] as usize & 0x0f) << 24 | be(&bytes[4..]),
+            (_, 0) => be(&bytes[..4]),
+            _ => be(&bytes[4..]),
+        })
+    }
+}
+
+// Over the data section, or the metadata, which pointers are relative to
+struct Decoder<'a>(&'a [u8]);
+
+impl Decoder<'_> {
+    fn byte(&self, offset: usize) -> Result<usize, ()> {
+        self.0.get(offset).map(|byte| *byte as usize).ok_or(())
+    }
+
+    fn slice(&self, offset: usize, length: usize) -> Result<&[u8], ()> {
+        self.0.get(offset..offset + length).ok_or(())
+    }
+
+    // The value at `offset`, and the offset after it
+    fn value(&self, mut offset: usize, depth: usize) -> Result<(Value, usize), ()> {
+        if depth > MAX_DEPTH {
+            return Err(());
+        }
+        let control = self.byte(offset)?;
+        offset += 1;
+        let mut kind = control >> 5;
+        if kind == 1 {
+            // a pointer, to a value elsewhere
+            let vvv = control & 0x7;
+            let (pointer, length) = match (control >> 3) & 0x3 {
+                0 => (vvv << 8 | self.byte(offset)?, 1),
+                1 => ((vvv << 16 | self.be(offset, 2)?) + 2048, 2),
+                2 => ((vvv << 24 | self.be(offset, 3)?) + 526336, 3),
+                _ => (self.be(offset, 4)?, 4),
+            };
+            let (value, _) = self.value(pointer, depth + 1)?;
+            return Ok((value, offset + length));
+        }
+        if kind == 0 {
+            kind = 7 + self.byte(offset)?;
+            offset += 1;
+        }
+        let mut size = control & 0x1f;
+        match size {
+            29 => {
+                size = 29 + self.byte(offset)?;
+                offset += 1;
+            }
+            30 => {
+                size = 285 + self.be(offset, 2)?;
+                offset += 2;
+            }
+            31 => {
+                size = 65821 + self.be(offset, 3)?;
+                offset += 3;
+            }
+            _ => (),
+        }
+        let value = match kind {
+            2 => {
+                let string = std::str::from_utf8(self.slice(offset, size)?).map_err(|_| ())?;
+                offset += size;
+                Value::String(string.to_owned())
+            }
+            3 => {
+                let bytes = self.slice(offset, 8)?;
+                offset += 8;
+                Value::Double(f64::from_be_bytes(bytes.try_into().map_err(|_| ())?))
+            }
+            4 => {
+                let bytes = self.slice(offset, size)?.to_vec();
+                offset += size;
+                Value::Bytes(bytes)
+            }
+            5 | 6 | 9 | 10 => {
+                let bytes = self.slice(offset, size)?;
+                offset += size;
+                if size > 16 {
+                    return Err(());
+                }
+                Value::Uint(
+                    bytes
+                        .iter()
+                        .fold(0, |acc, byte| acc << 8 | u128::from(*byte)),
+                )
+            }
+            7 => {
+                let mut entries = Vec::with_capacity(size.min(64));
+                for _ in 0..size {
+                    let (key, next) = self.value(offset, depth + 1)?;
+                    let Value::String(key) = key else {
+                        return Err(());
+                    };
+                    let (value, next) = self.value(next, depth + 1)?;
+                    entries.push((key, value));
+                    offset = next;
+                }
+                Value::Map(entries)
+            }
+            8 => {
+                let bytes = self.slice(offset, size.min(4))?;
+                offset += size;
+                let number = bytes
+                    .iter()
+                    .fold(0u32, |acc, byte| acc << 8 | u32::from(*byte));
+                Value::Int(number as i32)
+            }
+            11 => {
+                let mut values = Vec::with_capacity(size.min(64));
+                for _ in 0..size {
+                    let (value, next) = self.value(offset, depth + 1)?;
+                    values.push(value);
+                    offset = next;
+                }
+                Value::Array(values)
+            }
+            14 => Value::Bool(size != 0),
+            15 => {
+                let bytes = self.slice(offset, 4)?;
+                offset += 4;
+                Value::Double(f32::from_be_bytes(bytes.try_into().map_err(|_| ())?).into())
+            }
+            _ => return Err(()),
+        };
+        Ok((value, offset))
+    }
+
+    fn be(&self, offset: usize, length: usize) -> Result<usize, ()> {
+        Ok(self
+            .slice(offset, length)?
+            .iter()
+            .fold(0, |acc, byte| acc << 8 | *byte as usize))
+    }
+}
+
+// What the databases say about a client
+#[derive(Debug, Default, PartialEq)]
+pub struct Tags {
+    // ISO 3166-1 alpha-2, e.g. FR
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+}
+
+impl Tags {
+    // e.g. "FR AS3215", None when nothing is known
+    pub fn describe(&self) -> Option<String> {
+        let asn = self.asn.map(|asn| format!("AS{asn}"));
+        let parts: Vec<_> = self.country.iter().cloned().chain(asn).collect();
+        (!parts.is_empty()).then(|| parts.join(" "))
+    }
+
+    // Whether a --geo-allow or --geo-deny rule, a country code or ASnnn,
+    // is about this client
+    pub fn matches(&self, rule: &str) -> bool {
+        match rule.strip_prefix("AS").map(str::parse::<u32>) {
+            Some(Ok(asn)) => self.asn == Some(asn),
+            _ => self
+                .country
+                .as_ref()
+                .is_some_and(|country| country.eq_ignore_ascii_case(rule)),
+        }
+    }
+}
+
+// The first database which knows says
+pub fn tags(databases: &[Database], ip: IpAddr) -> Tags {
+    let mut res = Tags::default();
+    for database in databases {
+        let Some(record) = database.lookup(ip) else {
+            continue;
+        };
+        let iso_code = |key: &str| match record.get(key)?.get("iso_code")? {
+            Value::String(code) => Some(code.clone()),
+            _ => None,
+        };
+        if res.country.is_none() {
+            // where the network is registered, for e.g. anonymous proxies
+            res.country = iso_code("country").or_else(|| iso_code("registered_country"));
+        }
+        if res.asn.is_none()
+            && let Some(Value::Uint(asn)) = record.get("autonomous_system_number")
+        {
+            res.asn = u32::try_from(*asn).ok();
+        }
+    }
+    res
+}
+
+#[test]
+fn test_lookup() {
+    fn string(value: &str) -> Vec<u8> {
+        let mut res = vec![2 << 5 | value.len() as u8];
+        res.extend_from_slice(value.as_bytes());
+        res
+    }
+    fn uint(kind: u8, value: u32) -> Vec<u8> {
+        let mut res = vec![kind << 5 | 4];
+        res.extend_from_slice(&value.to_be_bytes());
+        res
+    }
+    fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut res = vec![7 << 5 | entries.len() as u8];
+        for (key, value) in entries {
+            res.extend(string(key));
+            res.extend_from_slice(value);
+        }
+        res
+    }
+
+    // one node: 0.0.0.0/1 has the record, 128.0.0.0/1 nothing
+    let node_count = 1u32;
+    let mut bytes = Vec::new();
+    let record = (node_count + DATA_SEPARATOR as u32).to_be_bytes();
+    bytes.extend_from_slice(&record[1..]);
+    bytes.extend_from_slice(&node_count.to_be_bytes()[1..]);
+    bytes.extend_from_slice(&[0; DATA_SEPARATOR]);
+    // the ASN first, then a pointer to it in place of a second copy
+    bytes.extend(uint(6, 3215));
+    let country = map(&[("iso_code", string("FR"))]);
+    bytes.extend(map(&[
+        ("country", country),
+        ("autonomous_system_number", vec![1 << 5, 0]),
+    ]));
+    // the record is after the ASN
+    let record = (node_count as usize + DATA_SEPARATOR + 5).to_be_bytes();
+    bytes[..3].copy_from_slice(&record[record.len() - 3..]);
+    bytes.extend_from_slice(METADATA_MARKER);
+    bytes.extend(map(&[
+        ("node_count", uint(6, node_count)),
+        ("record_size", uint(5, 24)),
+        ("ip_version", uint(5, 4)),
+    ]));
+
+    let path = std::env::temp_dir().join(format!("geoip-test-{}.mmdb", std::process::id()));
+    fs::write(&path, &bytes).unwrap();
+    let database = Database::open(&path.display().to_string()).unwrap();
+    fs::remove_file(&path).unwrap();
+    let databases = [database];
+
+    let known = tags(&databases, "1.2.3.4".parse().unwrap());
+    assert_eq!(known.country.as_deref(), Some("FR"));
+    assert_eq!(known.asn, Some(3215));
+    assert_eq!(known.describe().unwrap(), "FR AS3215");
+    assert!(known.matches("fr") && known.matches("AS3215") && !known.matches("AS1"));
+    let mapped = tags(&databases, "::ffff:1.2.3.4".parse().unwrap());
+    assert_eq!(mapped, known);
+    assert_eq!(
+        tags(&databases, "200.0.0.1".parse().unwrap()),
+        Tags::default()
+    );
+    assert_eq!(Tags::default().describe(), None);
+}
@@ -0,0 +1,561 @@
+// --git-ref: the files of a commit rather than those of the working
+// directory, read from the objects of the repository, loose or packed, the
+// way `git archive` does. The ref is resolved for each request, so new
+// commits show at once. Only SHA-1 repositories with files refs (the
+// default) are supported.
+
+use crate::{crypto, inflate};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+pub type Id = [u8; 20];
+
+// bigger objects aren't served
+const MAX_OBJECT_SIZE: usize = 256 * 1024 * 1024;
+// git itself stops at 50
+const MAX_DELTA_DEPTH: usize = 100;
+const MAX_SYMREF_DEPTH: usize = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+pub struct TreeEntry {
+    pub name: String,
+    // as octal in trees: 100644, 100755, 120000 (symbolic link),
+    // 40000 (directory), 160000 (submodule)
+    pub mode: u32,
+    pub id: Id,
+}
+
+impl TreeEntry {
+    pub fn is_dir(&self) -> bool {
+        self.mode == 0o40000
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & 0o170000 == 0o100000
+    }
+}
+
+// A pack file and its index
+struct Pack {
+    path: PathBuf,
+    // sorted, as in the index
+    ids: Vec<Id>,
+    offsets: Vec<u64>,
+    // every offset and the end of the last object, sorted, for where each
+    // object ends
+    bounds: Vec<u64>,
+}
+
+pub struct Repository {
+    // .git, or where a .git file points to
+    git_dir: PathBuf,
+    // where the objects and most refs are, the main .git of a worktree
+    common_dir: PathBuf,
+    packs: Mutex<Vec<Arc<Pack>>>,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("git: {}", message.into()))
+}
+
+fn not_found(what: &str) -> io::Error {
+    io::Error::new(ErrorKind::NotFound, format!("git: {what} not found"))
+}
+
+impl Repository {
+    pub fn open(work_tree: &Path) -> io::Result<Self> {
+        let dot_git = work_tree.join(".git");
+        let git_dir = if dot_git.is_file() {
+            let text = fs::read_to_string(&dot_git)?;
+            let target = text
+                .trim()
+                .strip_prefix("gitdir: ")
+                .ok_or_else(|| invalid(".git is neither a directory nor a gitdir file"))?;
+            work_tree.join(target)
+        } else {
+            dot_git
+        };
+        let git_dir = fs::canonicalize(git_dir)?;
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common) => fs::canonicalize(git_dir.join(common.trim()))?,
+            Err(_) => git_dir.clone(),
+        };
+        if !common_dir.join("objects").is_dir() {
+            return Err(invalid(format!("{} has no objects", common_dir.display())));
+        }
+        Ok(Repository {
+            git_dir,
+            common_dir,
+            packs: Mutex::default(),
+        })
+    }
+
+    // The commit `revision` (a ref, e.g. HEAD, main, v1.0 or
+    // refs/remotes/origin/main, or a full commit id) points to
+    pub fn resolve(&self, revision: &str) -> io::Result<Id> {
+        let mut id = match parse_id(revision) {
+            Some(id) => id,
+            None => self.resolve_ref(revision, 0)?,
+        };
+        // annotated tags point to what they tag
+        loop {
+            let (kind, data) = self.read(&id)?;
+            match kind {
+                Kind::Commit => return Ok(id),
+                Kind::Tag => id = header_id(&data, "object")?,
+                _ => return Err(invalid(format!("{revision} is not a commit"))),
+            }
+        }
+    }
+
+    // The same rules as git, see gitrevisions(7)
+    fn resolve_ref(&self, name: &str, depth: usize) -> io::Result<Id> {
+        if depth > MAX_SYMREF_DEPTH
+            || name.is_empty()
+            || name
+                .split('/')
+                .any(|part| part.is_empty() || part.starts_with('.'))
+        {
+            return Err(not_found(&format!("ref {name}")));
+        }
+        let candidates = [
+            name.to_owned(),
+            format!("refs/{name}"),
+            format!("refs/tags/{name}"),
+            format!("refs/heads/{name}"),
+            format!("refs/remotes/{name}"),
+            format!("refs/remotes/{name}/HEAD"),
+        ];
+        let packed = fs::read_to_string(self.common_dir.join("packed-refs")).unwrap_or_default();
+        for candidate in candidates {
+            // HEAD and the like are per worktree
+            for dir in [&self.git_dir, &self.common_dir] {
+                if let Ok(text) = fs::read_to_string(dir.join(&candidate)) {
+                    let text = text.trim();
+                    if let Some(target) = text.strip_prefix("ref: ") {
+                        return self.resolve_ref(target, depth + 1);
+                    }
+                    return parse_id(text)
+                        .ok_or_else(|| invalid(format!("broken ref {candidate}")));
+                }
+            }
+            let found = packed
+                .lines()
+                .filter_map(|line| line.split_once(' '))
+                .find(|(_, name)| *name == candidate);
+            if let Some((id, _)) = found {
+                return parse_id(id).ok_or_else(|| invalid(format!("broken ref {candidate}")));
+            }
+        }
+        Err(not_found(&format!("ref {name}")))
+    }
+
+    pub fn read(&self, id: &Id) -> io::Result<(Kind, Vec<u8>)> {
+        self.read_object(id, 0)
+    }
+
+    fn read_object(&self, id: &Id, depth: usize) -> io::Result<(Kind, Vec<u8>)> {
+        let hex = crypto::hex(id);
+        let loose = self
+            .common_dir
+            .join("objects")
+            .join(&hex[..2])
+            .join(&hex[2..]);
+        match fs::read(loose) {
+            Ok(compressed) => return parse_loose(&compressed),
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+            Err(_) => (),
+        }
+        // packs come and go with gc and fetches, a miss reloads them
+        for reload in [false, true] {
+            if reload {
+                self.load_packs()?;
+            }
+            let packs = self
+                .packs
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .clone();
+            for pack in packs {
+                if let Ok(idx) = pack.ids.binary_search(id) {
+                    match self.read_packed(&pack, pack.offsets[idx], depth) {
+                        Err(err) if err.kind() == ErrorKind::NotFound && !reload => break,
+                        res => return res,
+                    }
+                }
+            }
+        }
+        Err(not_found(&format!("object {hex}")))
+    }
+
+    fn load_packs(&self) -> io::Result<()> {
+        let directory = self.common_dir.join("objects/pack");
+        let mut packs = self.packs.lock().unwrap_or_else(|err| err.into_inner());
+        let mut loaded = Vec::new();
+        for entry in fs::read_dir(&directory).into_iter().flatten().flatten() {
+            let path = entry.path().with_extension("pack");
+            if entry
+                .path()
+                .extension()
+                .is_none_or(|extension| extension != "idx")
+            {
+                continue;
+            }
+            match packs.iter().find(|pack| pack.path == path) {
+                Some(pack) => loaded.push(Arc::clone(pack)),
+                None => loaded.push(Arc::new(load_pack(&entry.path(), path)?)),
+            }
+        }
+        *packs = loaded;
+        Ok(())
+    }
+
+    fn read_packed(&self, pack: &Pack, offset: u64, depth: usize) -> io::Result<(Kind, Vec<u8>)> {
+        if depth > MAX_DELTA_DEPTH {
+            return Err(invalid("delta chain too long"));
+        }
+        let end = pack
+            .bounds
+            .iter()
+            .find(|bound| **bound > offset)
+            .ok_or_else(|| invalid("object past the end of the pack"))?;
+        let length = usize::try_from(end - offset)
+            .ok()
+            .filter(|length| *length <= MAX_OBJECT_SIZE)
+            .ok_or_else(|| invalid("object too large"))?;
+        let mut buffer = vec![0; length];
+        let mut file = File::open(&pack.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buffer)?;
+
+        // the type and size, then the size goes on 7 bits at a time
+        let mut pos = 0;
+        let mut byte = next_byte(&buffer, &mut pos)?;
+        let kind = (byte >> 4) & 7;
+        let mut size = u64::from(byte & 15);
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            byte = next_byte(&buffer, &mut pos)?;
+            size |= u64::from(byte & 0x7f)
+                .checked_shl(shift)
+                .unwrap_or(u64::MAX);
+            shift += 7;
+        }
+        let size = usize::try_from(size)
+            .ok()
+            .filter(|size| *size <= MAX_OBJECT_SIZE)
+            .ok_or_else(|| invalid("object too large"))?;
+
+        let base = match kind {
+            1 => return Ok((Kind::Commit, zlib(&buffer[pos..], size)?)),
+            2 => return Ok((Kind::Tree, zlib(&buffer[pos..], size)?)),
+            3 => return Ok((Kind::Blob, zlib(&buffer[pos..], size)?)),
+            4 => return Ok((Kind::Tag, zlib(&buffer[pos..], size)?)),
+            // a delta against the object `distance` bytes before
+            6 => {
+                byte = next_byte(&buffer, &mut pos)?;
+                let mut distance = u64::from(byte & 0x7f);
+                while byte & 0x80 != 0 {
+                    byte = next_byte(&buffer, &mut pos)?;
+                    distance = distance
+                        .checked_add(1)
+                        .and_then(|distance| distance.checked_mul(128))
+                        .ok_or_else(|| invalid("bad delta offset"))?
+                        | u64::from(byte & 0x7f);
+                }
+                let base_offset = offset
+                    .checked_sub(distance)
+                    .ok_or_else(|| invalid("bad delta offset"))?;
+                self.read_packed(pack, base_offset, depth + 1)?
+            }
+            // a delta against the object of that id
+            7 => {
+                let id: Id = buffer
+                    .get(pos..pos + 20)
+                    .and_then(|id| id.try_into().ok())
+                    .ok_or_else(|| invalid("truncated pack"))?;
+                pos += 20;
+                self.read_object(&id, depth + 1)?
+            }
+            _ => return Err(invalid("unknown object type")),
+        };
+        let delta = zlib(&buffer[pos..], size)?;
+        Ok((base.0, apply_delta(&base.1, &delta)?))
+    }
+
+    // The entries of the tree `id`, skipping names which aren't UTF-8
+    pub fn tree(&self, id: &Id) -> io::Result<Vec<TreeEntry>> {
+        let (kind, data) = self.read(id)?;
+        if kind != Kind::Tree {
+            return Err(invalid("not a tree"));
+        }
+        parse_tree(&data)
+    }
+
+    // What `path` (normalized, "." for the top) is in the commit, None when
+    // it isn't there
+    pub fn lookup(&self, commit: &Id, path: &str) -> io::Result<Option<TreeEntry>> {
+        let (_, data) = self.read(commit)?;
+        let mut entry = TreeEntry {
+            name: String::new(),
+            mode: 0o40000,
+            id: header_id(&data, "tree")?,
+        };
+        for name in path.split('/').filter(|name| *name != ".") {
+            if !entry.is_dir() {
+                return Ok(None);
+            }
+            match self
+                .tree(&entry.id)?
+                .into_iter()
+                .find(|child| child.name == name)
+            {
+                Some(child) => entry = child,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(entry))
+    }
+}
+
+fn parse_id(hex: &str) -> Option<Id> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut id = [0; 20];
+    for (idx, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(idx * 2..idx * 2 + 2)?, 16).ok()?;
+    }
+    Some(id)
+}
+
+// The id of a "tree <hex>" or "object <hex>" line of a commit or tag
+fn header_id(data: &[u8], name: &str) -> io::Result<Id> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| {
+            line.strip_prefix(name)?
+                .strip_prefix(' ')
+                .and_then(parse_id)
+        })
+        .ok_or_else(|| invalid(format!("no {name} line")))
+}
+
+fn next_byte(buffer: &[u8], pos: &mut usize) -> io::Result<u8> {
+    let byte = *buffer.get(*pos).ok_or_else(|| invalid("truncated pack"))?;
+    *pos += 1;
+    Ok(byte)
+}
+
+// The zlib format (RFC 1950) around DEFLATE, without checking the checksum
+fn zlib(input: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+    match input {
+        [method, flags, rest @ ..]
+            if method & 0x0f == 8 && (u16::from(*method) << 8 | u16::from(*flags)) % 31 == 0 =>
+        {
+            inflate::inflate(rest, max_size)
+        }
+        _ => Err(invalid("not zlib data")),
+    }
+}
+
+// "<type> <size>\0" and the contents, compressed
+fn parse_loose(compressed: &[u8]) -> io::Result<(Kind, Vec<u8>)> {
+    let data = zlib(compressed, MAX_OBJECT_SIZE + 64)?;
+    let nul = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or_else(|| invalid("broken loose object"))?;
+    let header = String::from_utf8_lossy(&data[..nul]);
+    let kind = match header.split(' ').next() {
+        Some("commit") => Kind::Commit,
+        Some("tree") => Kind::Tree,
+        Some("blob") => Kind::Blob,
+        Some("tag") => Kind::Tag,
+        _ => return Err(invalid("unknown object type")),
+    };
+    Ok((kind, data[nul + 1..].to_vec()))
+}
+
+// "<octal mode> <name>\0" and 20 bytes of id, for each entry
+fn parse_tree(mut data: &[u8]) -> io::Result<Vec<TreeEntry>> {
+    let mut res = Vec::new();
+    while !data.is_empty() {
+        let nul = data
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| invalid("broken tree"))?;
+        let (mode, name) = data[..nul]
+            .iter()
+            .position(|byte| *byte == b' ')
+            .map(|space| (&data[..space], &data[space + 1..nul]))
+            .ok_or_else(|| invalid("broken tree"))?;
+        let id: Id = data
+            .get(nul + 1..nul + 21)
+            .and_then(|id| id.try_into().ok())
+            .ok_or_else(|| invalid("broken tree"))?;
+        let mode = std::str::from_utf8(mode)
+            .ok()
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+            .ok_or_else(|| invalid("broken tree"))?;
+        if let Ok(name) = String::from_utf8(name.to_vec()) {
+            res.push(TreeEntry { name, mode, id });
+        }
+        data = &data[nul + 21..];
+    }
+    Ok(res)
+}
+
+// Version 2 pack indexes: a fan-out table, the sorted ids, their CRCs, their
+// offsets, and the offsets past 2 GiB
+fn load_pack(idx_path: &Path, path: PathBuf) -> io::Result<Pack> {
+    let idx = fs::read(idx_path)?;
+    let u32_at = |pos: usize| -> io::Result<u32> {
+        idx.get(pos..pos + 4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .ok_or_else(|| invalid("truncated pack index"))
+    };
+    if idx.get(..4) != Some(b"\xfftOc") || u32_at(4)? != 2 {
+        return Err(invalid("unsupported pack index"));
+    }
+    let count = u32_at(8 + 255 * 4)? as usize;
+    let ids_at = 8 + 256 * 4;
+    let offsets_at = ids_at + count * 24;
+    let large_at = offsets_at + count * 4;
+    let mut ids = Vec::with_capacity(count);
+    let mut offsets = Vec::with_capacity(count);
+    for idx_pos in 0..count {
+        let id: Id = idx
+            .get(ids_at + idx_pos * 20..ids_at + idx_pos * 20 + 20)
+            .and_then(|id| id.try_into().ok())
+            .ok_or_else(|| invalid("truncated pack index"))?;
+        ids.push(id);
+        let offset = u32_at(offsets_at + idx_pos * 4)?;
+        let offset = if offset & 0x8000_0000 == 0 {
+            u64::from(offset)
+        } else {
+            let pos = large_at + (offset & 0x7fff_ffff) as usize * 8;
+            u64::from(u32_at(pos)?) << 32 | u64::from(u32_at(pos + 4)?)
+        };
+        offsets.push(offset);
+    }
+    // the pack ends with a 20 bytes checksum
+    let end = fs::metadata(&path)?.len().saturating_sub(20);
+    let mut bounds = offsets.clone();
+    bounds.push(end);
+    bounds.sort_unstable();
+    Ok(Pack {
+        path,
+        ids,
+        offsets,
+        bounds,
+    })
+}
+
+// A delta: the sizes of the base and of the result, then instructions
+// copying ranges of the base or inserting new bytes
+fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let mut pos = 0;
+    let mut size = || -> io::Result<usize> {
+        let mut res = 0_usize;
+        let mut shift = 0;
+        loop {
+            let byte = next_byte(delta, &mut pos)?;
+            res |= usize::from(byte & 0x7f)
+                .checked_shl(shift)
+                .ok_or_else(|| invalid("bad delta"))?;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(res);
+            }
+        }
+    };
+    let (base_size, result_size) = (size()?, size()?);
+    if base_size != base.len() || result_size > MAX_OBJECT_SIZE {
+        return Err(invalid("bad delta"));
+    }
+    let mut res = Vec::with_capacity(result_size);
+    while pos < delta.len() {
+        let op = next_byte(delta, &mut pos)?;
+        if op & 0x80 != 0 {
+            let mut offset = 0_usize;
+            let mut length = 0_usize;
+            for bit in 0..4 {
+                if op & (1 << bit) != 0 {
+                    offset |= usize::from(next_byte(delta, &mut pos)?) << (8 * bit);
+                }
+            }
+            for bit in 0..3 {
+                if op & (0x10 << bit) != 0 {
+                    length |= usize::from(next_byte(delta, &mut pos)?) << (8 * bit);
+                }
+            }
+            if length == 0 {
+                length = 0x10000;
+            }
+            let copied = offset
+                .checked_add(length)
+                .and_then(|end| base.get(offset..end))
+                .ok_or_else(|| invalid("bad delta"))?;
+            res.extend_from_slice(copied);
+        } else if op != 0 {
+            let inserted = delta
+                .get(pos..pos + usize::from(op))
+                .ok_or_else(|| invalid("bad delta"))?;
+            res.extend_from_slice(inserted);
+            pos += usize::from(op);
+        } else {
+            return Err(invalid("bad delta"));
+        }
+        if res.len() > result_size {
+            return Err(invalid("bad delta"));
+        }
+    }
+    if res.len() != result_size {
+        return Err(invalid("bad delta"));
+    }
+    Ok(res)
+}
+
+#[test]
+fn test_apply_delta() {
+    // sizes 10 and 9, copy 4 bytes from 6, insert "--", copy 3 bytes from 0
+    let delta = [10, 9, 0x91, 6, 4, 2, b'-', b'-', 0x90, 3];
+    assert_eq!(apply_delta(b"0123456789", &delta).unwrap(), b"6789--012");
+    assert!(apply_delta(b"012345678", &delta).is_err());
+    assert!(apply_delta(b"0123456789", &[10, 9, 0x91, 8, 4]).is_err());
+}
+
+#[test]
+fn test_parse_tree() {
+    let mut data = b"100644 a.txt\0".to_vec();
+    data.extend_from_slice(&[1; 20]);
+    data.extend_from_slice(b"40000 docs\0");
+    data.extend_from_slice(&[2; 20]);
+    let entries = parse_tree(&data).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(entries[0].is_file() && entries[0].name == "a.txt" && entries[0].id == [1; 20]);
+    assert!(entries[1].is_dir() && entries[1].name == "docs");
+    assert!(parse_tree(&data[..data.len() - 1]).is_err());
+    assert_eq!(
+        header_id(
+            b"tree 0123456789abcdef0123456789abcdef01234567\nparent x\n",
+            "tree"
+        )
+        .map(|id| crypto::hex(&id))
+        .unwrap(),
+        "0123456789abcdef0123456789abcdef01234567"
+    );
+}
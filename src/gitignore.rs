@@ -0,0 +1,270 @@
+// --git: what .gitignore files (and .git/info/exclude) ignore is hidden, with
+// the rules of gitignore(5). The served directory is taken to be the top of
+// the work tree. Files are read again when they change.
+
+use crate::root;
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+const EXCLUDE_FILE: &str = ".git/info/exclude";
+
+struct Pattern {
+    glob: String,
+    negated: bool,
+    directory_only: bool,
+    // relative to the directory of the file rather than matching names at
+    // any depth below it
+    anchored: bool,
+}
+
+// the patterns of a file, as of its modification time
+struct Entry {
+    modified: Option<SystemTime>,
+    patterns: Arc<Vec<Pattern>>,
+}
+
+pub struct Gitignore {
+    // the top of the work tree, relative to the served directory
+    root: PathBuf,
+    // by where they are, which may be in different releases
+    files: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+impl Gitignore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Gitignore {
+            root: root.into(),
+            files: Mutex::default(),
+        }
+    }
+
+    // `path` is normalized
+    pub fn is_ignored(&self, path: &str) -> bool {
+        let names: Vec<_> = path.split('/').filter(|name| *name != ".").collect();
+        // nothing can be re-included from an ignored directory, so the
+        // first ignored ancestor decides
+        (1..=names.len()).any(|len| {
+            let is_dir = len < names.len() || root::served(self.root.join(path)).is_dir();
+            self.check(&names[..len], is_dir)
+        })
+    }
+
+    fn check(&self, names: &[&str], is_dir: bool) -> bool {
+        let mut ignored = matched(&self.patterns(EXCLUDE_FILE), &names.join("/"), is_dir);
+        // deeper files take precedence
+        for depth in 0..names.len() {
+            let file = [&names[..depth], &[".gitignore"]].concat().join("/");
+            if let Some(res) = matched(&self.patterns(&file), &names[depth..].join("/"), is_dir) {
+                ignored = Some(res);
+            }
+        }
+        ignored == Some(true)
+    }
+
+    fn patterns(&self, file: &str) -> Arc<Vec<Pattern>> {
+        let path = root::served(self.root.join(file));
+        let modified = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let mut files = self.files.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(entry) = files.get(&path)
+            && entry.modified == modified
+        {
+            return Arc::clone(&entry.patterns);
+        }
+        let patterns = Arc::new(parse(&fs::read_to_string(&path).unwrap_or_default()));
+        let entry = Entry {
+            modified,
+            patterns: Arc::clone(&patterns),
+        };
+        files.insert(path, entry);
+        patterns
+    }
+}
+
+fn parse(text: &str) -> Vec<Pattern> {
+    text.lines()
+        .filter_map(|line| {
+            // trailing spaces don't count, unless escaped
+            let line = line.trim_end_matches('\r');
+            let trimmed = line.trim_end_matches(' ');
+            let line = if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+                &line[..=trimmed.len()]
+            } else {
+                trimmed
+            };
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (directory_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let glob = line.strip_prefix('/').unwrap_or(line).to_owned();
+            (!glob.is_empty()).then_some(Pattern {
+                glob,
+                negated,
+                directory_only,
+                anchored,
+            })
+        })
+        .collect()
+}
+
+// Whether the last pattern matching `path` (relative to their file) ignores
+// it, None when none does
+fn matched(patterns: &[Pattern], path: &str, is_dir: bool) -> Option<bool> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    patterns
+        .iter()
+        .rev()
+        .find(|pattern| {
+            (is_dir || !pattern.directory_only)
+                && if pattern.anchored {
+                    glob(pattern.glob.as_bytes(), path.as_bytes())
+                } else {
+                    glob(pattern.glob.as_bytes(), name.as_bytes())
+                }
+        })
+        .map(|pattern| !pattern.negated)
+}
+
+// * and ? within a name, [...] classes, and ** across directories
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            // "a/**/b" matches "a/b" too
+            if let Some(rest) = rest.strip_prefix(b"/")
+                && glob(rest, text)
+            {
+                return true;
+            }
+            (0..=text.len()).any(|idx| glob(rest, &text[idx..]))
+        }
+        [b'*', rest @ ..] => {
+            for idx in 0..=text.len() {
+                if glob(rest, &text[idx..]) {
+                    return true;
+                }
+                if text.get(idx) == Some(&b'/') {
+                    return false;
+                }
+            }
+            false
+        }
+        [b'?', rest @ ..] => match text {
+            [first, text @ ..] if *first != b'/' => glob(rest, text),
+            _ => false,
+        },
+        [b'[', class @ ..] => match (class_matches(class, text.first().copied()), text) {
+            (Some((true, rest)), [_, text @ ..]) => glob(rest, text),
+            (Some(_), _) => false,
+            // not a class after all
+            (None, [b'[', text @ ..]) => glob(class, text),
+            (None, _) => false,
+        },
+        [b'\\', escaped, rest @ ..] | [escaped, rest @ ..] => match text {
+            [first, text @ ..] if first == escaped => glob(rest, text),
+            _ => false,
+        },
+    }
+}
+
+// Whether `byte` is in the class `pattern` starts with (after the "["), and
+// what follows it, None without a closing "]"
+fn class_matches(pattern: &[u8], byte: Option<u8>) -> Option<(bool, &[u8])> {
+    let (negated, mut rest) = match pattern {
+        [b'!' | b'^', rest @ ..] => (true, rest),
+        _ => (false, pattern),
+    };
+    let mut found = false;
+    let mut first = true;
+    loop {
+        match rest {
+            [b']', after @ ..] if !first => {
+                let matches = byte.is_some_and(|byte| byte != b'/' && found != negated);
+                return Some((matches, after));
+            }
+            [low, b'-', high, after @ ..] if *high != b']' => {
+                found |= byte.is_some_and(|byte| (*low..=*high).contains(&byte));
+                rest = after;
+            }
+            [single, after @ ..] => {
+                found |= byte == Some(*single);
+                rest = after;
+            }
+            [] => return None,
+        }
+        first = false;
+    }
+}
+
+#[test]
+fn test_glob() {
+    assert!(glob(b"*.log", b"debug.log"));
+    assert!(!glob(b"*.log", b"logs/debug.log"));
+    assert!(glob(b"debug?.log", b"debug1.log"));
+    assert!(glob(b"debug[0-9].log", b"debug5.log"));
+    assert!(!glob(b"debug[!0-9].log", b"debug5.log"));
+    assert!(glob(b"[]a].txt", b"].txt"));
+    assert!(glob(b"[ab", b"[ab"));
+    assert!(glob(b"\\*.txt", b"*.txt"));
+    assert!(!glob(b"\\*.txt", b"a.txt"));
+    assert!(glob(b"**/logs", b"logs"));
+    assert!(glob(b"**/logs", b"a/b/logs"));
+    assert!(glob(b"logs/**", b"logs/a/debug.log"));
+    assert!(glob(b"a/**/b", b"a/b"));
+    assert!(glob(b"a/**/b", b"a/x/y/b"));
+    assert!(!glob(b"a/*/b", b"a/x/y/b"));
+}
+
+#[test]
+fn test_gitignore() {
+    let patterns =
+        parse("# comment\n\n*.log\n!keep.log\nbuild/\n/root.txt\ndoc/*.pdf\n\\#hash\nspace\\  \n");
+    assert_eq!(patterns.len(), 7);
+    let ignored = |path: &str, is_dir: bool| matched(&patterns, path, is_dir);
+    assert_eq!(ignored("a/debug.log", false), Some(true));
+    assert_eq!(ignored("a/keep.log", false), Some(false));
+    assert_eq!(ignored("a/build", true), Some(true));
+    assert_eq!(ignored("a/build", false), None);
+    assert_eq!(ignored("root.txt", false), Some(true));
+    assert_eq!(ignored("a/root.txt", false), None);
+    assert_eq!(ignored("doc/a.pdf", false), Some(true));
+    assert_eq!(ignored("a/doc/a.pdf", false), None);
+    assert_eq!(ignored("#hash", false), Some(true));
+    assert_eq!(ignored("space ", false), Some(true));
+
+    let root = std::env::temp_dir().join(format!("gitignore-test-{}", std::process::id()));
+    fs::create_dir_all(root.join("a/build")).unwrap();
+    fs::create_dir_all(root.join(".git/info")).unwrap();
+    fs::write(root.join(".gitignore"), "*.tmp\nbuild/\n").unwrap();
+    fs::write(root.join("a/.gitignore"), "!keep.tmp\n").unwrap();
+    fs::write(root.join(".git/info/exclude"), "secret\n").unwrap();
+    let gitignore = Gitignore::new(&root);
+    let is_ignored = |path: &str| gitignore.is_ignored(path);
+    assert!(is_ignored("x.tmp"));
+    assert!(!is_ignored("a/keep.tmp"));
+    assert!(is_ignored("a/other.tmp"));
+    assert!(is_ignored("a/build"));
+    assert!(is_ignored("a/build/keep.tmp"));
+    assert!(!is_ignored("a/x.txt"));
+    assert!(is_ignored("a/secret"));
+    assert!(!is_ignored("."));
+    // read again once changed
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    fs::write(root.join(".gitignore"), "").unwrap();
+    assert!(!is_ignored("a/other.tmp"));
+    fs::remove_dir_all(root).unwrap();
+}
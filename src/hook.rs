@@ -0,0 +1,222 @@
+// --hook: a command run on events worth alerting someone about, with the
+// event as JSON on its standard input, for alerts without a metrics stack:
+//
+// {"event":"5xx","count":10,"window_seconds":60,"time":1700000000}
+//
+// The events are "start" and "stop" (with the summary --report writes),
+// "4xx" and "5xx" when --hook-threshold responses of the class were sent in
+// a minute (once a minute at most), and "auth-failure" when credentials were
+// refused. Commands run one at a time from a thread of their own, so requests
+// don't wait, and are killed past TIMEOUT.
+
+use crate::{date, json, log, status::StatusCode};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+pub const EVENTS: [&str; 5] = ["start", "stop", "4xx", "5xx", "auth-failure"];
+pub const DEFAULT_THRESHOLD: u64 = 10;
+const WINDOW: Duration = Duration::from_secs(60);
+const TIMEOUT: Duration = Duration::from_secs(30);
+// events beyond this are dropped while commands are slow
+const MAX_QUEUED: usize = 100;
+
+// responses of a class sent since `start`
+struct Window {
+    start: Instant,
+    count: u64,
+}
+
+pub struct Hooks {
+    command: String,
+    events: Vec<String>,
+    threshold: u64,
+    // for 4xx and 5xx
+    windows: Mutex<[Window; 2]>,
+    // started with the first event, stopped by finish()
+    worker: Mutex<Option<(SyncSender<String>, JoinHandle<()>)>>,
+}
+
+impl Hooks {
+    // `events` of EVENTS, all of them when empty
+    pub fn new(command: String, events: Vec<String>, threshold: u64) -> Self {
+        let now = Instant::now();
+        Hooks {
+            command,
+            events,
+            threshold,
+            windows: Mutex::new([0, 1].map(|_| Window {
+                start: now,
+                count: 0,
+            })),
+            worker: Mutex::new(None),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let events = match self.events.is_empty() {
+            true => "all events".to_owned(),
+            false => self.events.join(","),
+        };
+        format!("{} on {events}", self.command)
+    }
+
+    // Sends the 4xx and 5xx events when a response makes a spike
+    pub fn observe(&self, status: StatusCode) {
+        let class = status.code() / 100;
+        if !(4..=5).contains(&class) {
+            return;
+        }
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap_or_else(|err| err.into_inner());
+        let window = &mut windows[class as usize - 4];
+        if now.duration_since(window.start) >= WINDOW {
+            window.start = now;
+            window.count = 0;
+        }
+        window.count += 1;
+        if window.count == self.threshold {
+            drop(windows);
+            let fields = format!(
+                "\"count\":{},\"window_seconds\":{}",
+                self.threshold,
+                WINDOW.as_secs()
+            );
+            self.send(&format!("{class}xx"), &fields);
+        }
+    }
+
+    pub fn auth_failure(&self, method: &str, path: &str, client: &str) {
+        let fields = format!(
+            "\"method\":{},\"path\":{},\"client\":{}",
+            json::quote(method),
+            json::quote(path),
+            json::quote(client)
+        );
+        self.send("auth-failure", &fields);
+    }
+
+    // `fields` are the members of the JSON object past the event's name,
+    // without braces
+    pub fn send(&self, event: &str, fields: &str) {
+        if !self.events.is_empty() && !self.events.iter().any(|name| name == event) {
+            return;
+        }
+        let time = date::now();
+        let body = event_body(event, fields, time);
+        let mut worker = self.worker.lock().unwrap_or_else(|err| err.into_inner());
+        let (queue, _) = worker.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED);
+            let command = self.command.clone();
+            let handle = thread::spawn(move || run_all(&command, &receiver));
+            (sender, handle)
+        });
+        if let Err(TrySendError::Full(_)) = queue.try_send(body) {
+            log::warning(&format!("hook queue full, {event} event dropped"));
+        }
+    }
+
+    // Waits for the events sent to be handled, before the process ends
+    pub fn finish(&self) {
+        let worker = self
+            .worker
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .take();
+        if let Some((queue, handle)) = worker {
+            drop(queue);
+            let _ = handle.join();
+        }
+    }
+}
+
+fn event_body(event: &str, fields: &str, time: u64) -> String {
+    let mut res = format!("{{\"event\":{}", json::quote(event));
+    if !fields.is_empty() {
+        res.push(',');
+        res.push_str(fields);
+    }
+    res.push_str(&format!(",\"time\":{time}}}\n"));
+    res
+}
+
+fn run_all(command: &str, events: &Receiver<String>) {
+    for body in events {
+        if let Err(err) = run(command, &body) {
+            log::error(&format!("hook '{command}' failed: {err}"));
+        }
+    }
+}
+
+// `command` as the shell runs it, for --filter too
+pub fn shell(command: &str) -> Command {
+    #[cfg(unix)]
+    let (shell, flag) = ("sh", "-c");
+    #[cfg(not(unix))]
+    let (shell, flag) = ("cmd", "/C");
+    let mut res = Command::new(shell);
+    res.arg(flag).arg(command);
+    res
+}
+
+fn run(command: &str, body: &str) -> Result<(), String> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|err| err.to_string())?;
+    // commands needn't read it
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(body.as_bytes());
+    }
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match child.try_wait().map_err(|err| err.to_string())? {
+            Some(status) if status.success() => return Ok(()),
+            Some(status) => return Err(status.to_string()),
+            None if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("killed after {}s", TIMEOUT.as_secs()));
+            }
+            None => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+#[test]
+fn test_hooks() {
+    assert_eq!(
+        event_body("5xx", "\"count\":10", 1700000000),
+        "{\"event\":\"5xx\",\"count\":10,\"time\":1700000000}\n"
+    );
+    assert_eq!(
+        event_body("start", "", 0),
+        "{\"event\":\"start\",\"time\":0}\n"
+    );
+
+    let output = std::env::temp_dir().join(format!("hook-test-{}", std::process::id()));
+    let hooks = Hooks::new(
+        format!("cat >> '{}'", output.display()),
+        vec!["5xx".to_owned()],
+        2,
+    );
+    hooks.send("start", "");
+    for _ in 0..3 {
+        hooks.observe(StatusCode::InternalServerError);
+        hooks.observe(StatusCode::NotFound);
+    }
+    hooks.finish();
+    let sent = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    // once in the minute, and not the events left out
+    assert_eq!(sent.lines().count(), 1);
+    assert!(sent.starts_with("{\"event\":\"5xx\",\"count\":2,\"window_seconds\":60,"));
+}
@@ -0,0 +1,276 @@
+// DEFLATE decompression (RFC 1951), for the members of zip files.
+
+use std::io;
+
+// RFC 1951 section 3.2.5
+pub const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+pub const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// the order code length code lengths come in, section 3.2.7
+pub const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+const MAX_BITS: usize = 15;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("deflate: {message}"))
+}
+
+// Bits come least significant first
+struct BitReader<'a> {
+    input: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = *self
+                .input
+                .get(self.pos)
+                .ok_or_else(|| invalid("truncated"))?;
+            self.pos += 1;
+            self.buffer |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let res = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(res)
+    }
+
+    // stored blocks start on a byte boundary
+    fn bytes(&mut self, n: usize) -> io::Result<&[u8]> {
+        self.buffer = 0;
+        self.count = 0;
+        let res = self
+            .input
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| invalid("truncated"))?;
+        self.pos += n;
+        Ok(res)
+    }
+}
+
+// A canonical Huffman code, as the number of codes of each length and the
+// symbols ordered by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0; MAX_BITS + 1];
+        for length in lengths {
+            counts[usize::from(*length)] += 1;
+        }
+        counts[0] = 0;
+        let mut left: i32 = 1;
+        for count in &counts[1..] {
+            left = (left << 1) - i32::from(*count);
+            if left < 0 {
+                return Err(invalid("over-subscribed code"));
+            }
+        }
+
+        let mut offsets = [0; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + usize::from(counts[length]);
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1]];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[usize::from(*length)]] = symbol as u16;
+                offsets[usize::from(*length)] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> io::Result<u16> {
+        // codes of each length follow those of the shorter ones
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for count in &self.counts[1..] {
+            code |= reader.bits(1)? as usize;
+            let count = usize::from(*count);
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid code"))
+    }
+}
+
+// Stops with an error once the output would exceed `max_size`
+pub fn inflate(input: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+    let mut reader = BitReader {
+        input,
+        pos: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut res = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                let header = reader.bytes(4)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(invalid("invalid stored block length"));
+                }
+                if res.len() + usize::from(length) > max_size {
+                    return Err(invalid("too large"));
+                }
+                res.extend_from_slice(reader.bytes(length.into())?);
+            }
+            1 => {
+                let (lengths, distances) = fixed_codes()?;
+                inflate_block(&mut reader, &mut res, &lengths, &distances, max_size)?;
+            }
+            2 => {
+                let (lengths, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut res, &lengths, &distances, max_size)?;
+            }
+            _ => return Err(invalid("invalid block type")),
+        }
+        if last {
+            return Ok(res);
+        }
+    }
+}
+
+fn fixed_codes() -> io::Result<(Huffman, Huffman)> {
+    let mut lengths = [8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literals = reader.bits(5)? as usize + 257;
+    let distances = reader.bits(5)? as usize + 1;
+    let code_lengths = reader.bits(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        return Err(invalid("too many codes"));
+    }
+
+    let mut lengths = [0; 19];
+    for idx in CODE_LENGTH_ORDER.iter().take(code_lengths) {
+        lengths[*idx] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths)?;
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let symbol = code_length_code.decode(reader)?;
+        let (length, repeat) = match symbol {
+            0..16 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| invalid("repeat with no length"))?;
+                (previous, reader.bits(2)? + 3)
+            }
+            17 => (0, reader.bits(3)? + 3),
+            _ => (0, reader.bits(7)? + 11),
+        };
+        if lengths.len() + repeat as usize > literals + distances {
+            return Err(invalid("too many lengths"));
+        }
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+    if lengths[256] == 0 {
+        return Err(invalid("no end of block code"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literals])?,
+        Huffman::new(&lengths[literals..])?,
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    res: &mut Vec<u8>,
+    lengths: &Huffman,
+    distances: &Huffman,
+    max_size: usize,
+) -> io::Result<()> {
+    loop {
+        let symbol = usize::from(lengths.decode(reader)?);
+        match symbol {
+            0..256 => res.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let symbol = symbol - 257;
+                if symbol >= LENGTH_BASE.len() {
+                    return Err(invalid("invalid length"));
+                }
+                let length = usize::from(LENGTH_BASE[symbol])
+                    + reader.bits(LENGTH_EXTRA[symbol].into())? as usize;
+                let symbol = usize::from(distances.decode(reader)?);
+                if symbol >= DISTANCE_BASE.len() {
+                    return Err(invalid("invalid distance"));
+                }
+                let distance = usize::from(DISTANCE_BASE[symbol])
+                    + reader.bits(DISTANCE_EXTRA[symbol].into())? as usize;
+                if distance > res.len() {
+                    return Err(invalid("distance too far back"));
+                }
+                if res.len() + length > max_size {
+                    return Err(invalid("too large"));
+                }
+                // the copy can overlap what it adds
+                let start = res.len() - distance;
+                for idx in start..start + length {
+                    res.push(res[idx]);
+                }
+            }
+        }
+        if res.len() > max_size {
+            return Err(invalid("too large"));
+        }
+    }
+}
+
+#[test]
+fn test_inflate() {
+    // from zlib, fixed and dynamic codes
+    assert_eq!(
+        inflate(b"\xcb\x48\xcd\xc9\xc9\x57\xc8\x40\x90\x00", 100).unwrap(),
+        b"hello hello hello"
+    );
+    let text = [
+        "The quick brown fox jumps over the lazy dog. ".repeat(4),
+        "Pack my box with five dozen liquor jugs! 0123456789 ".repeat(3),
+    ]
+    .concat();
+    let compressed = b"\xcd\xcb\xd9\x15\x40\x30\x14\x45\xd1\x56\xae\x06\x2c\xf3\xd0\x85\x0f\x0d\x18\x42\x62\x7a\x84\x18\x52\xbd\x57\x85\xe5\xfb\xec\x53\x4a\x81\xcd\xa8\x66\x44\xad\xe9\x5a\xd0\xd1\x8d\xc1\xcc\xeb\x0e\x3a\x85\xc6\xc1\x79\xaa\xec\x83\x96\x7a\x17\xe5\x3f\x70\x51\xb1\x9b\x1f\xd4\x8c\x2e\x75\x48\x74\xea\x14\x9c\xac\x58\x30\xa9\xcd\x90\xe6\xb7\xdf\x1d\x78\x7e\x10\x46\x71\x92\x66\xf9\x67\xcf\x0b";
+    assert_eq!(inflate(compressed, 1000).unwrap(), text.as_bytes());
+    assert!(inflate(compressed, 100).is_err());
+    assert!(inflate(&compressed[..50], 1000).is_err());
+    // stored
+    assert_eq!(
+        inflate(b"\x01\x03\x00\xfc\xff\x61\x62\x63", 100).unwrap(),
+        b"abc"
+    );
+    assert!(inflate(b"\x01\x03\x00\xfc\xfe\x61\x62\x63", 100).is_err());
+}
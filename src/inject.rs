@@ -0,0 +1,74 @@
+// --inject-html: a snippet (a script, a banner) added to the HTML pages served,
+// before their last </body>, or at their end without one. Pages are streamed
+// around it: only their tail is read ahead, to find where it goes, so their
+// length is known and they still go out as they're read. Those which are
+// gzipped or minified are in memory already, and get it there.
+
+use crate::crypto::{self, Digest, sha2::Sha256};
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+// how far from the end of pages </body> is looked for
+const TAIL: u64 = 64 * 1024;
+const BODY_END: &[u8] = b"</body>";
+
+pub struct Snippet {
+    pub content: Vec<u8>,
+    // for ETags, which must change with it
+    pub tag: String,
+}
+
+impl Snippet {
+    pub fn new(content: Vec<u8>) -> Self {
+        let tag = format!("html{}", &crypto::hex(&Sha256::digest(&content))[..8]);
+        Snippet { content, tag }
+    }
+
+    // `page` with the snippet
+    pub fn insert(&self, page: &[u8]) -> Vec<u8> {
+        let position = position(page);
+        let mut res = Vec::with_capacity(page.len() + self.content.len());
+        res.extend_from_slice(&page[..position]);
+        res.extend_from_slice(&self.content);
+        res.extend_from_slice(&page[position..]);
+        res
+    }
+}
+
+// Where the snippet goes in the page at `path`, of `size` bytes
+pub fn position_in_file(path: &Path, size: u64) -> io::Result<u64> {
+    let start = size.saturating_sub(TAIL);
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut tail = Vec::new();
+    file.take(size - start).read_to_end(&mut tail)?;
+    Ok(start + position(&tail) as u64)
+}
+
+// Before the last </body> of `page`, whatever its case, else at its end
+fn position(page: &[u8]) -> usize {
+    page.windows(BODY_END.len())
+        .rposition(|window| window.eq_ignore_ascii_case(BODY_END))
+        .unwrap_or(page.len())
+}
+
+#[test]
+fn test_snippet() {
+    let snippet = Snippet::new(b"<script src=/a.js></script>".to_vec());
+    assert_eq!(
+        snippet.insert(b"<p>a</body> <p>b</BODY></html>"),
+        b"<p>a</body> <p>b<script src=/a.js></script></BODY></html>"
+    );
+    assert_eq!(snippet.insert(b"<p>a"), b"<p>a<script src=/a.js></script>");
+    assert!(snippet.tag.starts_with("html") && snippet.tag.len() == 12);
+
+    let path = std::env::temp_dir().join(format!("inject-test-{}", std::process::id()));
+    let mut page = "x".repeat(100_000);
+    page.push_str("</body></html>");
+    std::fs::write(&path, &page).unwrap();
+    assert_eq!(position_in_file(&path, page.len() as u64).unwrap(), 100_000);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,282 @@
+// A small JSON parser, enough for the documents identity providers send us,
+// and string quoting for the few documents we write.
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(HashMap<String, Value>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        input: input.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos != parser.input.len() {
+        return Err(format!("trailing characters at {}", parser.pos));
+    }
+    Ok(value)
+}
+
+// nobody needs documents nested deeper than this, and it keeps the stack safe
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.input.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("{message} at {}", self.pos))
+    }
+
+    fn expect(&mut self, literal: &str, value: Value) -> Result<Value, String> {
+        if self.input[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            self.error("invalid literal")
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return self.error("too deeply nested");
+        }
+        self.skip_whitespace();
+        match self.input.get(self.pos) {
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b't') => self.expect("true", Value::Bool(true)),
+            Some(b'f') => self.expect("false", Value::Bool(false)),
+            Some(b'n') => self.expect("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => self.error("unexpected character"),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, String> {
+        let mut map = HashMap::new();
+        self.pos += 1;
+        self.skip_whitespace();
+        if self.input.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(map));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.input.get(self.pos) != Some(&b'"') {
+                return self.error("expected a key");
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if self.input.get(self.pos) != Some(&b':') {
+                return self.error("expected ':'");
+            }
+            self.pos += 1;
+            map.insert(key, self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.input.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(map));
+                }
+                _ => return self.error("expected ',' or '}'"),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, String> {
+        let mut values = Vec::new();
+        self.pos += 1;
+        self.skip_whitespace();
+        if self.input.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.input.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return self.error("expected ',' or ']'"),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.input.get(self.pos) {
+            self.pos += 1;
+        }
+        // the input is a &str and we only skipped ASCII
+        let number = std::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default();
+        match number.parse() {
+            Ok(number) => Ok(Value::Number(number)),
+            Err(_) => self.error("invalid number"),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        match digits {
+            Some(value) => {
+                self.pos += 4;
+                Ok(value)
+            }
+            None => self.error("invalid unicode escape"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let mut res = Vec::new();
+        self.pos += 1;
+        loop {
+            let Some(&byte) = self.input.get(self.pos) else {
+                return self.error("unterminated string");
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escaped) = self.input.get(self.pos) else {
+                        return self.error("unterminated string");
+                    };
+                    self.pos += 1;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // characters outside the BMP come as surrogate pairs
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.input[self.pos..].starts_with(b"\\u")
+                            {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000
+                                    + ((code - 0xd800) << 10)
+                                    + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        _ => return self.error("invalid escape"),
+                    };
+                    res.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                byte => res.push(byte),
+            }
+        }
+        String::from_utf8(res).or_else(|_| self.error("invalid UTF-8"))
+    }
+}
+
+// `value` as a JSON string, quotes included
+pub fn quote(value: &str) -> String {
+    let mut res = String::with_capacity(value.len() + 2);
+    res.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if c < ' ' => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[test]
+fn test_parse() {
+    let value = parse(
+        r#" {"iss": "http://idp", "aud": ["a", "b"], "exp": 1.5e3, "ok": true,
+            "none": null, "text": "\"\u00e9\ud83d\ude00\n", "empty": {}} "#,
+    )
+    .unwrap();
+    assert_eq!(value.get("iss").and_then(Value::as_str), Some("http://idp"));
+    assert_eq!(
+        value.get("aud"),
+        Some(&Value::Array(vec![
+            Value::String("a".to_owned()),
+            Value::String("b".to_owned())
+        ]))
+    );
+    assert_eq!(value.get("exp").and_then(Value::as_f64), Some(1500.0));
+    assert_eq!(value.get("ok"), Some(&Value::Bool(true)));
+    assert_eq!(value.get("none"), Some(&Value::Null));
+    assert_eq!(value.get("text").and_then(Value::as_str), Some("\"é😀\n"));
+    assert!(parse("[1, 2,]").is_err());
+    assert!(parse("{\"a\": 1} x").is_err());
+}
+
+#[test]
+fn test_quote() {
+    let text = "a \"b\" \\ é\n\u{1}";
+    assert_eq!(quote(text), "\"a \\\"b\\\" \\\\ é\\n\\u0001\"");
+    assert_eq!(parse(&quote(text)), Ok(Value::String(text.to_owned())));
+}
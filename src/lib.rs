@@ -0,0 +1,6326 @@
+mod access_log;
+mod agent;
+mod auth;
+mod base64;
+mod canary;
+mod checksum;
+mod chunked;
+mod cidr;
+mod client;
+mod coalesce;
+mod conditional;
+mod config;
+mod crypto;
+mod date;
+mod deadline;
+mod deflate;
+mod doctor;
+mod error_page;
+mod fastcgi;
+mod filter;
+mod geoip;
+mod git;
+mod gitignore;
+mod hook;
+mod inflate;
+mod inject;
+mod json;
+mod log;
+mod maintenance;
+mod map;
+mod media;
+mod metrics;
+mod minify;
+mod pool;
+mod proxy;
+mod quota;
+mod release;
+mod report;
+mod response;
+mod root;
+mod search;
+mod session;
+mod shutdown;
+mod signal;
+mod status;
+mod stream;
+mod tenant;
+#[cfg(feature = "tls")]
+mod tls;
+mod toml;
+mod trace;
+mod trash;
+mod tree;
+mod tunnel;
+mod upgrade;
+mod upload;
+mod upnp;
+mod vhost;
+mod viewer;
+mod watch;
+mod webdav;
+mod webhook;
+mod websocket;
+mod zip;
+
+use chunked::ChunkedWriter;
+use crypto::{Digest, sha2::Sha256};
+use status::StatusCode;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::OpenOptions,
+    io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Take, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket},
+    path::Path,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+use stream::Connection;
+
+const DEFAULT_PORT: u16 = 8080;
+// only this computer can connect, unless --expose or -b says otherwise
+const DEFAULT_ADDRESS: &str = "127.0.0.1";
+const EXPOSED_ADDRESS: &str = "0.0.0.0";
+const DEFAULT_DIR: &str = ".";
+const DEFAULT_THREADS: usize = 8;
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(5);
+// what RFC 7838 assumes without ma
+const DEFAULT_ALT_SVC_MAX_AGE: Duration = Duration::from_secs(24 * 3600);
+// so a busy client leaves its worker to others once in a while
+const MAX_CONNECTION_REQUESTS: u64 = 100;
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+const AUTH_REALM: &str = "rust-std-web-server";
+const CSRF_COOKIE: &str = "csrf";
+// management forms are tiny, no need to accept more
+const MAX_FORM_SIZE: u64 = 64 * 1024;
+// chunked request bodies are decoded before the request is answered, in
+// memory, up to --max-body or this
+const MAX_CHUNKED_BODY: u64 = 64 * 1024 * 1024;
+// names differing only in case are the same file there
+const CASE_INSENSITIVE_FS: bool = cfg!(any(target_os = "macos", target_os = "windows"));
+// for fingerprinted names: a year is as long as caches go, and immutable
+// (RFC 8246) spares revalidations
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+// files gzipped when the client takes it, smaller ones gain little and larger
+// ones would be read whole
+const MIN_COMPRESSED_SIZE: u64 = 512;
+const MAX_COMPRESSED_SIZE: u64 = 8 * 1024 * 1024;
+// the content codings of precompressed files and their extensions, in order
+// of preference
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+// the types of images converted ahead of time and their extensions, e.g.
+// pic.jpg.avif, which are sent instead to clients taking them when smaller
+const IMAGE_VARIANTS: [(&str, &str); 2] = [("image/avif", "avif"), ("image/webp", "webp")];
+// files already compressed whatever their type says, e.g. through --mime,
+// which gzip would only make larger
+const COMPRESSED_EXTENSIONS: [&str; 24] = [
+    "gz", "tgz", "br", "zst", "xz", "bz2", "lz", "zip", "7z", "rar", "jar", "deb", "rpm", "apk",
+    "svgz", "woff", "woff2", "png", "jpg", "jpeg", "gif", "webp", "avif", "mp4",
+];
+// how long the next pipelined request may take to arrive
+const PIPELINE_WAIT: Duration = Duration::from_millis(50);
+// how long a client may take to send the rest of a request it started, or
+// stop reading a response, before its connection is dropped: writes block
+// while the socket's send buffer is full, which is all that's queued for a
+// connection
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(60);
+// the request line and each header line, and how many of those, beyond which
+// the head gets a 431 rather than filling memory
+const MAX_HEAD_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+// request fields which can't be lists, refused when repeated; Content-Length
+// may repeat its value, see check_framing
+const SINGLE_FIELDS: [&str; 9] = [
+    "host",
+    "authorization",
+    "proxy-authorization",
+    "content-type",
+    "range",
+    "if-range",
+    "if-modified-since",
+    "if-unmodified-since",
+    "referer",
+];
+const UPLOAD_SCRIPT: &str = include_str!("upload.js");
+const WATCH_SCRIPT: &str = include_str!("watch.js");
+const USAGE: &str = "
+Usage: rust-std--web-server [-p port] [-b addr | --expose] [-d dir] [-j threads]
+                            [--max-conns n]
+                            [--keep-alive seconds] [--request-timeout duration]
+                            [--read-timeout duration] [--write-timeout duration]
+                            [--max-body size]
+                            [--cert file --key file]
+                            [--alt-svc protocol=[host]:port]... [--alt-svc-max-age duration]
+                            [--config file] [--check-config] [--doctor] [--upnp]
+                            [--strict-http] [--server-token token]
+                            [--mime ext=type]... [--no-compress type|.ext]...
+                            [--header 'name: value']...
+                            [--log-target target] [--log-time utc|local]
+                            [--access-log file [--access-log-format format]]
+                            [--log-exclude path]... [--log-sample n] [--log-timings]
+                            [--report file]
+                            [--metrics] [--checksums] [--fingerprints] [--minify] [--zip]
+                            [--inject-html file] [--filter type=command]...
+                            [--spa] [--media] [--viewer] [--webdav]
+                            [--search [--search-contents] [--search-refresh duration]]
+                            [--debug-routes] [--mode mode] [--put-conflict policy]
+                            [--max-upload size] [--create-dirs]
+                            [--trash dir [--trash-retention duration]]
+                            [--webhook url]... [--webhook-secret secret]
+                            [--hook command [--hook-on events] [--hook-threshold n]]
+                            [--early-hint path=link]...
+                            [--maintenance-file file] [--maintenance-allow path]...
+                            [--maintenance-retry-after duration]
+                            [--auth user:password | --htpasswd file | --pam service |
+                             --ldap url --ldap-user-dn template [--ldap-group dn] |
+                             --oidc-issuer url --oidc-client-id id
+                             --oidc-client-secret secret [--oidc-require claim=value]...]
+                            [--jwt-secret secret | --jwt-jwks url]
+                            [--jwt-issuer iss] [--jwt-audience aud]
+                            [--digest path]... [--auth-path path]...
+                            [--token path=token]... [--quota [path=]size]...
+                            [--hide-dotfiles] [--deny name]... [--git] [--git-ref ref]
+                            [--tenant host|/prefix=dir[,option]...]...
+                            [--canary dir=percent]
+                            [--vhost host:dir]... [--vhost-default host]
+                            [--proxy /prefix=url[,option]...]... [--upstream-proxy url]
+                            [--map /prefix=dir[,option]...]...
+                            [--fastcgi host:port|unix:path]
+                            [--geoip file]... [--geo-allow country|ASn]...
+                            [--geo-deny country|ASn]...
+                            [--ip-allow range]... [--ip-deny range]...
+                            [--rate-limit requests [--rate-burst requests]]
+                            [--agent class=policy[,policy]...]...
+
+An HTTP server using only the Rust standard library.
+
+Options
+  -b <addr>  Address to bind to, defaults to 127.0.0.1: only this computer
+             can connect.
+  -d <dir>   Directory to serve, defaults to your current directory. Its
+             404.html, 403.html, 500.html and 503.html, if any, are sent
+             with those errors instead of a page of ours. When it's a
+             symlink, e.g. to a release, each request is answered from where
+             it leads when the request arrives, those being answered when it
+             changes finishing with the previous one. SIGHUP checks it too.
+  -h         Print this message and exit.
+  -j <n>, --threads <n>
+             Handle up to n connections at once, 8 by default. When all of
+             them are busy, new connections wait for one, up to --max-conns.
+  --max-conns <n>
+             Connections open at once, answered or waiting for a thread, at
+             most; twice --threads by default and no fewer than them. Past
+             it, new connections get a 503 right away.
+  --keep-alive <seconds>
+             How long a connection is kept open waiting for the client's next
+             request, 5 by default; 0 closes it after the response, unless
+             requests are pipelined. Connections carry 100 requests at most.
+  --request-timeout <duration>
+             Longest a request can take to be answered, from its headers to
+             the last byte of the response, e.g. 10m, after which its
+             connection is closed and a warning logged. Unlimited by default;
+             WebSocket and other upgraded connections aren't concerned.
+  --read-timeout <duration>
+             Longest wait for the rest of a request once its first byte came,
+             30s by default: slower clients get a 408 and their connection is
+             closed. Connections which don't send a request within it are
+             closed without one.
+  --write-timeout <duration>
+             Longest a client can stop reading a response, 1m by default,
+             after which its connection is closed.
+  --max-body <size>
+             Largest request body, e.g. 10M, of uploads, forms and requests
+             passed to --proxy or --fastcgi backends alike. Larger ones get a
+             413 and their connection is closed. Unlimited by default, but
+             for chunked ones, decoded in memory first, up to 64M.
+  -p <port>  Port to bind to, defaults to 8080.
+  -v         Print the version number and exit.
+  --check-config
+             Print the effective configuration, check it and exit, with 1
+             when there are problems.
+  --config <file>
+             Read options from this TOML file, see Config file below.
+  --cert <file>
+  --key <file>
+             Serve HTTPS (TLS 1.2 and up) with this PEM certificate, followed
+             by its intermediate ones, and its private key. Needs the `tls`
+             cargo feature, which links to OpenSSL.
+  --alt-svc <protocol=[host]:port>
+             Tell HTTPS clients, with an Alt-Svc header on every response,
+             that this site is also served with protocol (its ALPN id, e.g.
+             h2 or h3) at host (this one without it) and port, e.g. h3=:443
+             for a QUIC proxy in front. Can be repeated, in order of
+             preference.
+  --alt-svc-max-age <duration>
+             How long clients may remember the alternatives, 24h by default.
+  --doctor   Check the served directory, the port, the certificate, the limit
+             of open files, the settings and the clock (against
+             pool.ntp.org) before starting, print what's wrong and how to fix
+             it, and exit with 1 instead of starting on errors.
+  --expose   Same as -b 0.0.0.0, to let every network this computer is on
+             connect.
+  --upnp     Ask the router to forward the port (NAT-PMP or UPnP) while running.
+  --strict-http
+             Reject requests with bare LF line endings, whitespace before
+             colons, folded or invalid header lines with 400, instead of
+             tolerating them.
+  --server-token <token>
+             The Server response header, rust-std-web-server/<version> by
+             default. Empty to leave the header out.
+  --mime <ext=type>
+             Serve files ending in .ext as type, e.g.
+             md=text/markdown;charset=utf-8, over the built-in types. Can be
+             repeated.
+  --no-compress <type|.ext>
+             Never gzip files of this type, e.g. text/csv or text/*, or
+             ending in .ext, on top of images, archives, fonts and video,
+             which already are compressed. Can be repeated.
+  --header <'name: value'>
+             Add this header to every response which doesn't set it, e.g.
+             'X-Frame-Options: DENY'. Can be repeated.
+  --log-target <target>
+             Where messages go: console (default), syslog or journald.
+  --log-time <utc|local>
+             Start messages on the console with the time, as ISO 8601 in
+             UTC or in the local time zone (of TZ, else /etc/localtime).
+  --access-log <file>
+             Write a line per request to file (- for the standard output) in
+             the Combined Log Format of Apache and nginx, instead of the short
+             ones among the messages.
+  --access-log-format <format>
+             combined (default), or common to leave out the Referer and
+             User-Agent.
+  --log-exclude <path>
+             Leave requests for path and below (e.g. /healthz) out of the
+             access log, unless they fail. Can be repeated.
+  --log-sample <n>
+             Only log one in n requests which don't fail.
+  --log-timings
+             Add how long parsing, access checks, the first byte and the
+             whole response took, and the transfer rate, to the access log,
+             and log how many requests each connection carried. Clients
+             which send TE: trailers get how long listings took as a
+             Server-Timing trailer.
+  --metrics  Serve request timing histograms, body bytes sent against the
+             sizes of what was asked, responses cut short and connection
+             reuse counts at /_metrics, in the Prometheus text format.
+  --report <file>
+             Write what was done, as JSON, to file when stopping: uptime,
+             requests by status class, body bytes sent and failed
+             connections. It's logged either way.
+  --checksums
+             Send the SHA-256 of files as ETag and Content-Digest headers,
+             answer If-None-Match, and serve file.sha256 (in the sha256sum
+             format) for files without one. Checksums are computed on first
+             download, once for downloads arriving together, and kept while
+             the file is unchanged. Clients which send TE: trailers get the
+             Content-Digest of listings as a trailer.
+  --fingerprints
+             Answer requests for app.<hash>.js with app.js when its SHA-256
+             starts with hash (8 hex digits or more), and let clients cache
+             such responses forever, for cache busting without a build step.
+  --minify   Send HTML, CSS and JavaScript without their comments and extra
+             whitespace, for source trees served without a build step. Files
+             named *.min.* are left alone, results are kept until the file
+             changes and shared by the requests which wait for them.
+  --inject-html <file>
+             Add the content of this file to the HTML pages served, before
+             their </body> or at their end, e.g. a script or a banner, without
+             changing them. Pages are still streamed, but without ranges.
+  --filter <type=command>
+             Send files of this type (e.g. text/html, or text/* for all text)
+             as this shell command outputs them, given the file on its
+             standard input, e.g. to add an analytics snippet to pages. It
+             runs in the served directory, gets the path in $FILTER_PATH and
+             the type in $FILTER_TYPE, and a failure or a run longer than 10s
+             is answered with a 500. Its output should only change with the
+             file, for caches. Can be repeated, filters running in order.
+  --spa      Answer GET requests for paths without a file (nor directory)
+             with the index.html of the site, for the client-side routes of
+             single-page apps, e.g. /users/42 of a React or Vue app.
+  --zip      Serve what's inside zip files: /site.zip/docs/a.html is the
+             member docs/a.html of site.zip, and /site.zip/ its index.html.
+             The original file is still at /site.zip.
+  --media    Offer to play the audio and video files of directories in a
+             player page (dir/?player) or an M3U playlist (dir/?playlist).
+  --viewer   Show PDF and EPUB files in a page of their own (doc.pdf?view),
+             PDFs in the browser's viewer and EPUBs in a small reader.
+  --webdav   Answer WebDAV's PROPFIND, read-only, for the served directory to
+             be mounted as a drive, e.g. by macOS Finder (Connect to Server)
+             or Windows Explorer (Map network drive).
+  --search   Index the names of everything served, to answer /_search and
+             the search box of listings. The index is rebuilt in the
+             background.
+  --search-contents
+             Same as --search, also indexing the text of files up to 1 MiB.
+  --search-refresh <duration>
+             How often the search index is rebuilt, e.g. 30m; 10m by
+             default. Uploads and management actions trigger it too.
+  --debug-routes
+             Answer requests with an X-Debug-Route header with how their
+             path would be resolved instead: normalization, rules checked,
+             files tried and what would answer, as JSON. After
+             authentication, for troubleshooting only.
+  --mode <mode>
+             What clients are allowed to do, one of:
+               read-only    browse and download (default)
+               upload-only  upload new files, without listing or downloading
+               read-write   browse, download, upload, and with --auth, rename,
+                            move, delete and create folders from listings
+  --allow-upload
+             Same as --mode read-write.
+  --put-conflict <policy>
+             What happens to an upload while another one to the same path
+             is running: last-writer-wins (default), where the one to finish
+             last stays, or reject, where it gets a 409. Uploads are written
+             to a temporary file renamed once complete, and overwrites can
+             be made conditional with If-Match and the ETag of downloads.
+  --max-upload <size>
+             Largest file uploaded with PUT, e.g. 100M, over which it gets a
+             413. Unlimited by default.
+  --create-dirs
+             Create the missing parent directories of files uploaded with
+             PUT, instead of answering with a 409.
+  --trash <dir>
+             Move what management actions delete to dir (relative to the
+             served directory, where it's hidden, and on the same file
+             system), from where listings offer to restore it.
+  --trash-retention <duration>
+             How long deleted files stay in the trash, e.g. 12h or 30d; 7d
+             by default.
+  --webhook <url>
+             POST uploads and management actions, as JSON events, to this
+             URL (http:// only), e.g. {\"event\": \"upload\", \"path\":
+             \"/a.txt\", \"time\": 1700000000}; rename and move events have
+             a \"to\". Retried for about half a minute while it fails. Can be
+             repeated.
+  --webhook-secret <secret>
+             Sign events with an X-Webhook-Signature header: sha256= and the
+             hex HMAC-SHA256 of the body with this secret.
+  --hook <command>
+             Run this shell command on events, with the event as JSON on its
+             standard input, e.g. {\"event\":\"5xx\",\"count\":10,
+             \"window_seconds\":60,\"time\":1700000000}: start, stop (with
+             the summary of --report), 4xx and 5xx when that many responses
+             of the class were sent within a minute, and auth-failure (with
+             the method, path and client) when credentials are refused.
+             Commands run one at a time, in the background, and are killed
+             after 30s.
+  --hook-on <events>
+             Run --hook on these events only, comma separated, e.g.
+             5xx,auth-failure.
+  --hook-threshold <n>
+             Responses of a class within a minute which make a 4xx or 5xx
+             event, 10 by default.
+  --early-hint <path=link>
+             Answer GET requests for path (a file, or the index.html or
+             listing of a directory) with a 103 Early Hints response carrying
+             this Link header first, e.g. '/=</app.css>;rel=preload;as=style',
+             so browsers fetch it while the page comes. Can be repeated.
+  --maintenance-file <file>
+             Answer requests with 503 and the 503.html of the site (or a page
+             of ours) while file exists, to take the content down briefly.
+             SIGUSR1 also turns maintenance on, and off again.
+  --maintenance-allow <path>
+             Keep serving path and below (e.g. /healthz) during maintenance.
+             Can be repeated.
+  --maintenance-retry-after <duration>
+             When clients should come back, 5m by default.
+  --auth <user:password>
+             Require HTTP Basic authentication for every request.
+  --digest <path>
+             Use HTTP Digest authentication (SHA-256 or MD5) rather than
+             Basic for the user of --auth below path (e.g. /private/**), so
+             the password isn't sent over plain HTTP. Can be repeated.
+  --auth-path <path>
+             Only require authentication, with any of the options below too,
+             for what's below path (e.g. /private/**) rather than for every
+             request. Can be repeated. Management actions are then disabled.
+  --htpasswd <file>
+             Same as --auth, but with the users of an Apache htpasswd file
+             (bcrypt, MD5, SHA-1 and SHA-2 hashes). Reloaded on SIGHUP.
+  --pam <service>
+             Same as --auth, but the credentials are checked by PAM using the
+             given service (e.g. login). Needs the `pam` cargo feature.
+  --ldap <url>
+             Same as --auth, but the credentials are checked by binding to
+             an LDAP server (ldap://host[:port]) as the user.
+  --ldap-user-dn <template>
+             The DN to bind as, where {user} is replaced by the user name,
+             e.g. uid={user},ou=people,dc=example,dc=org or {user}@example.org
+             for Active Directory.
+  --ldap-group <dn>
+             Only let in members of this group (member, uniqueMember or
+             memberUid attributes). Needs a DN in --ldap-user-dn.
+  --oidc-issuer <url>
+             Instead of --auth, send browsers to log in at this OpenID Connect
+             provider (http:// only). Register http(s)://<host>/_oidc/callback
+             as redirect URI; /_oidc/logout ends the session. ID tokens must
+             be signed by a key of its jwks_uri (RS256), or with the client
+             secret (HS256).
+  --oidc-client-id <id>
+  --oidc-client-secret <secret>
+             The credentials of this server at the provider.
+  --oidc-require <claim=value>
+             Only let in users whose ID token has this claim value (or array
+             member), e.g. email_verified=true. Can be repeated.
+  --jwt-secret <secret>
+             Also accept `Authorization: Bearer` JSON Web Tokens signed with
+             this secret (HS256), for API clients. Alone, only tokens are
+             accepted.
+  --jwt-jwks <url>
+             Same as --jwt-secret, for tokens signed (RS256) by a key of this
+             JWK Set (http:// only), fetched hourly.
+  --jwt-issuer <iss>
+  --jwt-audience <aud>
+             The iss and aud claims tokens must have.
+  --token <path=token>
+             Require this token, in the X-Access-Token header or the token
+             query parameter, for everything below path (e.g.
+             /private/**=s3cret). Can be repeated; the longest matching path
+             wins and any of its tokens is accepted. Applies on top of the
+             other authentication options.
+  --quota <[path=]size>
+             Refuse uploads with 507 when they would take what's below path
+             (the whole served directory without one) past size bytes, with
+             an optional K, M, G or T suffix (e.g. uploads=10G). Can be
+             repeated; every quota which applies is checked.
+  --hide-dotfiles
+             Answer 404 for files and directories whose name starts with a
+             dot, and leave them out of listings.
+  --deny <name>
+             Same as --hide-dotfiles, for everything named name (e.g. .git or
+             node_modules) and below. Can be repeated.
+  --git      Same as --hide-dotfiles, for .git and what the .gitignore files
+             and .git/info/exclude ignore, the served directory being the
+             top of the work tree.
+  --git-ref <ref>
+             Same as --git, serving the files of the commit ref (e.g. HEAD,
+             main or v1.0) points to rather than the working directory,
+             read-only. Follows the ref as it moves.
+  --geoip <file>
+             Look clients up in this MaxMind DB (e.g. GeoLite2-Country.mmdb
+             or GeoLite2-ASN.mmdb) for their country and autonomous system,
+             which are logged with each request and counted by country in
+             /_metrics. Can be repeated, the first database which knows
+             wins.
+  --geo-allow <country|ASn>
+             Answer 403 to clients of other countries (e.g. FR) or autonomous
+             systems (e.g. AS3215), except those the databases don't know
+             such as local ones. Can be repeated.
+  --geo-deny <country|ASn>
+             Answer 403 to clients of this country or autonomous system. Can
+             be repeated.
+  --rate-limit <requests per second>
+             Answer 429, with a Retry-After header, to clients making more
+             requests than this, e.g. 10 or 0.5, on top of the limits of
+             --tenant and --agent.
+  --rate-burst <requests>
+             How many requests clients may make at once under --rate-limit,
+             as long as their rate stays under it. A second's worth by
+             default.
+  --ip-allow <range>
+             Only accept the connections of clients in this range of
+             addresses (e.g. 192.168.0.0/16, or an address alone), closing
+             the others' at once. Can be repeated.
+  --ip-deny <range>
+             Close the connections of clients in this range at once, unless
+             an --ip-allow range has them, e.g. --ip-allow 192.168.0.0/16
+             --ip-deny 0.0.0.0/0. Can be repeated.
+  --agent <class=policy[,policy]...>
+             What to do with the requests of a class of clients, by their
+             User-Agent: browser, bot (crawlers), bad-bot (crawlers known to
+             ignore robots.txt, e.g. MJ12bot), cli (curl, wget, HTTP
+             libraries) or other (the rest, and no User-Agent). Policies,
+             comma separated:
+               deny       403 for all its requests
+               rate=<n>   requests per minute and client, then 429
+               plain      directory listings as text, a name per line
+             e.g. bad-bot=deny or cli=plain. Can be repeated.
+  --tenant <host|/prefix=dir[,option]...>
+             Serve dir (below the served directory) as a site of its own,
+             for requests with this Host header or below /prefix, e.g.
+             blog.example.org=sites/blog or /docs=sites/docs; what's in dir
+             is only served that way. Options, comma separated:
+               realm=<name>     the realm of authentication challenges
+               rate=<n>         requests per minute and client, then 429
+               bandwidth=<size> bytes per second for all its responses
+               quota=<size>     same as --quota dir=size
+             The /_ endpoints and management actions are the main site's.
+             Can be repeated.
+  --canary <dir=percent>
+             Serve the pages of the main site from dir (below the served
+             directory) instead, for percent of its visitors, e.g. next=10 to
+             try a new build on a tenth of them. Visitors keep theirs with a
+             cookie, and it's logged with each request. What's in dir is only
+             served that way.
+  --vhost <host:dir>
+             Serve dir (anywhere) as the site of requests with this Host
+             header, e.g. blog.example.org:/srv/blog, read-only and without
+             the /_ endpoints or tenants, which are the main site's. Can be
+             repeated.
+  --vhost-default <host>
+             Serve the site of this --vhost to requests for other hosts, or
+             without a Host header, instead of the main site.
+  --proxy </prefix=url[,option]...>
+             Forward the requests below /prefix to the HTTP server at url,
+             e.g. /api=http://127.0.0.1:3000 for the backend of a single-page
+             app, and its responses back, WebSockets included, without its
+             Server and X-Powered-By headers. A path in url replaces /prefix,
+             e.g. /api=http://127.0.0.1:3000/ forwards /api/users as /users.
+             Path tokens and authentication apply, not --mode. Options:
+               via=<proxy>        reach url through this proxy rather than
+                                  --upstream-proxy, or direct
+               add=<name:value>   set this header of requests
+               remove=<name>      drop this header of requests
+               rename=<name:new>  send this header of requests as new
+               response-add=, response-remove=, response-rename=
+                                  the same for responses
+             Rules apply in order, and header values can't hold commas. Can
+             be repeated.
+  --upstream-proxy <url>
+             Make outbound connections (--proxy routes, webhooks, identity
+             providers) through this proxy, http://host:port (asked with
+             CONNECT) or socks5://host:port, with user:password@ before the
+             host if it needs credentials. Connections to loopback
+             addresses, and to the UPnP gateway, are direct.
+  --map </prefix=dir[,option]...>
+             Serve dir (anywhere) below /prefix, read-only, e.g.
+             /ubuntu=/mnt/mirror/ubuntu for a package mirror whose trees are
+             on different disks. The longest prefix wins. Options:
+               listing=off      403 for directories without an index page
+               cache=<policy>   Cache-Control of its files: a duration (e.g.
+                                1h), immutable or no-store
+             Can be repeated.
+  --fastcgi <host:port|unix:path>
+             Run .php scripts with the FastCGI server at this address, e.g.
+             PHP-FPM at 127.0.0.1:9000 or unix:/run/php/php-fpm.sock, for
+             any method, path info included (/index.php/posts/1). Directories
+             without an index.html get their index.php. Path tokens and
+             authentication apply, not --mode.
+
+Endpoints
+  /_api/tree?path=<dir>&depth=<n>
+             The tree below dir (the root by default) as nested JSON, n
+             levels deep (1 by default, at most 32), leaving out what the
+             client couldn't download. Not in upload-only mode.
+  /_search?q=<words>&path=<dir>
+             With --search, what's below dir (the root by default) with all
+             the words in its name, or its contents, as JSON, or a page for
+             browsers; 100 results at most, leaving out what the client
+             couldn't download. Not in upload-only mode.
+
+Environment
+  Options can also be set with WEBSERVER_<OPTION> variables, e.g.
+  WEBSERVER_SERVER_TOKEN for --server-token, and WEBSERVER_PORT,
+  WEBSERVER_BIND and WEBSERVER_DIR for -p, -b and -d. Switches like --upnp
+  are on when their variable is 1, true or yes. Options which can be repeated
+  take a whitespace separated list, one per line for --header.
+
+Config file
+  The keys of a --config file (or WEBSERVER_CONFIG) are named like those
+  variables, in lowercase, e.g.:
+    port = 8080
+    dir = \"/srv/www\"
+    upnp = true
+    deny = [\".git\", \"node_modules\"]
+    [mime]
+    md = \"text/markdown\"
+    [headers]
+    X-Frame-Options = \"DENY\"
+    [agents]
+    bad-bot = \"deny\"
+    cli = \"plain,rate=60\"
+  Relative paths in it are relative to the current directory, as on the
+  command line.
+
+  The command line takes precedence over the environment, which takes
+  precedence over the config file, which takes precedence over the defaults;
+  repeated options add up.
+
+Exit status
+  0 once stopped by SIGINT or SIGTERM, 1 on other errors, 2 for invalid
+  options or configuration, 3 when the address can't be listened on, and
+  128 plus the signal's number when stopped by a second one.
+";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    ReadOnly,
+    UploadOnly,
+    ReadWrite,
+}
+
+impl Mode {
+    fn name(self) -> &'static str {
+        match self {
+            Mode::ReadOnly => "read-only",
+            Mode::UploadOnly => "upload-only",
+            Mode::ReadWrite => "read-write",
+        }
+    }
+}
+
+pub use filter::Filter;
+
+pub struct Config {
+    port: u16,
+    address: String,
+    directory: String,
+    threads: usize,
+    // --max-conns, threads included
+    max_conns: Option<usize>,
+    // --cert and --key, for HTTPS
+    #[cfg(feature = "tls")]
+    tls: Option<tls::Tls>,
+    // --alt-svc, e.g. h3=":443"
+    alt_svc: Vec<String>,
+    alt_svc_max_age: Duration,
+    // zero to only wait for pipelined requests
+    keep_alive: Duration,
+    // --request-timeout
+    request_timeout: Option<deadline::Watchdog>,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    // bytes, for the body of any request
+    max_body: Option<u64>,
+    // --report, absolute
+    report: Option<std::path::PathBuf>,
+    check_config: bool,
+    // -h or -v
+    output: Option<&'static str>,
+    doctor: bool,
+    upnp: bool,
+    mode: Mode,
+    auth: Option<auth::Credentials>,
+    // --digest, for the user of --auth
+    digest: Option<auth::Digest>,
+    oidc: Option<auth::Oidc>,
+    jwt: Option<auth::Jwt>,
+    // normalized paths authentication is limited to, everything when empty
+    auth_paths: Vec<String>,
+    tokens: Vec<PathToken>,
+    quotas: Vec<quota::Quota>,
+    put_conflict: upload::Conflict,
+    // bytes, for a file uploaded with PUT
+    max_upload: Option<u64>,
+    // missing parent directories of uploads are created rather than a 409
+    create_dirs: bool,
+    uploads: upload::Uploads,
+    trash: Option<trash::Trash>,
+    webhooks: Option<webhook::Webhooks>,
+    hooks: Option<hook::Hooks>,
+    // normalized paths, and a Link header sent ahead of them
+    early_hints: Vec<(String, String)>,
+    maintenance: maintenance::Maintenance,
+    hidden: Hidden,
+    // --git-ref, and the repository of the served directory
+    git_ref: Option<(String, git::Repository)>,
+    tenants: Vec<tenant::Tenant>,
+    canary: Option<canary::Canary>,
+    vhosts: Vec<vhost::Vhost>,
+    // the host of one of them
+    vhost_default: Option<String>,
+    maps: Vec<map::Map>,
+    geoip: Vec<geoip::Database>,
+    // uppercase country codes, and ASn
+    geo_allow: Vec<String>,
+    geo_deny: Vec<String>,
+    // --ip-allow and --ip-deny
+    ip_rules: cidr::Rules,
+    rate_limit: Option<tenant::TokenBuckets>,
+    agents: agent::Policies,
+    proxies: Vec<proxy::Route>,
+    upstream_proxy: Option<tunnel::Upstream>,
+    fastcgi: Option<fastcgi::Address>,
+    access_log: AccessLog,
+    log_timings: bool,
+    metrics: Option<metrics::Metrics>,
+    // for the headers and fingerprints, whichever is on
+    checksums: checksum::Checksums,
+    checksum_headers: bool,
+    fingerprints: bool,
+    minify: Option<minify::Minifier>,
+    inject_html: Option<inject::Snippet>,
+    // --filter's, then those added with add_filter
+    filters: Vec<Box<dyn filter::Filter>>,
+    // paths without a file get the index.html of the site
+    spa: bool,
+    zip: bool,
+    media: bool,
+    viewer: bool,
+    webdav: bool,
+    search: Option<search::Index>,
+    debug_routes: bool,
+    strict_http: bool,
+    // the Server header, None to leave it out
+    server: Option<String>,
+    // --mime, extensions without the dot
+    mime_types: Vec<(String, String)>,
+    // --no-compress, types (or "type/*") and extensions with the dot
+    no_compress: Vec<String>,
+    // --header, for every response
+    headers: Vec<(String, String)>,
+    log_target: log::Target,
+    // console messages start with the time when set
+    log_time: Option<date::Clock>,
+    changes: watch::Changes,
+}
+
+// Paths answered with 404 and left out of listings
+#[derive(Default)]
+struct Hidden {
+    dotfiles: bool,
+    // case folded, see fold_case
+    names: Vec<String>,
+    // normalized, e.g. the trash
+    paths: Vec<String>,
+    gitignore: Option<gitignore::Gitignore>,
+}
+
+impl Hidden {
+    // `path` is decoded and normalized, so "%2e%67it" or "a/../.git" can't
+    // slip past as something else than ".git"
+    fn matches(&self, path: &str) -> bool {
+        path.split('/').filter(|name| *name != ".").any(|name| {
+            (self.dotfiles && name.starts_with('.')) || self.names.contains(&fold_case(name))
+        }) || self.paths.iter().any(|prefix| is_below(path, prefix))
+            || self
+                .gitignore
+                .as_ref()
+                .is_some_and(|gitignore| gitignore.is_ignored(path))
+    }
+}
+
+fn fold_case(name: &str) -> String {
+    if CASE_INSENSITIVE_FS {
+        name.to_lowercase()
+    } else {
+        name.to_owned()
+    }
+}
+
+// Which responses make it to the log
+#[derive(Default)]
+struct AccessLog {
+    // normalized paths, see is_below
+    exclude: Vec<String>,
+    // one in `sample` successful responses is logged
+    sample: u64,
+    count: AtomicU64,
+    // --access-log, where they go instead of among the messages
+    file: Option<access_log::File>,
+}
+
+// Everything below `prefix` (a normalized path) needs `token`
+struct PathToken {
+    prefix: String,
+    token: String,
+}
+
+impl Config {
+    // Options as on the command line, `args` over the --config file among
+    // them, or what's wrong with them
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Config, ConfigError> {
+        let args = config::args(args, std::iter::empty()).map_err(ConfigError)?;
+        parse_args(args.into_iter()).map_err(ConfigError)
+    }
+
+    // The command line, over the WEBSERVER_<OPTION> variables, over the
+    // --config file
+    pub fn from_env() -> Result<Config, ConfigError> {
+        let args = config::args(std::env::args().skip(1), std::env::vars()).map_err(ConfigError)?;
+        parse_args(args.into_iter()).map_err(ConfigError)
+    }
+
+    // What -h or -v asked to print instead of serving
+    pub fn output(&self) -> Option<&'static str> {
+        self.output
+    }
+
+    // Whether --check-config asked only for check
+    pub fn checks_only(&self) -> bool {
+        self.check_config
+    }
+
+    // Prints the settings, the warnings and the problems of --check-config,
+    // and returns whether there were no problems
+    pub fn check(&self) -> bool {
+        for (key, value) in self.describe() {
+            println!("{key:<14}{value}");
+        }
+        for warning in self.warnings() {
+            eprintln!("warning: {warning}");
+        }
+        let problems = self.problems();
+        for problem in &problems {
+            eprintln!("error: {problem}");
+        }
+        problems.is_empty()
+    }
+
+    // For files of the types `filter` applies to, after the --filter ones
+    pub fn add_filter(&mut self, filter: Box<dyn Filter>) {
+        self.filters.push(filter);
+    }
+
+    fn has_auth(&self) -> bool {
+        self.auth.is_some() || self.oidc.is_some() || self.jwt.is_some()
+    }
+
+    // "https" with --cert and --key
+    fn scheme(&self) -> &'static str {
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() {
+            return "https";
+        }
+        "http"
+    }
+
+    // The Alt-Svc header, only sent over HTTPS as clients ignore it otherwise
+    fn alt_svc(&self) -> Option<String> {
+        if self.alt_svc.is_empty() || self.scheme() != "https" {
+            return None;
+        }
+        let max_age = self.alt_svc_max_age.as_secs();
+        let alternatives: Vec<_> = self
+            .alt_svc
+            .iter()
+            .map(|alternative| format!("{alternative}; ma={max_age}"))
+            .collect();
+        Some(alternatives.join(", "))
+    }
+
+    // What requests are read from: the TLS session, once the handshake is
+    // done, or the socket itself
+    fn connection(&self, tcp_stream: TcpStream) -> std::io::Result<Connection> {
+        tcp_stream.set_write_timeout(Some(self.write_timeout))?;
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            let stream: Connection = Box::new(tls.accept(tcp_stream)?);
+            // the handshake had a timeout of its own
+            stream.set_read_timeout(Some(self.read_timeout))?;
+            return Ok(stream);
+        }
+        tcp_stream.set_read_timeout(Some(self.read_timeout))?;
+        Ok(Box::new(tcp_stream))
+    }
+
+    // Connections open at once at most, those waiting for a thread included
+    fn max_conns(&self) -> usize {
+        self.max_conns.unwrap_or(self.threads * 2)
+    }
+
+    // management is too destructive to be left open to anyone
+    fn can_manage(&self) -> bool {
+        self.site_mode() == Mode::ReadWrite && self.has_auth() && self.auth_paths.is_empty()
+    }
+
+    // Whether requests for `path` must be authenticated, with any of the
+    // methods configured
+    fn requires_auth(&self, path: &str) -> bool {
+        self.auth_paths.is_empty()
+            || self.auth_paths.iter().any(|prefix| is_below(path, prefix))
+            // where OIDC logins come back to
+            || is_below(path, "_oidc")
+    }
+
+    // The mode of the site asked: --vhost sites are read-only
+    fn site_mode(&self) -> Mode {
+        match !root::is_main() {
+            true => Mode::ReadOnly,
+            false => self.mode,
+        }
+    }
+
+    // The settings in effect, without secrets
+    fn describe(&self) -> Vec<(&'static str, String)> {
+        let mut res = vec![
+            ("address", format!("{}:{}", self.address, self.port)),
+            ("directory", self.directory.clone()),
+            ("scheme", self.scheme().to_owned()),
+            ("mode", self.mode.name().to_owned()),
+            ("threads", self.threads.to_string()),
+            ("max conns", self.max_conns().to_string()),
+        ];
+        if self.keep_alive.is_zero() {
+            res.push(("keep-alive", "off".to_owned()));
+        } else {
+            res.push(("keep-alive", format!("{}s", self.keep_alive.as_secs())));
+        }
+        if let Some(watchdog) = &self.request_timeout {
+            res.push(("timeout", format_duration(watchdog.timeout)));
+        }
+        res.push(("read timeout", format_duration(self.read_timeout)));
+        res.push(("write timeout", format_duration(self.write_timeout)));
+        if let Some(max_body) = self.max_body {
+            res.push(("max body", quota::format_size(max_body)));
+        }
+        if let Some(report) = &self.report {
+            res.push(("report", report.display().to_string()));
+        }
+        if let Some(auth) = &self.auth {
+            res.push(("auth", auth.describe()));
+        }
+        if let Some(digest) = &self.digest {
+            res.push(("digest", digest.describe()));
+        }
+        if let Some(oidc) = &self.oidc {
+            res.push(("oidc", oidc.describe()));
+        }
+        if let Some(jwt) = &self.jwt {
+            res.push(("jwt", jwt.describe()));
+        }
+        for prefix in &self.auth_paths {
+            res.push(("auth path", format!("/{prefix}")));
+        }
+        for token in &self.tokens {
+            res.push(("token", format!("/{}", token.prefix)));
+        }
+        res.push(("put conflict", self.put_conflict.name().to_owned()));
+        if let Some(max_upload) = self.max_upload {
+            res.push(("max upload", quota::format_size(max_upload)));
+        }
+        if self.create_dirs {
+            res.push(("create dirs", "on".to_owned()));
+        }
+        if let Some(trash) = &self.trash {
+            let retention = format_duration(trash.retention);
+            res.push((
+                "trash",
+                format!("{} {retention}", trash.directory.display()),
+            ));
+        }
+        if let Some(webhooks) = &self.webhooks {
+            res.push(("webhooks", webhooks.describe()));
+        }
+        if let Some(hooks) = &self.hooks {
+            res.push(("hook", hooks.describe()));
+        }
+        for alternative in &self.alt_svc {
+            let max_age = self.alt_svc_max_age.as_secs();
+            res.push(("alt-svc", format!("{alternative} for {max_age}s")));
+        }
+        for (path, link) in &self.early_hints {
+            res.push(("early hint", format!("/{path} {link}")));
+        }
+        if let Some(file) = &self.maintenance.file {
+            res.push(("maintenance", format!("while {} exists", file.display())));
+        }
+        for prefix in &self.maintenance.allow {
+            res.push(("maintenance", format!("allows /{prefix}")));
+        }
+        for quota in &self.quotas {
+            let size = quota::format_size(quota.size);
+            res.push(("quota", format!("/{} {size}", quota.prefix)));
+        }
+        if self.hidden.dotfiles {
+            res.push(("hidden", "dotfiles".to_owned()));
+        }
+        for name in &self.hidden.names {
+            res.push(("hidden", name.clone()));
+        }
+        if self.hidden.gitignore.is_some() {
+            res.push(("hidden", ".gitignore".to_owned()));
+        }
+        if let Some((revision, _)) = &self.git_ref {
+            res.push(("git ref", revision.clone()));
+        }
+        for tenant in &self.tenants {
+            let mut description = format!("{} /{}", tenant.name(), tenant.root);
+            if let Some(realm) = &tenant.realm {
+                description.push_str(&format!(", realm {realm}"));
+            }
+            if let Some(rate) = tenant.rate.as_ref().map(|rate| rate.rate) {
+                description.push_str(&format!(", {rate}/min"));
+            }
+            if let Some(bandwidth) = &tenant.bandwidth {
+                let size = quota::format_size(bandwidth.bytes_per_second());
+                description.push_str(&format!(", {size}/s"));
+            }
+            res.push(("tenant", description));
+        }
+        for vhost in &self.vhosts {
+            let mut description = format!("{} {}", vhost.host, vhost.root.display());
+            if self.vhost_default.as_ref() == Some(&vhost.host) {
+                description.push_str(", default");
+            }
+            res.push(("vhost", description));
+        }
+        for route in &self.proxies {
+            res.push(("proxy", route.describe()));
+        }
+        if let Some(upstream) = &self.upstream_proxy {
+            res.push(("outbound via", upstream.to_string()));
+        }
+        for map in &self.maps {
+            res.push(("map", map.describe()));
+        }
+        if let Some(address) = &self.fastcgi {
+            res.push(("fastcgi", address.describe()));
+        }
+        for policy in self.agents.describe() {
+            res.push(("agent", policy));
+        }
+        for database in &self.geoip {
+            res.push(("geoip", database.path.clone()));
+        }
+        for rule in &self.geo_allow {
+            res.push(("geo allow", rule.clone()));
+        }
+        for rule in &self.geo_deny {
+            res.push(("geo deny", rule.clone()));
+        }
+        for range in &self.ip_rules.allow {
+            res.push(("ip allow", range.to_string()));
+        }
+        for range in &self.ip_rules.deny {
+            res.push(("ip deny", range.to_string()));
+        }
+        if let Some(buckets) = &self.rate_limit {
+            let rate = format!("{}/s, bursts of {}", buckets.rate, buckets.burst);
+            res.push(("rate limit", rate));
+        }
+        if let Some(canary) = &self.canary {
+            res.push(("canary", format!("/{} {}%", canary.root, canary.percent)));
+        }
+        let on_off = |on: bool| if on { "on" } else { "off" }.to_owned();
+        res.push(("upnp", on_off(self.upnp)));
+        res.push(("strict http", on_off(self.strict_http)));
+        res.push(("log target", self.log_target.name().to_owned()));
+        if let Some(clock) = self.log_time {
+            res.push(("log time", clock.name().to_owned()));
+        }
+        if let Some(file) = &self.access_log.file {
+            let format = file.format.name();
+            res.push(("access log", format!("{} ({format})", file.path)));
+        }
+        for prefix in &self.access_log.exclude {
+            res.push(("log exclude", format!("/{prefix}")));
+        }
+        if self.access_log.sample > 1 {
+            res.push(("log sample", format!("1 in {}", self.access_log.sample)));
+        }
+        res.push(("log timings", on_off(self.log_timings)));
+        res.push(("metrics", on_off(self.metrics.is_some())));
+        res.push(("checksums", on_off(self.checksum_headers)));
+        res.push(("fingerprints", on_off(self.fingerprints)));
+        res.push(("minify", on_off(self.minify.is_some())));
+        if let Some(snippet) = &self.inject_html {
+            let size = snippet.content.len();
+            res.push(("inject html", format!("{size} bytes before </body>")));
+        }
+        for filter in &self.filters {
+            res.push(("filter", filter.describe()));
+        }
+        res.push(("spa", on_off(self.spa)));
+        res.push(("zip browsing", on_off(self.zip)));
+        res.push(("media", on_off(self.media)));
+        res.push(("viewer", on_off(self.viewer)));
+        res.push(("webdav", on_off(self.webdav)));
+        match &self.search {
+            Some(index) => {
+                let indexed = if index.contents {
+                    "names and contents"
+                } else {
+                    "names"
+                };
+                let refresh = format_duration(index.refresh);
+                res.push(("search", format!("{indexed}, every {refresh}")));
+            }
+            None => res.push(("search", on_off(false))),
+        }
+        res.push(("debug routes", on_off(self.debug_routes)));
+        res.push((
+            "server header",
+            self.server.clone().unwrap_or_else(|| "off".to_owned()),
+        ));
+        for (ext, mime_type) in &self.mime_types {
+            res.push(("mime type", format!(".{ext} {mime_type}")));
+        }
+        for excluded in &self.no_compress {
+            res.push(("no compress", excluded.clone()));
+        }
+        for (name, value) in &self.headers {
+            res.push(("header", format!("{name}: {value}")));
+        }
+        res
+    }
+
+    // Problems which would keep the server from starting or working
+    fn problems(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        if !Path::new(&self.directory).is_dir() {
+            res.push(format!("'{}' is not a directory", self.directory));
+        }
+        if (self.address.as_str(), self.port)
+            .to_socket_addrs()
+            .is_err()
+        {
+            res.push(format!("'{}' is not an address to bind to", self.address));
+        }
+        if let Some(canary) = &self.canary
+            && !Path::new(&self.directory).join(&canary.root).is_dir()
+        {
+            res.push(format!(
+                "canary directory '{}' is not a directory",
+                canary.root
+            ));
+        }
+        for tenant in &self.tenants {
+            if !Path::new(&self.directory).join(&tenant.root).is_dir() {
+                res.push(format!(
+                    "tenant directory '{}' of {} is not a directory",
+                    tenant.root,
+                    tenant.name()
+                ));
+            }
+        }
+        for vhost in &self.vhosts {
+            if !vhost.root.is_dir() {
+                res.push(format!(
+                    "vhost directory '{}' of {} is not a directory",
+                    vhost.root.display(),
+                    vhost.host
+                ));
+            }
+        }
+        for map in &self.maps {
+            if !map.root.is_dir() {
+                res.push(format!(
+                    "map directory '{}' of /{} is not a directory",
+                    map.root.display(),
+                    map.prefix
+                ));
+            }
+        }
+        if self.git_ref.is_some() && self.mode != Mode::ReadOnly {
+            res.push(
+                "--git-ref serves a commit, which can't be changed: it needs --mode read-only"
+                    .to_owned(),
+            );
+        }
+        res
+    }
+
+    // Where other computers can connect, None when only this one can
+    fn exposure(&self) -> Option<String> {
+        let addresses: Vec<_> = (self.address.as_str(), self.port)
+            .to_socket_addrs()
+            .ok()?
+            .collect();
+        if addresses.iter().all(|address| address.ip().is_loopback()) {
+            return None;
+        }
+        if !addresses
+            .iter()
+            .any(|address| address.ip().is_unspecified())
+        {
+            let urls: Vec<_> = addresses
+                .iter()
+                .map(|address| format!("{}://{address}", self.scheme()))
+                .collect();
+            return Some(urls.join(", "));
+        }
+        // std can't list interfaces, but connecting a UDP socket (which
+        // sends nothing) tells the address of the one with the default route
+        let lan = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .and_then(|socket| {
+                socket.connect((Ipv4Addr::new(192, 0, 2, 1), 80))?;
+                socket.local_addr()
+            })
+            .ok()
+            .filter(|address| !address.ip().is_unspecified() && !address.ip().is_loopback());
+        Some(match lan {
+            Some(lan) => format!(
+                "every interface, e.g. {}://{}:{}",
+                self.scheme(),
+                lan.ip(),
+                self.port
+            ),
+            None => "every interface".to_owned(),
+        })
+    }
+
+    // Settings which work, but maybe not as intended
+    fn warnings(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        match self.exposure() {
+            Some(exposure) => {
+                let directory = std::fs::canonicalize(&self.directory).map_or_else(
+                    |_| self.directory.clone(),
+                    |path| path.display().to_string(),
+                );
+                res.push(format!(
+                    "'{directory}' is served to the network, on {exposure}"
+                ));
+            }
+            None if self.upnp => {
+                res.push("--upnp forwards the port of an address only this computer can reach, see --expose".to_owned());
+            }
+            None => (),
+        }
+        if self.mode == Mode::ReadWrite && !self.has_auth() {
+            res.push("management actions are disabled, they require --auth".to_owned());
+        } else if self.mode == Mode::ReadWrite && !self.auth_paths.is_empty() {
+            res.push("management actions are disabled, --auth-path leaves paths open".to_owned());
+        }
+        if !self.has_auth() && !self.auth_paths.is_empty() {
+            res.push("--auth-path is unused, there's no authentication".to_owned());
+        }
+        if self.trash.is_some() && !self.can_manage() {
+            res.push("the trash is unused, management actions are disabled".to_owned());
+        }
+        if self.git_ref.is_some() {
+            let unused: Vec<_> = [
+                ("--checksums", self.checksum_headers),
+                ("--fingerprints", self.fingerprints),
+                ("--minify", self.minify.is_some()),
+                ("--spa", self.spa),
+                ("--zip", self.zip),
+                ("--media", self.media),
+                ("--viewer", self.viewer),
+                ("--search", self.search.is_some()),
+            ]
+            .into_iter()
+            .filter_map(|(option, on)| on.then_some(option))
+            .collect();
+            if !unused.is_empty() {
+                res.push(format!(
+                    "{} only apply to the working directory, not --git-ref",
+                    unused.join(", ")
+                ));
+            }
+        }
+        if !self.alt_svc.is_empty() && self.scheme() != "https" {
+            res.push("--alt-svc is only advertised over HTTPS, see --cert".to_owned());
+        }
+        if self.mode == Mode::ReadOnly && !self.quotas.is_empty() {
+            res.push("quotas have no effect in read-only mode".to_owned());
+        }
+        for token in &self.tokens {
+            if !Path::new(&self.directory).join(&token.prefix).exists() {
+                res.push(format!(
+                    "token path '/{}' doesn't exist (yet)",
+                    token.prefix
+                ));
+            }
+        }
+        res
+    }
+}
+
+#[derive(Debug)]
+pub struct Request {
+    method: String,
+    path: String,
+    version: String,
+    // lowercase names, values without the whitespace around them
+    headers: HashMap<String, String>,
+    // what was tolerated while parsing, see --strict-http
+    deviations: Vec<&'static str>,
+    // fields of SINGLE_FIELDS which came more than once
+    repeated: Vec<String>,
+}
+
+impl Request {
+    // The head of a request, as a connection would read it
+    pub fn parse(mut input: &[u8]) -> Option<Request> {
+        parse_request(&mut input, None).ok().flatten()
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    fn cookie(&self, name: &str) -> Option<&str> {
+        self.header("Cookie")?
+            .split(';')
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    // Whether the query has `name`, with or without a value
+    fn has_query_param(&self, name: &str) -> bool {
+        self.path.split_once('?').is_some_and(|(_, query)| {
+            query.split('&').any(|param| {
+                url_decode(param.split('=').next().unwrap_or_default())
+                    .is_some_and(|key| key == name)
+            })
+        })
+    }
+
+    fn query_param(&self, name: &str) -> Option<String> {
+        let (_, query) = self.path.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| url_decode(key).is_some_and(|key| key == name))
+            .and_then(|(_, value)| url_decode(value))
+    }
+}
+
+// Returns None when the connection ends before a request; a head still coming
+// after `deadline` times out
+fn parse_request(
+    buf_reader: &mut impl BufRead,
+    deadline: Option<Instant>,
+) -> std::io::Result<Option<Request>> {
+    // This is the variable this function will return
+    let mut res = Request {
+        method: String::new(),
+        path: String::new(),
+        version: String::new(),
+        headers: HashMap::new(),
+        deviations: Vec::new(),
+        repeated: Vec::new(),
+    };
+
+    if let Some(status_line) = read_line(buf_reader, &mut res.deviations, deadline)? {
+        // parse the status line
+        // "GET /foo.txt HTTP/1.1"
+        let mut status_iter = status_line.split(' ');
+        let method = status_iter.next();
+        let path = status_iter.next();
+        let version = status_iter.next();
+        match (method, path, version) {
+            (Some(method), Some(path), Some(version)) => {
+                res.method = method.to_owned();
+                res.path = path.to_owned();
+                res.version = version.to_owned();
+            }
+            _ => {
+                let message = format!("invalid request line: {status_line}");
+                return Err(std::io::Error::new(ErrorKind::InvalidData, message));
+            }
+        };
+    } else {
+        return Ok(None);
+    };
+
+    // We suppose that all the other lines are headers
+    let mut last_key = None;
+    let mut lines = 0;
+    while let Some(line) = read_line(buf_reader, &mut res.deviations, deadline)? {
+        if line.is_empty() {
+            break;
+        }
+        lines += 1;
+        if lines > MAX_HEADERS {
+            let message = format!("more than {MAX_HEADERS} header fields");
+            return Err(std::io::Error::new(ErrorKind::FileTooLarge, message));
+        }
+        // obsolete line folding continues the previous field
+        if line.starts_with([' ', '\t']) {
+            note(&mut res.deviations, "obs-fold");
+            if let Some(value) = last_key.as_ref().and_then(|key| res.headers.get_mut(key)) {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            note(&mut res.deviations, "invalid field name");
+            continue;
+        };
+        if key.ends_with([' ', '\t']) {
+            note(&mut res.deviations, "whitespace before colon");
+        }
+        let key = key.trim_end();
+        if !is_token(key) {
+            note(&mut res.deviations, "invalid field name");
+            continue;
+        }
+        let key = key.to_ascii_lowercase();
+        let value = value.trim_matches([' ', '\t']);
+        // repeated fields are the same as one with a comma separated list
+        // (RFC 9110 section 5.3), but for cookies (RFC 6265 section 5.4)
+        match res.headers.get_mut(&key) {
+            Some(existing) => {
+                if SINGLE_FIELDS.contains(&key.as_str()) && !res.repeated.contains(&key) {
+                    res.repeated.push(key.clone());
+                }
+                existing.push_str(if key == "cookie" { "; " } else { ", " });
+                existing.push_str(value);
+            }
+            None => {
+                res.headers.insert(key.clone(), value.to_owned());
+            }
+        }
+        last_key = Some(key);
+    }
+
+    Ok(Some(res))
+}
+
+// The authority and the path of an absolute-form target (RFC 9112 section
+// 3.2.2), e.g. http://host/path, which servers must take too: the authority
+// stands for the Host field
+fn absolute_form(target: &str) -> Option<(String, String)> {
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let (authority, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    // userinfo is deprecated in http URIs (RFC 9110 section 4.2.4)
+    if authority.is_empty() || authority.contains('@') {
+        return None;
+    }
+    let path = match path.starts_with('/') {
+        true => path.to_owned(),
+        false => format!("/{path}"),
+    };
+    Some((authority.to_owned(), path))
+}
+
+// Reads a line without its ending, noting bare LF endings. Lines longer than
+// MAX_HEAD_LINE are refused as they come, not once they've all been read.
+fn read_line(
+    buf_reader: &mut impl BufRead,
+    deviations: &mut Vec<&'static str>,
+    deadline: Option<Instant>,
+) -> std::io::Result<Option<String>> {
+    let mut line = Vec::new();
+    loop {
+        // each read has its own timeout, a client trickling bytes would
+        // never hit it
+        if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            let message = "request head took too long";
+            return Err(std::io::Error::new(ErrorKind::TimedOut, message));
+        }
+        let available = match buf_reader.fill_buf() {
+            Ok(available) => available,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        let (length, complete) = match available.iter().position(|&byte| byte == b'\n') {
+            Some(end) => (end + 1, true),
+            None => (available.len(), false),
+        };
+        line.extend_from_slice(&available[..length]);
+        buf_reader.consume(length);
+        if line.len() > MAX_HEAD_LINE {
+            let message = format!("request head line longer than {MAX_HEAD_LINE} bytes");
+            return Err(std::io::Error::new(ErrorKind::FileTooLarge, message));
+        }
+        if complete || length == 0 {
+            break;
+        }
+    }
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let mut line = String::from_utf8(line)
+        .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "request head isn't UTF-8"))?;
+    if line.ends_with("\r\n") {
+        line.truncate(line.len() - 2);
+    } else if line.ends_with('\n') {
+        note(deviations, "bare LF");
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn note(deviations: &mut Vec<&'static str>, deviation: &'static str) {
+    if !deviations.contains(&deviation) {
+        deviations.push(deviation);
+    }
+}
+
+// RFC 9110 section 5.6.2
+fn is_token(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+fn url_encode(input: &str) -> String {
+    let mut res = String::new();
+
+    for c in input.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '~' | '_' | '-' => res.push(c),
+            c => {
+                for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                    res.push_str(&format!("%{byte:02X}"));
+                }
+            }
+        }
+    }
+
+    res
+}
+
+fn html_encode(input: String) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// None when a % isn't followed by two hex digits
+fn url_decode(input: &str) -> Option<String> {
+    let input = input.replace("+", " ");
+    // percent-encoded bytes are UTF-8 sequences, not chars
+    let mut res = Vec::new();
+    let mut iter = input.chars();
+
+    while let Some(c) = iter.next() {
+        if c == '%' {
+            // reading 2 more characters
+            let char1 = iter.next()?.to_digit(16)?;
+            let char2 = iter.next()?.to_digit(16)?;
+            res.push((char1 * 16 + char2) as u8);
+        } else {
+            res.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+    }
+
+    Some(String::from_utf8_lossy(&res).into_owned())
+}
+
+fn normalize_path(path: String) -> String {
+    let mut res = Vec::new();
+
+    for part in path.split("/") {
+        match part {
+            "" // ignore empty directories (multiple /)
+          | "." => (), // ignore current directory
+            ".." => {
+                let _ = res.pop();
+            }
+            part => res.push(part),
+        }
+    }
+
+    res.join("/")
+}
+
+// The HTML goes out as it's generated, so big directories don't have to fit
+// in memory as a page; only their names do, to be sorted.
+fn list_directory(
+    res: &mut impl Write,
+    directory: &str,
+    config: &Config,
+    csrf_token: &str,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(
+        res,
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+  <meta charset=\"utf-8\">
+  <title>Index of {directory}</title>
+  <style>
+  body {{
+    background-color: Canvas;
+    color: CanvasText;
+    color-scheme: light dark;
+  }}
+  a, a:visited, a:active {{
+    text-decoration: none;
+  }}
+  #drop-zone {{
+    border: 2px dashed GrayText;
+    padding: 1em;
+    text-align: center;
+  }}
+  #drop-zone.dragging {{
+    border-color: LinkText;
+  }}
+  details.actions {{
+    display: inline-block;
+  }}
+  details.actions form, form.restore {{
+    display: inline;
+  }}
+  </style>
+</head>"
+    )?;
+    writeln!(res, "<h1>Directory Listing</h1>")?;
+    writeln!(res, "<h2>Directory: {directory}</h2>")?;
+    writeln!(res, "<hr>")?;
+    // tenants and --vhost sites don't get the /_ endpoints
+    let main_site = tenant::owner(&config.tenants, directory).is_none() && root::is_main();
+    if config.search.is_some() && config.site_mode() != Mode::UploadOnly && main_site {
+        writeln!(res, "{}", search_form(directory, ""))?;
+        writeln!(res, "<hr>")?;
+    }
+    let can_manage = config.can_manage() && main_site;
+    if can_manage {
+        writeln!(
+            res,
+            "<form method=\"post\" action=\"/_manage/mkdir\">
+  <input type=\"hidden\" name=\"csrf\" value=\"{csrf_token}\">
+  <input type=\"hidden\" name=\"path\" value=\"{}\">
+  <input name=\"name\" placeholder=\"folder name\" required>
+  <button>New folder</button>
+</form>
+<hr>",
+            html_encode(directory.to_owned())
+        )?;
+    }
+    if config.site_mode() != Mode::ReadOnly {
+        writeln!(
+            res,
+            "<div id=\"drop-zone\">
+  Drop files here or <input id=\"file-input\" type=\"file\" multiple>
+  <ul id=\"uploads\"></ul>
+</div>
+<hr>"
+        )?;
+    }
+    if config.site_mode() == Mode::UploadOnly {
+        // drop boxes don't show what others dropped
+        writeln!(res, "<script>\n{UPLOAD_SCRIPT}</script>")?;
+        writeln!(res, "</html>")?;
+        return Ok(());
+    }
+
+    writeln!(res, "<ul>")?;
+
+    // The first entry is always '..'
+    writeln!(res, "  <li><a href=\"..\">..</a></li>")?;
+
+    let (directories, files) = list_entries(directory, config)?;
+    let has_media = config.media && files.iter().any(|file| media::is_media(&mime_type(file)));
+
+    let now = date::now();
+    for path_string in directories {
+        writeln!(
+            res,
+            "  <li><a href=\"{}\">{}</a>{}{}</li>",
+            url_encode(&path_string),
+            html_encode(format!("📁 {path_string}/")),
+            modified_ago(directory, &path_string, now),
+            manage_actions(directory, &path_string, can_manage, csrf_token)
+        )?;
+    }
+
+    for path_string in files {
+        let view = if config.viewer && viewer::is_viewable(&mime_type(&path_string)) {
+            format!(
+                " <a href=\"{}?{}\">view</a>",
+                url_encode(&path_string),
+                viewer::PARAM
+            )
+        } else {
+            String::new()
+        };
+        writeln!(
+            res,
+            "  <li><a href=\"{}\">{}</a>{}{view}{}</li>",
+            url_encode(&path_string),
+            html_encode(format!("📄 {path_string}")),
+            modified_ago(directory, &path_string, now),
+            manage_actions(directory, &path_string, can_manage, csrf_token)
+        )?;
+    }
+
+    writeln!(res, "</ul>")?;
+    if has_media {
+        writeln!(
+            res,
+            "<p>▶ <a href=\"?{}\">Play all</a> · <a href=\"?{}\">Playlist (M3U)</a></p>",
+            media::PLAYER_PARAM,
+            media::PLAYLIST_PARAM
+        )?;
+    }
+    writeln!(res, "<hr>")?;
+    if can_manage && let Some(trash) = &config.trash {
+        list_trash(res, directory, trash, csrf_token)?;
+    }
+    if config.site_mode() == Mode::ReadWrite {
+        writeln!(res, "<script>\n{UPLOAD_SCRIPT}</script>")?;
+    }
+    // changes to --vhost sites aren't watched
+    if root::is_main() {
+        writeln!(res, "<script>\n{WATCH_SCRIPT}</script>")?;
+    }
+    writeln!(res, "</html>")?;
+
+    Ok(())
+}
+
+// When an entry of a listing last changed, e.g. " <small>3 hours ago</small>"
+fn modified_ago(directory: &str, name: &str, now: u64) -> String {
+    let modified = std::fs::metadata(root::path(&normalize_path(format!("{directory}/{name}"))))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+    match modified {
+        Some(modified) => format!(" <small>{}</small>", time_element(modified.as_secs(), now)),
+        None => String::new(),
+    }
+}
+
+// How long ago, with the date itself for tooltips and scripts
+fn time_element(timestamp: u64, now: u64) -> String {
+    format!(
+        "<time datetime=\"{}\" title=\"{}\">{}</time>",
+        date::iso8601(timestamp, date::Clock::Utc),
+        date::http_date(timestamp),
+        date::ago(timestamp, now)
+    )
+}
+
+// The names of the directories and files in `directory` which are listed,
+// sorted
+fn list_entries(directory: &str, config: &Config) -> std::io::Result<(Vec<String>, Vec<String>)> {
+    let mut directories = Vec::new();
+    let mut files = Vec::new();
+
+    for path in std::fs::read_dir(root::path(directory))? {
+        let path = path?;
+        let path_string = path
+            .file_name()
+            .into_string()
+            .unwrap_or_else(|_| panic!("cannot convert '{path:?}' into a string!"));
+        // by path, as names alone don't say whether e.g. the trash is below
+        let entry = normalize_path(format!("{directory}/{path_string}"));
+        if config.hidden.matches(&entry)
+            || root::is_main()
+                && (tenant::owner(&config.tenants, &entry)
+                    != tenant::owner(&config.tenants, directory)
+                    || canary::owns(config.canary.as_ref(), &entry)
+                        != canary::owns(config.canary.as_ref(), directory))
+        {
+            continue;
+        }
+        if path.file_type()?.is_dir() {
+            directories.push(path_string);
+        } else {
+            files.push(path_string);
+        }
+    }
+
+    directories.sort();
+    files.sort();
+    Ok((directories, files))
+}
+
+// --agent plain: a name per line, with a slash after those of directories
+fn list_plain(directory: &str, config: &Config) -> std::io::Result<String> {
+    let (directories, files) = list_entries(directory, config)?;
+    let mut res = String::new();
+    for name in directories {
+        res.push_str(&format!("{name}/\n"));
+    }
+    for name in files {
+        res.push_str(&format!("{name}\n"));
+    }
+    Ok(res)
+}
+
+// What was deleted from `directory` and can still be restored
+fn list_trash(
+    res: &mut impl Write,
+    directory: &str,
+    trash: &trash::Trash,
+    csrf_token: &str,
+) -> Result<(), Box<dyn Error>> {
+    let directory = normalize_path(directory.to_owned());
+    let entries: Vec<_> = trash
+        .entries()
+        .into_iter()
+        .filter(|entry| {
+            entry
+                .origin
+                .rsplit_once('/')
+                .map_or("", |(parent, _)| parent)
+                == directory
+        })
+        .collect();
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(res, "<h3>Recently deleted</h3>")?;
+    writeln!(res, "<ul>")?;
+    for entry in entries {
+        let name = entry.origin.rsplit('/').next().unwrap_or_default();
+        writeln!(
+            res,
+            "  <li>{} <small>{}</small> <form class=\"restore\" method=\"post\" action=\"/_manage/undelete\">\
+<input type=\"hidden\" name=\"csrf\" value=\"{csrf_token}\">\
+<input type=\"hidden\" name=\"path\" value=\"{}\">\
+<input type=\"hidden\" name=\"id\" value=\"{}\"> <button>Restore</button></form></li>",
+            html_encode(format!("🗑 {name}")),
+            time_element(entry.deleted, date::now()),
+            html_encode(entry.origin.clone()),
+            entry.id
+        )?;
+    }
+    writeln!(res, "</ul>")?;
+    writeln!(res, "<hr>")?;
+    Ok(())
+}
+
+fn manage_actions(directory: &str, name: &str, can_manage: bool, csrf_token: &str) -> String {
+    if !can_manage {
+        return String::new();
+    }
+
+    let path = html_encode(format!("{directory}/{name}"));
+    let name = html_encode(name.to_owned());
+    let fields = format!(
+        "<input type=\"hidden\" name=\"csrf\" value=\"{csrf_token}\">\
+<input type=\"hidden\" name=\"path\" value=\"{path}\">"
+    );
+    format!(
+        " <details class=\"actions\"><summary>actions</summary>
+    <form method=\"post\" action=\"/_manage/rename\">\
+{fields}\
+<input name=\"to\" value=\"{name}\" required> <button>Rename</button></form>
+    <form method=\"post\" action=\"/_manage/move\">\
+{fields}\
+<input name=\"to\" placeholder=\"destination folder\" required> <button>Move</button></form>
+    <form method=\"post\" action=\"/_manage/delete\" data-name=\"{name}\" \
+onsubmit=\"return confirm('Delete ' + this.dataset.name + '?')\">\
+{fields} <button>Delete</button></form>
+  </details>"
+    )
+}
+
+// --mime, over the types below
+static MIME_TYPES: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+fn mime_type(file_path: &str) -> String {
+    let filename = Path::new(file_path)
+        .file_name()
+        .unwrap_or_else(|| panic!("invalid file_path: {file_path}"));
+
+    let Some(ext) = filename
+        .to_str()
+        .unwrap_or_else(|| panic!("filename '{filename:?}' is not utf-8 valid"))
+        .split('.')
+        .next_back()
+    else {
+        return String::from(DEFAULT_MIME_TYPE);
+    };
+
+    if let Some((_, mime_type)) = MIME_TYPES
+        .get()
+        .into_iter()
+        .flatten()
+        .find(|(known, _)| known.eq_ignore_ascii_case(ext))
+    {
+        return mime_type.clone();
+    }
+
+    match ext {
+        "html" | "htm" => String::from("text/html"),
+        "jpeg" | "jpg" => String::from("image/jpeg"),
+        "png" => String::from("image/png"),
+        "webp" => String::from("image/webp"),
+        "avif" => String::from("image/avif"),
+        "txt" => String::from("text/plain"),
+        "css" => String::from("text/css"),
+        "js" => String::from("text/javascript"),
+        "json" => String::from("application/json"),
+        "mp3" => String::from("audio/mpeg"),
+        "m4a" => String::from("audio/mp4"),
+        "aac" => String::from("audio/aac"),
+        "flac" => String::from("audio/flac"),
+        "wav" => String::from("audio/wav"),
+        "ogg" | "oga" | "opus" => String::from("audio/ogg"),
+        "mp4" | "m4v" => String::from("video/mp4"),
+        "webm" => String::from("video/webm"),
+        "ogv" => String::from("video/ogg"),
+        "mkv" => String::from("video/x-matroska"),
+        "mov" => String::from("video/quicktime"),
+        "pdf" => String::from("application/pdf"),
+        "epub" => String::from("application/epub+zip"),
+        _ => String::from(DEFAULT_MIME_TYPE),
+    }
+}
+
+// Text gets much smaller gzipped, images and archives already are compressed
+fn is_compressible(mime_type: &str) -> bool {
+    let essence = mime_type.split(';').next().unwrap_or_default().trim();
+    essence.starts_with("text/")
+        || essence.ends_with("+xml")
+        || essence.ends_with("+json")
+        || matches!(
+            essence,
+            "application/json" | "application/javascript" | "application/xml"
+        )
+}
+
+// Whether `file` of `mime_type` must be sent as it is, being compressed already
+// or excluded with --no-compress
+fn is_compression_excluded(file: &str, mime_type: &str, excluded: &[String]) -> bool {
+    let essence = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let ext = file
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    COMPRESSED_EXTENSIONS.contains(&ext.as_str())
+        || excluded
+            .iter()
+            .any(|excluded| match excluded.strip_prefix('.') {
+                Some(excluded) => *excluded == ext,
+                None => match excluded.strip_suffix("/*") {
+                    Some(top) => essence.split('/').next() == Some(top),
+                    None => *excluded == essence,
+                },
+            })
+}
+
+// Whether Accept lists `mime_type` with a nonzero q-value; wildcards don't
+// count, as clients which send them may not know the newer image types
+fn accepts_type(accept: &str, mime_type: &str) -> bool {
+    accept.split(',').any(|item| {
+        let mut params = item.split(';').map(str::trim);
+        params
+            .next()
+            .is_some_and(|name| name.eq_ignore_ascii_case(mime_type))
+            && params
+                .filter_map(|param| param.strip_prefix("q=").or(param.strip_prefix("Q=")))
+                .next()
+                .is_none_or(|q| q.parse::<f32>().is_ok_and(|q| q > 0.0))
+    })
+}
+
+// Whether Accept-Encoding allows `coding`, named or through "*", with a
+// nonzero q-value (RFC 9110 section 12.5.3)
+fn accepts_coding(accept_encoding: &str, coding: &str) -> bool {
+    let mut wildcard = false;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let accepted = params
+            .filter_map(|param| param.strip_prefix("q=").or(param.strip_prefix("Q=")))
+            .next()
+            .is_none_or(|q| q.parse::<f32>().is_ok_and(|q| q > 0.0));
+        if name.eq_ignore_ascii_case(coding) {
+            return accepted;
+        }
+        if name == "*" {
+            wildcard = accepted;
+        }
+    }
+    wildcard
+}
+
+// Requests are answered one after the other, until the client closes the
+// connection, asks to, or keeps it idle longer than --keep-alive.
+fn handle_connection(tcp_stream: Connection, config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut connection = metrics::Connection::start();
+    let peer = tcp_stream.peer_addr();
+    let mut buf_reader = BufReader::new(tcp_stream);
+    let wait = if config.keep_alive.is_zero() {
+        PIPELINE_WAIT
+    } else {
+        config.keep_alive
+    };
+    let res = loop {
+        match process_request(&mut buf_reader, config, &mut connection) {
+            Ok(true) if has_next_request(&mut buf_reader, wait, config.read_timeout) => {}
+            Ok(_) => break Ok(()),
+            Err(err) => break Err(err),
+        }
+    };
+
+    let end = Instant::now();
+    if let Some(metrics) = &config.metrics {
+        metrics.observe_connection(&connection, end);
+    }
+    if config.log_timings {
+        let peer = peer.map_or_else(|_| "unknown".to_owned(), |peer| peer.to_string());
+        let stats = connection.describe(end);
+        log::record(
+            log::Level::Info,
+            &format!("connection from {peer} closed: {stats}"),
+            &[("HTTP_PEER", &peer), ("HTTP_CONNECTION", &stats)],
+        );
+    }
+    res
+}
+
+// Whether the client sends another request within `wait`. Clients pipelining
+// don't wait for our responses, so theirs is already there or about to be.
+fn has_next_request(
+    buf_reader: &mut BufReader<Connection>,
+    wait: Duration,
+    read_timeout: Duration,
+) -> bool {
+    if !buf_reader.buffer().is_empty() {
+        return true;
+    }
+    if buf_reader.get_ref().set_read_timeout(Some(wait)).is_err() {
+        return false;
+    }
+    let res = buf_reader.fill_buf().is_ok_and(|buf| !buf.is_empty());
+    res && buf_reader
+        .get_ref()
+        .set_read_timeout(Some(read_timeout))
+        .is_ok()
+}
+
+// Whether a read failed for taking longer than the socket's timeout, which
+// shows as either kind depending on the platform
+fn timed_out(err: &std::io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+// Answers one request and returns whether the connection is still in a state
+// to read another one
+fn process_request(
+    buf_reader: &mut BufReader<Connection>,
+    config: &Config,
+    connection: &mut metrics::Connection,
+) -> Result<bool, Box<dyn Error>> {
+    // the clock starts with the request, not while the connection is idle
+    match buf_reader.fill_buf() {
+        Ok(buf) if !buf.is_empty() => {}
+        // no request came within the read timeout, or at all
+        Ok(_) => return Ok(false),
+        Err(err) if timed_out(&err) => return Ok(false),
+        Err(err) => return Err(err.into()),
+    }
+    let mut timings = metrics::Timings::start();
+    let deadline = Instant::now() + config.read_timeout;
+    let mut request = match parse_request(buf_reader, Some(deadline)) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(false),
+        // a head which stopped halfway, which isn't HTTP or which is too
+        // large, in strict and compat modes alike
+        Err(err)
+            if timed_out(&err)
+                || matches!(err.kind(), ErrorKind::InvalidData | ErrorKind::FileTooLarge) =>
+        {
+            let status = match err.kind() {
+                _ if timed_out(&err) => StatusCode::RequestTimeout,
+                ErrorKind::FileTooLarge => {
+                    log::warning(&err.to_string());
+                    StatusCode::RequestHeaderFieldsTooLarge
+                }
+                _ => {
+                    log::warning(&err.to_string());
+                    StatusCode::BadRequest
+                }
+            };
+            response::set_closing(true);
+            response::set_http_10(false);
+            response::set_head_only(false);
+            let mut tcp_stream = buf_reader.get_ref().try_clone()?;
+            send_status(&mut tcp_stream, status, &[])?;
+            if let Some(sent) = response::take_sent() {
+                report::observe(sent.status, sent.body_bytes);
+            }
+            return Ok(false);
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let _in_flight = shutdown::InFlight::new();
+    // all of a request is answered from the same release
+    if release::enter()?
+        && let Some(index) = &config.search
+    {
+        index.changed();
+    }
+    let mut tcp_stream = buf_reader.get_ref().try_clone()?;
+    let peer = tcp_stream.peer_addr().ok().map(|peer| peer.ip());
+    // upgraded connections last as long as they're used
+    let _watch = match &config.request_timeout {
+        Some(watchdog) if request.header("Upgrade").is_none() => {
+            let from = peer.map_or_else(|| "unknown".to_owned(), |peer| peer.to_string());
+            let what = format!("{} {} from {from}", request.method, request.path);
+            Some(watchdog.watch(tcp_stream.try_clone()?, what))
+        }
+        _ => None,
+    };
+    // until it's known whether the connection can go on
+    response::set_closing(true);
+    response::set_http_10(request.version == "HTTP/1.0");
+    response::set_head_only(request.method == "HEAD");
+    response::set_throttle(None);
+    response::set_extra(Vec::new());
+    canary::set_variant(None);
+    error_page::set_root(".");
+    root::set(None);
+    // validate the request
+    if !["HTTP/1.0", "HTTP/1.1"].contains(&request.version.as_str()) {
+        log::warning(&format!("unsupported HTTP version: {}", request.version));
+        send_status(&mut tcp_stream, StatusCode::HttpVersionNotSupported, &[])?;
+        record_request(config, &request, peer, &timings, connection);
+        return Ok(false);
+    }
+    if response::http_10() {
+        // RFC 9110 section 7.8: upgrades are for HTTP/1.1
+        request.headers.remove("upgrade");
+    }
+    // about the server rather than a resource (RFC 9110 section 9.3.7)
+    if request.method == "OPTIONS" && request.path == "*" {
+        let allow = server_methods(config);
+        let mut headers = vec![("Allow", allow.as_str())];
+        if config.webdav {
+            headers.push(("DAV", "1"));
+        }
+        send_status(&mut tcp_stream, StatusCode::Ok, &headers)?;
+        record_request(config, &request, peer, &timings, connection);
+        return Ok(false);
+    }
+    if let Some((authority, path)) = absolute_form(&request.path) {
+        request.headers.insert("host".to_owned(), authority);
+        request.path = path;
+    }
+    if !request.path.starts_with('/') {
+        log::warning(&format!("request target isn't a path: {}", request.path));
+        send_status(&mut tcp_stream, StatusCode::BadRequest, &[])?;
+        record_request(config, &request, peer, &timings, connection);
+        return Ok(false);
+    }
+
+    if !request.deviations.is_empty() {
+        let deviations = request.deviations.join(", ");
+        if config.strict_http {
+            log::warning(&format!("rejected in strict mode: {deviations}"));
+            send_status(&mut tcp_stream, StatusCode::BadRequest, &[])?;
+            record_request(config, &request, peer, &timings, connection);
+            return Ok(false);
+        }
+        log::info(&format!("tolerated in compat mode: {deviations}"));
+    }
+
+    if let Err(status) = check_framing(&mut request) {
+        send_status(&mut tcp_stream, status, &[])?;
+        record_request(config, &request, peer, &timings, connection);
+        return Ok(false);
+    }
+    timings.parsed = Some(Instant::now());
+
+    // decoded here, then read as if it had been sent with its length, also
+    // by --proxy and --fastcgi backends
+    let mut decoded = None;
+    if request.headers.remove("transfer-encoding").is_some() {
+        // an upgraded connection would go on from the decoded body
+        request.headers.remove("upgrade");
+        let max = config.max_body.unwrap_or(MAX_CHUNKED_BODY);
+        match client::read_chunked(buf_reader, max) {
+            Ok(body) => {
+                let length = body.len().to_string();
+                request.headers.insert("content-length".to_owned(), length);
+                let memory: Connection = Box::new(stream::Memory::new(body));
+                decoded = Some(BufReader::new(memory));
+            }
+            Err(err) => {
+                let status = match err.kind() {
+                    ErrorKind::FileTooLarge => StatusCode::ContentTooLarge,
+                    _ if timed_out(&err) => StatusCode::RequestTimeout,
+                    _ => StatusCode::BadRequest,
+                };
+                send_status(&mut tcp_stream, status, &[])?;
+                record_request(config, &request, peer, &timings, connection);
+                return Ok(false);
+            }
+        }
+    }
+
+    // the next request starts after the body, whether we read it or not
+    let length = match request.header("Content-Length") {
+        Some(length) => length.parse()?,
+        None => 0,
+    };
+    if config.max_body.is_some_and(|max| length > max) {
+        // rather than reading it all to get to the next request
+        send_status(&mut tcp_stream, StatusCode::ContentTooLarge, &[])?;
+        record_request(config, &request, peer, &timings, connection);
+        return Ok(false);
+    }
+    let has_option = |name: &str| {
+        request.header("Connection").is_some_and(|value| {
+            value
+                .split(',')
+                .any(|option| option.trim().eq_ignore_ascii_case(name))
+        })
+    };
+    // HTTP/1.0 connections only go on when the client asks
+    let close = has_option("close")
+        || (response::http_10() && !has_option("keep-alive"))
+        || request.header("Upgrade").is_some()
+        || connection.requests + 1 >= MAX_CONNECTION_REQUESTS
+        || shutdown::draining();
+    response::set_closing(close);
+    let mut body = match &mut decoded {
+        Some(decoded) => decoded.take(length),
+        None => buf_reader.take(length),
+    };
+    if let Err(err) = respond(&request, &mut body, &mut tcp_stream, config, &mut timings) {
+        // unless the response had started, the client gets to know
+        if !response::started() {
+            response::set_closing(true);
+            let status = match err.downcast_ref::<std::io::Error>() {
+                Some(err) if timed_out(err) => StatusCode::RequestTimeout,
+                _ => StatusCode::InternalServerError,
+            };
+            let _ = send_status(&mut tcp_stream, status, &[]);
+        }
+        record_request(config, &request, peer, &timings, connection);
+        return Err(err);
+    }
+    record_request(config, &request, peer, &timings, connection);
+
+    Ok(body.limit() == 0 && !response::closing())
+}
+
+// Metrics, and the access log where errors are always logged while the rest
+// can be left out or sampled
+fn record_request(
+    config: &Config,
+    request: &Request,
+    peer: Option<IpAddr>,
+    timings: &metrics::Timings,
+    connection: &mut metrics::Connection,
+) {
+    connection.requests += 1;
+    let variant = canary::take_variant();
+    let Some(sent) = response::take_sent() else {
+        return;
+    };
+    connection.upgraded |= sent.status == StatusCode::SwitchingProtocols;
+    report::observe(sent.status, sent.body_bytes);
+    if let Some(hooks) = &config.hooks {
+        hooks.observe(sent.status);
+    }
+    let end = Instant::now();
+    let tags = peer
+        .filter(|_| !config.geoip.is_empty())
+        .map(|peer| geoip::tags(&config.geoip, peer));
+    if let Some(metrics) = &config.metrics {
+        metrics.observe(timings, &sent, end);
+        if let Some(tags) = &tags {
+            metrics.observe_country(tags.country.as_deref().unwrap_or("unknown"));
+        }
+    }
+
+    let status = sent.status;
+    let rules = &config.access_log;
+    if status.code() < 400 {
+        let path = request.path.split('?').next().unwrap_or_default();
+        // respond answered 400 to paths which don't decode
+        let path = normalize_path(url_decode(path).unwrap_or_default());
+        if rules.exclude.iter().any(|prefix| is_below(&path, prefix)) {
+            return;
+        }
+        if rules.sample > 1
+            && !rules
+                .count
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(rules.sample)
+        {
+            return;
+        }
+    }
+
+    if let Some(file) = &rules.file {
+        let line = access_log::line(file.format, request, peer, status.code(), sent.body_bytes);
+        if let Err(err) = file.write(&line) {
+            log::error(&format!("writing to the access log failed: {err}"));
+        }
+        return;
+    }
+
+    let code = status.code().to_string();
+    let mut message = format!("{} {} {}", request.method, request.path, code);
+    // partial content, and downloads which didn't finish
+    let bytes = sent.body_bytes.to_string();
+    let size = sent.size.map(|size| size.to_string());
+    if let Some(size) = sent.size
+        && sent.length.is_some()
+        && sent.body_bytes != size
+    {
+        message.push_str(&format!(" {bytes} of {size} bytes"));
+    }
+    if sent.cut_short() {
+        message.push_str(" cut short");
+    }
+    let timing = config.log_timings.then(|| timings.describe(&sent, end));
+    let mut fields = vec![
+        ("HTTP_METHOD", request.method.as_str()),
+        ("HTTP_PATH", &request.path),
+        ("HTTP_STATUS", &code),
+        ("HTTP_BYTES", &bytes),
+    ];
+    if let Some(size) = &size {
+        fields.push(("HTTP_SIZE", size));
+    }
+    if let Some(timing) = &timing {
+        message.push(' ');
+        message.push_str(timing);
+        fields.push(("HTTP_TIMING", timing));
+    }
+    if let Some(variant) = variant {
+        message.push_str(&format!(" [{}]", variant.name()));
+        fields.push(("HTTP_VARIANT", variant.name()));
+    }
+    let asn = tags
+        .as_ref()
+        .and_then(|tags| tags.asn)
+        .map(|asn| asn.to_string());
+    if let Some(tags) = &tags
+        && let Some(description) = tags.describe()
+    {
+        message.push_str(&format!(" [{description}]"));
+        if let Some(country) = &tags.country {
+            fields.push(("HTTP_COUNTRY", country));
+        }
+    }
+    if let Some(asn) = &asn {
+        fields.push(("HTTP_ASN", asn));
+    }
+    log::record(log::Level::Info, &message, &fields);
+}
+
+fn respond(
+    request: &Request,
+    body: &mut Take<&mut BufReader<Connection>>,
+    tcp_stream: &mut Connection,
+    config: &Config,
+    timings: &mut metrics::Timings,
+) -> Result<(), Box<dyn Error>> {
+    // if we are here, we should reply to the caller
+    let request_path = match request.path.split_once('?') {
+        Some((path, _query_parameters)) => path,
+        None => &request.path,
+    };
+    let Some(path) = url_decode(request_path) else {
+        log::warning(&format!("invalid percent-encoding: {}", request.path));
+        send_status(tcp_stream, StatusCode::BadRequest, &[])?;
+        return Ok(());
+    };
+    let mut path = normalize_path(path);
+
+    // handle empty path (root path)
+    if path.is_empty() {
+        path.push('.');
+    }
+
+    // --vhost sites are sites of their own, without tenants
+    let vhost = vhost::resolve(
+        &config.vhosts,
+        config.vhost_default.as_deref(),
+        request.header("Host"),
+    );
+    root::set(vhost.map(|vhost| vhost.root.as_path()));
+    let tenant =
+        tenant::resolve(&config.tenants, request.header("Host"), &path).filter(|_| vhost.is_none());
+    if let Some(tenant) = tenant {
+        if let Some(retry_after) = tenant.limit(tcp_stream.peer_addr()?.ip()) {
+            let retry_after = retry_after.to_string();
+            send_status(
+                tcp_stream,
+                StatusCode::TooManyRequests,
+                &[("Retry-After", &retry_after)],
+            )?;
+            return Ok(());
+        }
+        response::set_throttle(tenant.bandwidth.clone());
+        error_page::set_root(&tenant.root);
+    }
+    let map = map::resolve(&config.maps, &path).filter(|_| vhost.is_none() && tenant.is_none());
+    if let Some(map) = map {
+        root::mount(&map.prefix, &map.root);
+    }
+
+    if let Some(buckets) = &config.rate_limit
+        && let Some(retry_after) = buckets.limit(tcp_stream.peer_addr()?.ip())
+    {
+        let retry_after = retry_after.to_string();
+        send_status(
+            tcp_stream,
+            StatusCode::TooManyRequests,
+            &[("Retry-After", &retry_after)],
+        )?;
+        return Ok(());
+    }
+
+    let agent = config
+        .agents
+        .get(agent::classify(request.header("User-Agent")));
+    if agent.deny {
+        send_status(tcp_stream, StatusCode::Forbidden, &[])?;
+        return Ok(());
+    }
+    if let Some(rate) = &agent.rate
+        && let Some(retry_after) = rate.limit(tcp_stream.peer_addr()?.ip())
+    {
+        let retry_after = retry_after.to_string();
+        send_status(
+            tcp_stream,
+            StatusCode::TooManyRequests,
+            &[("Retry-After", &retry_after)],
+        )?;
+        return Ok(());
+    }
+
+    if !(config.geo_allow.is_empty() && config.geo_deny.is_empty()) {
+        let tags = geoip::tags(&config.geoip, tcp_stream.peer_addr()?.ip());
+        let known = tags.describe().is_some();
+        if config.geo_deny.iter().any(|rule| tags.matches(rule))
+            || (known
+                && !config.geo_allow.is_empty()
+                && !config.geo_allow.iter().any(|rule| tags.matches(rule)))
+        {
+            send_status(tcp_stream, StatusCode::Forbidden, &[])?;
+            return Ok(());
+        }
+    }
+
+    if config.maintenance.active()
+        && !config
+            .maintenance
+            .allow
+            .iter()
+            .any(|prefix| is_below(&path, prefix))
+    {
+        let retry_after = config.maintenance.retry_after.as_secs().to_string();
+        send_status(
+            tcp_stream,
+            StatusCode::ServiceUnavailable,
+            &[("Retry-After", &retry_after), ("Cache-Control", "no-store")],
+        )?;
+        return Ok(());
+    }
+
+    if let Some(tenant) = tenant {
+        path = tenant.map(&path);
+    }
+
+    let realm = tenant
+        .and_then(|tenant| tenant.realm.as_deref())
+        .unwrap_or(AUTH_REALM);
+    if config.requires_auth(&path)
+        && let Some((status, headers)) = authenticate(request, config, &path, realm)
+    {
+        // rather than challenges to clients which haven't tried yet
+        if let Some(hooks) = &config.hooks
+            && request.header("Authorization").is_some()
+            && matches!(status, StatusCode::Unauthorized | StatusCode::Forbidden)
+        {
+            let client = tcp_stream
+                .peer_addr()
+                .map_or_else(|_| "unknown".to_owned(), |peer| peer.ip().to_string());
+            hooks.auth_failure(&request.method, &request.path, &client);
+        }
+        let headers: Vec<_> = headers
+            .iter()
+            .map(|(key, value)| (*key, value.as_str()))
+            .collect();
+        send_status(tcp_stream, status, &headers)?;
+        return Ok(());
+    }
+
+    if config.debug_routes && request.header(trace::HEADER).is_some() {
+        let trace = trace::route(request, config);
+        let length = trace.len().to_string();
+        response::write_head(
+            tcp_stream,
+            StatusCode::Ok,
+            &[
+                ("Content-Type", "application/json"),
+                ("Content-Length", &length),
+                ("Cache-Control", "no-store"),
+            ],
+        )?;
+        response::write_body(tcp_stream, trace.as_bytes())?;
+        return Ok(());
+    }
+
+    // what's in a tenant's directory, or the canary's, is only served through
+    // it
+    if root::is_main()
+        && (tenant::owner(&config.tenants, &path) != tenant.map(|tenant| tenant.root.as_str())
+            || canary::owns(config.canary.as_ref(), &path))
+    {
+        send_status(tcp_stream, StatusCode::NotFound, &[])?;
+        return Ok(());
+    }
+
+    if root::is_main()
+        && tenant.is_none()
+        && let Some(route) = proxy::resolve(&config.proxies, &path)
+    {
+        if let Some(status) = check_token(config, request, &path) {
+            send_status(tcp_stream, status, &[])?;
+            return Ok(());
+        }
+        timings.resolved = Some(Instant::now());
+        return proxy::forward(route, request, &path, body, tcp_stream, config.scheme());
+    }
+
+    // scripts take any method, as proxied requests do, but are files of the
+    // site which may be hidden or need a token
+    if let Some(address) = &config.fastcgi
+        && let Some((script, path_info)) = fastcgi::script(&path, request_path.ends_with('/'))
+    {
+        if let Some(status) =
+            check_access(config, "GET", &script).or_else(|| check_token(config, request, &script))
+        {
+            send_status(tcp_stream, status, &[])?;
+            return Ok(());
+        }
+        timings.resolved = Some(Instant::now());
+        return fastcgi::forward(
+            address,
+            request,
+            &script,
+            &path_info,
+            body,
+            tcp_stream,
+            config.scheme(),
+        );
+    }
+
+    if let Some(status) = check_access(config, &request.method, &path)
+        .or_else(|| check_token(config, request, &path))
+        .or_else(|| {
+            // WebSockets aren't subject to the same-origin policy either
+            let changes_state = !matches!(request.method.as_str(), "GET" | "HEAD")
+                || request.header("Upgrade").is_some();
+            (changes_state && is_cross_site(request)).then_some(StatusCode::Forbidden)
+        })
+    {
+        let allow = allowed_methods(config, &path);
+        let headers: &[_] = if status == StatusCode::MethodNotAllowed {
+            &[("Allow", allow.as_str())]
+        } else {
+            &[]
+        };
+        send_status(tcp_stream, status, headers)?;
+        return Ok(());
+    }
+    timings.resolved = Some(Instant::now());
+
+    let is_get = matches!(request.method.as_str(), "GET" | "HEAD");
+    if let Some(metrics) = &config.metrics
+        && is_get
+        && root::is_main()
+        && path == metrics::PATH
+    {
+        let metrics = metrics.render();
+        let length = metrics.len().to_string();
+        response::write_head(
+            tcp_stream,
+            StatusCode::Ok,
+            &[
+                ("Content-Type", "text/plain; version=0.0.4"),
+                ("Content-Length", &length),
+            ],
+        )?;
+        response::write_body(tcp_stream, metrics.as_bytes())?;
+        return Ok(());
+    }
+
+    if let Some((revision, repository)) = &config.git_ref
+        && is_get
+        && root::is_main()
+    {
+        return send_git(
+            request,
+            request_path,
+            tcp_stream,
+            config,
+            revision,
+            repository,
+            &path,
+        );
+    }
+
+    if is_get && root::is_main() && path == tree::PATH && config.site_mode() != Mode::UploadOnly {
+        return send_tree(request, tcp_stream, config);
+    }
+
+    if let Some(index) = &config.search
+        && is_get
+        && root::is_main()
+        && path == search::PATH
+        && config.site_mode() != Mode::UploadOnly
+    {
+        return send_search(request, tcp_stream, config, index);
+    }
+
+    if request.method == "OPTIONS" {
+        let allow = allowed_methods(config, &path);
+        let mut headers = vec![("Allow", allow.as_str())];
+        if config.webdav {
+            // Windows wants the latter to mount the directory
+            headers.extend([("DAV", "1"), ("MS-Author-Via", "DAV")]);
+        }
+        send_status(tcp_stream, StatusCode::Ok, &headers)?;
+        return Ok(());
+    }
+
+    if request.method == "PROPFIND" {
+        return send_propfind(request, request_path, body, tcp_stream, config, &path);
+    }
+
+    if upgrade::requested(request, "websocket") && root::is_main() {
+        return watch_listing(body.get_mut(), tcp_stream, request, &path, config);
+    }
+
+    if request.method == "PUT" {
+        let status = receive_file(&path, request, body, config)?;
+        // 202 for a part of a file, which isn't there until the last one
+        if status.is_success() && status != StatusCode::Accepted {
+            let directory = path.rsplit_once('/').map(|(parent, _)| parent);
+            config.changes.notify(directory.unwrap_or_default());
+            if let Some(index) = &config.search {
+                index.changed();
+            }
+            if let Some(webhooks) = &config.webhooks {
+                webhooks.send("upload", &path, None);
+            }
+        }
+        send_status(tcp_stream, status, &[])?;
+        return Ok(());
+    }
+
+    if request.method == "POST" {
+        let action = path.strip_prefix("_manage/").unwrap_or_default();
+        let form = read_form(request, body)?;
+        let csrf_ok = csrf_token(request, config).is_some_and(|token| {
+            let sent = form.get("csrf").map(String::as_str).unwrap_or_default();
+            crypto::constant_time_eq(token.as_bytes(), sent.as_bytes())
+        });
+        if !csrf_ok {
+            send_status(tcp_stream, StatusCode::Forbidden, &[])?;
+            return Ok(());
+        }
+        // the paths acted upon are in the form, not in the request path
+        if ["path", "to", "name"]
+            .into_iter()
+            .filter_map(|field| form.get(field))
+            .map(|target| normalize_path(target.clone()))
+            .any(|target| {
+                config.hidden.matches(&target)
+                    || tenant::owner(&config.tenants, &target).is_some()
+                    || canary::owns(config.canary.as_ref(), &target)
+                    || map::resolve(&config.maps, &target).is_some()
+            })
+        {
+            send_status(tcp_stream, StatusCode::NotFound, &[])?;
+            return Ok(());
+        }
+        let targets = [Some("path"), (action == "move").then_some("to")];
+        if let Some(status) = targets
+            .into_iter()
+            .flatten()
+            .filter_map(|field| form.get(field))
+            .find_map(|target| check_token(config, request, &normalize_path(target.clone())))
+        {
+            send_status(tcp_stream, status, &[])?;
+            return Ok(());
+        }
+        match manage(action, &form, config.trash.as_ref()) {
+            Ok(change) => {
+                config.changes.notify(&change.directory);
+                if action == "move"
+                    && let Some(to) = form.get("to")
+                {
+                    config.changes.notify(to);
+                }
+                if let Some(index) = &config.search {
+                    index.changed();
+                }
+                if let Some(webhooks) = &config.webhooks {
+                    webhooks.send(action, &change.path, change.to.as_deref());
+                }
+                // back to the listing the form was sent from
+                let location = format!("/{}", url_encode_path(&change.directory));
+                send_status(tcp_stream, StatusCode::SeeOther, &[("Location", &location)])?;
+            }
+            Err(status) => send_status(tcp_stream, status, &[])?,
+        }
+        return Ok(());
+    }
+
+    if let Some(canary) = &config.canary
+        && tenant.is_none()
+        && root::is_main()
+        && is_get
+    {
+        let (variant, cookie) = canary.pick(request);
+        let mut headers = vec![("Vary", "Cookie".to_owned())];
+        headers.extend(cookie.map(|cookie| ("Set-Cookie", cookie)));
+        response::set_extra(headers);
+        canary::set_variant(Some(variant));
+        if variant == canary::Variant::Canary {
+            path = canary.map(&path);
+            error_page::set_root(&canary.root);
+        }
+    }
+
+    if config.zip
+        && let Some((archive, member)) = split_zip_path(&path, request_path.ends_with('/'))
+    {
+        return send_zip_member(request_path, tcp_stream, archive, member);
+    }
+
+    // try to serve an index page
+    let mut file = None;
+    let to_try = [
+        &path,
+        &format!("{path}/index.html"),
+        &format!("{path}/index.htm"),
+    ];
+
+    for try_ in to_try {
+        if config.site_mode() != Mode::UploadOnly && root::path(try_).is_file() {
+            file = Some(try_);
+            break;
+        }
+    }
+    // the file of a fingerprinted name, which is only right as long as the
+    // file doesn't change
+    let original = checksum::strip_fingerprint(&path).filter(|(original, fingerprint)| {
+        file.is_none()
+            && config.fingerprints
+            && config.site_mode() != Mode::UploadOnly
+            && check_access(config, "GET", original).is_none()
+            && check_token(config, request, original).is_none()
+            && root::path(original).is_file()
+            && config
+                .checksums
+                .sha256(&root::path(original))
+                .is_ok_and(|digest| crypto::hex(&digest).starts_with(fingerprint))
+    });
+    if let Some((original, _)) = &original {
+        file = Some(original);
+    }
+    // --spa: the routes of the app's router are its page
+    let spa_index = normalize_path(format!("{}/index.html", error_page::root()));
+    if file.is_none()
+        && config.spa
+        && is_get
+        && config.site_mode() != Mode::UploadOnly
+        && !root::path(&path).is_dir()
+        && !(config.checksum_headers && path.ends_with(checksum::SIDECAR_EXTENSION))
+        && check_access(config, "GET", &spa_index).is_none()
+        && root::path(&spa_index).is_file()
+    {
+        file = Some(&spa_index);
+    }
+    let cache_control = match &original {
+        Some(_) => Some(IMMUTABLE.to_owned()),
+        None => map
+            .and_then(|map| map.cache.as_ref())
+            .map(map::Cache::header),
+    };
+    if request.method == "GET" {
+        let served = file.map(|file| normalize_path(file.clone()));
+        send_early_hints(tcp_stream, config, &[Some(&path), served.as_ref()])?;
+    }
+
+    if let Some(file) = file {
+        // a static file was found!
+        if config.viewer
+            && request.has_query_param(viewer::PARAM)
+            && viewer::is_viewable(&mime_type(file))
+        {
+            return send_viewer(request, tcp_stream, file);
+        }
+        let mut content_type = mime_type(file);
+        // the same image in newer formats, e.g. pic.jpg.webp, which are sent
+        // in its place when the client takes them and they're smaller
+        let image_variants: Vec<_> = IMAGE_VARIANTS
+            .iter()
+            .filter(|_| content_type.starts_with("image/"))
+            .map(|(image_type, extension)| (*image_type, format!("{file}.{extension}")))
+            .filter(|(_, variant)| {
+                check_access(config, "GET", variant).is_none()
+                    && check_token(config, request, variant).is_none()
+                    && root::path(variant).is_file()
+            })
+            .collect();
+        let accept = request.header("Accept").unwrap_or_default();
+        let size = |file: &str| {
+            std::fs::metadata(root::path(file)).map_or(u64::MAX, |metadata| metadata.len())
+        };
+        let file = match image_variants
+            .iter()
+            .filter(|(image_type, _)| accepts_type(accept, image_type))
+            .min_by_key(|(_, variant)| size(variant))
+            .filter(|(_, variant)| size(variant) < size(file))
+        {
+            Some((image_type, variant)) => {
+                (*image_type).clone_into(&mut content_type);
+                variant
+            }
+            None => file,
+        };
+        // variants compressed ahead of time, e.g. app.js.br or app.js.gz,
+        // which are sent as they are to clients taking them
+        let filtered = config
+            .filters
+            .iter()
+            .any(|filter| filter.applies(&content_type));
+        let essence = content_type.split(';').next().unwrap_or_default();
+        let snippet = config
+            .inject_html
+            .as_ref()
+            .filter(|_| essence.trim() == "text/html");
+        let precompressed: Vec<_> = PRECOMPRESSED
+            .iter()
+            .filter(|_| !filtered && snippet.is_none())
+            .map(|(coding, extension)| (*coding, format!("{file}.{extension}")))
+            .filter(|(_, variant)| {
+                check_access(config, "GET", variant).is_none()
+                    && check_token(config, request, variant).is_none()
+                    && root::path(variant).is_file()
+            })
+            .collect();
+        let accept_encoding = request.header("Accept-Encoding").unwrap_or_default();
+        let (sent, coding) = match precompressed
+            .iter()
+            .find(|(coding, _)| accepts_coding(accept_encoding, coding))
+        {
+            Some((coding, variant)) => (variant.as_str(), Some(*coding)),
+            None => (file.as_str(), None),
+        };
+        let metadata = std::fs::metadata(root::path(sent))?;
+        // --minify, precompressed variants having been built already
+        let minified = match (&config.minify, coding) {
+            (Some(minifier), None) => minifier.get(&root::path(file), &content_type)?,
+            _ => None,
+        };
+        let is_minified = minified.is_some();
+        // the content to send when it's not the file's: minified, then
+        // through the filters
+        let rewritten = match filtered {
+            true => {
+                let content = match minified {
+                    Some(minified) => minified.to_vec(),
+                    None => std::fs::read(root::path(sent))?,
+                };
+                let served = normalize_path(file.clone());
+                let content = filter::apply(&config.filters, &served, &content_type, content)?;
+                Some(Arc::new(content))
+            }
+            false => minified,
+        };
+        // --inject-html, into what's in memory, else while the file is sent
+        let (rewritten, injected_at) = match (snippet, rewritten) {
+            (Some(snippet), Some(rewritten)) => (Some(Arc::new(snippet.insert(&rewritten))), None),
+            (Some(_), None) => {
+                let position = inject::position_in_file(&root::path(sent), metadata.len())?;
+                (None, Some(position))
+            }
+            (None, rewritten) => (rewritten, None),
+        };
+        let size = match (&rewritten, snippet) {
+            (Some(rewritten), _) => rewritten.len() as u64,
+            (None, Some(snippet)) => metadata.len() + snippet.content.len() as u64,
+            (None, None) => metadata.len(),
+        };
+        let digest = match &rewritten {
+            Some(rewritten) if config.checksum_headers => Some(Sha256::digest(rewritten)),
+            // pages being injected are hashed as sent, when gzipped
+            None if config.checksum_headers && injected_at.is_none() => {
+                Some(config.checksums.sha256(&root::path(sent))?)
+            }
+            _ => None,
+        };
+        // whether the response depends on Accept-Encoding, which caches must
+        // know
+        let compressible = is_compressible(&content_type)
+            && !is_compression_excluded(file, &content_type, &config.no_compress);
+        let gzip = coding.is_none()
+            && compressible
+            && (MIN_COMPRESSED_SIZE..=MAX_COMPRESSED_SIZE).contains(&size)
+            && request.header("Range").is_none()
+            && accepts_coding(accept_encoding, "gzip");
+        let mut validators = conditional::Validators::new(&metadata, digest.as_deref());
+        if is_minified {
+            validators.set_coding("min");
+        }
+        if filtered {
+            validators.set_coding("filtered");
+        }
+        if let Some(snippet) = snippet {
+            validators.set_coding(&snippet.tag);
+        }
+        if let Some(coding) = coding.or(gzip.then_some("gzip")) {
+            validators.set_coding(coding);
+        }
+        let mut vary = Vec::new();
+        if compressible || !precompressed.is_empty() {
+            vary.push("Accept-Encoding");
+        }
+        if !image_variants.is_empty() {
+            vary.push("Accept");
+        }
+        let vary = vary.join(", ");
+        let vary = (!vary.is_empty()).then_some(("Vary", vary.as_str()));
+        if validators.not_modified(request) {
+            let mut headers = validators.headers();
+            headers.extend(vary);
+            headers.extend(
+                cache_control
+                    .as_deref()
+                    .map(|cache_control| ("Cache-Control", cache_control)),
+            );
+            response::write_head(tcp_stream, StatusCode::NotModified, &headers)?;
+            return Ok(());
+        }
+        if gzip {
+            let body = match (&rewritten, snippet) {
+                (Some(rewritten), _) => deflate::gzip(rewritten),
+                (None, Some(snippet)) => {
+                    deflate::gzip(&snippet.insert(&std::fs::read(root::path(file))?))
+                }
+                (None, None) => deflate::gzip(&std::fs::read(root::path(file))?),
+            };
+            let length = body.len().to_string();
+            let mut headers = vec![
+                ("Content-Type", content_type),
+                ("Content-Encoding", "gzip".to_owned()),
+                ("Content-Length", length),
+            ];
+            if let Some(cache_control) = cache_control {
+                headers.push(("Cache-Control", cache_control));
+            }
+            // of the gzipped bytes, as they are what's sent
+            if config.checksum_headers {
+                let digest = format!("sha-256=:{}:", base64::encode(&Sha256::digest(&body)));
+                headers.push(("Content-Digest", digest));
+            }
+            let mut headers: Vec<_> = headers
+                .iter()
+                .map(|(key, value)| (*key, value.as_str()))
+                .collect();
+            headers.extend(validators.headers());
+            headers.extend(vary);
+            response::write_head(tcp_stream, StatusCode::Ok, &headers)?;
+            response::write_body(tcp_stream, &body)?;
+            return Ok(());
+        }
+        // pages being injected are sent whole
+        let range = request
+            .header("Range")
+            .filter(|_| validators.range_applies(request) && injected_at.is_none());
+        let (status, start, end) = match parse_range(range, size) {
+            ByteRange::Full => (StatusCode::Ok, 0, size),
+            ByteRange::Partial(first, last) => (StatusCode::PartialContent, first, last + 1),
+            ByteRange::Unsatisfiable => {
+                let content_range = format!("bytes */{size}");
+                send_status(
+                    tcp_stream,
+                    StatusCode::RangeNotSatisfiable,
+                    &[("Content-Range", &content_range)],
+                )?;
+                return Ok(());
+            }
+        };
+        // pipelined responses must say where they end
+        let length = (end - start).to_string();
+        let content_range = format!("bytes {start}-{}/{size}", end.saturating_sub(1));
+        let mut headers = vec![
+            ("Content-Type", content_type),
+            ("Content-Length", length),
+            (
+                "Accept-Ranges",
+                match injected_at {
+                    Some(_) => "none".to_owned(),
+                    None => "bytes".to_owned(),
+                },
+            ),
+        ];
+        if let Some(coding) = coding {
+            headers.push(("Content-Encoding", coding.to_owned()));
+        }
+        if status == StatusCode::PartialContent {
+            headers.push(("Content-Range", content_range));
+        }
+        if let Some(cache_control) = cache_control {
+            headers.push(("Cache-Control", cache_control));
+        }
+        // of what's sent, which only the whole file matches
+        if let Some(digest) = digest
+            && status == StatusCode::Ok
+        {
+            let digest = format!("sha-256=:{}:", base64::encode(&digest));
+            headers.push(("Content-Digest", digest));
+        }
+        let mut headers: Vec<_> = headers
+            .iter()
+            .map(|(key, value)| (*key, value.as_str()))
+            .collect();
+        headers.extend(validators.headers());
+        headers.extend(vary);
+        response::write_head(tcp_stream, status, &headers)?;
+        match &rewritten {
+            Some(rewritten) => {
+                response::write_body(tcp_stream, &rewritten[start as usize..end as usize])?;
+            }
+            None if response::head_only() => {}
+            None => match (snippet, injected_at) {
+                (Some(snippet), Some(position)) => {
+                    send_file(sent, 0, position, tcp_stream)?;
+                    response::write_body(tcp_stream, &snippet.content)?;
+                    send_file(sent, position, metadata.len() - position, tcp_stream)?;
+                }
+                _ => send_file(sent, start, end - start, tcp_stream)?,
+            },
+        }
+    } else if root::path(&path).is_dir() {
+        let media = config.media && config.site_mode() != Mode::UploadOnly;
+        if !request_path.ends_with('/') {
+            // the query, such as a token, stays
+            let query = &request.path[request_path.len()..];
+            let location = format!("{request_path}/{query}");
+            send_status(
+                tcp_stream,
+                StatusCode::MovedPermanently,
+                &[("Location", &location)],
+            )?;
+        } else if map.is_some_and(|map| !map.listing) {
+            send_status(tcp_stream, StatusCode::Forbidden, &[])?;
+        } else if media && request.has_query_param(media::PLAYLIST_PARAM) {
+            send_media(request, tcp_stream, config, &path, request_path, true)?;
+        } else if media && request.has_query_param(media::PLAYER_PARAM) {
+            send_media(request, tcp_stream, config, &path, request_path, false)?;
+        } else if agent.plain && config.site_mode() != Mode::UploadOnly {
+            let listing = list_plain(&path, config)?;
+            let length = listing.len().to_string();
+            response::write_head(
+                tcp_stream,
+                StatusCode::Ok,
+                &[
+                    ("Content-Type", "text/plain; charset=utf-8"),
+                    ("Content-Length", &length),
+                ],
+            )?;
+            response::write_body(tcp_stream, listing.as_bytes())?;
+        } else {
+            // try a directory listing
+            let mut headers = vec![("Content-Type", "text/html; charset=utf-8".to_owned())];
+            let csrf_token = match csrf_token(request, config) {
+                Some(token) => token,
+                None if config.can_manage() => {
+                    let token = crypto::random_token()?;
+                    let cookie =
+                        format!("{CSRF_COOKIE}={token}; Path=/; HttpOnly; SameSite=Strict");
+                    headers.push(("Set-Cookie", cookie));
+                    token
+                }
+                None => String::new(),
+            };
+            headers.push(("Transfer-Encoding", "chunked".to_owned()));
+            let trailers = trailers(request, config);
+            if let Some(names) = trailers.names() {
+                headers.push(("Trailer", names));
+            }
+            let headers: Vec<_> = headers
+                .iter()
+                .map(|(key, value)| (*key, value.as_str()))
+                .collect();
+            response::write_head(tcp_stream, StatusCode::Ok, &headers)?;
+            if !response::head_only() {
+                let mut listing = ChunkedWriter::new(&mut *tcp_stream, trailers);
+                list_directory(&mut listing, &path, config, &csrf_token)?;
+                response::count_body(listing.finish()?);
+            }
+        }
+    } else if config.checksum_headers
+        && let Some(file) = path.strip_suffix(checksum::SIDECAR_EXTENSION)
+        && config.site_mode() != Mode::UploadOnly
+        && check_access(config, "GET", file).is_none()
+        && check_token(config, request, file).is_none()
+        && root::path(file).is_file()
+    {
+        // the checksum of a file without one of its own
+        let name = file.rsplit('/').next().unwrap_or(file);
+        let sidecar = checksum::sidecar(&config.checksums.sha256(&root::path(file))?, name);
+        let length = sidecar.len().to_string();
+        response::write_head(
+            tcp_stream,
+            StatusCode::Ok,
+            &[
+                ("Content-Type", "text/plain; charset=utf-8"),
+                ("Content-Length", &length),
+            ],
+        )?;
+        response::write_body(tcp_stream, sidecar.as_bytes())?;
+    } else {
+        // nothing was found
+        send_status(tcp_stream, StatusCode::NotFound, &[])?;
+    }
+
+    Ok(())
+}
+
+// RFC 8297: the Link headers of --early-hint for `paths` (normalized), what
+// was asked and the file which answers it, which browsers can start fetching
+// while the response is made
+fn send_early_hints(
+    tcp_stream: &mut Connection,
+    config: &Config,
+    paths: &[Option<&String>],
+) -> Result<(), Box<dyn Error>> {
+    let links: Vec<_> = config
+        .early_hints
+        .iter()
+        .filter(|(hinted, _)| paths.contains(&Some(hinted)))
+        .map(|(_, link)| ("Link", link.as_str()))
+        .collect();
+    if !links.is_empty() {
+        response::write_interim(tcp_stream, StatusCode::EarlyHints, &links)?;
+    }
+    Ok(())
+}
+
+// What chunked responses end with, for clients which said they take trailer
+// fields (TE: trailers): others may drop them, or not expect any
+fn trailers(request: &Request, config: &Config) -> chunked::Trailers {
+    let accepted = request.header("TE").is_some_and(|codings| {
+        codings.split(',').any(|coding| {
+            let coding = coding.split(';').next().unwrap_or_default();
+            coding.trim().eq_ignore_ascii_case("trailers")
+        })
+    });
+    chunked::Trailers {
+        digest: accepted && config.checksum_headers,
+        timing: accepted && config.log_timings,
+    }
+}
+
+// The tree below the path query parameter, without what the client couldn't
+// get: hidden names and what needs another token
+fn send_tree(
+    request: &Request,
+    tcp_stream: &mut Connection,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let mut directory = normalize_path(request.query_param("path").unwrap_or_default());
+    if directory.is_empty() {
+        directory.push('.');
+    }
+    let depth = match request
+        .query_param("depth")
+        .map(|depth| depth.parse::<u32>())
+    {
+        None => tree::DEFAULT_DEPTH,
+        Some(Ok(depth)) => depth.min(tree::MAX_DEPTH),
+        Some(Err(_)) => return send_status(tcp_stream, StatusCode::BadRequest, &[]),
+    };
+    let visible = |path: &str| {
+        check_access(config, "GET", path).is_none()
+            && check_token(config, request, path).is_none()
+            && tenant::owner(&config.tenants, path).is_none()
+            && !canary::owns(config.canary.as_ref(), path)
+    };
+    if !visible(&directory) || !root::path(&directory).is_dir() {
+        return send_status(tcp_stream, StatusCode::NotFound, &[]);
+    }
+
+    let trailers = trailers(request, config);
+    let names = trailers.names();
+    let mut headers = vec![
+        ("Content-Type", "application/json"),
+        ("Transfer-Encoding", "chunked"),
+    ];
+    headers.extend(names.as_deref().map(|names| ("Trailer", names)));
+    response::write_head(tcp_stream, StatusCode::Ok, &headers)?;
+    if !response::head_only() {
+        let mut res = ChunkedWriter::new(&mut *tcp_stream, trailers);
+        tree::write(&mut res, &directory, depth, &visible)?;
+        response::count_body(res.finish()?);
+    }
+    Ok(())
+}
+
+// Search results for the q query parameter below the path one, as JSON or
+// as a page for browsers, without what the client couldn't get
+fn send_search(
+    request: &Request,
+    tcp_stream: &mut Connection,
+    config: &Config,
+    index: &search::Index,
+) -> Result<(), Box<dyn Error>> {
+    let query = request.query_param("q").unwrap_or_default();
+    let mut directory = normalize_path(request.query_param("path").unwrap_or_default());
+    if directory.is_empty() {
+        directory.push('.');
+    }
+    let visible = |path: &str| {
+        check_access(config, "GET", path).is_none()
+            && check_token(config, request, path).is_none()
+            && tenant::owner(&config.tenants, path).is_none()
+            && !canary::owns(config.canary.as_ref(), path)
+    };
+    let html = request
+        .header("Accept")
+        .is_some_and(|accept| accept.contains("text/html"));
+
+    let mut results = Vec::new();
+    let (truncated, indexed) = index.search(&query, &directory, &visible, &mut |found| {
+        if !html {
+            results.push(found.to_json());
+            return;
+        }
+        let mut href: String = found
+            .path
+            .split('/')
+            .map(|part| format!("/{}", url_encode(part)))
+            .collect();
+        if found.kind == "directory" {
+            href.push('/');
+        }
+        let path = html_encode(found.path.to_owned());
+        let note = if found.in_text { " (in contents)" } else { "" };
+        results.push(format!("  <li><a href=\"{href}\">{path}</a>{note}</li>"));
+    });
+
+    let (content_type, body) = if html {
+        let summary = match (indexed, results.is_empty(), truncated) {
+            (None, _, _) => "<p>The index isn't ready yet, try again in a moment.</p>",
+            (_, true, _) => "<p>Nothing found.</p>",
+            (_, _, true) => "<p>Only the first results are shown.</p>",
+            _ => "",
+        };
+        let page = format!(
+            "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+  <meta charset=\"utf-8\">
+  <title>Search for {query}</title>
+  <style>
+  body {{
+    background-color: Canvas;
+    color: CanvasText;
+    color-scheme: light dark;
+  }}
+  </style>
+</head>
+<h1>Search</h1>
+{form}
+<hr>
+<ul>
+{results}
+</ul>
+{summary}
+</html>
+",
+            query = html_encode(query.clone()),
+            form = search_form(&directory, &query),
+            results = results.join("\n"),
+        );
+        ("text/html; charset=utf-8", page)
+    } else {
+        let indexed = indexed.map_or_else(|| "null".to_owned(), |indexed| indexed.to_string());
+        let json = format!(
+            "{{\"query\":{},\"indexed\":{indexed},\"truncated\":{truncated},\"results\":[{}]}}\n",
+            json::quote(&query),
+            results.join(",")
+        );
+        ("application/json", json)
+    };
+    let length = body.len().to_string();
+    response::write_head(
+        tcp_stream,
+        StatusCode::Ok,
+        &[("Content-Type", content_type), ("Content-Length", &length)],
+    )?;
+    response::write_body(tcp_stream, body.as_bytes())?;
+    Ok(())
+}
+
+// The playlist or the player page of the media files in `directory`
+fn send_media(
+    request: &Request,
+    tcp_stream: &mut Connection,
+    config: &Config,
+    directory: &str,
+    request_path: &str,
+    playlist: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(root::path(directory))? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let path = normalize_path(format!("{directory}/{name}"));
+        if root::path(&path).is_file()
+            && !config.hidden.matches(&path)
+            && media::is_media(&mime_type(&name))
+        {
+            names.push(name);
+        }
+    }
+    names.sort();
+    // the files need the token the directory did
+    let query = request
+        .query_param("token")
+        .map(|token| format!("?token={}", url_encode(&token)))
+        .unwrap_or_default();
+
+    let (content_type, body) = if playlist {
+        // players resolve the URLs without knowing where the playlist came from
+        let base = match request.header("Host") {
+            Some(host) => format!("{}://{host}{request_path}", config.scheme()),
+            None => request_path.to_owned(),
+        };
+        ("audio/x-mpegurl", media::playlist(&base, &names, &query))
+    } else {
+        let page = media::player_page(directory, &names, &query);
+        ("text/html; charset=utf-8", page)
+    };
+    let length = body.len().to_string();
+    let mut headers = vec![("Content-Type", content_type), ("Content-Length", &length)];
+    if playlist {
+        headers.push(("Content-Disposition", "inline; filename=\"playlist.m3u\""));
+    }
+    response::write_head(tcp_stream, StatusCode::Ok, &headers)?;
+    response::write_body(tcp_stream, body.as_bytes())?;
+    Ok(())
+}
+
+// The page showing the document `file`
+fn send_viewer(
+    request: &Request,
+    tcp_stream: &mut Connection,
+    file: &str,
+) -> Result<(), Box<dyn Error>> {
+    let name = Path::new(file)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    // the document needs the token the page did
+    let query = request
+        .query_param("token")
+        .map(|token| format!("?token={}", url_encode(&token)))
+        .unwrap_or_default();
+    let page = viewer::page(name, &mime_type(file), &query);
+    let length = page.len().to_string();
+    response::write_head(
+        tcp_stream,
+        StatusCode::Ok,
+        &[
+            ("Content-Type", "text/html; charset=utf-8"),
+            ("Content-Length", &length),
+        ],
+    )?;
+    response::write_body(tcp_stream, page.as_bytes())?;
+    Ok(())
+}
+
+// --webdav: the properties of `path`, and of its entries for a directory
+fn send_propfind(
+    request: &Request,
+    request_path: &str,
+    body: &mut Take<&mut BufReader<Connection>>,
+    tcp_stream: &mut Connection,
+    config: &Config,
+    path: &str,
+) -> Result<(), Box<dyn Error>> {
+    // which properties are asked for doesn't matter, all are sent
+    std::io::copy(&mut body.take(MAX_FORM_SIZE), &mut std::io::sink())?;
+    let Some(depth) = webdav::depth(request) else {
+        let error = webdav::finite_depth_error();
+        let length = error.len().to_string();
+        response::write_head(
+            tcp_stream,
+            StatusCode::Forbidden,
+            &[
+                ("Content-Type", "application/xml; charset=utf-8"),
+                ("Content-Length", &length),
+            ],
+        )?;
+        response::write_body(tcp_stream, error.as_bytes())?;
+        return Ok(());
+    };
+    let Ok(metadata) = std::fs::metadata(root::path(path)) else {
+        return send_status(tcp_stream, StatusCode::NotFound, &[]);
+    };
+    let resource = |href: String, path: &str, metadata: &std::fs::Metadata| {
+        let name = match path {
+            "." => "",
+            path => path.rsplit('/').next().unwrap_or(path),
+        };
+        webdav::Resource {
+            href,
+            name: name.to_owned(),
+            directory: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs()),
+            content_type: match metadata.is_dir() {
+                true => String::new(),
+                false => mime_type(path),
+            },
+            // that of downloads, unless it's the SHA-256 of --checksums,
+            // which would be computed for every file listed
+            etag: (!metadata.is_dir() && !config.checksum_headers)
+                .then(|| conditional::Validators::new(metadata, None).etag),
+        }
+    };
+    let mut href = request_path.to_owned();
+    if metadata.is_dir() && !href.ends_with('/') {
+        href.push('/');
+    }
+    let mut resources = vec![resource(href.clone(), path, &metadata)];
+    if depth > 0 && metadata.is_dir() {
+        let (directories, files) = list_entries(path, config)?;
+        for name in directories.iter().chain(&files) {
+            let entry = normalize_path(format!("{path}/{name}"));
+            let Ok(metadata) = std::fs::metadata(root::path(&entry)) else {
+                continue;
+            };
+            let mut entry_href = format!("{href}{}", url_encode(name));
+            if metadata.is_dir() {
+                entry_href.push('/');
+            }
+            resources.push(resource(entry_href, &entry, &metadata));
+        }
+    }
+    let multistatus = webdav::multistatus(&resources);
+    let length = multistatus.len().to_string();
+    response::write_head(
+        tcp_stream,
+        StatusCode::MultiStatus,
+        &[
+            ("Content-Type", "application/xml; charset=utf-8"),
+            ("Content-Length", &length),
+        ],
+    )?;
+    response::write_body(tcp_stream, multistatus.as_bytes())?;
+    Ok(())
+}
+
+// Searches below `directory`, for listings and results pages
+fn search_form(directory: &str, query: &str) -> String {
+    format!(
+        "<form action=\"/{}\">
+  <input type=\"hidden\" name=\"path\" value=\"{}\">
+  <input type=\"search\" name=\"q\" value=\"{}\" placeholder=\"search here\" required>
+  <button>Search</button>
+</form>",
+        search::PATH,
+        html_encode(directory.to_owned()),
+        html_encode(query.to_owned())
+    )
+}
+
+// "a.zip/b/c" is the member b/c of the file a.zip, and "a.zip" its root
+// when the request ends with a slash
+fn split_zip_path(path: &str, slash: bool) -> Option<(&str, &str)> {
+    path.match_indices('/')
+        .map(|(idx, _)| idx)
+        .chain(slash.then_some(path.len()))
+        .map(|idx| (&path[..idx], path.get(idx + 1..).unwrap_or_default()))
+        .find(|(archive, _)| {
+            archive.to_ascii_lowercase().ends_with(".zip") && root::path(archive).is_file()
+        })
+}
+
+// A member of an archive, or the index.html of one of its directories
+fn send_zip_member(
+    request_path: &str,
+    tcp_stream: &mut Connection,
+    archive_path: &str,
+    member: &str,
+) -> Result<(), Box<dyn Error>> {
+    let file = BufReader::new(std::fs::File::open(root::path(archive_path))?);
+    let Ok(mut archive) = zip::Archive::new(file) else {
+        // not a zip file after all
+        return send_status(tcp_stream, StatusCode::NotFound, &[]);
+    };
+    let name = if archive.is_dir(member) {
+        if !request_path.ends_with('/') {
+            let location = format!("{request_path}/");
+            return send_status(
+                tcp_stream,
+                StatusCode::MovedPermanently,
+                &[("Location", &location)],
+            );
+        }
+        [member, "index.html"].join(if member.is_empty() { "" } else { "/" })
+    } else {
+        member.to_owned()
+    };
+
+    match archive.read(&name) {
+        Ok(Some(content)) => {
+            let length = content.len().to_string();
+            response::write_head(
+                tcp_stream,
+                StatusCode::Ok,
+                &[
+                    ("Content-Type", &mime_type(&name)),
+                    ("Content-Length", &length),
+                ],
+            )?;
+            response::write_body(tcp_stream, &content)?;
+            Ok(())
+        }
+        Ok(None) => send_status(tcp_stream, StatusCode::NotFound, &[]),
+        Err(err) => {
+            log::error(&format!("{archive_path}: {name}: {err}"));
+            send_status(tcp_stream, StatusCode::InternalServerError, &[])
+        }
+    }
+}
+
+// --git-ref: `path` in the commit the ref points to now. Directories are
+// listed unless they have an index page; symbolic links and submodules are
+// left out.
+fn send_git(
+    request: &Request,
+    request_path: &str,
+    tcp_stream: &mut Connection,
+    config: &Config,
+    revision: &str,
+    repository: &git::Repository,
+    path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let found = repository.resolve(revision).and_then(|commit| {
+        let Some(entry) = repository.lookup(&commit, path)? else {
+            return Ok(None);
+        };
+        if !entry.is_dir() {
+            return Ok(Some((entry, Vec::new())));
+        }
+        let entries = repository.tree(&entry.id)?;
+        Ok(Some((entry, entries)))
+    });
+    let (entry, entries) = match found {
+        Ok(Some(found)) => found,
+        Ok(None) => return send_status(tcp_stream, StatusCode::NotFound, &[]),
+        Err(err) => {
+            log::error(&format!("{revision}: {path}: {err}"));
+            return send_status(tcp_stream, StatusCode::InternalServerError, &[]);
+        }
+    };
+
+    if !entry.is_dir() {
+        if !entry.is_file() {
+            return send_status(tcp_stream, StatusCode::NotFound, &[]);
+        }
+        return send_git_blob(request, tcp_stream, repository, path, &entry.id);
+    }
+    if !request_path.ends_with('/') {
+        let query = &request.path[request_path.len()..];
+        let location = format!("{request_path}/{query}");
+        return send_status(
+            tcp_stream,
+            StatusCode::MovedPermanently,
+            &[("Location", &location)],
+        );
+    }
+    if let Some(index) = ["index.html", "index.htm"].into_iter().find_map(|name| {
+        entries
+            .iter()
+            .find(|entry| entry.is_file() && entry.name == name)
+    }) {
+        let index_path = normalize_path(format!("{path}/{}", index.name));
+        return send_git_blob(request, tcp_stream, repository, &index_path, &index.id);
+    }
+
+    let mut listing = String::new();
+    list_git_tree(&mut listing, path, revision, &entries, config)?;
+    let length = listing.len().to_string();
+    response::write_head(
+        tcp_stream,
+        StatusCode::Ok,
+        &[
+            ("Content-Type", "text/html; charset=utf-8"),
+            ("Content-Length", &length),
+        ],
+    )?;
+    response::write_body(tcp_stream, listing.as_bytes())?;
+    Ok(())
+}
+
+// A file of the commit: its id makes a strong ETag
+fn send_git_blob(
+    request: &Request,
+    tcp_stream: &mut Connection,
+    repository: &git::Repository,
+    path: &str,
+    id: &git::Id,
+) -> Result<(), Box<dyn Error>> {
+    let etag = format!("\"{}\"", crypto::hex(id));
+    if request
+        .header("If-None-Match")
+        .is_some_and(|tags| conditional::etag_matches(tags, &etag))
+    {
+        response::write_head(tcp_stream, StatusCode::NotModified, &[("ETag", &etag)])?;
+        return Ok(());
+    }
+    let content = match repository.read(id) {
+        Ok((git::Kind::Blob, content)) => content,
+        Ok(_) => return send_status(tcp_stream, StatusCode::NotFound, &[]),
+        Err(err) => {
+            log::error(&format!("{path}: {err}"));
+            return send_status(tcp_stream, StatusCode::InternalServerError, &[]);
+        }
+    };
+    let size = content.len() as u64;
+    let range = request.header("Range").filter(|_| {
+        request
+            .header("If-Range")
+            .is_none_or(|validator| validator.trim() == etag)
+    });
+    let (status, start, end) = match parse_range(range, size) {
+        ByteRange::Full => (StatusCode::Ok, 0, size),
+        ByteRange::Partial(first, last) => (StatusCode::PartialContent, first, last + 1),
+        ByteRange::Unsatisfiable => {
+            let content_range = format!("bytes */{size}");
+            return send_status(
+                tcp_stream,
+                StatusCode::RangeNotSatisfiable,
+                &[("Content-Range", &content_range)],
+            );
+        }
+    };
+    let content_type = mime_type(path);
+    let length = (end - start).to_string();
+    let content_range = format!("bytes {start}-{}/{size}", end.saturating_sub(1));
+    let mut headers = vec![
+        ("Content-Type", content_type.as_str()),
+        ("Content-Length", &length),
+        ("Accept-Ranges", "bytes"),
+        ("ETag", &etag),
+    ];
+    if status == StatusCode::PartialContent {
+        headers.push(("Content-Range", &content_range));
+    }
+    response::write_head(tcp_stream, status, &headers)?;
+    response::write_body(tcp_stream, &content[start as usize..end as usize])?;
+    Ok(())
+}
+
+fn list_git_tree(
+    res: &mut impl std::fmt::Write,
+    directory: &str,
+    revision: &str,
+    entries: &[git::TreeEntry],
+    config: &Config,
+) -> std::fmt::Result {
+    writeln!(
+        res,
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+  <meta charset=\"utf-8\">
+  <title>Index of {directory}</title>
+  <style>
+  body {{
+    background-color: Canvas;
+    color: CanvasText;
+    color-scheme: light dark;
+  }}
+  a, a:visited, a:active {{
+    text-decoration: none;
+  }}
+  </style>
+</head>"
+    )?;
+    writeln!(res, "<h1>Directory Listing</h1>")?;
+    writeln!(
+        res,
+        "<h2>Directory: {directory} at {}</h2>",
+        html_encode(revision.to_owned())
+    )?;
+    writeln!(res, "<hr>")?;
+    writeln!(res, "<ul>")?;
+    writeln!(res, "  <li><a href=\"..\">..</a></li>")?;
+    let visible = |entry: &&git::TreeEntry| {
+        !config
+            .hidden
+            .matches(&normalize_path(format!("{directory}/{}", entry.name)))
+    };
+    let mut directories: Vec<_> = entries
+        .iter()
+        .filter(|entry| entry.is_dir())
+        .filter(visible)
+        .collect();
+    let mut files: Vec<_> = entries
+        .iter()
+        .filter(|entry| entry.is_file())
+        .filter(visible)
+        .collect();
+    directories.sort_by(|a, b| a.name.cmp(&b.name));
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    for entry in directories {
+        writeln!(
+            res,
+            "  <li><a href=\"{}/\">{}</a></li>",
+            url_encode(&entry.name),
+            html_encode(format!("📁 {}/", entry.name))
+        )?;
+    }
+    for entry in files {
+        writeln!(
+            res,
+            "  <li><a href=\"{}\">{}</a></li>",
+            url_encode(&entry.name),
+            html_encode(format!("📄 {}", entry.name))
+        )?;
+    }
+    writeln!(res, "</ul>")?;
+    writeln!(res, "<hr>")?;
+    writeln!(res, "</html>")
+}
+
+// RFC 9112 section 6: a request a proxy in front of us could frame or route
+// differently (request smuggling) is refused rather than guessed at, and so is
+// one with two values of a field which only has one (section 3.2 for Host).
+// What's left is framed by Content-Length, or by chunks alone.
+fn check_framing(request: &mut Request) -> Result<(), StatusCode> {
+    if !request.repeated.is_empty() {
+        return Err(StatusCode::BadRequest);
+    }
+    if let Some(codings) = request.header("Transfer-Encoding") {
+        // HTTP/1.0 has no transfer codings (section 6.1)
+        if request.header("Content-Length").is_some() || request.version == "HTTP/1.0" {
+            return Err(StatusCode::BadRequest);
+        }
+        if !codings.trim().eq_ignore_ascii_case("chunked") {
+            return Err(StatusCode::NotImplemented);
+        }
+        return Ok(());
+    }
+
+    if let Some(lengths) = request.header("Content-Length") {
+        // repeated but identical values are fine (section 6.3)
+        let mut lengths = lengths.split(',').map(str::trim);
+        let length = lengths.next().unwrap_or_default().to_owned();
+        if !is_digits(&length)
+            || length.parse::<u64>().is_err()
+            || lengths.any(|other| other != length)
+        {
+            return Err(StatusCode::BadRequest);
+        }
+        request.headers.insert("content-length".to_owned(), length);
+    }
+
+    Ok(())
+}
+
+// Listings open a WebSocket on their directory to hear about changes
+fn watch_listing(
+    buf_reader: &mut BufReader<Connection>,
+    tcp_stream: &mut Connection,
+    request: &Request,
+    path: &str,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let key = request
+        .header("Sec-WebSocket-Key")
+        .filter(|_| request.header("Sec-WebSocket-Version") == Some("13"));
+    let Some(key) =
+        key.filter(|_| config.site_mode() != Mode::UploadOnly && root::path(path).is_dir())
+    else {
+        send_status(tcp_stream, StatusCode::BadRequest, &[])?;
+        return Ok(());
+    };
+
+    let changes = config.changes.subscribe(path);
+    let accept = websocket::accept_key(key);
+    let upgraded = upgrade::switch(
+        buf_reader,
+        "websocket",
+        &[("Sec-WebSocket-Accept", &accept)],
+    )?;
+    // the connection stays open as long as the page, not on the accept loop
+    std::thread::spawn(move || watch::serve(upgraded, changes));
+    Ok(())
+}
+
+// The access mode is only enforced here. Returns the status to reply with
+// when the request isn't allowed.
+fn check_access(config: &Config, method: &str, path: &str) -> Option<StatusCode> {
+    // as if they didn't exist
+    if config.hidden.matches(path) {
+        return Some(StatusCode::NotFound);
+    }
+    let target = root::path(path);
+    let allowed = match (config.site_mode(), method) {
+        // only the upload page of directories is left in upload-only mode
+        (Mode::UploadOnly, "GET" | "HEAD") => target.is_dir(),
+        (_, "GET" | "HEAD") => true,
+        (Mode::ReadOnly, "PUT") => false,
+        // drop boxes never overwrite what's already there, while the parts
+        // of a file gather elsewhere until the last one
+        (Mode::UploadOnly, "PUT") => !target.exists(),
+        (Mode::ReadWrite, "PUT") => true,
+        (_, "POST") => config.can_manage() && path.starts_with("_manage/"),
+        (Mode::UploadOnly, "PROPFIND") => false,
+        (_, "PROPFIND") => config.webdav,
+        (_, "OPTIONS") => true,
+        _ => false,
+    };
+    (!allowed).then_some(StatusCode::MethodNotAllowed)
+}
+
+// For Allow headers, so they always agree with check_access
+fn allowed_methods(config: &Config, path: &str) -> String {
+    ["GET", "HEAD", "PUT", "POST", "PROPFIND", "OPTIONS"]
+        .into_iter()
+        .filter(|method| check_access(config, method, path).is_none())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// For OPTIONS *, the methods some path may allow
+fn server_methods(config: &Config) -> String {
+    let mode = config.site_mode();
+    ["GET", "HEAD", "PUT", "POST", "PROPFIND", "OPTIONS"]
+        .into_iter()
+        .filter(|method| match *method {
+            "PUT" => mode != Mode::ReadOnly,
+            "POST" => config.can_manage(),
+            "PROPFIND" => config.webdav && mode != Mode::UploadOnly,
+            _ => true,
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Browsers say where requests come from, other clients send neither header
+fn is_cross_site(request: &Request) -> bool {
+    if let Some(site) = request.header("Sec-Fetch-Site") {
+        return !matches!(site, "same-origin" | "none");
+    }
+    match (request.header("Origin"), request.header("Host")) {
+        (Some(origin), Some(host)) => {
+            origin.split_once("://").map(|(_, origin)| origin) != Some(host)
+        }
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+// The token management forms must send back: the one of the login session,
+// or else the random one of the csrf cookie (double-submit). Other sites can
+// read neither.
+fn csrf_token(request: &Request, config: &Config) -> Option<String> {
+    config
+        .oidc
+        .as_ref()
+        .and_then(|oidc| oidc.csrf_token(request))
+        .or_else(|| {
+            request
+                .cookie(CSRF_COOKIE)
+                // it ends up in the page, only take what we generated
+                .filter(|token| {
+                    token.len() == 64 && token.bytes().all(|byte| byte.is_ascii_hexdigit())
+                })
+                .map(str::to_owned)
+        })
+}
+
+// Path tokens come in the X-Access-Token header or the token query parameter.
+// The longest matching prefix wins, so a subtree can have its own tokens.
+fn check_token(config: &Config, request: &Request, path: &str) -> Option<StatusCode> {
+    let matches = |prefix: &str| is_below(path, prefix);
+    let longest = config
+        .tokens
+        .iter()
+        .filter(|rule| matches(&rule.prefix))
+        .map(|rule| rule.prefix.len())
+        .max()?;
+
+    let token = request
+        .header("X-Access-Token")
+        .map(str::to_owned)
+        .or_else(|| request.query_param("token"))
+        .unwrap_or_default();
+    let valid = config
+        .tokens
+        .iter()
+        .filter(|rule| rule.prefix.len() == longest && matches(&rule.prefix))
+        .any(|rule| crypto::constant_time_eq(rule.token.as_bytes(), token.as_bytes()));
+    (!valid).then_some(StatusCode::Forbidden)
+}
+
+// Whether `path` is `prefix` or inside it, both normalized
+fn is_below(path: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn send_status(
+    tcp_stream: &mut Connection,
+    status: StatusCode,
+    headers: &[(&str, &str)],
+) -> Result<(), Box<dyn Error>> {
+    let mut headers = headers.to_vec();
+    let page = error_page::page(status);
+    let length = page.as_ref().map_or(0, Vec::len).to_string();
+    if page.is_some() {
+        headers.push(("Content-Type", "text/html; charset=utf-8"));
+    }
+    headers.push(("Content-Length", &length));
+    response::write_head(tcp_stream, status, &headers)?;
+    if let Some(page) = page {
+        response::write_body(tcp_stream, &page)?;
+    }
+    Ok(())
+}
+
+// Returns the response to send instead of serving an unauthenticated request.
+// Bearer tokens are accepted next to the other methods, for API clients.
+// `realm` is that of the site asked, see --tenant
+fn authenticate(
+    request: &Request,
+    config: &Config,
+    path: &str,
+    realm: &str,
+) -> Option<(StatusCode, Vec<(&'static str, String)>)> {
+    let unauthorized = |challenge: String| {
+        Some((
+            StatusCode::Unauthorized,
+            vec![("WWW-Authenticate", challenge)],
+        ))
+    };
+
+    if let Some(jwt) = &config.jwt
+        && let Some(token) = request
+            .header("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        return match jwt.verify(token.trim()) {
+            Ok(_) => None,
+            Err(err) => {
+                log::warning(&format!("rejected bearer token: {err}"));
+                unauthorized(format!("Bearer realm=\"{realm}\", error=\"invalid_token\""))
+            }
+        };
+    }
+
+    if let Some(digest) = &config.digest
+        && digest.applies(path)
+    {
+        let verdict = digest.verify(request, realm);
+        if verdict == auth::Verdict::Authorized {
+            return None;
+        }
+        let challenges = digest.challenges(realm, verdict == auth::Verdict::Stale);
+        let headers = challenges
+            .into_iter()
+            .map(|challenge| ("WWW-Authenticate", challenge));
+        return Some((StatusCode::Unauthorized, headers.collect()));
+    }
+
+    if let Some(credentials) = &config.auth {
+        if is_authorized(request, credentials) {
+            return None;
+        }
+        return unauthorized(format!("Basic realm=\"{realm}\", charset=\"UTF-8\""));
+    }
+
+    if let Some(oidc) = &config.oidc {
+        return match oidc.authorize(request) {
+            auth::Outcome::Authorized => None,
+            auth::Outcome::Redirect { location, cookie } => {
+                let mut headers = vec![("Location", location)];
+                headers.extend(cookie.map(|cookie| ("Set-Cookie", cookie)));
+                Some((StatusCode::Found, headers))
+            }
+            auth::Outcome::Unauthorized => Some((StatusCode::Unauthorized, Vec::new())),
+            auth::Outcome::Forbidden => Some((StatusCode::Forbidden, Vec::new())),
+        };
+    }
+
+    if config.jwt.is_some() {
+        return unauthorized(format!("Bearer realm=\"{realm}\""));
+    }
+    None
+}
+
+fn is_authorized(request: &Request, credentials: &auth::Credentials) -> bool {
+    request
+        .header("Authorization")
+        .and_then(auth::parse_basic)
+        .is_some_and(|(user, password)| credentials.verify(&user, &password))
+}
+
+// Parses an application/x-www-form-urlencoded body
+fn read_form(
+    request: &Request,
+    body: &mut impl Read,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let length = match request.header("Content-Length") {
+        Some(length) => length.parse::<u64>()?,
+        None => 0,
+    };
+    if length > MAX_FORM_SIZE {
+        return Err(format!("form too big: {length} bytes").into());
+    }
+
+    let mut form = String::new();
+    body.take(length).read_to_string(&mut form)?;
+
+    Ok(form
+        .split('&')
+        .filter_map(|field| field.split_once('='))
+        .filter_map(|(key, value)| Some((url_decode(key)?, url_decode(value)?)))
+        .collect())
+}
+
+fn url_encode_path(path: &str) -> String {
+    path.split('/')
+        .filter(|part| !part.is_empty())
+        .map(|part| url_encode(part) + "/")
+        .collect()
+}
+
+// What a management action did, paths normalized
+struct Change {
+    // the listing the form was sent from, to redirect to
+    directory: String,
+    path: String,
+    // where renames and moves went
+    to: Option<String>,
+}
+
+// Runs a management action
+fn manage(
+    action: &str,
+    form: &HashMap<String, String>,
+    trash: Option<&trash::Trash>,
+) -> Result<Change, StatusCode> {
+    let field = |name: &str| form.get(name).map(|value| normalize_path(value.clone()));
+    let parent = |path: &str| match path.rsplit_once('/') {
+        Some((parent, _)) => parent.to_owned(),
+        None => String::new(),
+    };
+    let is_valid_name = |name: &str| {
+        !name.is_empty() && normalize_path(name.to_owned()) == name && !name.contains('/')
+    };
+    let change = |directory: String, path: String, to: Option<String>| Change {
+        directory,
+        path,
+        to,
+    };
+
+    let res = match action {
+        "mkdir" => {
+            let path = field("path").ok_or(StatusCode::BadRequest)?;
+            let name = form.get("name").ok_or(StatusCode::BadRequest)?;
+            if !is_valid_name(name) {
+                return Err(StatusCode::BadRequest);
+            }
+            let created = normalize_path(format!("{path}/{name}"));
+            std::fs::create_dir(root::path(&created)).map(|_| change(path, created, None))
+        }
+        "rename" | "move" => {
+            let path = field("path")
+                .filter(|path| !path.is_empty())
+                .ok_or(StatusCode::BadRequest)?;
+            let to = form.get("to").ok_or(StatusCode::BadRequest)?;
+            let (destination, directory) = if action == "rename" {
+                if !is_valid_name(to) {
+                    return Err(StatusCode::BadRequest);
+                }
+                let directory = parent(&path);
+                (normalize_path(format!("{directory}/{to}")), directory)
+            } else {
+                let name = path.rsplit('/').next().unwrap_or_default();
+                (normalize_path(format!("{to}/{name}")), parent(&path))
+            };
+            if root::path(&destination).exists() {
+                return Err(StatusCode::Conflict);
+            }
+            std::fs::rename(root::path(&path), root::path(&destination))
+                .map(|_| change(directory, path, Some(destination)))
+        }
+        "delete" => {
+            let path = field("path")
+                .filter(|path| !path.is_empty())
+                .ok_or(StatusCode::BadRequest)?;
+            if let Some(trash) = trash {
+                trash.put(&path).map(|_| ())
+            } else if root::path(&path).is_dir() {
+                std::fs::remove_dir_all(root::path(&path))
+            } else {
+                std::fs::remove_file(root::path(&path))
+            }
+            .map(|_| change(parent(&path), path, None))
+        }
+        "undelete" => {
+            let trash = trash.ok_or(StatusCode::NotFound)?;
+            // the path is what access was checked against
+            let path = field("path").ok_or(StatusCode::BadRequest)?;
+            let id = form.get("id").ok_or(StatusCode::BadRequest)?;
+            if trash.origin(id).ok() != Some(path.clone()) {
+                return Err(StatusCode::NotFound);
+            }
+            trash.restore(id).map(|_| change(parent(&path), path, None))
+        }
+        _ => return Err(StatusCode::NotFound),
+    };
+
+    res.map_err(|err| {
+        log::error(&format!("{action} failed: {err}"));
+        match err.kind() {
+            std::io::ErrorKind::NotFound => StatusCode::NotFound,
+            std::io::ErrorKind::AlreadyExists => StatusCode::Conflict,
+            _ => StatusCode::InternalServerError,
+        }
+    })
+}
+
+// Content-Range: bytes 0-499/1234
+fn parse_content_range(value: &str) -> Option<(u64, u64, u64)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let number = |digits: &str| is_digits(digits).then(|| digits.parse().ok()).flatten();
+    let (start, end, total) = (number(start)?, number(end)?, number(total)?);
+    if start > end || end >= total {
+        return None;
+    }
+    Some((start, end, total))
+}
+
+// What part of a file a GET asked for
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    // first and last byte, both included
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// The Range header (RFC 9110 section 14.2) for a file of `size` bytes. Invalid
+// ones are ignored as the spec asks, and so are multiple ranges, which we
+// don't send as multipart.
+fn parse_range(value: Option<&str>, size: u64) -> ByteRange {
+    let Some((_, spec)) = value
+        .and_then(|value| value.trim().split_once('='))
+        .filter(|(unit, spec)| unit.eq_ignore_ascii_case("bytes") && !spec.contains(','))
+    else {
+        return ByteRange::Full;
+    };
+    let Some((first, last)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    // positions past what u64 holds are past the end of any file anyway
+    let number = |digits: &str| digits.parse().unwrap_or(u64::MAX);
+
+    match (first, last) {
+        // the last N bytes
+        ("", suffix) if is_digits(suffix) => match number(suffix) {
+            0 => ByteRange::Unsatisfiable,
+            _ if size == 0 => ByteRange::Unsatisfiable,
+            suffix => ByteRange::Partial(size.saturating_sub(suffix), size - 1),
+        },
+        (first, last) if is_digits(first) && (last.is_empty() || is_digits(last)) => {
+            let first = number(first);
+            let last = if last.is_empty() {
+                u64::MAX
+            } else {
+                number(last)
+            };
+            if last < first {
+                ByteRange::Full
+            } else if first >= size {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Partial(first, last.min(size - 1))
+            }
+        }
+        _ => ByteRange::Full,
+    }
+}
+
+fn is_digits(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+// An alternative of --alt-svc (RFC 7838), e.g. h3=:443, as in the header:
+// h3=":443"
+fn parse_alternative(value: &str) -> Option<String> {
+    let (protocol, authority) = value.split_once('=')?;
+    let (host, port) = authority.rsplit_once(':')?;
+    let token = |value: &str| {
+        !value.is_empty()
+            && value
+                .chars()
+                .all(|char| char.is_ascii_alphanumeric() || "-._/".contains(char))
+    };
+    if !token(protocol) || !(host.is_empty() || token(host)) {
+        return None;
+    }
+    let port = port.parse::<u16>().ok().filter(|&port| port != 0)?;
+    Some(format!("{protocol}=\"{host}:{port}\""))
+}
+
+// "30s", "15m", "12h" or "7d"
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (number, unit) = value.split_at_checked(value.len().checked_sub(1)?)?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    if !is_digits(number) {
+        return None;
+    }
+    let seconds = number.parse::<u64>().ok()?.checked_mul(unit)?;
+    Some(Duration::from_secs(seconds))
+}
+
+// The largest unit parse_duration reads back the same
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let units = [(24 * 60 * 60, "d"), (60 * 60, "h"), (60, "m")];
+    match units
+        .iter()
+        .find(|(unit, _)| seconds > 0 && seconds.is_multiple_of(*unit))
+    {
+        Some((unit, name)) => format!("{}{name}", seconds / unit),
+        None => format!("{seconds}s"),
+    }
+}
+
+// Writes the request body to `path`, honoring Content-Range so big files can
+// be sent in several chunks. Returns the status to reply with.
+fn receive_file(
+    path: &str,
+    request: &Request,
+    body: &mut impl Read,
+    config: &Config,
+) -> Result<StatusCode, Box<dyn Error>> {
+    let Some(length) = request.header("Content-Length") else {
+        return Ok(StatusCode::LengthRequired);
+    };
+    let Ok(length) = length.parse::<u64>() else {
+        return Ok(StatusCode::BadRequest);
+    };
+    let range = match request.header("Content-Range") {
+        Some(value) => match parse_content_range(value) {
+            Some((start, end, total)) if end - start + 1 == length => Some((start, total)),
+            _ => return Ok(StatusCode::BadRequest),
+        },
+        None => None,
+    };
+
+    if config
+        .max_upload
+        .is_some_and(|max| range.map_or(length, |(_, total)| total) > max)
+    {
+        return Ok(StatusCode::ContentTooLarge);
+    }
+
+    let target = &root::path(path);
+    let parent = target.parent().unwrap_or(Path::new(""));
+    // the parent of "foo.txt" is "", the current directory without a server
+    let mut parent_exists = parent.as_os_str().is_empty() || parent.is_dir();
+    if !parent_exists && config.create_dirs && !target.is_dir() {
+        parent_exists = std::fs::create_dir_all(parent).is_ok();
+    }
+    if target.is_dir() || !parent_exists {
+        return Ok(StatusCode::Conflict);
+    }
+    let existed = target.exists();
+    if !preconditions_hold(request, config, path, existed)? {
+        return Ok(StatusCode::PreconditionFailed);
+    }
+    let replaced = if existed { target.metadata()?.len() } else { 0 };
+    let size = range.map_or(length, |(_, total)| total);
+    if let Some(quota) = quota::exceeded(&config.quotas, path, size, replaced) {
+        log::warning(&format!(
+            "upload of '{path}' refused, over the quota of /{}",
+            quota.prefix
+        ));
+        return Ok(StatusCode::InsufficientStorage);
+    }
+
+    let (mut file, written_to, _upload) = match range {
+        // parts of a file, which can come in any order or in parallel, are
+        // gathered in one temporary file
+        Some((start, total)) => {
+            // in drop boxes, only the upload the parts belong to continues
+            let other = config
+                .uploads
+                .in_parts(path)
+                .is_some_and(|size| size != total);
+            if other && config.mode == Mode::UploadOnly {
+                return Ok(StatusCode::Conflict);
+            }
+            let temp = config.uploads.part_file(path, target, total)?;
+            let mut file = OpenOptions::new().write(true).open(&temp)?;
+            file.seek(SeekFrom::Start(start))?;
+            (file, temp, None)
+        }
+        None => {
+            let upload = config.uploads.start(path);
+            // drop boxes never overwrite, not even what's still arriving
+            let drop_box = config.mode == Mode::UploadOnly;
+            let reject = config.put_conflict == upload::Conflict::Reject || drop_box;
+            if (upload.is_none() && reject) || (config.uploads.in_parts(path).is_some() && drop_box)
+            {
+                return Ok(StatusCode::Conflict);
+            }
+            let temp = upload::temp_path(target);
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp)?;
+            (file, temp, upload)
+        }
+    };
+    let copied = std::io::copy(&mut body.take(length), &mut file).and_then(|written| {
+        if written != length {
+            let message = format!("interrupted after {written} bytes");
+            return Err(std::io::Error::new(ErrorKind::UnexpectedEof, message));
+        }
+        file.sync_all()
+    });
+    drop(file);
+    if let Err(err) = copied {
+        // a failed part can be sent again, the others stay
+        if range.is_none() {
+            std::fs::remove_file(&written_to)?;
+        }
+        if err.kind() == ErrorKind::StorageFull {
+            log::error(&format!("upload of '{path}' failed: {err}"));
+            return Ok(StatusCode::InsufficientStorage);
+        }
+        if timed_out(&err) {
+            log::warning(&format!(
+                "upload of '{path}' stalled, over the read timeout"
+            ));
+            // the rest of the body may still come, in place of a request
+            response::set_closing(true);
+            return Ok(StatusCode::RequestTimeout);
+        }
+        return Err(format!("upload of '{path}' failed: {err}").into());
+    }
+    if let Some((start, _)) = range
+        && !config
+            .uploads
+            .part_written(path, &written_to, start, start + length)
+    {
+        // more parts to come, the target is left as it was
+        return Ok(StatusCode::Accepted);
+    }
+    let existed = target.exists();
+    // another upload may have finished first
+    if existed && config.mode == Mode::UploadOnly {
+        std::fs::remove_file(&written_to)?;
+        return Ok(StatusCode::Conflict);
+    }
+    std::fs::rename(&written_to, target)?;
+    Ok(if existed {
+        StatusCode::NoContent
+    } else {
+        StatusCode::Created
+    })
+}
+
+// If-Match and If-None-Match (RFC 9110 section 13.1) on uploads, against the
+// ETag downloads get, so clients don't overwrite changes they haven't seen or
+// files they didn't expect
+fn preconditions_hold(
+    request: &Request,
+    config: &Config,
+    path: &str,
+    existed: bool,
+) -> std::io::Result<bool> {
+    let etag = || -> std::io::Result<String> {
+        let digest = if config.checksum_headers {
+            Some(config.checksums.sha256(&root::path(path))?)
+        } else {
+            None
+        };
+        let metadata = std::fs::metadata(root::path(path))?;
+        Ok(conditional::Validators::new(&metadata, digest.as_deref()).etag)
+    };
+    if let Some(if_match) = request.header("If-Match") {
+        if !existed {
+            return Ok(false);
+        }
+        // the strong comparison, weak tags never match
+        if if_match != "*" {
+            let etag = etag()?;
+            if !if_match.split(',').any(|tag| tag.trim() == etag) {
+                return Ok(false);
+            }
+        }
+    }
+    if let Some(if_none_match) = request.header("If-None-Match")
+        && existed
+        && (if_none_match == "*" || conditional::etag_matches(if_none_match, &etag()?))
+    {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+fn send_file(
+    file: &str,
+    start: u64,
+    length: u64,
+    tcp_stream: &mut Connection,
+) -> Result<(), Box<dyn Error>> {
+    let mut buffer = [0_u8; 1024];
+    let mut file = std::fs::File::open(root::path(file))?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut file = file.take(length);
+    while let bytes_read = file.read(&mut buffer)?
+        && bytes_read != 0
+    {
+        tcp_stream.write_all(&buffer[..bytes_read])?;
+        response::count_body(bytes_read as u64);
+    }
+    Ok(())
+}
+
+// Of --auth, --htpasswd, --ldap and --pam: one repeated replaces what it gave
+// before, e.g. in the config file, but two different ones would leave one unused
+fn choose_auth(chosen: &mut Option<&'static str>, option: &'static str) -> Result<(), String> {
+    match chosen.replace(option) {
+        Some(previous) if previous != option => Err(format!(
+            "'{option}' can't be combined with '{previous}': only one source of credentials is used"
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn parse_args(mut iter: impl Iterator<Item = String>) -> Result<Config, String> {
+    let mut res = Config {
+        port: DEFAULT_PORT,
+        address: DEFAULT_ADDRESS.to_owned(),
+        directory: DEFAULT_DIR.to_owned(),
+        threads: DEFAULT_THREADS,
+        max_conns: None,
+        #[cfg(feature = "tls")]
+        tls: None,
+        alt_svc: Vec::new(),
+        alt_svc_max_age: DEFAULT_ALT_SVC_MAX_AGE,
+        keep_alive: DEFAULT_KEEP_ALIVE,
+        request_timeout: None,
+        read_timeout: DEFAULT_READ_TIMEOUT,
+        write_timeout: DEFAULT_WRITE_TIMEOUT,
+        max_body: None,
+        report: None,
+        check_config: false,
+        output: None,
+        doctor: false,
+        upnp: false,
+        mode: Mode::ReadOnly,
+        auth: None,
+        digest: None,
+        oidc: None,
+        jwt: None,
+        auth_paths: Vec::new(),
+        tokens: Vec::new(),
+        quotas: Vec::new(),
+        put_conflict: upload::Conflict::LastWriterWins,
+        max_upload: None,
+        create_dirs: false,
+        uploads: upload::Uploads::default(),
+        trash: None,
+        webhooks: None,
+        hooks: None,
+        early_hints: Vec::new(),
+        maintenance: maintenance::Maintenance::default(),
+        hidden: Hidden::default(),
+        git_ref: None,
+        tenants: Vec::new(),
+        canary: None,
+        vhosts: Vec::new(),
+        vhost_default: None,
+        maps: Vec::new(),
+        geoip: Vec::new(),
+        geo_allow: Vec::new(),
+        geo_deny: Vec::new(),
+        ip_rules: cidr::Rules::default(),
+        rate_limit: None,
+        agents: agent::Policies::default(),
+        proxies: Vec::new(),
+        upstream_proxy: None,
+        fastcgi: None,
+        access_log: AccessLog::default(),
+        log_timings: false,
+        metrics: None,
+        checksums: checksum::Checksums::default(),
+        checksum_headers: false,
+        fingerprints: false,
+        minify: None,
+        inject_html: None,
+        filters: Vec::new(),
+        spa: false,
+        zip: false,
+        media: false,
+        viewer: false,
+        webdav: false,
+        search: None,
+        debug_routes: false,
+        strict_http: false,
+        server: Some(response::default_server()),
+        mime_types: Vec::new(),
+        no_compress: Vec::new(),
+        headers: Vec::new(),
+        log_target: log::Target::Console,
+        log_time: None,
+        changes: watch::Changes::default(),
+    };
+
+    let mut ldap_url = None;
+    // which of --auth, --htpasswd, --ldap and --pam gave the credentials
+    let mut auth_option = None;
+    let mut ldap_user_dn = None;
+    let mut ldap_group = None;
+    let mut oidc_issuer = None;
+    let mut oidc_client_id = None;
+    let mut oidc_client_secret = None;
+    let mut oidc_required_claims = Vec::new();
+    let mut jwt_secret = None;
+    let mut jwt_jwks = None;
+    let mut jwt_issuer = None;
+    let mut jwt_audience = None;
+    let mut digest_paths = Vec::new();
+    let mut rate_limit = None;
+    let mut rate_burst = None;
+    let mut trash_directory = None;
+    let mut trash_retention = None;
+    let mut webhook_urls = Vec::new();
+    let mut search = false;
+    let mut search_contents = false;
+    let mut search_refresh = None;
+    let mut webhook_secret = None;
+    let mut hook = None;
+    let mut hook_on = None;
+    let mut hook_threshold = None;
+    let mut git = false;
+    let mut git_ref = None;
+    let mut access_log = None;
+    let mut access_log_format = access_log::Format::Combined;
+    #[cfg(feature = "tls")]
+    let (mut tls_cert, mut tls_key) = (None, None);
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-b" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'-b' needs a value".to_owned());
+                };
+                res.address = arg_value;
+            }
+            "-p" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'-p' needs a value".to_owned());
+                };
+                res.port = arg_value
+                    .parse()
+                    .map_err(|_| "port number must be a u16".to_owned())?;
+            }
+            "-d" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'-d' needs a value".to_owned());
+                };
+                res.directory = arg_value;
+            }
+            "-j" | "--threads" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err(format!("'{arg}' needs a value"));
+                };
+                res.threads = arg_value
+                    .parse()
+                    .ok()
+                    .filter(|threads| *threads > 0)
+                    .ok_or_else(|| "number of threads must be a positive integer".to_owned())?;
+            }
+            "--max-conns" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--max-conns' needs a value".to_owned());
+                };
+                let max_conns = arg_value
+                    .parse()
+                    .ok()
+                    .filter(|max_conns| *max_conns > 0)
+                    .ok_or_else(|| "'--max-conns' value must be a positive integer".to_owned())?;
+                res.max_conns = Some(max_conns);
+            }
+            "--keep-alive" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--keep-alive' needs a value".to_owned());
+                };
+                let seconds = arg_value
+                    .parse()
+                    .map_err(|_| "keep-alive must be a number of seconds".to_owned())?;
+                res.keep_alive = Duration::from_secs(seconds);
+            }
+            "--request-timeout" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--request-timeout' needs a value".to_owned());
+                };
+                let Some(timeout) = parse_duration(&arg_value).filter(|timeout| !timeout.is_zero())
+                else {
+                    return Err("'--request-timeout' value must be a duration, e.g. 5m".to_owned());
+                };
+                res.request_timeout = Some(deadline::Watchdog::new(timeout));
+            }
+            "--read-timeout" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--read-timeout' needs a value".to_owned());
+                };
+                let Some(timeout) = parse_duration(&arg_value).filter(|timeout| !timeout.is_zero())
+                else {
+                    return Err("'--read-timeout' value must be a duration, e.g. 30s".to_owned());
+                };
+                res.read_timeout = timeout;
+            }
+            "--write-timeout" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--write-timeout' needs a value".to_owned());
+                };
+                let Some(timeout) = parse_duration(&arg_value).filter(|timeout| !timeout.is_zero())
+                else {
+                    return Err("'--write-timeout' value must be a duration, e.g. 1m".to_owned());
+                };
+                res.write_timeout = timeout;
+            }
+            "--expose" => res.address = EXPOSED_ADDRESS.to_owned(),
+            "--upnp" => res.upnp = true,
+            "--check-config" => res.check_config = true,
+            "--doctor" => res.doctor = true,
+            "--strict-http" => res.strict_http = true,
+            "--server-token" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--server-token' needs a value".to_owned());
+                };
+                res.server = Some(arg_value).filter(|server| !server.is_empty());
+            }
+            "--mime" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--mime' needs a value".to_owned());
+                };
+                let Some((ext, mime_type)) = arg_value
+                    .split_once('=')
+                    .map(|(ext, mime_type)| (ext.trim_start_matches('.'), mime_type.trim()))
+                    .filter(|(ext, mime_type)| !ext.is_empty() && mime_type.contains('/'))
+                else {
+                    return Err(
+                        "'--mime' value must be 'ext=type', e.g. 'md=text/markdown'".to_owned()
+                    );
+                };
+                res.mime_types.push((ext.to_owned(), mime_type.to_owned()));
+            }
+            "--no-compress" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--no-compress' needs a value".to_owned());
+                };
+                if !((arg_value.len() > 1 && arg_value.starts_with('.')) || arg_value.contains('/'))
+                {
+                    return Err(
+                        "'--no-compress' value must be a type or '.ext', e.g. 'text/csv'"
+                            .to_owned(),
+                    );
+                }
+                res.no_compress.push(arg_value.to_ascii_lowercase());
+            }
+            "--header" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--header' needs a value".to_owned());
+                };
+                let Some((name, value)) = arg_value
+                    .split_once(':')
+                    .map(|(name, value)| (name.trim(), value.trim()))
+                    .filter(|(name, value)| {
+                        !name.is_empty()
+                            && name
+                                .bytes()
+                                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+                            && !value.bytes().any(|byte| byte.is_ascii_control())
+                    })
+                else {
+                    return Err(
+                        "'--header' value must be 'name: value', e.g. 'X-Frame-Options: DENY'"
+                            .to_owned(),
+                    );
+                };
+                // what frames messages, or has an option of its own
+                if [
+                    "content-length",
+                    "transfer-encoding",
+                    "connection",
+                    "keep-alive",
+                    "upgrade",
+                    "trailer",
+                    "date",
+                    "server",
+                    "alt-svc",
+                ]
+                .contains(&name.to_ascii_lowercase().as_str())
+                {
+                    return Err(format!("'--header' can't set {name}"));
+                }
+                res.headers.push((name.to_owned(), value.to_owned()));
+            }
+            "--log-target" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--log-target' needs a value".to_owned());
+                };
+                let Some(target) = log::Target::parse(&arg_value) else {
+                    return Err(format!("unknown log target: {arg_value}"));
+                };
+                res.log_target = target;
+            }
+            "--log-time" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--log-time' needs a value".to_owned());
+                };
+                let Some(clock) = date::Clock::parse(&arg_value) else {
+                    return Err(format!("unknown log time: {arg_value}"));
+                };
+                res.log_time = Some(clock);
+            }
+            "--access-log" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--access-log' needs a value".to_owned());
+                };
+                access_log = Some(arg_value);
+            }
+            "--access-log-format" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--access-log-format' needs a value".to_owned());
+                };
+                let Some(format) = access_log::Format::parse(&arg_value) else {
+                    return Err(format!("unknown access log format: {arg_value}"));
+                };
+                access_log_format = format;
+            }
+            "--log-exclude" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--log-exclude' needs a value".to_owned());
+                };
+                let prefix = normalize_path(arg_value.trim_end_matches("/**").to_owned());
+                res.access_log.exclude.push(prefix);
+            }
+            "--log-timings" => res.log_timings = true,
+            "--metrics" => res.metrics = Some(metrics::Metrics::default()),
+            "--checksums" => res.checksum_headers = true,
+            "--fingerprints" => res.fingerprints = true,
+            "--minify" => res.minify = Some(minify::Minifier::default()),
+            "--inject-html" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--inject-html' needs a value".to_owned());
+                };
+                let content = std::fs::read(&arg_value)
+                    .map_err(|err| format!("failed to read '{arg_value}': {err}"))?;
+                res.inject_html = Some(inject::Snippet::new(content));
+            }
+            "--filter" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--filter' needs a value".to_owned());
+                };
+                let filter = filter::Command::parse(&arg_value)?;
+                res.filters.push(Box::new(filter));
+            }
+            "--spa" => res.spa = true,
+            "--zip" => res.zip = true,
+            "--media" => res.media = true,
+            "--viewer" => res.viewer = true,
+            "--webdav" => res.webdav = true,
+            "--search" => search = true,
+            "--search-contents" => search_contents = true,
+            "--search-refresh" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--search-refresh' needs a value".to_owned());
+                };
+                let Some(refresh) = parse_duration(&arg_value).filter(|refresh| !refresh.is_zero())
+                else {
+                    return Err("'--search-refresh' value must be a duration, e.g. 30m".to_owned());
+                };
+                search_refresh = Some(refresh);
+            }
+            "--debug-routes" => res.debug_routes = true,
+            "--log-sample" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--log-sample' needs a value".to_owned());
+                };
+                res.access_log.sample = match arg_value.parse() {
+                    Ok(sample) if sample > 0 => sample,
+                    _ => return Err("'--log-sample' must be a positive number".to_owned()),
+                };
+            }
+            "--mode" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--mode' needs a value".to_owned());
+                };
+                res.mode = match arg_value.as_str() {
+                    "read-only" => Mode::ReadOnly,
+                    "upload-only" => Mode::UploadOnly,
+                    "read-write" => Mode::ReadWrite,
+                    _ => return Err(format!("unknown mode: {arg_value}")),
+                };
+            }
+            "--allow-upload" => res.mode = Mode::ReadWrite,
+            "--put-conflict" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--put-conflict' needs a value".to_owned());
+                };
+                res.put_conflict = upload::Conflict::parse(&arg_value)
+                    .ok_or_else(|| format!("unknown PUT conflict policy: {arg_value}"))?;
+            }
+            "--report" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--report' needs a value".to_owned());
+                };
+                // written once we moved to the served directory
+                let path = std::path::absolute(&arg_value)
+                    .map_err(|err| format!("'--report' {arg_value}: {err}"))?;
+                res.report = Some(path);
+            }
+            "--max-body" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--max-body' needs a value".to_owned());
+                };
+                match quota::parse_size(&arg_value) {
+                    Some(size) if size > 0 => res.max_body = Some(size),
+                    _ => return Err("'--max-body' value must be a size, e.g. 10M".to_owned()),
+                }
+            }
+            "--max-upload" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--max-upload' needs a value".to_owned());
+                };
+                match quota::parse_size(&arg_value) {
+                    Some(size) if size > 0 => res.max_upload = Some(size),
+                    _ => return Err("'--max-upload' value must be a size, e.g. 100M".to_owned()),
+                }
+            }
+            "--create-dirs" => res.create_dirs = true,
+            "--trash" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--trash' needs a value".to_owned());
+                };
+                trash_directory = Some(arg_value);
+            }
+            "--alt-svc" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--alt-svc' needs a value".to_owned());
+                };
+                let Some(alternative) = parse_alternative(&arg_value) else {
+                    return Err(
+                        "'--alt-svc' value must be 'protocol=[host]:port', e.g. 'h3=:443'"
+                            .to_owned(),
+                    );
+                };
+                res.alt_svc.push(alternative);
+            }
+            "--alt-svc-max-age" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--alt-svc-max-age' needs a value".to_owned());
+                };
+                let Some(max_age) = parse_duration(&arg_value) else {
+                    return Err("'--alt-svc-max-age' value must be a duration, e.g. 24h".to_owned());
+                };
+                res.alt_svc_max_age = max_age;
+            }
+            "--trash-retention" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--trash-retention' needs a value".to_owned());
+                };
+                let Some(retention) =
+                    parse_duration(&arg_value).filter(|retention| !retention.is_zero())
+                else {
+                    return Err("'--trash-retention' value must be a duration, e.g. 30d".to_owned());
+                };
+                trash_retention = Some(retention);
+            }
+            "--webhook" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--webhook' needs a value".to_owned());
+                };
+                webhook_urls.push(arg_value);
+            }
+            "--webhook-secret" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--webhook-secret' needs a value".to_owned());
+                };
+                webhook_secret = Some(arg_value);
+            }
+            "--hook" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--hook' needs a value".to_owned());
+                };
+                hook = Some(arg_value);
+            }
+            "--hook-on" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--hook-on' needs a value".to_owned());
+                };
+                let events: Vec<String> = arg_value
+                    .split(',')
+                    .map(|event| event.trim().to_owned())
+                    .collect();
+                if let Some(event) = events
+                    .iter()
+                    .find(|event| !hook::EVENTS.contains(&event.as_str()))
+                {
+                    return Err(format!(
+                        "unknown '--hook-on' event '{event}', expected some of {}",
+                        hook::EVENTS.join(", ")
+                    ));
+                }
+                hook_on = Some(events);
+            }
+            "--hook-threshold" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--hook-threshold' needs a value".to_owned());
+                };
+                let threshold = arg_value
+                    .parse()
+                    .ok()
+                    .filter(|threshold| *threshold > 0)
+                    .ok_or_else(|| {
+                        "'--hook-threshold' value must be a positive integer".to_owned()
+                    })?;
+                hook_threshold = Some(threshold);
+            }
+            "--auth" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--auth' needs a value".to_owned());
+                };
+                let Some((user, password)) = arg_value.split_once(':') else {
+                    return Err("'--auth' value must be 'user:password'".to_owned());
+                };
+                choose_auth(&mut auth_option, "--auth")?;
+                res.auth = Some(auth::Credentials::User {
+                    user: user.to_owned(),
+                    password: password.to_owned(),
+                });
+            }
+            "--digest" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--digest' needs a value".to_owned());
+                };
+                digest_paths.push(normalize_path(arg_value.trim_end_matches("/**").to_owned()));
+            }
+            "--auth-path" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--auth-path' needs a value".to_owned());
+                };
+                res.auth_paths
+                    .push(normalize_path(arg_value.trim_end_matches("/**").to_owned()));
+            }
+            "--htpasswd" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--htpasswd' needs a value".to_owned());
+                };
+                let htpasswd = auth::Htpasswd::load(Path::new(&arg_value))
+                    .map_err(|err| format!("failed to load '{arg_value}': {err}"))?;
+                choose_auth(&mut auth_option, "--htpasswd")?;
+                res.auth = Some(auth::Credentials::Htpasswd(htpasswd));
+            }
+            "--ldap" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--ldap' needs a value".to_owned());
+                };
+                choose_auth(&mut auth_option, "--ldap")?;
+                ldap_url = Some(arg_value);
+            }
+            "--ldap-user-dn" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--ldap-user-dn' needs a value".to_owned());
+                };
+                ldap_user_dn = Some(arg_value);
+            }
+            "--ldap-group" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--ldap-group' needs a value".to_owned());
+                };
+                ldap_group = Some(arg_value);
+            }
+            "--oidc-issuer" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--oidc-issuer' needs a value".to_owned());
+                };
+                oidc_issuer = Some(arg_value);
+            }
+            "--oidc-client-id" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--oidc-client-id' needs a value".to_owned());
+                };
+                oidc_client_id = Some(arg_value);
+            }
+            "--oidc-client-secret" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--oidc-client-secret' needs a value".to_owned());
+                };
+                oidc_client_secret = Some(arg_value);
+            }
+            "--oidc-require" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--oidc-require' needs a value".to_owned());
+                };
+                let Some((claim, value)) = arg_value.split_once('=') else {
+                    return Err("'--oidc-require' value must be 'claim=value'".to_owned());
+                };
+                oidc_required_claims.push((claim.to_owned(), value.to_owned()));
+            }
+            "--jwt-secret" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--jwt-secret' needs a value".to_owned());
+                };
+                jwt_secret = Some(arg_value);
+            }
+            "--jwt-jwks" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--jwt-jwks' needs a value".to_owned());
+                };
+                jwt_jwks = Some(arg_value);
+            }
+            "--jwt-issuer" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--jwt-issuer' needs a value".to_owned());
+                };
+                jwt_issuer = Some(arg_value);
+            }
+            "--jwt-audience" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--jwt-audience' needs a value".to_owned());
+                };
+                jwt_audience = Some(arg_value);
+            }
+            "--token" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--token' needs a value".to_owned());
+                };
+                let Some((path, token)) = arg_value
+                    .split_once('=')
+                    .filter(|(_, token)| !token.is_empty())
+                else {
+                    return Err("'--token' value must be 'path=token'".to_owned());
+                };
+                res.tokens.push(PathToken {
+                    prefix: normalize_path(path.trim_end_matches("/**").to_owned()),
+                    token: token.to_owned(),
+                });
+            }
+            "--early-hint" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--early-hint' needs a value".to_owned());
+                };
+                let Some((path, link)) = arg_value
+                    .split_once('=')
+                    .filter(|(path, link)| path.starts_with('/') && link.starts_with('<'))
+                else {
+                    return Err(
+                        "'--early-hint' value must be 'path=link', e.g. '/=</app.css>;rel=preload'"
+                            .to_owned(),
+                    );
+                };
+                let mut path = normalize_path(path.to_owned());
+                if path.is_empty() {
+                    path.push('.');
+                }
+                res.early_hints.push((path, link.to_owned()));
+            }
+            "--maintenance-file" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--maintenance-file' needs a value".to_owned());
+                };
+                let file = std::path::absolute(&arg_value)
+                    .map_err(|_| format!("invalid maintenance file: {arg_value}"))?;
+                res.maintenance.file = Some(file);
+            }
+            "--maintenance-allow" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--maintenance-allow' needs a value".to_owned());
+                };
+                let prefix = normalize_path(arg_value.trim_end_matches("/**").to_owned());
+                res.maintenance.allow.push(prefix);
+            }
+            "--maintenance-retry-after" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--maintenance-retry-after' needs a value".to_owned());
+                };
+                let Some(retry_after) = parse_duration(&arg_value) else {
+                    return Err(
+                        "'--maintenance-retry-after' value must be a duration, e.g. 5m".to_owned(),
+                    );
+                };
+                res.maintenance.retry_after = retry_after;
+            }
+            "--quota" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--quota' needs a value".to_owned());
+                };
+                let (path, size) = arg_value.split_once('=').unwrap_or(("", &arg_value));
+                let Some(size) = quota::parse_size(size) else {
+                    return Err("'--quota' value must be '[path=]size', e.g. 10G".to_owned());
+                };
+                res.quotas.push(quota::Quota {
+                    prefix: normalize_path(path.to_owned()),
+                    size,
+                });
+            }
+            "--geoip" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--geoip' needs a value".to_owned());
+                };
+                let database = geoip::Database::open(&arg_value).map_err(|err| err.to_string())?;
+                res.geoip.push(database);
+            }
+            "--geo-allow" | "--geo-deny" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err(format!("'{arg}' needs a value"));
+                };
+                let rule = arg_value.to_ascii_uppercase();
+                let country = rule.len() == 2 && rule.bytes().all(|byte| byte.is_ascii_uppercase());
+                let asn = rule
+                    .strip_prefix("AS")
+                    .is_some_and(|asn| asn.parse::<u32>().is_ok());
+                if !country && !asn {
+                    return Err(format!(
+                        "'{arg}' value must be a country code (e.g. FR) or ASn (e.g. AS3215)"
+                    ));
+                }
+                match arg.as_str() {
+                    "--geo-allow" => res.geo_allow.push(rule),
+                    _ => res.geo_deny.push(rule),
+                }
+            }
+            "--rate-limit" | "--rate-burst" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err(format!("'{arg}' needs a value"));
+                };
+                let Some(value) = arg_value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|value| value.is_finite() && *value > 0.0)
+                else {
+                    return Err(format!("'{arg}' value must be a positive number, e.g. 10"));
+                };
+                match arg.as_str() {
+                    "--rate-limit" => rate_limit = Some(value),
+                    _ => rate_burst = Some(value),
+                }
+            }
+            "--ip-allow" | "--ip-deny" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err(format!("'{arg}' needs a value"));
+                };
+                let Some(range) = cidr::Cidr::parse(&arg_value) else {
+                    return Err(format!(
+                        "'{arg}' value must be an address or a range, e.g. 10.0.0.0/8"
+                    ));
+                };
+                match arg.as_str() {
+                    "--ip-allow" => res.ip_rules.allow.push(range),
+                    _ => res.ip_rules.deny.push(range),
+                }
+            }
+            "--agent" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--agent' needs a value".to_owned());
+                };
+                res.agents.parse(&arg_value)?;
+            }
+            "--canary" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--canary' needs a value".to_owned());
+                };
+                res.canary = Some(canary::parse(&arg_value)?);
+            }
+            "--tenant" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--tenant' needs a value".to_owned());
+                };
+                let tenant = tenant::parse(&arg_value)?;
+                if let Some(size) = tenant.quota {
+                    res.quotas.push(quota::Quota {
+                        prefix: tenant.root.clone(),
+                        size,
+                    });
+                }
+                res.tenants.push(tenant);
+            }
+            "--vhost" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--vhost' needs a value".to_owned());
+                };
+                res.vhosts.push(vhost::parse(&arg_value)?);
+            }
+            "--upstream-proxy" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--upstream-proxy' needs a value".to_owned());
+                };
+                let upstream = tunnel::Upstream::parse(&arg_value)
+                    .map_err(|err| format!("'--upstream-proxy' {err}"))?;
+                res.upstream_proxy = Some(upstream);
+            }
+            "--proxy" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--proxy' needs a value".to_owned());
+                };
+                res.proxies.push(proxy::parse(&arg_value)?);
+            }
+            "--fastcgi" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--fastcgi' needs a value".to_owned());
+                };
+                res.fastcgi = Some(fastcgi::parse(&arg_value)?);
+            }
+            "--map" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--map' needs a value".to_owned());
+                };
+                res.maps.push(map::parse(&arg_value)?);
+            }
+            "--vhost-default" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--vhost-default' needs a value".to_owned());
+                };
+                res.vhost_default = Some(tenant::host_name(&arg_value));
+            }
+            "--hide-dotfiles" => res.hidden.dotfiles = true,
+            "--git" => git = true,
+            "--git-ref" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--git-ref' needs a value".to_owned());
+                };
+                git_ref = Some(arg_value);
+            }
+            "--deny" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--deny' needs a value".to_owned());
+                };
+                if arg_value.is_empty() || arg_value.contains('/') {
+                    return Err("'--deny' value must be a file name".to_owned());
+                }
+                res.hidden.names.push(fold_case(&arg_value));
+            }
+            #[cfg(feature = "pam")]
+            "--pam" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--pam' needs a value".to_owned());
+                };
+                let pam = auth::Pam::new(&arg_value)
+                    .map_err(|_| format!("invalid PAM service: {arg_value}"))?;
+                choose_auth(&mut auth_option, "--pam")?;
+                res.auth = Some(auth::Credentials::Pam(pam));
+            }
+            #[cfg(not(feature = "pam"))]
+            "--pam" => return Err("'--pam' needs a build with the `pam` cargo feature".to_owned()),
+            #[cfg(feature = "tls")]
+            "--cert" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--cert' needs a value".to_owned());
+                };
+                tls_cert = Some(arg_value);
+            }
+            #[cfg(feature = "tls")]
+            "--key" => {
+                let Some(arg_value) = iter.next() else {
+                    return Err("'--key' needs a value".to_owned());
+                };
+                tls_key = Some(arg_value);
+            }
+            #[cfg(not(feature = "tls"))]
+            "--cert" | "--key" => {
+                return Err(format!(
+                    "'{arg}' needs a build with the `tls` cargo feature"
+                ));
+            }
+            "-v" => {
+                res.output = Some(env!("CARGO_PKG_VERSION"));
+                return Ok(res);
+            }
+            "-h" => {
+                res.output = Some(USAGE);
+                return Ok(res);
+            }
+            _ => return Err(format!("unknown option '{arg}', see -h")),
+        }
+    }
+
+    if res.geoip.is_empty() && !(res.geo_allow.is_empty() && res.geo_deny.is_empty()) {
+        return Err("'--geo-allow' and '--geo-deny' need '--geoip'".to_owned());
+    }
+    if let Some(host) = &res.vhost_default
+        && !res.vhosts.iter().any(|vhost| &vhost.host == host)
+    {
+        return Err(format!(
+            "'--vhost-default' must be the host of a '--vhost': {host}"
+        ));
+    }
+
+    if let Some(path) = access_log {
+        let file = access_log::File::open(&path, access_log_format)
+            .map_err(|err| format!("failed to open access log '{path}': {err}"))?;
+        res.access_log.file = Some(file);
+    }
+
+    if let Some(url) = ldap_url {
+        let Some(user_dn) = ldap_user_dn else {
+            return Err("'--ldap' needs '--ldap-user-dn'".to_owned());
+        };
+        let ldap = auth::Ldap::new(&url, user_dn, ldap_group)?;
+        res.auth = Some(auth::Credentials::Ldap(ldap));
+    }
+
+    match (rate_limit, rate_burst) {
+        // a second's worth of requests by default
+        (Some(rate), burst) => {
+            let burst = burst.unwrap_or(rate).max(1.0);
+            res.rate_limit = Some(tenant::TokenBuckets::new(rate, burst));
+        }
+        (None, Some(_)) => return Err("'--rate-burst' needs '--rate-limit'".to_owned()),
+        (None, None) => {}
+    }
+
+    if !digest_paths.is_empty() {
+        let Some(auth::Credentials::User { user, password }) = &res.auth else {
+            return Err("'--digest' needs '--auth user:password'".to_owned());
+        };
+        let digest = auth::Digest::new(digest_paths, user.clone(), password.clone())
+            .map_err(|err| err.to_string())?;
+        res.digest = Some(digest);
+    }
+
+    #[cfg(feature = "tls")]
+    match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => {
+            let tls = tls::Tls::new(&cert, &key).map_err(|err| err.to_string())?;
+            res.tls = Some(tls);
+        }
+        (None, None) => {}
+        _ => return Err("'--cert' and '--key' go together".to_owned()),
+    }
+
+    if let Some(issuer) = oidc_issuer {
+        if res.auth.is_some() {
+            return Err(
+                "'--oidc-issuer' can't be combined with other authentication options".to_owned(),
+            );
+        }
+        let (Some(client_id), Some(client_secret)) = (oidc_client_id, oidc_client_secret) else {
+            return Err(
+                "'--oidc-issuer' needs '--oidc-client-id' and '--oidc-client-secret'".to_owned(),
+            );
+        };
+        let oidc = auth::Oidc::new(
+            &issuer,
+            client_id,
+            client_secret,
+            res.scheme(),
+            oidc_required_claims,
+        )?;
+        res.oidc = Some(oidc);
+    }
+
+    if jwt_secret.is_some() || jwt_jwks.is_some() {
+        let jwt = auth::Jwt::new(jwt_secret, jwt_jwks, jwt_issuer, jwt_audience)?;
+        res.jwt = Some(jwt);
+    } else if jwt_issuer.is_some() || jwt_audience.is_some() {
+        return Err(
+            "'--jwt-issuer' and '--jwt-audience' need '--jwt-secret' or '--jwt-jwks'".to_owned(),
+        );
+    }
+
+    if let Some(directory) = trash_directory {
+        if Path::new(&directory).is_relative() {
+            let prefix = normalize_path(directory.clone());
+            if prefix.is_empty() {
+                return Err("'--trash' can't be the served directory".to_owned());
+            }
+            res.hidden.paths.push(prefix);
+        }
+        res.trash = Some(trash::Trash {
+            directory: directory.into(),
+            retention: trash_retention.unwrap_or(trash::DEFAULT_RETENTION),
+        });
+    } else if trash_retention.is_some() {
+        return Err("'--trash-retention' needs '--trash'".to_owned());
+    }
+
+    if search || search_contents {
+        let refresh = search_refresh.unwrap_or(search::DEFAULT_REFRESH);
+        res.search = Some(search::Index::new(search_contents, refresh));
+    } else if search_refresh.is_some() {
+        return Err("'--search-refresh' needs '--search'".to_owned());
+    }
+
+    if git || git_ref.is_some() {
+        res.hidden.names.push(fold_case(".git"));
+        // relative to the served directory of each request
+        res.hidden.gitignore = Some(gitignore::Gitignore::new("."));
+    }
+    if let Some(revision) = git_ref {
+        let repository = git::Repository::open(Path::new(&res.directory))
+            .and_then(|repository| repository.resolve(&revision).map(|_| repository))
+            .map_err(|err| format!("'--git-ref' {revision}: {err}"))?;
+        res.git_ref = Some((revision, repository));
+    }
+
+    if !webhook_urls.is_empty() {
+        let webhooks =
+            webhook::Webhooks::new(webhook_urls, webhook_secret).map_err(|err| err.to_string())?;
+        res.webhooks = Some(webhooks);
+    } else if webhook_secret.is_some() {
+        return Err("'--webhook-secret' needs '--webhook'".to_owned());
+    }
+
+    if res
+        .max_conns
+        .is_some_and(|max_conns| max_conns < res.threads)
+    {
+        return Err(format!(
+            "'--max-conns' can't be less than the {} threads",
+            res.threads
+        ));
+    }
+
+    if let Some(command) = hook {
+        let threshold = hook_threshold.unwrap_or(hook::DEFAULT_THRESHOLD);
+        res.hooks = Some(hook::Hooks::new(
+            command,
+            hook_on.unwrap_or_default(),
+            threshold,
+        ));
+    } else if hook_on.is_some() || hook_threshold.is_some() {
+        return Err("'--hook-on' and '--hook-threshold' need '--hook'".to_owned());
+    }
+
+    Ok(res)
+}
+
+// Signals are all handled here, and this has to run before any other thread
+// is spawned.
+// `local_addr` is that of the listener, which a connection wakes up to stop.
+fn handle_signals(config: &Arc<Config>, local_addr: SocketAddr) -> Result<(), Box<dyn Error>> {
+    let mut signals = vec![
+        signal::SIGHUP,
+        signal::SIGUSR1,
+        signal::SIGINT,
+        signal::SIGTERM,
+    ];
+    // as PID 1, in a container, orphans are ours to reap
+    if std::process::id() == 1 {
+        signals.push(signal::SIGCHLD);
+    }
+    let config = Arc::clone(config);
+    let mut wake = local_addr;
+    if wake.ip().is_unspecified() {
+        wake.set_ip(match wake {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    signal::handle(&signals, move |signal| {
+        if signal == signal::SIGHUP {
+            if let Some(Err(err)) = config.auth.as_ref().map(auth::Credentials::reload) {
+                log::error(&format!("failed to reload credentials: {err}"));
+            }
+            match release::refresh() {
+                Ok(Some(directory)) => {
+                    log::info(&format!("now serving out of {}", directory.display()));
+                    if let Some(index) = &config.search {
+                        index.changed();
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => log::error(&format!("failed to switch directories: {err}")),
+            }
+            return;
+        }
+        if signal == signal::SIGUSR1 {
+            match config.maintenance.toggle() {
+                true => log::info("maintenance on"),
+                false => log::info("maintenance off"),
+            }
+            return;
+        }
+        if signal == signal::SIGCHLD {
+            signal::reap_children();
+            return;
+        }
+        if !shutdown::start() {
+            log::warning("stopped before requests finished");
+            std::process::exit(128 + signal);
+        }
+        let in_flight = shutdown::in_flight();
+        log::info(&format!(
+            "shutting down, waiting for {in_flight} requests (again to stop now)"
+        ));
+        // out of accept(), to close the listener
+        let _ = TcpStream::connect_timeout(&wake, Duration::from_secs(1));
+    })?;
+
+    Ok(())
+}
+
+fn map_port(config: &Config, mapping: &OnceLock<upnp::PortMapping>) {
+    match upnp::PortMapping::new(config.port) {
+        Ok(new_mapping) => {
+            log::info(&format!(
+                "Reachable from the internet on {}://{} ({})",
+                config.scheme(),
+                new_mapping.external_addr(),
+                new_mapping.protocol_name()
+            ));
+            let _ = mapping.set(new_mapping);
+        }
+        Err(err) => log::error(&format!("port mapping failed: {err}")),
+    }
+}
+
+// A server bound to its port
+pub struct Server {
+    config: Arc<Config>,
+    listener: TcpListener,
+}
+
+// Exit statuses, for supervisors to tell options to fix from a port which is
+// taken, which may free up
+pub const EXIT_FAILURE: u8 = 1;
+pub const EXIT_CONFIG: u8 = 2;
+pub const EXIT_BIND: u8 = 3;
+
+// Server::new failed to listen on the address asked
+#[derive(Debug)]
+pub struct BindError {
+    address: String,
+    err: std::io::Error,
+}
+
+impl std::fmt::Display for BindError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "failed to listen on {}: {}", self.address, self.err)
+    }
+}
+
+impl Error for BindError {}
+
+// Options which are invalid, or which can't be served, e.g. a -d which doesn't
+// exist
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for ConfigError {}
+
+// A response as clients get it, see Server::handle
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl Server {
+    // Binds the listener, without changing the directory of the process;
+    // --doctor prints what it found, and fails on errors
+    pub fn new(config: Config) -> Result<Server, Box<dyn Error>> {
+        let config = Arc::new(config);
+        response::set_server(config.server.clone());
+        response::set_headers(config.headers.clone());
+        let _ = MIME_TYPES.set(config.mime_types.clone());
+        response::set_alt_svc(config.alt_svc());
+        if let Some(upstream) = &config.upstream_proxy {
+            tunnel::set_default(upstream.clone());
+        }
+        log::set_target(config.log_target)?;
+        if let Some(clock) = config.log_time {
+            log::set_time(clock);
+        }
+
+        let warnings = config.warnings();
+        let problems = config.problems();
+
+        let directory = std::path::absolute(&config.directory)?;
+        release::init(&directory)
+            .map_err(|err| ConfigError(format!("failed to serve '{}': {err}", config.directory)))?;
+        if config.doctor {
+            let findings = doctor::run(&config, &problems, &warnings);
+            for finding in &findings {
+                println!("{finding}");
+            }
+            if findings
+                .iter()
+                .any(|finding| finding.level == doctor::Level::Error)
+            {
+                return Err("--doctor found errors".into());
+            }
+        }
+
+        let address = format!("{}:{}", config.address, config.port);
+        let listener = TcpListener::bind(&address).map_err(|err| BindError { address, err })?;
+        report::start();
+
+        log::info(&format!(
+            "Listening on {}://{}",
+            config.scheme(),
+            listener.local_addr()?
+        ));
+        log::info(&format!("serving out of {}", release::current().display()));
+        for (key, value) in config.describe().into_iter().skip(2) {
+            log::info(&format!("  {key:<14}{value}"));
+        }
+        // the doctor already told
+        if !config.doctor {
+            for warning in warnings {
+                log::warning(&warning);
+            }
+        }
+        Ok(Server { config, listener })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Accepts connections until the process ends
+    pub fn serve(self) -> Result<(), Box<dyn Error>> {
+        let Server { config, listener } = self;
+        // kept alive until this returns, which removes the mapping
+        let port_mapping = Arc::new(OnceLock::new());
+        handle_signals(&config, listener.local_addr()?)?;
+        // its thread must be spawned with the signals blocked, or it could get
+        // SIGTERM, which would end the process
+        if let Some(hooks) = &config.hooks {
+            let address = json::quote(&listener.local_addr()?.to_string());
+            hooks.send("start", &format!("\"address\":{address}"));
+        }
+        if config.upnp {
+            map_port(&config, &port_mapping);
+        }
+        if config.search.is_some() {
+            let config = Arc::clone(&config);
+            std::thread::spawn(move || {
+                if let Some(index) = &config.search {
+                    index.run(&|path| {
+                        config.hidden.matches(path)
+                            || tenant::owner(&config.tenants, path).is_some()
+                            || canary::owns(config.canary.as_ref(), path)
+                    });
+                }
+            });
+        }
+        if config.request_timeout.is_some() {
+            let config = Arc::clone(&config);
+            std::thread::spawn(move || {
+                if let Some(watchdog) = &config.request_timeout {
+                    watchdog.run();
+                }
+            });
+        }
+        if let Some(trash) = &config.trash {
+            let config = Arc::clone(&config);
+            let interval = trash.retention.min(trash::PURGE_INTERVAL);
+            std::thread::spawn(move || {
+                let Some(trash) = &config.trash else {
+                    return;
+                };
+                loop {
+                    if let Err(err) = release::enter() {
+                        log::error(&format!("failed to switch directories: {err}"));
+                    }
+                    let purged = trash.purge(std::time::SystemTime::now());
+                    if purged > 0 {
+                        log::info(&format!("{purged} expired trash entries removed"));
+                    }
+                    std::thread::sleep(interval);
+                }
+            });
+        }
+
+        let pool = {
+            let config = Arc::clone(&config);
+            let waiting = config.max_conns() - config.threads;
+            pool::Pool::new(config.threads, waiting, move |tcp_stream: TcpStream| {
+                let peer = tcp_stream.peer_addr();
+                let res = config
+                    .connection(tcp_stream)
+                    .map_err(Box::<dyn Error>::from)
+                    .and_then(|stream| handle_connection(stream, &config));
+                if let Err(err) = res {
+                    report::connection_failed();
+                    let peer = peer.map(|peer| peer.to_string()).unwrap_or_default();
+                    log::warning(&format!("connection from {peer} failed: {err}"));
+                }
+            })?
+        };
+        loop {
+            if shutdown::draining() {
+                break;
+            }
+            let tcp_stream = match listener.accept() {
+                Ok((_, peer)) if !config.ip_rules.permits(peer.ip()) => {
+                    log::info(&format!("connection from {} refused", peer.ip()));
+                    continue;
+                }
+                Ok((tcp_stream, _sock_addr)) => tcp_stream,
+                // e.g. out of file descriptors, or the client already left
+                Err(err) => {
+                    log::error(&format!("accepting a connection failed: {err}"));
+                    // rather than spinning while descriptors run out
+                    std::thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+            };
+            if let Err(tcp_stream) = pool.dispatch(tcp_stream) {
+                if config.scheme() == "https" {
+                    // a handshake here would hold up the accepting thread
+                    log::warning(&format!(
+                        "{} connections open, connection closed",
+                        config.max_conns()
+                    ));
+                    continue;
+                }
+                log::warning(&format!(
+                    "{} connections open, connection refused with 503",
+                    config.max_conns()
+                ));
+                let mut stream: Connection = Box::new(tcp_stream);
+                let _ = send_status(
+                    &mut stream,
+                    StatusCode::ServiceUnavailable,
+                    &[("Retry-After", "1"), ("Connection", "close")],
+                );
+            }
+        }
+
+        drop(listener);
+        let left = shutdown::wait(shutdown::TIMEOUT);
+        if left > 0 {
+            log::warning(&format!("stopped with {left} requests unfinished"));
+        }
+        let summary = report::summary(left);
+        log::info(&format!("stopped: {}", summary.describe()));
+        if let Some(path) = &config.report
+            && let Err(err) = std::fs::write(path, summary.to_json())
+        {
+            log::error(&format!("failed to write '{}': {err}", path.display()));
+        }
+        if let Some(hooks) = &config.hooks {
+            hooks.send(
+                "stop",
+                &format!("\"summary\":{}", summary.to_json().trim_end()),
+            );
+            hooks.finish();
+        }
+        Ok(())
+    }
+
+    // Answers `request`, the bytes a client would send, like a connection
+    // would, without one: for embedding and tests
+    pub fn handle(&self, request: &[u8]) -> Result<Response, Box<dyn Error>> {
+        let method = request
+            .split(|byte| *byte == b' ')
+            .next()
+            .unwrap_or_default();
+        let method = String::from_utf8_lossy(method).into_owned();
+        let stream = stream::Memory::new(request.to_vec());
+        handle_connection(Box::new(stream.clone()), &self.config)?;
+        let output = stream.output();
+        let response = client::read_response(&mut output.as_slice(), &method)?;
+        Ok(Response {
+            status: response.status.map(StatusCode::code).unwrap_or_default(),
+            headers: response.headers,
+            body: response.body,
+        })
+    }
+}
+
+#[test]
+fn test_is_below() {
+    assert!(is_below("healthz", "healthz"));
+    assert!(is_below("metrics/cpu", "metrics"));
+    assert!(!is_below("metricsx", "metrics"));
+    assert!(is_below("anything", ""));
+}
+
+#[test]
+fn test_url_decode() {
+    assert_eq!(url_decode("a%20b+c%C3%A9").as_deref(), Some("a b cé"));
+    assert_eq!(url_decode("%zz"), None);
+    assert_eq!(url_decode("a.txt%"), None);
+    assert_eq!(url_decode("a.txt%4"), None);
+}
+
+#[test]
+fn test_requires_auth() {
+    let args = [
+        "--auth",
+        "a:b",
+        "--auth-path",
+        "/private/**",
+        "--auth-path",
+        "/x",
+    ];
+    let config = Config::from_args(args.map(str::to_owned)).unwrap();
+    assert!(config.requires_auth("private"));
+    assert!(config.requires_auth("private/a.txt"));
+    assert!(config.requires_auth("x/y"));
+    assert!(!config.requires_auth("privately"));
+    assert!(!config.requires_auth(""));
+    assert!(config.requires_auth("_oidc/callback"));
+    assert!(!config.can_manage());
+    let config = Config::from_args(["--auth", "a:b"].map(str::to_owned)).unwrap();
+    assert!(config.requires_auth("anything"));
+}
+
+#[test]
+fn test_from_args() {
+    let from_args = |args: &[&str]| Config::from_args(args.iter().map(|arg| arg.to_string()));
+    assert_eq!(from_args(&["-h"]).unwrap().output(), Some(USAGE));
+    assert_eq!(from_args(&["-p", "80"]).unwrap().output(), None);
+    assert!(!from_args(&["-p", "80"]).unwrap().checks_only());
+    assert!(from_args(&["--check-config"]).unwrap().checks_only());
+    assert_eq!(
+        from_args(&["--nope"]).err().unwrap().to_string(),
+        "unknown option '--nope', see -h"
+    );
+    assert!(from_args(&["-p"]).is_err());
+    assert!(from_args(&["--auth", "a:b", "--auth", "c:d"]).is_ok());
+    assert_eq!(
+        from_args(&["--auth", "a:b", "--ldap", "ldap://localhost"])
+            .err()
+            .unwrap()
+            .to_string(),
+        "'--ldap' can't be combined with '--auth': only one source of credentials is used"
+    );
+    assert!(from_args(&["-p", "port"]).is_err());
+}
+
+#[test]
+fn test_manage_actions() {
+    let actions = manage_actions("docs", "x');alert(1);//", true, "token");
+    assert!(actions.contains("data-name=\"x&apos;);alert(1);//\""));
+    assert!(!actions.contains("alert(1);//?"));
+    assert!(actions.contains("confirm('Delete ' + this.dataset.name + '?')"));
+    assert!(manage_actions("docs", "a.txt", false, "token").is_empty());
+}
+
+#[test]
+fn test_hidden() {
+    let hidden = Hidden {
+        dotfiles: false,
+        names: vec![fold_case(".git")],
+        paths: Vec::new(),
+        gitignore: None,
+    };
+    let matches = |path: &str| hidden.matches(&normalize_path(url_decode(path).unwrap()));
+    assert!(matches("%2e%67it/config"));
+    assert!(matches("/docs/../.git/"));
+    assert!(matches("a/.git"));
+    assert!(!matches("a/.gitignore"));
+    assert!(!matches("."));
+    assert_eq!(matches(".GIT/config"), CASE_INSENSITIVE_FS);
+
+    let hidden = Hidden {
+        dotfiles: true,
+        names: Vec::new(),
+        paths: Vec::new(),
+        gitignore: None,
+    };
+    assert!(hidden.matches("a/.env"));
+    assert!(!hidden.matches("."));
+    assert!(!hidden.matches("a/b.txt"));
+
+    let hidden = Hidden {
+        paths: vec!["var/trash".to_owned()],
+        ..Hidden::default()
+    };
+    assert!(hidden.matches("var/trash/1-0/item"));
+    assert!(!hidden.matches("var/trashy"));
+    assert!(!hidden.matches("trash"));
+}
+
+#[test]
+fn test_normalize_path() {
+    assert_eq!(
+        normalize_path("../../../../../../..///etc///passwd".to_owned()),
+        "etc/passwd"
+    );
+    assert_eq!(
+        normalize_path("/./.././.././//././//./././tmp/././././".to_owned()),
+        "tmp"
+    );
+    assert_eq!(normalize_path("/usr/bin/../lib//./".to_owned()), "usr/lib")
+}
+
+#[test]
+fn test_check_framing() {
+    let check = |headers: &[(&str, &str)]| {
+        let mut input = String::from("PUT / HTTP/1.1\r\n");
+        for (key, value) in headers {
+            input.push_str(&format!("{key}:{value}\r\n"));
+        }
+        input.push_str("\r\n");
+        let mut request = parse_request(&mut input.as_bytes(), None).unwrap().unwrap();
+        check_framing(&mut request).map(|_| request.header("Content-Length").map(str::to_owned))
+    };
+    assert_eq!(check(&[("Content-Length", " 5")]), Ok(Some("5".to_owned())));
+    assert_eq!(
+        check(&[("content-length", " 5, 5")]),
+        Ok(Some("5".to_owned()))
+    );
+    assert_eq!(
+        check(&[("Content-Length", " 5, 6")]),
+        Err(StatusCode::BadRequest)
+    );
+    assert_eq!(
+        check(&[("Content-Length", " +5")]),
+        Err(StatusCode::BadRequest)
+    );
+    assert_eq!(
+        check(&[("Content-Length", " 5"), ("Transfer-Encoding", " chunked")]),
+        Err(StatusCode::BadRequest)
+    );
+    assert_eq!(
+        check(&[("Transfer-Encoding", " gzip, chunked")]),
+        Err(StatusCode::NotImplemented)
+    );
+    assert_eq!(check(&[("Transfer-Encoding", " Chunked")]), Ok(None));
+    assert_eq!(
+        check(&[("Content-Length", "5"), ("content-length", " 5")]),
+        Ok(Some("5".to_owned()))
+    );
+    assert_eq!(
+        check(&[("Host", " a"), ("HOST", " b")]),
+        Err(StatusCode::BadRequest)
+    );
+    assert_eq!(
+        check(&[("Range", " bytes=0-1"), ("Range", " bytes=0-1")]),
+        Err(StatusCode::BadRequest)
+    );
+    assert_eq!(check(&[("Accept", " a"), ("Accept", " b")]), Ok(None));
+}
+
+#[test]
+fn test_parse_range() {
+    use ByteRange::*;
+    let range = |value: &str| parse_range(Some(value), 1000);
+    assert_eq!(parse_range(None, 1000), Full);
+    assert_eq!(range("bytes=0-499"), Partial(0, 499));
+    assert_eq!(range("bytes=500-999"), Partial(500, 999));
+    assert_eq!(range("Bytes=0-0"), Partial(0, 0));
+    // clamped to the size
+    assert_eq!(range("bytes=500-5000"), Partial(500, 999));
+    assert_eq!(range("bytes=100-"), Partial(100, 999));
+    assert_eq!(range("bytes=0-99999999999999999999999"), Partial(0, 999));
+    // suffixes
+    assert_eq!(range("bytes=-500"), Partial(500, 999));
+    assert_eq!(range("bytes=-5000"), Partial(0, 999));
+    assert_eq!(range("bytes=-0"), Unsatisfiable);
+    assert_eq!(parse_range(Some("bytes=-1"), 0), Unsatisfiable);
+    // past the end
+    assert_eq!(range("bytes=1000-"), Unsatisfiable);
+    assert_eq!(range("bytes=1000-2000"), Unsatisfiable);
+    assert_eq!(range("bytes=99999999999999999999999-"), Unsatisfiable);
+    assert_eq!(parse_range(Some("bytes=0-"), 0), Unsatisfiable);
+    // ignored
+    for value in [
+        "bytes=500-100",
+        "bytes=0-1,5-6",
+        "items=0-1",
+        "bytes=-",
+        "bytes=a-1",
+        "bytes=+1-2",
+        "bytes=1-2-3",
+        "bytes=--1",
+        "bytes 0-1",
+        "bytes = 0-1",
+        "bytes=",
+    ] {
+        assert_eq!(range(value), Full, "{value}");
+    }
+
+    assert_eq!(parse_content_range("bytes 0-99/1000"), Some((0, 99, 1000)));
+    assert_eq!(parse_content_range("bytes +0-99/1000"), None);
+    assert_eq!(parse_content_range("bytes 0-1000/1000"), None);
+    assert_eq!(parse_content_range("bytes 0-99/*"), None);
+}
+
+#[test]
+fn test_parse_request() {
+    let mut input =
+        &b"GET / HTTP/1.1\r\nHost: a\r\nAccept: x,\r\n  y\r\nX-A: 1\r\nx-a: 2\r\n\r\nGET /b HTTP/1.1\r\n\r\n"[..];
+    let request = parse_request(&mut input, None).unwrap().unwrap();
+    assert_eq!(request.header("accept"), Some("x, y"));
+    assert_eq!(request.header("X-A"), Some("1, 2"));
+    assert_eq!(request.headers.get("x-a").map(String::as_str), Some("1, 2"));
+    assert_eq!(request.deviations, ["obs-fold"]);
+    // pipelined
+    assert_eq!(parse_request(&mut input, None).unwrap().unwrap().path, "/b");
+    assert!(parse_request(&mut input, None).unwrap().is_none());
+    let err = parse_request(&mut &b"GARBAGE\r\n\r\n"[..], None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_HEAD_LINE));
+    let err = parse_request(&mut long.as_bytes(), None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+    let many = format!(
+        "GET / HTTP/1.1\r\n{}\r\n",
+        "X-A: 1\r\n".repeat(MAX_HEADERS + 1)
+    );
+    let err = parse_request(&mut many.as_bytes(), None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+    let err = parse_request(&mut &b"GET / HTTP/1.1\r\n\r\n"[..], Some(Instant::now())).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+    let request = parse_request(
+        &mut &b"GET / HTTP/1.1\r\nCookie: a=1\r\nCookie:b=2 \t\r\n\r\n"[..],
+        None,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(request.header("cookie"), Some("a=1; b=2"));
+    assert_eq!(request.cookie("b"), Some("2"));
+
+    let request = parse_request(
+        &mut &b"GET / HTTP/1.1\nHost : a\r\nbad line\r\n\r\n"[..],
+        None,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(request.header("Host"), Some("a"));
+    assert_eq!(
+        request.deviations,
+        ["bare LF", "whitespace before colon", "invalid field name"]
+    );
+}
+
+#[test]
+fn test_absolute_form() {
+    assert_eq!(
+        absolute_form("http://example.com:8080/a?b"),
+        Some(("example.com:8080".to_owned(), "/a?b".to_owned()))
+    );
+    assert_eq!(
+        absolute_form("HTTPS://example.com?b"),
+        Some(("example.com".to_owned(), "/?b".to_owned()))
+    );
+    assert_eq!(absolute_form("http:///a"), None);
+    assert_eq!(absolute_form("http://user@example.com/"), None);
+    assert_eq!(absolute_form("ftp://example.com/"), None);
+    assert_eq!(absolute_form("/a"), None);
+}
+
+#[test]
+fn test_parse_alternative() {
+    assert_eq!(parse_alternative("h3=:443").unwrap(), "h3=\":443\"");
+    assert_eq!(
+        parse_alternative("h2=alt.example.org:8443").unwrap(),
+        "h2=\"alt.example.org:8443\""
+    );
+    assert_eq!(parse_alternative("h3=443"), None);
+    assert_eq!(parse_alternative("h3=:0"), None);
+    assert_eq!(parse_alternative("=:443"), None);
+    assert_eq!(parse_alternative("h3=a\"b:443"), None);
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("7d"), Some(Duration::from_secs(604800)));
+    assert_eq!(parse_duration("90m"), Some(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("d"), None);
+    assert_eq!(parse_duration("7"), None);
+    assert_eq!(parse_duration("-1h"), None);
+    assert_eq!(parse_duration("7é"), None);
+    assert_eq!(parse_duration(""), None);
+    assert_eq!(format_duration(Duration::from_secs(604800)), "7d");
+    assert_eq!(format_duration(Duration::from_secs(5400)), "90m");
+    assert_eq!(format_duration(Duration::from_secs(61)), "61s");
+}
+
+#[test]
+fn test_compression() {
+    assert!(is_compressible("text/html"));
+    assert!(is_compressible("text/css; charset=utf-8"));
+    assert!(is_compressible("application/json"));
+    assert!(is_compressible("image/svg+xml"));
+    assert!(!is_compressible("image/png"));
+    assert!(!is_compressible("application/zip"));
+    let excluded = ["text/csv", ".log", "application/*"].map(str::to_owned);
+    assert!(is_compression_excluded("a/b.svgz", "image/svg+xml", &[]));
+    assert!(is_compression_excluded("b.TXT.GZ", "text/plain", &[]));
+    assert!(!is_compression_excluded("a/b.svg", "image/svg+xml", &[]));
+    assert!(is_compression_excluded(
+        "a.csv",
+        "text/csv; charset=utf-8",
+        &excluded
+    ));
+    assert!(is_compression_excluded("a/b.log", "text/plain", &excluded));
+    assert!(is_compression_excluded(
+        "a.json",
+        "application/json",
+        &excluded
+    ));
+    assert!(!is_compression_excluded("a.txt", "text/plain", &excluded));
+    assert!(!is_compression_excluded("log", "text/plain", &excluded));
+
+    assert!(accepts_coding("gzip, deflate, br", "gzip"));
+    assert!(accepts_coding("deflate, GZIP;q=0.5", "gzip"));
+    assert!(accepts_coding("*", "gzip"));
+    assert!(!accepts_coding("gzip;q=0", "gzip"));
+    assert!(!accepts_coding("*, gzip;q=0", "gzip"));
+    assert!(!accepts_coding("*;q=0", "gzip"));
+    assert!(!accepts_coding("identity", "gzip"));
+    assert!(!accepts_coding("", "gzip"));
+    let accept = "image/avif,image/webp;q=0.9,image/apng,*/*;q=0.8";
+    assert!(accepts_type(accept, "image/avif"));
+    assert!(accepts_type(accept, "image/webp"));
+    assert!(!accepts_type("image/webp;q=0, image/*", "image/webp"));
+    assert!(!accepts_type("*/*", "image/avif"));
+}
+
+#[test]
+fn test_server() {
+    let root = std::env::temp_dir().join(format!("server-test-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("a.txt"), "hello").unwrap();
+    let directory = root.display().to_string();
+    let args = [
+        "-d",
+        &directory,
+        "-p",
+        "0",
+        "--early-hint",
+        "/a.txt=</a.css>;rel=preload",
+        "--spa",
+        "--allow-upload",
+        "--create-dirs",
+        "--max-upload",
+        "10",
+        "--max-body",
+        "1K",
+    ]
+    .map(str::to_owned);
+    let server = Server::new(Config::from_args(args).unwrap()).unwrap();
+    assert_ne!(server.local_addr().unwrap().port(), 0);
+
+    let response = server
+        .handle(b"GET /a.txt HTTP/1.1\r\nHost: a\r\n\r\n")
+        .unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-length"), Some("5"));
+    assert_eq!(response.body, b"hello");
+    // after the 103 Early Hints
+    assert_eq!(response.header("link"), None);
+    let response = server.handle(b"HEAD /b.txt HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(response.status, 404);
+    assert!(response.body.is_empty());
+
+    let response = server
+        .handle(b"PUT /new/dir/b.txt HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc")
+        .unwrap();
+    assert_eq!(response.status, 201);
+    assert_eq!(std::fs::read(root.join("new/dir/b.txt")).unwrap(), b"abc");
+    let response = server
+        .handle(b"PUT /c.txt HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world")
+        .unwrap();
+    assert_eq!(response.status, 413);
+    assert!(!root.join("c.txt").exists());
+    let response = server
+        .handle(b"PUT /d.txt HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n1;x=y\r\nc\r\n0\r\n\r\n")
+        .unwrap();
+    assert_eq!(response.status, 201);
+    assert_eq!(std::fs::read(root.join("d.txt")).unwrap(), b"abc");
+    let response = server
+        .handle(b"POST /a.txt HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n800\r\n")
+        .unwrap();
+    assert_eq!(response.status, 413);
+    let response = server
+        .handle(b"PUT /e.txt HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n")
+        .unwrap();
+    assert_eq!(response.status, 400);
+    let response = server
+        .handle(b"POST /a.txt HTTP/1.1\r\nContent-Length: 2048\r\n\r\n")
+        .unwrap();
+    assert_eq!(response.status, 413);
+    assert_eq!(response.header("connection"), Some("close"));
+
+    // HTTP/1.0 clients get bodies without chunks, and the connection closes
+    let response = server.handle(b"GET /a.txt HTTP/1.0\r\n\r\n").unwrap();
+    assert_eq!(response.body, b"hello");
+    assert_eq!(response.header("connection"), Some("close"));
+    let response = server.handle(b"GET /new/ HTTP/1.0\r\n\r\n").unwrap();
+    assert_eq!(response.header("transfer-encoding"), None);
+    assert!(String::from_utf8_lossy(&response.body).contains("dir/"));
+    let response = server.handle(b"GET /a.txt HTTP/2.0\r\n\r\n").unwrap();
+    assert_eq!(response.status, 505);
+
+    let response = server.handle(b"OPTIONS * HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(response.header("allow"), Some("GET, HEAD, PUT, OPTIONS"));
+    let response = server.handle(b"GET a.txt HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(response.status, 400);
+    let response = server
+        .handle(b"GET http://localhost/a.txt HTTP/1.1\r\nHost: other\r\n\r\n")
+        .unwrap();
+    assert_eq!(response.status, 200);
+    let response = server.handle(b"GARBAGE\r\n\r\n").unwrap();
+    assert_eq!(response.status, 400);
+    let long = format!(
+        "GET / HTTP/1.1\r\nX-A: {}\r\n\r\n",
+        "a".repeat(MAX_HEAD_LINE)
+    );
+    let response = server.handle(long.as_bytes()).unwrap();
+    assert_eq!(response.status, 431);
+    let response = server.handle(b"GET /%zz HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(response.status, 400);
+    let response = server.handle(b"GET /a.txt% HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(response.status, 400);
+
+    // the routes of single-page apps, once there's an index.html
+    std::fs::write(root.join("index.html"), "app").unwrap();
+    let response = server.handle(b"GET /users/42 HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!((response.status, &response.body[..]), (200, &b"app"[..]));
+    let response = server.handle(b"GET /new/ HTTP/1.1\r\n\r\n").unwrap();
+    assert!(String::from_utf8_lossy(&response.body).contains("dir/"));
+
+    let request = Request::parse(b"GET /a HTTP/1.1\r\nX-A:  1 \r\n\r\n").unwrap();
+    assert_eq!((request.method(), request.path()), ("GET", "/a"));
+    assert_eq!(request.header("x-a"), Some("1"));
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,204 @@
+// Where messages go: the console by default, or the local syslog daemon
+// (RFC 3164 over /dev/log) or systemd-journald's native protocol, which keeps
+// extra fields such as the request path searchable with journalctl.
+
+use crate::date::{self, Clock};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::{io, sync::OnceLock};
+
+const IDENTIFIER: &str = env!("CARGO_PKG_NAME");
+#[cfg(unix)]
+const SYSLOG_SOCKET: &str = "/dev/log";
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+// LOG_DAEMON
+const SYSLOG_FACILITY: u8 = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Target {
+    Console,
+    Syslog,
+    Journald,
+}
+
+impl Target {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "console" => Some(Target::Console),
+            "syslog" => Some(Target::Syslog),
+            "journald" => Some(Target::Journald),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Console => "console",
+            Target::Syslog => "syslog",
+            Target::Journald => "journald",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Level {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Level {
+    // syslog severities, which journald uses too
+    fn severity(self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warning => 4,
+            Level::Info => 6,
+        }
+    }
+}
+
+enum Sink {
+    Console,
+    #[cfg(unix)]
+    Socket {
+        socket: UnixDatagram,
+        path: &'static str,
+        journald: bool,
+    },
+}
+
+// unset until main chose, messages go to the console meanwhile
+static SINK: OnceLock<Sink> = OnceLock::new();
+// --log-time, for the console: syslog and journald keep their own
+static CLOCK: OnceLock<Clock> = OnceLock::new();
+
+pub fn set_target(target: Target) -> io::Result<()> {
+    let sink = match target {
+        Target::Console => Sink::Console,
+        #[cfg(unix)]
+        Target::Syslog | Target::Journald => {
+            let journald = target == Target::Journald;
+            let path = if journald {
+                JOURNALD_SOCKET
+            } else {
+                SYSLOG_SOCKET
+            };
+            let socket = UnixDatagram::unbound()?;
+            socket
+                .connect(path)
+                .map_err(|err| io::Error::new(err.kind(), format!("{path}: {err}")))?;
+            Sink::Socket {
+                socket,
+                path,
+                journald,
+            }
+        }
+        #[cfg(not(unix))]
+        _ => return Err(io::Error::other("only the console is supported here")),
+    };
+    let _ = SINK.set(sink);
+    Ok(())
+}
+
+pub fn set_time(clock: Clock) {
+    let _ = CLOCK.set(clock);
+}
+
+pub fn info(message: &str) {
+    record(Level::Info, message, &[]);
+}
+
+pub fn warning(message: &str) {
+    record(Level::Warning, message, &[]);
+}
+
+pub fn error(message: &str) {
+    record(Level::Error, message, &[]);
+}
+
+// `fields` are only kept by journald, their names must be upper case
+pub fn record(level: Level, message: &str, fields: &[(&str, &str)]) {
+    match SINK.get().unwrap_or(&Sink::Console) {
+        Sink::Console => {
+            let line = match CLOCK.get() {
+                Some(clock) => format!("{} {message}", date::iso8601(date::now(), *clock)),
+                None => message.to_owned(),
+            };
+            match level {
+                Level::Info => println!("{line}"),
+                Level::Warning | Level::Error => eprintln!("{line}"),
+            }
+        }
+        #[cfg(unix)]
+        Sink::Socket {
+            socket,
+            path,
+            journald,
+        } => {
+            let datagram = if *journald {
+                journald_message(level, message, fields)
+            } else {
+                syslog_message(level, message).into_bytes()
+            };
+            // a restarted daemon comes back with a new socket
+            let sent = socket
+                .send(&datagram)
+                .or_else(|_| socket.connect(path).and_then(|_| socket.send(&datagram)));
+            if let Err(err) = sent {
+                eprintln!("{message} (logging failed: {err})");
+            }
+        }
+    }
+}
+
+fn syslog_message(level: Level, message: &str) -> String {
+    format!(
+        "<{}>{IDENTIFIER}[{}]: {message}",
+        SYSLOG_FACILITY * 8 + level.severity(),
+        std::process::id()
+    )
+}
+
+// KEY=value lines, or KEY, the length as 64 bits little endian and the value
+// for values with newlines (systemd's "Native Journal Protocol")
+fn journald_message(level: Level, message: &str, fields: &[(&str, &str)]) -> Vec<u8> {
+    let priority = level.severity().to_string();
+    let mut res = Vec::new();
+    let common = [
+        ("MESSAGE", message),
+        ("PRIORITY", &priority),
+        ("SYSLOG_IDENTIFIER", IDENTIFIER),
+    ];
+    for (key, value) in common.iter().chain(fields) {
+        if value.contains('\n') {
+            res.extend_from_slice(key.as_bytes());
+            res.push(b'\n');
+            res.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            res.extend_from_slice(value.as_bytes());
+            res.push(b'\n');
+        } else {
+            res.extend_from_slice(format!("{key}={value}\n").as_bytes());
+        }
+    }
+    res
+}
+
+#[test]
+fn test_messages() {
+    let pid = std::process::id();
+    assert_eq!(
+        syslog_message(Level::Error, "oops"),
+        format!("<27>{IDENTIFIER}[{pid}]: oops")
+    );
+    assert_eq!(
+        journald_message(Level::Info, "GET /", &[("HTTP_PATH", "/a\nb")]),
+        [
+            &b"MESSAGE=GET /\nPRIORITY=6\nSYSLOG_IDENTIFIER="[..],
+            IDENTIFIER.as_bytes(),
+            b"\nHTTP_PATH\n\x04\0\0\0\0\0\0\0/a\nb\n",
+        ]
+        .concat()
+    );
+}
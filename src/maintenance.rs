@@ -0,0 +1,62 @@
+// Maintenance mode: while it's on, requests get a 503 with Retry-After (and
+// the 503.html of the site) instead of the content, except those below
+// --maintenance-allow. On while --maintenance-file exists, or toggled with
+// SIGUSR1, without restarting.
+
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+pub struct Maintenance {
+    // absolute, as we move to the served directory
+    pub file: Option<PathBuf>,
+    // normalized paths, served anyway
+    pub allow: Vec<String>,
+    pub retry_after: Duration,
+    // by SIGUSR1
+    switched: AtomicBool,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Maintenance {
+            file: None,
+            allow: Vec::new(),
+            retry_after: DEFAULT_RETRY_AFTER,
+            switched: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Maintenance {
+    pub fn active(&self) -> bool {
+        self.switched.load(Ordering::Relaxed)
+            || self.file.as_ref().is_some_and(|file| file.exists())
+    }
+
+    // Returns whether the switch is now on
+    pub fn toggle(&self) -> bool {
+        !self.switched.fetch_xor(true, Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn test_maintenance() {
+    let file = std::env::temp_dir().join(format!("maintenance-test-{}", std::process::id()));
+    let maintenance = Maintenance {
+        file: Some(file.clone()),
+        ..Maintenance::default()
+    };
+    assert!(!maintenance.active());
+    assert!(maintenance.toggle());
+    assert!(maintenance.active());
+    assert!(!maintenance.toggle());
+    std::fs::write(&file, "").unwrap();
+    assert!(maintenance.active());
+    std::fs::remove_file(&file).unwrap();
+    assert!(!maintenance.active());
+}
@@ -0,0 +1,138 @@
+// --map: trees anywhere on the disk served below a URL prefix of the main
+// site, e.g. /ubuntu=/mnt/mirror/ubuntu, as a package mirror whose trees are
+// on different disks. They're served read-only, see root, the longest prefix
+// winning, each with whether its directories are listed and how long clients
+// may cache its files.
+
+use crate::{format_duration, is_below, normalize_path, parse_duration};
+use std::{path::PathBuf, time::Duration};
+
+pub enum Cache {
+    // seconds
+    MaxAge(Duration),
+    // files which never change once published, e.g. packages
+    Immutable,
+    NoStore,
+}
+
+impl Cache {
+    // The value of the Cache-Control header
+    pub fn header(&self) -> String {
+        match self {
+            Cache::MaxAge(duration) => format!("public, max-age={}", duration.as_secs()),
+            Cache::Immutable => crate::IMMUTABLE.to_owned(),
+            Cache::NoStore => "no-store".to_owned(),
+        }
+    }
+}
+
+pub struct Map {
+    // normalized
+    pub prefix: String,
+    // absolute, as we move to the served directory
+    pub root: PathBuf,
+    pub listing: bool,
+    pub cache: Option<Cache>,
+}
+
+// "/prefix=dir", then ",listing=on|off" or ",cache=duration|immutable|no-store"
+pub fn parse(value: &str) -> Result<Map, String> {
+    let mut options = value.split(',');
+    let Some((prefix, root)) = options
+        .next()
+        .and_then(|map| map.split_once('='))
+        .filter(|(prefix, root)| prefix.starts_with('/') && !root.is_empty())
+    else {
+        return Err(
+            "'--map' value must be '/prefix=dir', e.g. '/ubuntu=/mnt/mirror/ubuntu'".to_owned(),
+        );
+    };
+    let prefix = normalize_path(prefix.to_owned());
+    if prefix.is_empty() {
+        return Err("'--map' prefix can't be /, the served directory's".to_owned());
+    }
+    let root =
+        std::path::absolute(root).map_err(|_| format!("invalid '--map' directory: {root}"))?;
+    let mut map = Map {
+        prefix,
+        root,
+        listing: true,
+        cache: None,
+    };
+    for option in options {
+        match option.split_once('=') {
+            Some(("listing", "on")) => map.listing = true,
+            Some(("listing", "off")) => map.listing = false,
+            Some(("cache", "immutable")) => map.cache = Some(Cache::Immutable),
+            Some(("cache", "no-store")) => map.cache = Some(Cache::NoStore),
+            Some(("cache", duration)) => match parse_duration(duration) {
+                Some(duration) => map.cache = Some(Cache::MaxAge(duration)),
+                None => {
+                    return Err(format!(
+                        "'--map' cache must be a duration, e.g. 1h, immutable or no-store: \
+                         {duration}"
+                    ));
+                }
+            },
+            _ => return Err(format!("unknown '--map' option: {option}")),
+        }
+    }
+    Ok(map)
+}
+
+// The map of `path` (normalized), the longest prefix winning
+pub fn resolve<'a>(maps: &'a [Map], path: &str) -> Option<&'a Map> {
+    maps.iter()
+        .filter(|map| is_below(path, &map.prefix))
+        .max_by_key(|map| map.prefix.len())
+}
+
+impl Map {
+    // e.g. "/ubuntu /mnt/mirror/ubuntu, listing off, cache 1d"
+    pub fn describe(&self) -> String {
+        let mut res = format!("/{} {}", self.prefix, self.root.display());
+        if !self.listing {
+            res.push_str(", listing off");
+        }
+        match &self.cache {
+            Some(Cache::MaxAge(duration)) => {
+                res.push_str(&format!(", cache {}", format_duration(*duration)));
+            }
+            Some(Cache::Immutable) => res.push_str(", cache immutable"),
+            Some(Cache::NoStore) => res.push_str(", cache no-store"),
+            None => {}
+        }
+        res
+    }
+}
+
+#[test]
+fn test_map() {
+    let maps = [
+        parse("/ubuntu=/mnt/mirror/ubuntu,cache=immutable").unwrap(),
+        parse("/ubuntu/dists=/mnt/fast/dists,listing=off,cache=5m").unwrap(),
+        parse("/debian/=/mnt/mirror2/debian").unwrap(),
+    ];
+    assert!(parse("ubuntu=/mnt/mirror/ubuntu").is_err());
+    assert!(parse("/=/mnt/mirror").is_err());
+    assert!(parse("/ubuntu=").is_err());
+    assert!(parse("/ubuntu=/mnt,listing=maybe").is_err());
+    assert!(parse("/ubuntu=/mnt,cache=forever").is_err());
+
+    let prefix = |path| resolve(&maps, path).map(|map| map.prefix.as_str());
+    assert_eq!(prefix("ubuntu/pool/main/a.deb"), Some("ubuntu"));
+    assert_eq!(prefix("ubuntu/dists/noble/Release"), Some("ubuntu/dists"));
+    assert_eq!(prefix("debian"), Some("debian"));
+    assert_eq!(prefix("ubuntu-ports"), None);
+
+    assert_eq!(maps[0].cache.as_ref().unwrap().header(), crate::IMMUTABLE);
+    assert_eq!(
+        maps[1].cache.as_ref().unwrap().header(),
+        "public, max-age=300"
+    );
+    assert_eq!(
+        maps[1].describe(),
+        "/ubuntu/dists /mnt/fast/dists, listing off, cache 5m"
+    );
+    assert_eq!(maps[2].describe(), "/debian /mnt/mirror2/debian");
+}
@@ -0,0 +1,107 @@
+// --media: directories with audio or video files get a playlist (dir/?playlist,
+// M3U with absolute URLs, for players like VLC) and a player page
+// (dir/?player) going through them in order. Both fetch the files themselves,
+// so seeking relies on range requests.
+
+use crate::{html_encode, url_encode};
+
+pub const PLAYLIST_PARAM: &str = "playlist";
+pub const PLAYER_PARAM: &str = "player";
+
+pub fn is_media(mime_type: &str) -> bool {
+    mime_type.starts_with("audio/") || mime_type.starts_with("video/")
+}
+
+// `base` is the URL of the directory, ending with a slash, and `query` what
+// the file URLs need after them, e.g. "?token=..."
+pub fn playlist(base: &str, names: &[String], query: &str) -> String {
+    let mut res = String::from("#EXTM3U\n");
+    for name in names {
+        // titles end at the line
+        let title = name.replace(['\r', '\n'], " ");
+        res.push_str(&format!(
+            "#EXTINF:-1,{title}\n{base}{}{query}\n",
+            url_encode(name)
+        ));
+    }
+    res
+}
+
+pub fn player_page(directory: &str, names: &[String], query: &str) -> String {
+    let playlist_query = format!("?{PLAYLIST_PARAM}{}", query.replacen('?', "&", 1));
+    let tracks: String = names
+        .iter()
+        .map(|name| {
+            format!(
+                "  <li><a href=\"{}{query}\">{}</a></li>\n",
+                url_encode(name),
+                html_encode(name.clone())
+            )
+        })
+        .collect();
+    format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+  <meta charset=\"utf-8\">
+  <title>Playing {directory}</title>
+  <style>
+  body {{
+    background-color: Canvas;
+    color: CanvasText;
+    color-scheme: light dark;
+  }}
+  video {{
+    width: 100%;
+    max-height: 70vh;
+  }}
+  li.playing {{
+    font-weight: bold;
+  }}
+  </style>
+</head>
+<h1>{directory}</h1>
+<p><a href=\"./{query}\">Back to the listing</a> · <a href=\"{playlist_query}\">Playlist (M3U)</a></p>
+<video id=\"player\" controls autoplay></video>
+<ol id=\"tracks\">
+{tracks}</ol>
+<script>
+const player = document.getElementById('player');
+const links = [...document.querySelectorAll('#tracks a')];
+let current = -1;
+function play(index) {{
+  if (index < 0 || index >= links.length) return;
+  links[current]?.parentElement.classList.remove('playing');
+  current = index;
+  links[current].parentElement.classList.add('playing');
+  player.src = links[current].href;
+  player.play().catch(() => {{}});
+}}
+links.forEach((link, index) => link.addEventListener('click', event => {{
+  event.preventDefault();
+  play(index);
+}}));
+player.addEventListener('ended', () => play(current + 1));
+play(0);
+</script>
+</html>
+",
+        directory = html_encode(directory.to_owned()),
+    )
+}
+
+#[test]
+fn test_playlist() {
+    let names = ["01 Intro.mp3".to_owned(), "a&b.ogg".to_owned()];
+    assert_eq!(
+        playlist("http://nas:8080/music/", &names, "?token=t"),
+        "#EXTM3U\n\
+         #EXTINF:-1,01 Intro.mp3\nhttp://nas:8080/music/01%20Intro.mp3?token=t\n\
+         #EXTINF:-1,a&b.ogg\nhttp://nas:8080/music/a%26b.ogg?token=t\n"
+    );
+    let page = player_page("music", &names, "");
+    assert!(page.contains("<li><a href=\"a%26b.ogg\">a&amp;b.ogg</a></li>"));
+    let page = player_page("music", &names, "?token=t");
+    assert!(page.contains("<a href=\"?playlist&token=t\">"));
+    assert!(is_media("audio/mpeg") && is_media("video/mp4") && !is_media("image/png"));
+}
@@ -0,0 +1,344 @@
+// How long requests take, phase by phase, and how many requests connections
+// carry: histograms and counters served in the Prometheus text format at
+// /_metrics with --metrics, and the same figures in the log with
+// --log-timings.
+
+use crate::response::Sent;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+pub const PATH: &str = "_metrics";
+// upper bounds in seconds, from a cached small file to a slow client
+const BUCKETS: [f64; 10] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+const PHASES: [&str; 4] = ["parse", "resolve", "first_byte", "total"];
+// what connections ended up speaking, there's no TLS nor HTTP/2 here
+const PROTOCOLS: [&str; 2] = ["http/1.1", "websocket"];
+
+// Marks taken while answering one request
+pub struct Timings {
+    // when the first byte of the request was there
+    start: Instant,
+    pub parsed: Option<Instant>,
+    // authentication and access checks are done
+    pub resolved: Option<Instant>,
+}
+
+impl Timings {
+    pub fn start() -> Self {
+        Timings {
+            start: Instant::now(),
+            parsed: None,
+            resolved: None,
+        }
+    }
+
+    // Durations of PHASES, None for those the request didn't get to.
+    // first_byte and total count from the start, the others are the time
+    // spent in the phase.
+    pub fn phases(&self, sent: &Sent, end: Instant) -> [Option<Duration>; 4] {
+        [
+            self.parsed.map(|parsed| parsed - self.start),
+            self.resolved
+                .zip(self.parsed)
+                .map(|(resolved, parsed)| resolved - parsed),
+            Some(sent.at - self.start),
+            Some(end - self.start),
+        ]
+    }
+
+    // "parse=0.12ms resolve=0.05ms first_byte=0.40ms total=3.10ms 1.2MB/s"
+    pub fn describe(&self, sent: &Sent, end: Instant) -> String {
+        let mut res = String::new();
+        for (name, duration) in PHASES.iter().zip(self.phases(sent, end)) {
+            if let Some(duration) = duration {
+                let _ = write!(res, "{name}={:.2}ms ", duration.as_secs_f64() * 1000.0);
+            }
+        }
+        let seconds = (end - self.start).as_secs_f64();
+        let rate = if seconds > 0.0 {
+            sent.body_bytes as f64 / seconds
+        } else {
+            0.0
+        };
+        let _ = write!(res, "{:.1}MB/s", rate / 1_000_000.0);
+        res
+    }
+}
+
+// One client connection, from accept to close or to its hand-off after an
+// upgrade
+pub struct Connection {
+    start: Instant,
+    pub requests: u64,
+    pub upgraded: bool,
+}
+
+impl Connection {
+    pub fn start() -> Self {
+        Connection {
+            start: Instant::now(),
+            requests: 0,
+            upgraded: false,
+        }
+    }
+
+    fn protocol(&self) -> usize {
+        usize::from(self.upgraded)
+    }
+
+    // "requests=3 protocol=http/1.1 duration=12.30ms"
+    pub fn describe(&self, end: Instant) -> String {
+        format!(
+            "requests={} protocol={} duration={:.2}ms",
+            self.requests,
+            PROTOCOLS[self.protocol()],
+            (end - self.start).as_secs_f64() * 1000.0
+        )
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    // not cumulative, unlike what's served
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(idx) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    // `labels` are the other ones of the series, "" for none
+    fn render(&self, res: &mut String, name: &str, labels: &str) {
+        let series = |suffix: &str, extra: &str| {
+            let labels: Vec<&str> = [labels, extra]
+                .into_iter()
+                .filter(|l| !l.is_empty())
+                .collect();
+            if labels.is_empty() {
+                format!("{name}{suffix}")
+            } else {
+                format!("{name}{suffix}{{{}}}", labels.join(","))
+            }
+        };
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let bucket = series("_bucket", &format!("le=\"{bound}\""));
+            let _ = writeln!(res, "{bucket} {cumulative}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(res, "{} {count}", series("_bucket", "le=\"+Inf\""));
+        let _ = writeln!(res, "{} {sum}", series("_sum", ""));
+        let _ = writeln!(res, "{} {count}", series("_count", ""));
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    phases: [Histogram; PHASES.len()],
+    body_bytes: AtomicU64,
+    // the sizes of the resources those bodies were of, or parts of
+    resource_bytes: AtomicU64,
+    // responses which ended before their announced length
+    cut_short: AtomicU64,
+    connections: [AtomicU64; PROTOCOLS.len()],
+    // connections which carried more than one request
+    reused: AtomicU64,
+    connection_requests: AtomicU64,
+    connection_duration: Histogram,
+    // requests by the country of the client, with --geoip
+    countries: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Metrics {
+    pub fn observe(&self, timings: &Timings, sent: &Sent, end: Instant) {
+        for (histogram, duration) in self.phases.iter().zip(timings.phases(sent, end)) {
+            if let Some(duration) = duration {
+                histogram.observe(duration);
+            }
+        }
+        self.body_bytes
+            .fetch_add(sent.body_bytes, Ordering::Relaxed);
+        if let Some(size) = sent.size.filter(|_| sent.length.is_some()) {
+            self.resource_bytes.fetch_add(size, Ordering::Relaxed);
+        }
+        if sent.cut_short() {
+            self.cut_short.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // "unknown" for clients the databases don't know
+    pub fn observe_country(&self, country: &str) {
+        let mut countries = self.countries.lock().unwrap_or_else(|err| err.into_inner());
+        *countries.entry(country.to_owned()).or_default() += 1;
+    }
+
+    pub fn observe_connection(&self, connection: &Connection, end: Instant) {
+        self.connections[connection.protocol()].fetch_add(1, Ordering::Relaxed);
+        if connection.requests > 1 {
+            self.reused.fetch_add(1, Ordering::Relaxed);
+        }
+        self.connection_requests
+            .fetch_add(connection.requests, Ordering::Relaxed);
+        self.connection_duration.observe(end - connection.start);
+    }
+
+    pub fn render(&self) -> String {
+        let mut res = String::new();
+        res.push_str(
+            "# HELP http_request_phase_seconds Time spent answering requests, by phase.\n",
+        );
+        res.push_str("# TYPE http_request_phase_seconds histogram\n");
+        for (phase, histogram) in PHASES.iter().zip(&self.phases) {
+            histogram.render(
+                &mut res,
+                "http_request_phase_seconds",
+                &format!("phase=\"{phase}\""),
+            );
+        }
+        res.push_str("# HELP http_response_body_bytes_total Bytes sent in response bodies.\n");
+        res.push_str("# TYPE http_response_body_bytes_total counter\n");
+        let _ = writeln!(
+            res,
+            "http_response_body_bytes_total {}",
+            self.body_bytes.load(Ordering::Relaxed)
+        );
+        res.push_str(
+            "# HELP http_response_resource_bytes_total Full sizes of the resources sent, whole or in ranges.\n",
+        );
+        res.push_str("# TYPE http_response_resource_bytes_total counter\n");
+        let _ = writeln!(
+            res,
+            "http_response_resource_bytes_total {}",
+            self.resource_bytes.load(Ordering::Relaxed)
+        );
+        res.push_str(
+            "# HELP http_responses_cut_short_total Responses which ended before their announced length.\n",
+        );
+        res.push_str("# TYPE http_responses_cut_short_total counter\n");
+        let _ = writeln!(
+            res,
+            "http_responses_cut_short_total {}",
+            self.cut_short.load(Ordering::Relaxed)
+        );
+
+        res.push_str(
+            "# HELP http_connections_total Closed or upgraded connections, by protocol.\n",
+        );
+        res.push_str("# TYPE http_connections_total counter\n");
+        for (protocol, count) in PROTOCOLS.iter().zip(&self.connections) {
+            let _ = writeln!(
+                res,
+                "http_connections_total{{protocol=\"{protocol}\"}} {}",
+                count.load(Ordering::Relaxed)
+            );
+        }
+        res.push_str(
+            "# HELP http_connections_reused_total Connections which carried more than one request.\n",
+        );
+        res.push_str("# TYPE http_connections_reused_total counter\n");
+        let _ = writeln!(
+            res,
+            "http_connections_reused_total {}",
+            self.reused.load(Ordering::Relaxed)
+        );
+        res.push_str(
+            "# HELP http_connection_requests_total Requests answered on those connections.\n",
+        );
+        res.push_str("# TYPE http_connection_requests_total counter\n");
+        let _ = writeln!(
+            res,
+            "http_connection_requests_total {}",
+            self.connection_requests.load(Ordering::Relaxed)
+        );
+        res.push_str("# HELP http_connection_duration_seconds How long connections stayed open.\n");
+        res.push_str("# TYPE http_connection_duration_seconds histogram\n");
+        self.connection_duration
+            .render(&mut res, "http_connection_duration_seconds", "");
+        let countries = self.countries.lock().unwrap_or_else(|err| err.into_inner());
+        if !countries.is_empty() {
+            res.push_str(
+                "# HELP http_requests_by_country_total Requests, by the country of the client.\n",
+            );
+            res.push_str("# TYPE http_requests_by_country_total counter\n");
+            for (country, count) in countries.iter() {
+                let _ = writeln!(
+                    res,
+                    "http_requests_by_country_total{{country=\"{country}\"}} {count}"
+                );
+            }
+        }
+        res
+    }
+}
+
+#[test]
+fn test_metrics() {
+    let metrics = Metrics::default();
+    let timings = Timings::start();
+    let sent = Sent {
+        status: crate::status::StatusCode::Ok,
+        at: timings.start + Duration::from_millis(2),
+        body_bytes: 100,
+        length: Some(200),
+        size: Some(1000),
+    };
+    metrics.observe(&timings, &sent, timings.start + Duration::from_millis(20));
+    metrics.observe_country("FR");
+    let rendered = metrics.render();
+    assert!(
+        rendered
+            .contains("http_request_phase_seconds_bucket{phase=\"first_byte\",le=\"0.001\"} 0\n")
+    );
+    assert!(
+        rendered
+            .contains("http_request_phase_seconds_bucket{phase=\"first_byte\",le=\"0.005\"} 1\n")
+    );
+    assert!(
+        rendered.contains("http_request_phase_seconds_bucket{phase=\"total\",le=\"0.05\"} 1\n")
+    );
+    assert!(rendered.contains("http_request_phase_seconds_count{phase=\"parse\"} 0\n"));
+    assert!(rendered.contains("http_request_phase_seconds_sum{phase=\"total\"} 0.02\n"));
+    assert!(rendered.contains("http_response_body_bytes_total 100\n"));
+    assert!(rendered.contains("http_response_resource_bytes_total 1000\n"));
+    assert!(rendered.contains("http_responses_cut_short_total 1\n"));
+    assert!(rendered.contains("http_requests_by_country_total{country=\"FR\"} 1\n"));
+    assert_eq!(
+        timings.describe(&sent, timings.start + Duration::from_millis(20)),
+        "first_byte=2.00ms total=20.00ms 0.0MB/s"
+    );
+
+    let mut connection = Connection::start();
+    connection.requests = 3;
+    let end = connection.start + Duration::from_millis(30);
+    metrics.observe_connection(&connection, end);
+    metrics.observe_connection(&Connection::start(), end);
+    let rendered = metrics.render();
+    assert!(rendered.contains("http_connections_total{protocol=\"http/1.1\"} 2\n"));
+    assert!(rendered.contains("http_connections_reused_total 1\n"));
+    assert!(rendered.contains("http_connection_requests_total 3\n"));
+    assert!(rendered.contains("http_connection_duration_seconds_bucket{le=\"0.01\"} 0\n"));
+    assert!(rendered.contains("http_connection_duration_seconds_bucket{le=\"0.05\"} 2\n"));
+    assert!(rendered.contains("http_connection_duration_seconds_count 2\n"));
+    assert_eq!(
+        connection.describe(end),
+        "requests=3 protocol=http/1.1 duration=30.00ms"
+    );
+}
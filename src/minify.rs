@@ -0,0 +1,362 @@
+// --minify: HTML, CSS and JavaScript sent without their comments and most of
+// their whitespace, for source trees served as they are. It's only stripping,
+// nothing is renamed or rewritten: scripts keep their line breaks, as
+// semicolons may be left out, and what's in <pre>, <textarea>, <script> and
+// <style> elements of pages is left alone. The result is kept until the file
+// changes.
+
+use crate::coalesce::Coalescer;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+// larger files are sent as they are, having been built most likely
+const MAX_SIZE: u64 = 1024 * 1024;
+// files past this many are forgotten, all at once
+const MAX_ENTRIES: usize = 1024;
+// elements of pages whose content is left alone, lowercase
+const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Html,
+    Css,
+    Js,
+}
+
+struct Entry {
+    size: u64,
+    modified: SystemTime,
+    minified: Arc<Vec<u8>>,
+}
+
+// a file, by its size and modification time too
+type Version = (PathBuf, u64, SystemTime);
+
+#[derive(Default)]
+pub struct Minifier {
+    cache: Mutex<HashMap<PathBuf, Entry>>,
+    // files being minified
+    minifying: Coalescer<Version, Option<Arc<Vec<u8>>>>,
+}
+
+impl Minifier {
+    // The minified content of `path`, None when it's not of a type which is
+    // minified, or already is
+    pub fn get(&self, path: &Path, mime_type: &str) -> io::Result<Option<Arc<Vec<u8>>>> {
+        let essence = mime_type.split(';').next().unwrap_or_default().trim();
+        let kind = match essence {
+            "text/html" => Kind::Html,
+            "text/css" => Kind::Css,
+            "text/javascript" | "application/javascript" => Kind::Js,
+            _ => return Ok(None),
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.contains(".min.") {
+            return Ok(None);
+        }
+        let mut file = fs::File::open(path)?;
+        let metadata = file.metadata()?;
+        let (size, modified) = (metadata.len(), metadata.modified()?);
+        if size > MAX_SIZE {
+            return Ok(None);
+        }
+        let cached = self
+            .cache
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(path)
+            .filter(|entry| entry.size == size && entry.modified == modified)
+            .map(|entry| entry.minified.clone());
+        if let Some(minified) = cached {
+            return Ok(Some(minified));
+        }
+
+        let key = (path.to_path_buf(), size, modified);
+        self.minifying.run(key, || {
+            let mut content = String::new();
+            // files which aren't UTF-8 are sent as they are
+            if file.read_to_string(&mut content).is_err() {
+                return Ok(None);
+            }
+            let minified = Arc::new(
+                match kind {
+                    Kind::Html => html(&content),
+                    Kind::Css => css(&content),
+                    Kind::Js => js(&content),
+                }
+                .into_bytes(),
+            );
+            let mut cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+            if cache.len() >= MAX_ENTRIES {
+                cache.clear();
+            }
+            cache.insert(
+                path.to_path_buf(),
+                Entry {
+                    size,
+                    modified,
+                    minified: minified.clone(),
+                },
+            );
+            Ok(Some(minified))
+        })
+    }
+}
+
+// Comments out, whitespace runs between tags and in text made a single space
+// or line break, except in raw elements
+fn html(input: &str) -> String {
+    let mut res = String::with_capacity(input.len());
+    let mut rest = input;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--")
+            // conditional comments are for old browsers to read
+            && !comment.starts_with("[if")
+        {
+            rest = comment
+                .find("-->")
+                .map_or("", |end| &comment[end + "-->".len()..]);
+            continue;
+        }
+        let lowercase = rest.get(..12).unwrap_or(rest).to_ascii_lowercase();
+        if let Some(element) = RAW_ELEMENTS.iter().find(|element| {
+            lowercase
+                .strip_prefix('<')
+                .and_then(|tag| tag.strip_prefix(**element))
+                .is_some_and(|after| after.starts_with(['>', ' ', '\t', '\n', '\r', '/']))
+        }) {
+            let close = format!("</{element}");
+            let end = find_ignore_case(rest, &close).unwrap_or(rest.len());
+            res.push_str(&rest[..end]);
+            rest = &rest[end..];
+            if !rest.is_empty() {
+                res.push_str(&rest[..close.len()]);
+                rest = &rest[close.len()..];
+            }
+            continue;
+        }
+        let mut chars = rest.chars();
+        let c = chars.next().unwrap_or_default();
+        if c.is_ascii_whitespace() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_whitespace())
+                .unwrap_or(rest.len());
+            let newline = rest[..end].contains('\n');
+            // after a comment which was left out, there may be one already
+            if res.ends_with(' ') && newline {
+                res.pop();
+            }
+            if !res.ends_with(['\n', ' ']) {
+                res.push(if newline { '\n' } else { ' ' });
+            }
+            rest = &rest[end..];
+        } else {
+            res.push(c);
+            rest = chars.as_str();
+        }
+    }
+    res.trim().to_owned()
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+// Comments out, and whitespace where it doesn't separate anything
+fn css(input: &str) -> String {
+    let mut res = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    let mut space = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            '"' | '\'' => {
+                if space {
+                    res.push(' ');
+                    space = false;
+                }
+                res.push(c);
+                while let Some(inner) = chars.next() {
+                    res.push(inner);
+                    if inner == '\\' {
+                        res.extend(chars.next());
+                    } else if inner == c {
+                        break;
+                    }
+                }
+            }
+            c if c.is_ascii_whitespace() => space = true,
+            '{' | '}' | ':' | ';' | ',' | '>' => {
+                if c == '}' && res.ends_with(';') {
+                    res.pop();
+                }
+                // "a :hover" is another selector than "a:hover", so a space
+                // before a colon stays
+                if space && c == ':' && !res.ends_with(['{', '}', ';', ',', '>']) {
+                    res.push(' ');
+                }
+                res.push(c);
+                space = false;
+            }
+            c => {
+                if space && !res.is_empty() && !res.ends_with(['{', '}', ':', ';', ',', '>']) {
+                    res.push(' ');
+                }
+                res.push(c);
+                space = false;
+            }
+        }
+    }
+    res
+}
+
+// Comments out, and whitespace runs made a single space or line break
+fn js(input: &str) -> String {
+    let mut res = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    // a run of whitespace or comments, and whether it has a line break
+    let mut space = None;
+    while let Some(c) = chars.next() {
+        let comment = c == '/' && matches!(chars.peek(), Some('/' | '*'));
+        if c.is_ascii_whitespace() || comment {
+            let mut newline = c == '\n';
+            if comment && chars.next() == Some('/') {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        newline = true;
+                        break;
+                    }
+                }
+            } else if comment {
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    // a comment over lines still ends a statement
+                    newline |= c == '\n';
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            space = Some(space.unwrap_or(false) || newline);
+            continue;
+        }
+        if let Some(newline) = space.take()
+            && !res.is_empty()
+        {
+            res.push(if newline { '\n' } else { ' ' });
+        }
+        res.push(c);
+        match c {
+            '"' | '\'' | '`' => {
+                while let Some(inner) = chars.next() {
+                    res.push(inner);
+                    if inner == '\\' {
+                        res.extend(chars.next());
+                    } else if inner == c {
+                        break;
+                    }
+                }
+            }
+            '/' if starts_regex(&res[..res.len() - 1]) => {
+                let mut class = false;
+                while let Some(inner) = chars.next() {
+                    res.push(inner);
+                    match inner {
+                        '\\' => res.extend(chars.next()),
+                        '[' => class = true,
+                        ']' => class = false,
+                        '/' if !class => break,
+                        '\n' => break,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+// Whether a slash after `code` starts a regular expression rather than being
+// a division, going by what's before it
+fn starts_regex(code: &str) -> bool {
+    let code = code.trim_end();
+    let word = code
+        .rsplit(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '$')
+        .next()
+        .unwrap_or_default();
+    code.is_empty()
+        || code.ends_with(|c| "(,=:[!&|?{};+-*%<>~^".contains(c))
+        || [
+            "return",
+            "typeof",
+            "instanceof",
+            "in",
+            "of",
+            "new",
+            "delete",
+            "void",
+            "throw",
+            "case",
+            "do",
+            "else",
+            "yield",
+            "await",
+        ]
+        .contains(&word)
+}
+
+#[test]
+fn test_html() {
+    let page = "<!DOCTYPE html>\n<!-- a comment -->\n<html>\n  <body>\n    <p>Some   \
+                text,\n      <b>bold</b></p>\n    <pre>  keep\n   this </pre>\n    \
+                <SCRIPT>if (a  <  b) {}</script>\n<!--[if IE]>old<![endif]-->\n  </body>\n</html>\n";
+    assert_eq!(
+        html(page),
+        "<!DOCTYPE html>\n<html>\n<body>\n<p>Some text,\n<b>bold</b></p>\n<pre>  keep\n   \
+         this </pre>\n<SCRIPT>if (a  <  b) {}</script>\n<!--[if IE]>old<![endif]-->\n</body>\n\
+         </html>"
+    );
+    assert_eq!(html("<p>unclosed <!-- comment"), "<p>unclosed");
+}
+
+#[test]
+fn test_css() {
+    let style = "/* header */\nbody {\n  margin: 0 auto;\n  font: 12px/1.5 \"Open  Sans\";\n}\n\
+                 a :hover, ul > li { color: red ; }\n";
+    assert_eq!(
+        css(style),
+        "body{margin:0 auto;font:12px/1.5 \"Open  Sans\"}a :hover,ul>li{color:red}"
+    );
+}
+
+#[test]
+fn test_js() {
+    let script = "// a comment\nconst url = 'http://example.com'; /* inline */ let a = 1\n\n  \
+                  let re = /\\/\\/[/]x/g, b = a / 2 // half\n  /* over\n lines */ f()\n\
+                  const t = `\n    kept  `\nif (!/^a/.test(t)) return /\\//\n";
+    assert_eq!(
+        js(script),
+        "const url = 'http://example.com'; let a = 1\nlet re = /\\/\\/[/]x/g, b = a / 2\nf()\n\
+         const t = `\n    kept  `\nif (!/^a/.test(t)) return /\\//"
+    );
+}
@@ -0,0 +1,94 @@
+// A fixed number of worker threads taking jobs (connections) from a bounded
+// queue, so a slow client only holds up one of them. A job which panics ends
+// alone, its worker goes on with the next one.
+
+use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+    },
+    thread,
+};
+
+pub struct Pool<T> {
+    sender: SyncSender<T>,
+}
+
+impl<T: Send + 'static> Pool<T> {
+    // Up to `waiting` jobs wait for a worker, the others are given back by
+    // dispatch
+    pub fn new(
+        threads: usize,
+        waiting: usize,
+        handle: impl Fn(T) + Send + Sync + 'static,
+    ) -> io::Result<Self> {
+        // with none, jobs are only taken by idle workers
+        let (sender, receiver) = mpsc::sync_channel(waiting);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handle = Arc::new(handle);
+        for idx in 0..threads {
+            let receiver = Arc::clone(&receiver);
+            let handle = Arc::clone(&handle);
+            thread::Builder::new()
+                .name(format!("worker-{idx}"))
+                .spawn(move || work(&receiver, &*handle))?;
+        }
+        Ok(Pool { sender })
+    }
+
+    // The job back when every worker is busy and the queue is full
+    pub fn dispatch(&self, job: T) -> Result<(), T> {
+        self.sender.try_send(job).map_err(|err| match err {
+            TrySendError::Full(job) | TrySendError::Disconnected(job) => job,
+        })
+    }
+}
+
+fn work<T>(receiver: &Mutex<Receiver<T>>, handle: &(impl Fn(T) + ?Sized)) {
+    loop {
+        // the lock is only held while waiting, not while working
+        let job = receiver
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .recv();
+        let Ok(job) = job else {
+            return;
+        };
+        // the panic hook already printed what happened
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| handle(job)));
+    }
+}
+
+#[test]
+fn test_pool() {
+    use std::sync::mpsc::channel;
+
+    let (done, results) = channel();
+    let (release, wait) = channel::<()>();
+    let wait = Mutex::new(wait);
+    let pool = Pool::new(1, 1, move |job: u32| {
+        if job == 0 {
+            let _ = wait.lock().unwrap().recv();
+        }
+        if job == 1 {
+            panic!("job 1 fails");
+        }
+        done.send(job).unwrap();
+    })
+    .unwrap();
+    // 0 keeps the worker busy, 1 waits in the queue, 2 doesn't fit
+    assert!(pool.dispatch(0).is_ok());
+    while pool.dispatch(1).is_err() {
+        thread::yield_now();
+    }
+    assert_eq!(pool.dispatch(2), Err(2));
+    release.send(()).unwrap();
+    assert_eq!(results.recv().unwrap(), 0);
+    // the worker survived 1
+    while pool.dispatch(3).is_err() {
+        thread::yield_now();
+    }
+    assert_eq!(results.recv().unwrap(), 3);
+}
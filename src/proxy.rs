@@ -0,0 +1,491 @@
+// --proxy: requests below a prefix are forwarded to another HTTP server, e.g.
+// /api=http://127.0.0.1:3000 for the backend of a single-page app being
+// developed. Without a path in its URL the upstream gets the path of the
+// request as it is, else the prefix is replaced by that path, as with nginx's
+// proxy_pass. Bodies are streamed both ways, and upgraded connections (the
+// WebSockets of hot reloading) are relayed until either side closes them.
+// Upstreams are reached through --upstream-proxy, unless the route says
+// otherwise with via=. Routes can add, remove and rename the headers of the
+// requests they forward and of the responses, in the order given, e.g.
+// rename=X-User:X-Remote-User; the upstream's Server and X-Powered-By are
+// never passed on.
+
+use crate::{
+    Request, client, is_below, is_token, log, normalize_path, response, send_status,
+    status::StatusCode, stream::Connection, tunnel, upgrade, url_encode_path,
+};
+use std::{
+    error::Error,
+    io::{self, BufReader, Read, Take, Write},
+    net::{Shutdown, TcpStream},
+    thread,
+    time::Duration,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// backends may take a while to answer, or between parts of a response
+const TIMEOUT: Duration = Duration::from_secs(60);
+// RFC 9110 section 7.6.1: about the connection they come on, not passed on,
+// as are those the Connection header names
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+// set by us, for the upstream to know who asked
+const FORWARDED: [&str; 5] = [
+    "host",
+    "expect",
+    "x-forwarded-for",
+    "x-forwarded-host",
+    "x-forwarded-proto",
+];
+// about the upstream, which clients needn't know
+const HIDDEN: [&str; 3] = ["date", "server", "x-powered-by"];
+
+pub struct Route {
+    // normalized
+    pub prefix: String,
+    pub url: String,
+    upstream: client::Url,
+    // whether the URL has a path, which then replaces the prefix
+    replace: bool,
+    via: tunnel::Via,
+    request_rules: Vec<Rule>,
+    response_rules: Vec<Rule>,
+}
+
+// A header rewrite, the names it matches in lower case, those it sends as given
+enum Rule {
+    Add(String, String),
+    Remove(String),
+    Rename(String, String),
+}
+
+impl Rule {
+    // "Name:value" to add, "Name" to remove, "Name:New-Name" to rename
+    fn parse(kind: &str, value: &str) -> Result<Rule, String> {
+        let (name, rest) = match value.split_once(':') {
+            Some((name, rest)) => (name, Some(rest)),
+            None => (value, None),
+        };
+        let check = |name: &str| {
+            let lower = name.to_ascii_lowercase();
+            if !is_token(name) {
+                return Err(format!(
+                    "'--proxy' {kind}= needs a header name, not '{name}'"
+                ));
+            }
+            // the framing and routing of messages are ours
+            if HOP_BY_HOP.contains(&lower.as_str()) || lower == "host" || lower == "content-length"
+            {
+                return Err(format!("'--proxy' can't rewrite {name}"));
+            }
+            Ok(lower)
+        };
+        let lower = check(name)?;
+        match (kind, rest) {
+            ("add", Some(value)) => Ok(Rule::Add(name.to_owned(), value.trim().to_owned())),
+            ("remove", None) => Ok(Rule::Remove(lower)),
+            ("rename", Some(to)) => {
+                check(to)?;
+                Ok(Rule::Rename(lower, to.to_owned()))
+            }
+            _ => Err(format!(
+                "'--proxy' {kind}= value must be {}",
+                match kind {
+                    "add" => "'Name:value'",
+                    "remove" => "'Name'",
+                    _ => "'Name:New-Name'",
+                }
+            )),
+        }
+    }
+}
+
+// Applies `rules` in order to `headers`, whose names may have any case
+fn rewrite(rules: &[Rule], headers: &mut Vec<(String, String)>) {
+    for rule in rules {
+        match rule {
+            Rule::Add(name, value) => {
+                headers.retain(|(other, _)| !other.eq_ignore_ascii_case(name));
+                headers.push((name.clone(), value.clone()));
+            }
+            Rule::Remove(name) => headers.retain(|(other, _)| !other.eq_ignore_ascii_case(name)),
+            Rule::Rename(from, to) => {
+                for (name, _) in headers.iter_mut() {
+                    if name.eq_ignore_ascii_case(from) {
+                        name.clone_from(to);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// "/prefix=http://host:port[/path][,via=<proxy url|direct>][,<rule>]..."
+pub fn parse(value: &str) -> Result<Route, String> {
+    let Some((prefix, url)) = value
+        .split_once('=')
+        .filter(|(prefix, _)| prefix.starts_with('/'))
+    else {
+        return Err(
+            "'--proxy' value must be '/prefix=url', e.g. '/api=http://127.0.0.1:3000'".to_owned(),
+        );
+    };
+    let (url, options) = url.split_once(',').unwrap_or((url, ""));
+    let mut via = tunnel::Via::Default;
+    let mut request_rules = Vec::new();
+    let mut response_rules = Vec::new();
+    for option in options.split(',').filter(|option| !option.is_empty()) {
+        match option.split_once('=') {
+            Some(("via", value)) => {
+                via = tunnel::Via::parse(value).map_err(|err| format!("'--proxy' {err}"))?
+            }
+            Some((kind @ ("add" | "remove" | "rename"), value)) => {
+                request_rules.push(Rule::parse(kind, value)?);
+            }
+            Some((kind, value)) if kind.starts_with("response-") => {
+                let kind = &kind["response-".len()..];
+                if !["add", "remove", "rename"].contains(&kind) {
+                    return Err(format!("unknown '--proxy' option '{option}'"));
+                }
+                response_rules.push(Rule::parse(kind, value)?);
+            }
+            _ => return Err(format!("unknown '--proxy' option '{option}'")),
+        }
+    }
+    let prefix = normalize_path(prefix.to_owned());
+    if prefix.is_empty() {
+        return Err("'--proxy' prefix can't be /, the served directory's".to_owned());
+    }
+    let upstream = client::Url::parse(url).map_err(|err| format!("'--proxy' {err}"))?;
+    let replace = url["http://".len()..].contains('/');
+    Ok(Route {
+        prefix,
+        url: url.to_owned(),
+        upstream,
+        replace,
+        via,
+        request_rules,
+        response_rules,
+    })
+}
+
+// The route of `path` (normalized), the longest prefix winning
+pub fn resolve<'a>(routes: &'a [Route], path: &str) -> Option<&'a Route> {
+    routes
+        .iter()
+        .filter(|route| is_below(path, &route.prefix))
+        .max_by_key(|route| route.prefix.len())
+}
+
+impl Route {
+    pub fn describe(&self) -> String {
+        let mut res = format!("/{} {}", self.prefix, self.url);
+        match &self.via {
+            tunnel::Via::Default => {}
+            tunnel::Via::Direct => res.push_str(" direct"),
+            tunnel::Via::Proxy(upstream) => res.push_str(&format!(" via {upstream}")),
+        }
+        let rules = self.request_rules.len() + self.response_rules.len();
+        if rules > 0 {
+            res.push_str(&format!(", {rules} header rules"));
+        }
+        res
+    }
+
+    // The request target upstream for `path` (normalized, below the prefix),
+    // which is asked as a directory with `slash`
+    fn target(&self, path: &str, slash: bool, query: &str) -> String {
+        let (base, rest) = match self.replace {
+            true => (
+                self.upstream.path.trim_end_matches('/'),
+                path[self.prefix.len()..].trim_start_matches('/'),
+            ),
+            false => ("", path),
+        };
+        let rest = url_encode_path(rest);
+        let mut res = format!("{base}/{}", rest.trim_end_matches('/'));
+        if res.len() > 1 && res.ends_with('/') && !slash {
+            res.pop();
+        }
+        if slash && !res.ends_with('/') {
+            res.push('/');
+        }
+        res + query
+    }
+}
+
+// Forwards `request` and its body, then the response back
+pub fn forward(
+    route: &Route,
+    request: &Request,
+    path: &str,
+    body: &mut Take<&mut BufReader<Connection>>,
+    tcp_stream: &mut Connection,
+    scheme: &str,
+) -> Result<(), Box<dyn Error>> {
+    let (request_path, query) = match request.path.find('?') {
+        Some(idx) => request.path.split_at(idx),
+        None => (request.path.as_str(), ""),
+    };
+    let target = route.target(path, request_path.ends_with('/'), query);
+    let protocol = request
+        .header("Upgrade")
+        .filter(|protocol| upgrade::requested(request, protocol));
+
+    let mut upstream = match connect(route) {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            log::warning(&format!("proxy to {}: {err}", route.url));
+            return send_status(tcp_stream, StatusCode::BadGateway, &[]);
+        }
+    };
+    // in the version of the client, so HTTP/1.0 ones don't get chunks the
+    // upstream chose
+    let mut head = format!(
+        "{} {target} {}\r\nHost: {}\r\n",
+        request.method,
+        response::version(),
+        route.upstream.host
+    );
+    let connection = request
+        .header("Connection")
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let listed: Vec<_> = connection.split(',').map(str::trim).collect();
+    let mut headers: Vec<_> = request
+        .headers
+        .iter()
+        .filter(|(name, _)| {
+            let name = name.as_str();
+            !HOP_BY_HOP.contains(&name) && !listed.contains(&name) && !FORWARDED.contains(&name)
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    let peer = tcp_stream.peer_addr()?.ip();
+    let forwarded_for = match request.header("X-Forwarded-For") {
+        Some(previous) => format!("{previous}, {peer}"),
+        None => peer.to_string(),
+    };
+    headers.push(("X-Forwarded-For".to_owned(), forwarded_for));
+    if let Some(host) = request.header("Host") {
+        headers.push(("X-Forwarded-Host".to_owned(), host.to_owned()));
+    }
+    headers.push(("X-Forwarded-Proto".to_owned(), scheme.to_owned()));
+    rewrite(&route.request_rules, &mut headers);
+    for (name, value) in &headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    match protocol {
+        Some(protocol) => head.push_str(&format!("Connection: Upgrade\r\nUpgrade: {protocol}\r\n")),
+        None => head.push_str("Connection: close\r\n"),
+    }
+    head.push_str("\r\n");
+    upstream.write_all(head.as_bytes())?;
+    io::copy(body, &mut upstream)?;
+
+    let mut reader = BufReader::new(upstream.try_clone()?);
+    let response = match client::read_head(&mut reader) {
+        Ok(response) => response,
+        Err(err) => {
+            log::warning(&format!("proxy to {}: {err}", route.url));
+            let timed_out = err.downcast_ref::<io::Error>().is_some_and(|err| {
+                matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                )
+            });
+            let status = match timed_out {
+                true => StatusCode::GatewayTimeout,
+                false => StatusCode::BadGateway,
+            };
+            return send_status(tcp_stream, status, &[]);
+        }
+    };
+    let code = response
+        .status_line
+        .split(' ')
+        .nth(1)
+        .and_then(|code| code.parse().ok());
+    let Some(status) = code
+        .and_then(StatusCode::from_code_or_class)
+        .filter(|status| *status != StatusCode::SwitchingProtocols || protocol.is_some())
+    else {
+        log::warning(&format!(
+            "proxy to {}: invalid status line: {}",
+            route.url, response.status_line
+        ));
+        return send_status(tcp_stream, StatusCode::BadGateway, &[]);
+    };
+
+    let connection = response
+        .header("Connection")
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let listed: Vec<_> = connection.split(',').map(str::trim).collect();
+    // ours are sent instead of Date and Server
+    let mut headers: Vec<_> = response
+        .headers
+        .iter()
+        .filter(|(name, _)| {
+            let name = name.to_ascii_lowercase();
+            !HOP_BY_HOP.contains(&name.as_str())
+                && !listed.contains(&name.as_str())
+                && !HIDDEN.contains(&name.as_str())
+        })
+        .cloned()
+        .collect();
+    rewrite(&route.response_rules, &mut headers);
+    let mut headers: Vec<_> = headers
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+
+    if let Some(protocol) = protocol
+        && status == StatusCode::SwitchingProtocols
+    {
+        let upgraded = upgrade::switch(body.get_mut(), protocol, &headers)?;
+        // the connection stays open as long as both sides keep it, not on the
+        // accept loop
+        thread::spawn(move || relay(upgraded, reader, upstream));
+        return Ok(());
+    }
+
+    let code = status.code();
+    let has_body = request.method != "HEAD" && code >= 200 && code != 204 && code != 304;
+    let chunked = response
+        .header("Transfer-Encoding")
+        .is_some_and(|codings| codings.to_ascii_lowercase().ends_with("chunked"));
+    let length = match response.header("Content-Length") {
+        Some(length) if !chunked => Some(length.parse::<u64>()?),
+        _ => None,
+    };
+    if chunked {
+        headers.push(("Transfer-Encoding", "chunked"));
+    } else if has_body && length.is_none() {
+        // it ends when the upstream closes the connection, so must ours
+        response::set_closing(true);
+    }
+    response::write_head(tcp_stream, status, &headers)?;
+    if !has_body {
+        return Ok(());
+    }
+    // chunked bodies are passed on as they are, until the upstream closes
+    // the connection as asked
+    let mut body = reader.take(length.unwrap_or(u64::MAX));
+    let mut buffer = [0; 16 * 1024];
+    let mut sent = 0;
+    while let read = body.read(&mut buffer)?
+        && read != 0
+    {
+        tcp_stream.write_all(&buffer[..read])?;
+        response::count_body(read as u64);
+        sent += read as u64;
+    }
+    if length.is_some_and(|length| sent < length) {
+        return Err(format!("proxy to {}: response cut short", route.url).into());
+    }
+    Ok(())
+}
+
+fn connect(route: &Route) -> io::Result<TcpStream> {
+    let address = route.upstream.address();
+    let stream = tunnel::connect(&address, &route.via, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+// Copies what each side sends to the other, until one of them closes
+fn relay(mut client: upgrade::Upgraded, mut reader: BufReader<TcpStream>, upstream: TcpStream) {
+    let Ok(mut to_client) = client.try_clone_stream() else {
+        return;
+    };
+    // idle WebSockets are fine
+    let _ = to_client.set_read_timeout(None);
+    let _ = upstream.set_read_timeout(None);
+    let mut to_upstream = upstream;
+    let sending = thread::spawn(move || {
+        let _ = io::copy(&mut client, &mut to_upstream);
+        let _ = to_upstream.shutdown(Shutdown::Write);
+    });
+    let _ = io::copy(&mut reader, &mut to_client);
+    let _ = to_client.shutdown(Shutdown::Both);
+    let _ = sending.join();
+}
+
+#[test]
+fn test_route() {
+    let routes = [
+        parse("/api=http://127.0.0.1:3000").unwrap(),
+        parse("/api/v2/=http://127.0.0.1:4000/").unwrap(),
+        parse("/auth=http://idp:8080/realms/main,via=socks5://proxy.corp").unwrap(),
+    ];
+    assert!(parse("api=http://127.0.0.1:3000").is_err());
+    assert!(parse("/=http://127.0.0.1:3000").is_err());
+    assert!(parse("/api=https://example.com").is_err());
+    assert!(parse("/api=http://example.com,via=ftp://proxy").is_err());
+    assert!(parse("/api=http://example.com,cache=1h").is_err());
+    assert!(parse("/api=http://example.com,add=Host:a").is_err());
+    assert!(parse("/api=http://example.com,remove=X-A:b").is_err());
+    assert!(parse("/api=http://example.com,rename=X-A").is_err());
+    assert!(parse("/api=http://example.com,response-rename=X-A:Transfer-Encoding").is_err());
+    assert!(parse("/api=http://example.com,response-drop=X-A").is_err());
+    assert_eq!(
+        routes[2].describe(),
+        "/auth http://idp:8080/realms/main via socks5://proxy.corp:1080"
+    );
+
+    let route = |path| resolve(&routes, path).map(|route| route.url.as_str());
+    assert_eq!(route("api/users"), Some("http://127.0.0.1:3000"));
+    assert_eq!(route("api/v2/users"), Some("http://127.0.0.1:4000/"));
+    assert_eq!(route("apix"), None);
+
+    assert_eq!(routes[0].target("api/a b", false, "?q=1"), "/api/a%20b?q=1");
+    assert_eq!(routes[0].target("api", true, ""), "/api/");
+    assert_eq!(routes[1].target("api/v2/users", false, ""), "/users");
+    assert_eq!(routes[1].target("api/v2", false, ""), "/");
+    assert_eq!(
+        routes[2].target("auth/login", true, ""),
+        "/realms/main/login/"
+    );
+    assert_eq!(routes[2].target("auth", false, ""), "/realms/main");
+}
+
+#[test]
+fn test_rewrite() {
+    let route = parse(
+        "/api=http://127.0.0.1:3000,rename=X-User:X-Remote-User,add=X-Env: dev,remove=cookie,\
+         response-remove=x-debug,response-add=X-Frame-Options:DENY",
+    )
+    .unwrap();
+    assert_eq!(
+        route.describe(),
+        "/api http://127.0.0.1:3000, 5 header rules"
+    );
+    let mut headers = vec![
+        ("x-user".to_owned(), "ann".to_owned()),
+        ("Cookie".to_owned(), "a=1".to_owned()),
+        ("x-env".to_owned(), "prod".to_owned()),
+        ("accept".to_owned(), "*/*".to_owned()),
+    ];
+    rewrite(&route.request_rules, &mut headers);
+    let expected = [
+        ("X-Remote-User", "ann"),
+        ("accept", "*/*"),
+        ("X-Env", "dev"),
+    ];
+    assert_eq!(headers, expected.map(|(a, b)| (a.to_owned(), b.to_owned())));
+    let mut headers = vec![("X-Debug".to_owned(), "1".to_owned())];
+    rewrite(&route.response_rules, &mut headers);
+    let expected = [("X-Frame-Options".to_owned(), "DENY".to_owned())];
+    assert_eq!(headers, expected);
+}
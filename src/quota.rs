@@ -0,0 +1,133 @@
+// Upload quotas: how much the served directory, or a directory below it, may
+// hold. Usage is what's on disk, added up when an upload starts, so files
+// put there by other means count too.
+
+use crate::{is_below, root};
+use std::{fs, path::Path};
+
+pub struct Quota {
+    // normalized, "" for the whole served directory
+    pub prefix: String,
+    pub size: u64,
+}
+
+// "500", "64K", "10M", "2G" or "1T" bytes, powers of 1024
+pub fn parse_size(value: &str) -> Option<u64> {
+    let (number, shift) = match value.as_bytes().last()?.to_ascii_uppercase() {
+        b'K' => (&value[..value.len() - 1], 10),
+        b'M' => (&value[..value.len() - 1], 20),
+        b'G' => (&value[..value.len() - 1], 30),
+        b'T' => (&value[..value.len() - 1], 40),
+        _ => (value, 0),
+    };
+    if number.is_empty() || !number.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+// "10G", or the number of bytes when it isn't a whole number of units
+pub fn format_size(size: u64) -> String {
+    let units = [(40, "T"), (30, "G"), (20, "M"), (10, "K")];
+    match units.iter().find(|(shift, _)| size >> shift > 0) {
+        Some((shift, unit)) if size.trailing_zeros() >= *shift => {
+            format!("{}{unit}", size >> shift)
+        }
+        _ => size.to_string(),
+    }
+}
+
+// The first quota a file of `size` bytes replacing `replaced` bytes at
+// `path` would exceed
+pub fn exceeded<'a>(
+    quotas: &'a [Quota],
+    path: &str,
+    size: u64,
+    replaced: u64,
+) -> Option<&'a Quota> {
+    quotas
+        .iter()
+        .filter(|quota| is_below(path, &quota.prefix))
+        .find(|quota| {
+            let directory = if quota.prefix.is_empty() {
+                "."
+            } else {
+                &quota.prefix
+            };
+            let usage = disk_usage(&root::path(directory)).saturating_sub(replaced);
+            usage.saturating_add(size) > quota.size
+        })
+}
+
+// Sizes of the files below `path`, without following symbolic links, and
+// skipping what can't be read
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| disk_usage(&entry.path()))
+        .sum()
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("500"), Some(500));
+    assert_eq!(parse_size("64k"), Some(64 * 1024));
+    assert_eq!(parse_size("2G"), Some(2 << 30));
+    assert_eq!(parse_size("G"), None);
+    assert_eq!(parse_size("-1"), None);
+    assert_eq!(parse_size("1.5M"), None);
+    assert_eq!(parse_size("99999999999T"), None);
+    assert_eq!(format_size(2 << 30), "2G");
+    assert_eq!(format_size(1536), "1536");
+    assert_eq!(format_size(500), "500");
+}
+
+#[test]
+fn test_exceeded() {
+    let root = std::env::temp_dir().join(format!("quota-test-{}", std::process::id()));
+    fs::create_dir_all(root.join("a")).unwrap();
+    fs::write(root.join("a/x"), [0; 100]).unwrap();
+    fs::write(root.join("y"), [0; 50]).unwrap();
+    let root = root.to_str().unwrap();
+    let quotas = [
+        Quota {
+            prefix: format!("{root}/a"),
+            size: 120,
+        },
+        Quota {
+            prefix: root.to_owned(),
+            size: 200,
+        },
+    ];
+    let size_of = |quota: Option<&Quota>| quota.map(|quota| quota.size);
+    assert_eq!(
+        size_of(exceeded(&quotas, &format!("{root}/a/z"), 20, 0)),
+        None
+    );
+    assert_eq!(
+        size_of(exceeded(&quotas, &format!("{root}/a/z"), 21, 0)),
+        Some(120)
+    );
+    // overwriting x
+    assert_eq!(
+        size_of(exceeded(&quotas, &format!("{root}/a/x"), 120, 100)),
+        None
+    );
+    assert_eq!(
+        size_of(exceeded(&quotas, &format!("{root}/z"), 51, 0)),
+        Some(200)
+    );
+    assert_eq!(
+        size_of(exceeded(&quotas, &format!("{root}/z"), 50, 0)),
+        None
+    );
+    fs::remove_dir_all(root).unwrap();
+}
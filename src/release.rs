@@ -0,0 +1,70 @@
+// Deploys which swap the served directory: with -d pointing at a symlink,
+// e.g. /srv/current -> releases/42, each request is answered from where it
+// leads when the request arrives, so switching releases or rolling back
+// takes effect at once, while the requests being answered finish with the
+// previous one and none gets a mix of both. That directory is resolved once
+// per request, and its files are then found through root::path.
+
+use crate::{log, root};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+// -d, absolute but with its symlinks, then what it resolved to last
+static DIRECTORY: RwLock<(PathBuf, PathBuf)> = RwLock::new((PathBuf::new(), PathBuf::new()));
+// whether -d goes through symlinks, which are then resolved for each request
+static FOLLOW: AtomicBool = AtomicBool::new(false);
+
+// With `configured` being -d made absolute
+pub fn init(configured: &Path) -> io::Result<()> {
+    let configured = configured.to_path_buf();
+    let resolved = std::fs::canonicalize(&configured)?;
+    FOLLOW.store(configured != resolved, Ordering::SeqCst);
+    *DIRECTORY.write().unwrap_or_else(|err| err.into_inner()) = (configured, resolved);
+    Ok(())
+}
+
+// Resolves -d again, and returns where it now leads if that changed
+pub fn refresh() -> io::Result<Option<PathBuf>> {
+    let configured = DIRECTORY
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .0
+        .clone();
+    let resolved = std::fs::canonicalize(&configured)?;
+    let mut directory = DIRECTORY.write().unwrap_or_else(|err| err.into_inner());
+    if resolved == directory.1 {
+        return Ok(None);
+    }
+    directory.1.clone_from(&resolved);
+    Ok(Some(resolved))
+}
+
+// Where -d led last, empty without a server, e.g. in tests
+pub fn current() -> PathBuf {
+    DIRECTORY
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .1
+        .clone()
+}
+
+// Before answering a request, so all of it is from the same release, or
+// going through the served directory in the background. Returns whether -d
+// now leads somewhere else.
+pub fn enter() -> io::Result<bool> {
+    let mut moved = false;
+    if FOLLOW.load(Ordering::SeqCst)
+        && let Some(directory) = refresh()?
+    {
+        log::info(&format!("now serving out of {}", directory.display()));
+        moved = true;
+    }
+    root::enter(&current());
+    Ok(moved)
+}
@@ -0,0 +1,120 @@
+// What the server did while it ran, counted whether or not there's --metrics:
+// logged when it stops and, with --report, written to a file as JSON for
+// supervisors and scripts to look at.
+
+use crate::status::StatusCode;
+use std::{
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+// by status class, 1xx to 5xx
+static REQUESTS: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+static BODY_BYTES: AtomicU64 = AtomicU64::new(0);
+// which ended on an error, like a failed TLS handshake or a client leaving
+static FAILED_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+pub struct Summary {
+    pub uptime: Duration,
+    pub requests: [u64; 5],
+    pub body_bytes: u64,
+    pub failed_connections: u64,
+    // still being answered when the server stopped
+    pub unfinished: usize,
+}
+
+pub fn start() {
+    STARTED.get_or_init(Instant::now);
+}
+
+pub fn observe(status: StatusCode, body_bytes: u64) {
+    let class = (status.code() / 100).clamp(1, 5) as usize - 1;
+    REQUESTS[class].fetch_add(1, Ordering::Relaxed);
+    BODY_BYTES.fetch_add(body_bytes, Ordering::Relaxed);
+}
+
+pub fn connection_failed() {
+    FAILED_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn summary(unfinished: usize) -> Summary {
+    Summary {
+        uptime: STARTED.get().map(Instant::elapsed).unwrap_or_default(),
+        requests: REQUESTS
+            .each_ref()
+            .map(|count| count.load(Ordering::Relaxed)),
+        body_bytes: BODY_BYTES.load(Ordering::Relaxed),
+        failed_connections: FAILED_CONNECTIONS.load(Ordering::Relaxed),
+        unfinished,
+    }
+}
+
+impl Summary {
+    // For the log
+    pub fn describe(&self) -> String {
+        let total: u64 = self.requests.iter().sum();
+        format!(
+            "up {}s, {total} requests ({} client errors, {} server errors), {} body bytes sent, \
+             {} failed connections, {} requests unfinished",
+            self.uptime.as_secs(),
+            self.requests[3],
+            self.requests[4],
+            self.body_bytes,
+            self.failed_connections,
+            self.unfinished
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        let classes: Vec<_> = self
+            .requests
+            .iter()
+            .enumerate()
+            .map(|(idx, count)| format!("\"{}xx\": {count}", idx + 1))
+            .collect();
+        format!(
+            "{{\"uptime_seconds\": {}, \"requests\": {}, \"requests_by_status\": {{{}}}, \
+             \"body_bytes\": {}, \"failed_connections\": {}, \"unfinished_requests\": {}}}\n",
+            self.uptime.as_secs(),
+            self.requests.iter().sum::<u64>(),
+            classes.join(", "),
+            self.body_bytes,
+            self.failed_connections,
+            self.unfinished
+        )
+    }
+}
+
+#[test]
+fn test_summary() {
+    let summary = Summary {
+        uptime: Duration::from_secs(90),
+        requests: [0, 10, 2, 3, 1],
+        body_bytes: 1024,
+        failed_connections: 4,
+        unfinished: 0,
+    };
+    assert_eq!(
+        summary.describe(),
+        "up 90s, 16 requests (3 client errors, 1 server errors), 1024 body bytes sent, \
+         4 failed connections, 0 requests unfinished"
+    );
+    let json = crate::json::parse(&summary.to_json()).unwrap();
+    assert_eq!(
+        json.get("requests").and_then(|value| value.as_f64()),
+        Some(16.0)
+    );
+    let by_status = json.get("requests_by_status").unwrap();
+    assert_eq!(
+        by_status.get("4xx").and_then(|value| value.as_f64()),
+        Some(3.0)
+    );
+    assert_eq!(
+        json.get("body_bytes").and_then(|value| value.as_f64()),
+        Some(1024.0)
+    );
+}
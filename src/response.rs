@@ -0,0 +1,319 @@
+// The head of every response is written here, so all of them carry the Date
+// and Server headers (RFC 9110 sections 6.6.1 and 10.2.4).
+
+use crate::{date, status::StatusCode, tenant::Bandwidth};
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, Write},
+    sync::{Arc, Mutex, OnceLock},
+    time::Instant,
+};
+
+// None when the Server header was turned off
+static SERVER: OnceLock<Option<String>> = OnceLock::new();
+// --alt-svc, for HTTPS
+static ALT_SVC: OnceLock<Option<String>> = OnceLock::new();
+// --header, unless responses set them
+static HEADERS: OnceLock<Vec<(String, String)>> = OnceLock::new();
+// the formatted date only changes once per second
+static DATE: Mutex<(u64, String)> = Mutex::new((0, String::new()));
+
+// What went out for a request, for the access log and metrics
+#[derive(Clone, Copy)]
+pub struct Sent {
+    pub status: StatusCode,
+    // when the head was written
+    pub at: Instant,
+    pub body_bytes: u64,
+    // what Content-Length announced, None for HEAD requests and unknown
+    // lengths
+    pub length: Option<u64>,
+    // of the whole resource, which ranges are part of
+    pub size: Option<u64>,
+}
+
+impl Sent {
+    // Whether the body ended before its announced length, e.g. as the client
+    // went away
+    pub fn cut_short(&self) -> bool {
+        self.length.is_some_and(|length| self.body_bytes < length)
+    }
+}
+
+thread_local! {
+    static SENT: Cell<Option<Sent>> = const { Cell::new(None) };
+    // whether the connection ends after this response
+    static CLOSING: Cell<bool> = const { Cell::new(false) };
+    // for HTTP/1.0 clients, which get responses of their version, without
+    // chunked coding nor interim responses
+    static HTTP_10: Cell<bool> = const { Cell::new(false) };
+    // for HEAD requests, which get the headers of a GET without its body
+    static HEAD_ONLY: Cell<bool> = const { Cell::new(false) };
+    // the bandwidth of the tenant asked, see --tenant
+    static THROTTLE: RefCell<Option<Arc<Bandwidth>>> = const { RefCell::new(None) };
+    // for every response to the request, e.g. the cookie of --canary
+    static EXTRA: RefCell<Vec<(&'static str, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn set_server(server: Option<String>) {
+    let _ = SERVER.set(server);
+}
+
+pub fn set_alt_svc(alt_svc: Option<String>) {
+    let _ = ALT_SVC.set(alt_svc);
+}
+
+pub fn set_headers(headers: Vec<(String, String)>) {
+    let _ = HEADERS.set(headers);
+}
+
+// Responses then carry Connection: close, unless they set Connection
+pub fn set_closing(closing: bool) {
+    CLOSING.set(closing);
+}
+
+// Whether the connection ends after this response, which handlers may decide
+pub fn closing() -> bool {
+    CLOSING.get()
+}
+
+pub fn set_http_10(http_10: bool) {
+    HTTP_10.set(http_10);
+}
+
+pub fn http_10() -> bool {
+    HTTP_10.get()
+}
+
+pub fn set_head_only(head_only: bool) {
+    HEAD_ONLY.set(head_only);
+}
+
+pub fn set_throttle(bandwidth: Option<Arc<Bandwidth>>) {
+    THROTTLE.set(bandwidth);
+}
+
+pub fn set_extra(headers: Vec<(&'static str, String)>) {
+    EXTRA.set(headers);
+}
+
+// Where bodies are streamed, whether to skip producing them
+pub fn head_only() -> bool {
+    HEAD_ONLY.get()
+}
+
+pub fn write_head(
+    stream: &mut impl Write,
+    status: StatusCode,
+    headers: &[(&str, &str)],
+) -> io::Result<()> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    };
+    let length = header("Content-Length").and_then(|length| length.parse().ok());
+    // "bytes 0-99/5000"
+    let size = match header("Content-Range") {
+        Some(range) => range
+            .rsplit_once('/')
+            .and_then(|(_, size)| size.parse().ok()),
+        None => length,
+    };
+    SENT.set(Some(Sent {
+        status,
+        at: Instant::now(),
+        body_bytes: 0,
+        length: length.filter(|_| !head_only()),
+        size,
+    }));
+    let http_10 = HTTP_10.get();
+    let is_chunking = |key: &str| {
+        key.eq_ignore_ascii_case("Transfer-Encoding") || key.eq_ignore_ascii_case("Trailer")
+    };
+    // HTTP/1.0 clients get those bodies unchunked, ended by the end of the
+    // connection
+    if http_10 && headers.iter().any(|(key, _)| is_chunking(key)) {
+        CLOSING.set(true);
+    }
+    let mut head = format!("{} {status}\r\nDate: {}\r\n", version(), date());
+    if let Some(server) = SERVER.get_or_init(|| Some(default_server())) {
+        head.push_str(&format!("Server: {server}\r\n"));
+    }
+    if let Some(Some(alt_svc)) = ALT_SVC.get() {
+        head.push_str(&format!("Alt-Svc: {alt_svc}\r\n"));
+    }
+    for (key, value) in headers {
+        if !(http_10 && is_chunking(key)) {
+            head.push_str(&format!("{key}: {value}\r\n"));
+        }
+    }
+    EXTRA.with_borrow(|extra| {
+        for (key, value) in extra {
+            head.push_str(&format!("{key}: {value}\r\n"));
+        }
+    });
+    for (key, value) in HEADERS.get().into_iter().flatten() {
+        if !headers.iter().any(|(set, _)| set.eq_ignore_ascii_case(key)) {
+            head.push_str(&format!("{key}: {value}\r\n"));
+        }
+    }
+    let has_connection = headers
+        .iter()
+        .any(|(key, _)| key.eq_ignore_ascii_case("Connection"));
+    if CLOSING.get() && !has_connection {
+        head.push_str("Connection: close\r\n");
+    } else if http_10 && !has_connection {
+        // which they must ask for, and be told of
+        head.push_str("Connection: keep-alive\r\n");
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())
+}
+
+// Interim responses (1xx), which come before the final one written with
+// write_head, so they aren't what was sent for the request
+pub fn write_interim(
+    stream: &mut impl Write,
+    status: StatusCode,
+    headers: &[(&str, &str)],
+) -> io::Result<()> {
+    // RFC 9110 section 15.2: not for HTTP/1.0 clients
+    if HTTP_10.get() {
+        return Ok(());
+    }
+    let mut head = format!("HTTP/1.1 {status}\r\n");
+    for (key, value) in headers {
+        head.push_str(&format!("{key}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())
+}
+
+// The version responses are sent with
+pub fn version() -> &'static str {
+    match HTTP_10.get() {
+        true => "HTTP/1.0",
+        false => "HTTP/1.1",
+    }
+}
+
+// For bodies written after write_head, unless it's a HEAD request
+pub fn write_body(stream: &mut impl Write, body: &[u8]) -> io::Result<()> {
+    if head_only() {
+        return Ok(());
+    }
+    stream.write_all(body)?;
+    count_body(body.len() as u64);
+    Ok(())
+}
+
+// For bodies written after write_head, which waits while they go past the
+// bandwidth of the tenant
+pub fn count_body(bytes: u64) {
+    SENT.set(SENT.get().map(|sent| Sent {
+        body_bytes: sent.body_bytes + bytes,
+        ..sent
+    }));
+    THROTTLE.with_borrow(|bandwidth| {
+        if let Some(bandwidth) = bandwidth {
+            bandwidth.take(bytes);
+        }
+    });
+}
+
+// Whether the head of a response was written since the last take_sent
+pub fn started() -> bool {
+    SENT.get().is_some()
+}
+
+// The last response written by this thread
+pub fn take_sent() -> Option<Sent> {
+    SENT.take()
+}
+
+pub fn default_server() -> String {
+    format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+fn date() -> String {
+    let now = date::now();
+    let mut cached = DATE.lock().unwrap_or_else(|err| err.into_inner());
+    if cached.0 != now || cached.1.is_empty() {
+        *cached = (now, date::http_date(now));
+    }
+    cached.1.clone()
+}
+
+#[test]
+fn test_closing() {
+    let head = |headers: &[(&str, &str)]| {
+        let mut res = Vec::new();
+        write_head(&mut res, StatusCode::Ok, headers).unwrap();
+        String::from_utf8(res).unwrap()
+    };
+    assert!(!head(&[]).contains("Connection"));
+    set_closing(true);
+    assert!(head(&[]).contains("\r\nConnection: close\r\n"));
+    let upgrade = head(&[("Connection", "Upgrade")]);
+    assert_eq!(upgrade.matches("Connection").count(), 1);
+    set_closing(false);
+
+    // HTTP/1.0 clients are told the connection stays, and don't get chunks
+    set_http_10(true);
+    assert!(head(&[]).starts_with("HTTP/1.0 200 OK\r\n"));
+    assert!(head(&[]).contains("\r\nConnection: keep-alive\r\n"));
+    let chunked = head(&[
+        ("Transfer-Encoding", "chunked"),
+        ("Trailer", "Server-Timing"),
+    ]);
+    assert!(!chunked.contains("chunked") && !chunked.contains("Trailer"));
+    assert!(chunked.contains("\r\nConnection: close\r\n"));
+    let mut interim = Vec::new();
+    write_interim(&mut interim, StatusCode::EarlyHints, &[]).unwrap();
+    assert!(interim.is_empty());
+    set_http_10(false);
+    set_closing(false);
+}
+
+#[test]
+fn test_head_only() {
+    let mut res = Vec::new();
+    write_head(&mut res, StatusCode::Ok, &[]).unwrap();
+    set_head_only(true);
+    write_body(&mut res, b"body").unwrap();
+    assert!(res.ends_with(b"\r\n\r\n"));
+    assert_eq!(take_sent().map(|sent| sent.body_bytes), Some(0));
+    set_head_only(false);
+    write_body(&mut res, b"body").unwrap();
+    assert!(res.ends_with(b"body"));
+}
+
+#[test]
+fn test_sent() {
+    let mut res = Vec::new();
+    let headers = [
+        ("Content-Length", "100"),
+        ("Content-Range", "bytes 0-99/5000"),
+    ];
+    write_head(&mut res, StatusCode::PartialContent, &headers).unwrap();
+    write_body(&mut res, &[0; 40]).unwrap();
+    let sent = take_sent().unwrap();
+    assert_eq!(
+        (sent.body_bytes, sent.length, sent.size),
+        (40, Some(100), Some(5000))
+    );
+    assert!(sent.cut_short());
+
+    write_head(
+        &mut res,
+        StatusCode::Ok,
+        &[("Transfer-Encoding", "chunked")],
+    )
+    .unwrap();
+    write_body(&mut res, b"body").unwrap();
+    let sent = take_sent().unwrap();
+    assert_eq!((sent.length, sent.size), (None, None));
+    assert!(!sent.cut_short());
+}
@@ -0,0 +1,111 @@
+// Where the files of a request are: in the served directory, as -d led when
+// the request arrived, in that of the --vhost asked, or in that of a --map
+// below its prefix. Those are served read-only, without the /_ endpoints,
+// which are the main site's.
+
+use crate::is_below;
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+};
+
+#[derive(Default)]
+struct Root {
+    // absolute, empty for the current directory
+    served: PathBuf,
+    // normalized, what's below it is in `dir`, empty for a whole site
+    prefix: String,
+    // empty for the served directory
+    dir: PathBuf,
+}
+
+thread_local! {
+    static ROOT: RefCell<Root> = RefCell::default();
+}
+
+// Where the served directory is until the next call, see release::enter
+pub fn enter(served: &Path) {
+    ROOT.with_borrow_mut(|root| served.clone_into(&mut root.served));
+}
+
+// `path`, relative to the served directory, e.g. the trash
+pub fn served(path: impl AsRef<Path>) -> PathBuf {
+    ROOT.with_borrow(|root| root.served.join(path))
+}
+
+// The site asked, None for the main site
+pub fn set(dir: Option<&Path>) {
+    ROOT.with_borrow_mut(|root| {
+        root.prefix.clear();
+        root.dir.clear();
+        root.dir.extend(dir);
+    });
+}
+
+// Paths below `prefix` (normalized) are in `dir`
+pub fn mount(prefix: &str, dir: &Path) {
+    ROOT.with_borrow_mut(|root| {
+        prefix.clone_into(&mut root.prefix);
+        dir.clone_into(&mut root.dir);
+    });
+}
+
+// Whether the request being answered is for the main site's own files
+pub fn is_main() -> bool {
+    ROOT.with_borrow(|root| root.dir.as_os_str().is_empty())
+}
+
+// The file of `path` (normalized)
+pub fn path(path: &str) -> PathBuf {
+    ROOT.with_borrow(|root| {
+        if root.dir.as_os_str().is_empty() {
+            return root.served.join(path);
+        }
+        if root.prefix.is_empty() {
+            return root.dir.join(path);
+        }
+        if !is_below(path, &root.prefix) {
+            return root.served.join(path);
+        }
+        let rest = path[root.prefix.len()..].trim_start_matches('/');
+        match rest {
+            "" | "." => root.dir.clone(),
+            rest => root.dir.join(rest),
+        }
+    })
+}
+
+#[test]
+fn test_root() {
+    assert!(is_main());
+    assert_eq!(path("a/b"), Path::new("a/b"));
+    set(Some(Path::new("/srv/example")));
+    assert!(!is_main());
+    assert_eq!(path("a/b"), Path::new("/srv/example/a/b"));
+    assert_eq!(path("."), Path::new("/srv/example/."));
+    mount("ubuntu", Path::new("/mnt/mirror/ubuntu"));
+    assert!(!is_main());
+    assert_eq!(path("ubuntu"), Path::new("/mnt/mirror/ubuntu"));
+    assert_eq!(
+        path("ubuntu/dists/noble"),
+        Path::new("/mnt/mirror/ubuntu/dists/noble")
+    );
+    assert_eq!(path("ubuntux"), Path::new("ubuntux"));
+    assert_eq!(path("."), Path::new("."));
+    set(None);
+    assert!(is_main());
+    assert_eq!(path("."), Path::new("."));
+
+    enter(Path::new("/srv/releases/42"));
+    assert_eq!(path("a/b"), Path::new("/srv/releases/42/a/b"));
+    assert_eq!(served(".trash"), Path::new("/srv/releases/42/.trash"));
+    assert_eq!(served("/var/trash"), Path::new("/var/trash"));
+    mount("ubuntu", Path::new("/mnt/mirror/ubuntu"));
+    assert_eq!(path("ubuntu/a"), Path::new("/mnt/mirror/ubuntu/a"));
+    assert_eq!(path("ubuntux"), Path::new("/srv/releases/42/ubuntux"));
+    set(Some(Path::new("/srv/example")));
+    assert_eq!(path("a"), Path::new("/srv/example/a"));
+    set(None);
+    enter(Path::new(""));
+    assert_eq!(path("a/b"), Path::new("a/b"));
+}
@@ -0,0 +1,280 @@
+// --search: an index of the names below the served directory, and with
+// --search-contents of the text in small files, rebuilt in the background
+// every --search-refresh and soon after changes made through the server.
+// Searches are answered from the last complete index:
+//
+// {"query": "report", "indexed": 1700000000, "truncated": false, "results": [
+//   {"path": "/docs/report.pdf", "type": "file", "size": 1234, "match": "name"}]}
+//
+// Every word of the query has to be in the name, or in the contents. Symbolic
+// links are indexed as such and not followed.
+
+use crate::{date, json, log, release, root};
+use std::{
+    fs,
+    io::Read,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+pub const PATH: &str = "_search";
+pub const DEFAULT_REFRESH: Duration = Duration::from_secs(10 * 60);
+pub const MAX_RESULTS: usize = 100;
+// bigger files are only indexed by name
+const MAX_TEXT_SIZE: u64 = 1024 * 1024;
+// changes come in bursts, e.g. a folder being uploaded
+const CHANGE_DELAY: Duration = Duration::from_secs(2);
+
+struct Entry {
+    // normalized
+    path: String,
+    // lower case
+    name: String,
+    kind: &'static str,
+    size: u64,
+    // lower case, for text files with --search-contents
+    text: Option<String>,
+}
+
+pub struct Match<'a> {
+    pub path: &'a str,
+    pub kind: &'static str,
+    pub size: u64,
+    // whether the contents matched rather than the name
+    pub in_text: bool,
+}
+
+#[derive(Default)]
+struct Snapshot {
+    entries: Vec<Entry>,
+    // unix seconds, None until the first index is complete
+    indexed: Option<u64>,
+}
+
+pub struct Index {
+    pub contents: bool,
+    pub refresh: Duration,
+    snapshot: Mutex<Arc<Snapshot>>,
+    changed: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl Index {
+    pub fn new(contents: bool, refresh: Duration) -> Self {
+        Index {
+            contents,
+            refresh,
+            snapshot: Mutex::default(),
+            changed: Mutex::new(false),
+            wake: Condvar::new(),
+        }
+    }
+
+    // Indexes the served directory forever, leaving out the paths `skip`
+    // says are hidden
+    pub fn run(&self, skip: &dyn Fn(&str) -> bool) {
+        loop {
+            // of the release -d leads to now
+            if let Err(err) = release::enter() {
+                log::error(&format!("failed to switch directories: {err}"));
+            }
+            let mut entries = Vec::new();
+            self.walk(".", skip, &mut entries);
+            let indexed = date::now();
+            *self.snapshot.lock().unwrap_or_else(|err| err.into_inner()) = Arc::new(Snapshot {
+                entries,
+                indexed: Some(indexed),
+            });
+
+            let changed = self.changed.lock().unwrap_or_else(|err| err.into_inner());
+            let (mut changed, _) = self
+                .wake
+                .wait_timeout_while(changed, self.refresh, |changed| !*changed)
+                .unwrap_or_else(|err| err.into_inner());
+            *changed = false;
+            drop(changed);
+            std::thread::sleep(CHANGE_DELAY);
+        }
+    }
+
+    // Something was uploaded, deleted, renamed...
+    pub fn changed(&self) {
+        *self.changed.lock().unwrap_or_else(|err| err.into_inner()) = true;
+        self.wake.notify_one();
+    }
+
+    fn walk(&self, directory: &str, skip: &dyn Fn(&str) -> bool, entries: &mut Vec<Entry>) {
+        let Ok(dir_entries) = fs::read_dir(root::path(directory)) else {
+            return;
+        };
+        for dir_entry in dir_entries.flatten() {
+            let Ok(name) = dir_entry.file_name().into_string() else {
+                continue;
+            };
+            let path = if directory == "." {
+                name.clone()
+            } else {
+                format!("{directory}/{name}")
+            };
+            let Ok(metadata) = dir_entry.metadata() else {
+                continue;
+            };
+            if skip(&path) {
+                continue;
+            }
+            let file_type = metadata.file_type();
+            let kind = if file_type.is_dir() {
+                "directory"
+            } else if file_type.is_symlink() {
+                "symlink"
+            } else {
+                "file"
+            };
+            let text = (self.contents && file_type.is_file() && metadata.len() <= MAX_TEXT_SIZE)
+                .then(|| read_text(&path))
+                .flatten();
+            entries.push(Entry {
+                path: path.clone(),
+                name: name.to_lowercase(),
+                kind,
+                size: metadata.len(),
+                text,
+            });
+            if file_type.is_dir() {
+                self.walk(&path, skip, entries);
+            }
+        }
+    }
+
+    // The entries below `directory` (normalized, "." for all) matching
+    // `query`, which `visible` lets the client see, up to MAX_RESULTS.
+    // Calls `found` with each, and returns whether there were more and when
+    // the index was made.
+    pub fn search(
+        &self,
+        query: &str,
+        directory: &str,
+        visible: &dyn Fn(&str) -> bool,
+        found: &mut dyn FnMut(Match),
+    ) -> (bool, Option<u64>) {
+        let snapshot = Arc::clone(&self.snapshot.lock().unwrap_or_else(|err| err.into_inner()));
+        let terms: Vec<_> = query.split_whitespace().map(str::to_lowercase).collect();
+        if terms.is_empty() {
+            return (false, snapshot.indexed);
+        }
+        let below = |path: &str| {
+            directory == "."
+                || path
+                    .strip_prefix(directory)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        };
+
+        let mut count = 0;
+        for entry in &snapshot.entries {
+            let in_name = terms.iter().all(|term| entry.name.contains(term));
+            let in_text = !in_name
+                && entry
+                    .text
+                    .as_ref()
+                    .is_some_and(|text| terms.iter().all(|term| text.contains(term)));
+            if !(in_name || in_text) || !below(&entry.path) || !visible(&entry.path) {
+                continue;
+            }
+            if count == MAX_RESULTS {
+                return (true, snapshot.indexed);
+            }
+            count += 1;
+            found(Match {
+                path: &entry.path,
+                kind: entry.kind,
+                size: entry.size,
+                in_text,
+            });
+        }
+        (false, snapshot.indexed)
+    }
+}
+
+// The contents of UTF-8 files without NUL bytes, lower case
+fn read_text(path: &str) -> Option<String> {
+    let mut text = String::new();
+    fs::File::open(root::path(path))
+        .ok()?
+        .take(MAX_TEXT_SIZE)
+        .read_to_string(&mut text)
+        .ok()?;
+    (!text.contains('\0')).then(|| text.to_lowercase())
+}
+
+impl Match<'_> {
+    pub fn to_json(&self) -> String {
+        let mut res = format!(
+            "{{\"path\":{},\"type\":\"{}\"",
+            json::quote(&format!("/{}", self.path)),
+            self.kind
+        );
+        if self.kind == "file" {
+            res.push_str(&format!(",\"size\":{}", self.size));
+        }
+        let matched = if self.in_text { "contents" } else { "name" };
+        res.push_str(&format!(",\"match\":\"{matched}\"}}"));
+        res
+    }
+}
+
+#[test]
+fn test_search() {
+    let root = std::env::temp_dir().join(format!("search-test-{}", std::process::id()));
+    fs::create_dir_all(root.join("docs/old")).unwrap();
+    fs::write(root.join("docs/Annual Report.txt"), "Revenue grew").unwrap();
+    fs::write(root.join("docs/old/report.bin"), b"\0revenue").unwrap();
+    fs::write(root.join("docs/notes.md"), "the annual revenue").unwrap();
+    fs::write(root.join("secret-report"), "").unwrap();
+
+    let index = Index::new(true, DEFAULT_REFRESH);
+    let mut entries = Vec::new();
+    let root = root.to_str().unwrap();
+    index.walk(root, &|path| path.ends_with("secret-report"), &mut entries);
+    *index.snapshot.lock().unwrap() = Arc::new(Snapshot {
+        entries,
+        indexed: Some(1),
+    });
+
+    let search = |query: &str, directory: &str, visible: &dyn Fn(&str) -> bool| {
+        let mut res = Vec::new();
+        index.search(query, directory, visible, &mut |found| {
+            let path = found.path.strip_prefix(root).unwrap().to_owned();
+            res.push((path, found.in_text));
+        });
+        res.sort();
+        res
+    };
+    let all = |_: &str| true;
+    assert_eq!(
+        search("REPORT", root, &all),
+        [
+            ("/docs/Annual Report.txt".to_owned(), false),
+            ("/docs/old/report.bin".to_owned(), false)
+        ]
+    );
+    assert_eq!(
+        search("annual revenue", root, &all),
+        [("/docs/notes.md".to_owned(), true)]
+    );
+    assert_eq!(search("report", &format!("{root}/docs/old"), &all).len(), 1);
+    let no_old = |path: &str| !path.contains("/old");
+    assert_eq!(search("report", root, &no_old).len(), 1);
+    assert!(search(" ", root, &all).is_empty());
+
+    let found = Match {
+        path: "docs/a \"b\"",
+        kind: "file",
+        size: 3,
+        in_text: true,
+    };
+    assert_eq!(
+        found.to_json(),
+        "{\"path\":\"/docs/a \\\"b\\\"\",\"type\":\"file\",\"size\":3,\"match\":\"contents\"}"
+    );
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,71 @@
+// Server side sessions, identified by a random token stored in a cookie.
+
+use crate::{crypto, json};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub const COOKIE: &str = "session";
+const LIFETIME: Duration = Duration::from_secs(8 * 60 * 60);
+
+struct Session {
+    expires: Instant,
+    claims: json::Value,
+    // forms have to send it back, which other sites can't do
+    csrf_token: String,
+}
+
+#[derive(Default)]
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Sessions {
+    // Returns the Set-Cookie header value for the new session
+    pub fn create(&self, claims: json::Value) -> std::io::Result<String> {
+        let id = crypto::random_token()?;
+        let csrf_token = crypto::random_token()?;
+        let now = Instant::now();
+
+        let mut sessions = self.sessions.lock().unwrap_or_else(|err| err.into_inner());
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(
+            id.clone(),
+            Session {
+                expires: now + LIFETIME,
+                claims,
+                csrf_token,
+            },
+        );
+
+        Ok(format!(
+            "{COOKIE}={id}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+            LIFETIME.as_secs()
+        ))
+    }
+
+    pub fn claims(&self, id: &str) -> Option<json::Value> {
+        let sessions = self.sessions.lock().unwrap_or_else(|err| err.into_inner());
+        sessions
+            .get(id)
+            .filter(|session| session.expires > Instant::now())
+            .map(|session| session.claims.clone())
+    }
+
+    pub fn csrf_token(&self, id: &str) -> Option<String> {
+        let sessions = self.sessions.lock().unwrap_or_else(|err| err.into_inner());
+        sessions
+            .get(id)
+            .filter(|session| session.expires > Instant::now())
+            .map(|session| session.csrf_token.clone())
+    }
+
+    // Returns the Set-Cookie header value removing the cookie
+    pub fn remove(&self, id: &str) -> String {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|err| err.into_inner());
+        sessions.remove(id);
+        format!("{COOKIE}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0")
+    }
+}
@@ -0,0 +1,66 @@
+// Graceful shutdown on SIGINT and SIGTERM: the listener closes, requests
+// being answered get TIMEOUT to finish, on connections which then close, and
+// the server stops. A second signal ends the process at once.
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+// what `docker stop` waits by default is 10s, long downloads get more
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static DRAINING: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+// Returns false when it had already started
+pub fn start() -> bool {
+    !DRAINING.swap(true, Ordering::SeqCst)
+}
+
+pub fn draining() -> bool {
+    DRAINING.load(Ordering::SeqCst)
+}
+
+// Counts a request as being answered while it's kept
+pub struct InFlight(());
+
+impl InFlight {
+    pub fn new() -> Self {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        InFlight(())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub fn in_flight() -> usize {
+    IN_FLIGHT.load(Ordering::SeqCst)
+}
+
+// Waits for the requests being answered, up to `timeout`, and returns how
+// many are left
+pub fn wait(timeout: Duration) -> usize {
+    let deadline = Instant::now() + timeout;
+    while in_flight() > 0 && Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+    }
+    in_flight()
+}
+
+#[test]
+fn test_wait() {
+    let request = InFlight::new();
+    assert!(in_flight() >= 1);
+    let waiter = thread::spawn(|| wait(Duration::from_secs(5)));
+    thread::sleep(Duration::from_millis(100));
+    drop(request);
+    // other tests' requests don't last either
+    assert_eq!(waiter.join().unwrap(), 0);
+}
@@ -0,0 +1,96 @@
+// std has no signal handling, so we talk to libc directly: the signals we
+// care about are blocked in every thread and collected by a dedicated thread
+// calling sigwait(3), which means the handler can run arbitrary Rust code.
+
+use std::io;
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGTERM: i32 = 15;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const SIGUSR1: i32 = 10;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub const SIGUSR1: i32 = 30;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const SIGCHLD: i32 = 17;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub const SIGCHLD: i32 = 20;
+
+#[cfg(unix)]
+mod sys {
+    // large enough for every libc's sigset_t (glibc uses 128 bytes)
+    #[repr(C, align(8))]
+    pub struct SigSet(pub [u8; 128]);
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub const SIG_BLOCK: i32 = 0;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub const SIG_BLOCK: i32 = 1;
+    pub const WNOHANG: i32 = 1;
+
+    unsafe extern "C" {
+        pub fn sigemptyset(set: *mut SigSet) -> i32;
+        pub fn sigaddset(set: *mut SigSet, signum: i32) -> i32;
+        pub fn pthread_sigmask(how: i32, set: *const SigSet, oldset: *mut SigSet) -> i32;
+        pub fn sigwait(set: *const SigSet, sig: *mut i32) -> i32;
+        pub fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+    }
+}
+
+// Must be called before any other thread is spawned, otherwise those threads
+// won't have the signals blocked and may receive them instead.
+#[cfg(unix)]
+pub fn handle<F>(signals: &[i32], mut handler: F) -> io::Result<()>
+where
+    F: FnMut(i32) + Send + 'static,
+{
+    let mut set = sys::SigSet([0; 128]);
+    // SAFETY: `set` is a valid, writable sigset_t-sized buffer
+    unsafe {
+        sys::sigemptyset(&mut set);
+        for signal in signals {
+            if sys::sigaddset(&mut set, *signal) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        let err = sys::pthread_sigmask(sys::SIG_BLOCK, &set, std::ptr::null_mut());
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err));
+        }
+    }
+
+    std::thread::Builder::new()
+        .name("signals".to_owned())
+        .spawn(move || {
+            loop {
+                let mut signal = 0;
+                // SAFETY: `set` was initialized above and outlives the thread
+                if unsafe { sys::sigwait(&set, &mut signal) } == 0 {
+                    handler(signal);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+// Waits for every child which exited, which falls to PID 1 for the orphans
+// of the whole container. Children waited for with std::process would lose
+// their exit status to this.
+#[cfg(unix)]
+pub fn reap_children() {
+    let mut status = 0;
+    // SAFETY: waitpid only writes to `status`
+    while unsafe { sys::waitpid(-1, &mut status, sys::WNOHANG) } > 0 {}
+}
+
+#[cfg(not(unix))]
+pub fn reap_children() {}
+
+#[cfg(not(unix))]
+pub fn handle<F>(_signals: &[i32], _handler: F) -> io::Result<()>
+where
+    F: FnMut(i32) + Send + 'static,
+{
+    Ok(())
+}
@@ -0,0 +1,243 @@
+// Status codes with their canonical reason phrases (RFC 9110 section 15), so
+// status lines can't be misspelled or made up.
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusCode {
+    SwitchingProtocols,
+    EarlyHints,
+    Ok,
+    Created,
+    Accepted,
+    NoContent,
+    PartialContent,
+    MultiStatus,
+    MultipleChoices,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
+    Conflict,
+    Gone,
+    LengthRequired,
+    PreconditionFailed,
+    ContentTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    UnprocessableContent,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported,
+    InsufficientStorage,
+}
+
+use StatusCode::*;
+
+const ALL: [StatusCode; 41] = [
+    SwitchingProtocols,
+    EarlyHints,
+    Ok,
+    Created,
+    Accepted,
+    NoContent,
+    PartialContent,
+    MultiStatus,
+    MultipleChoices,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
+    Conflict,
+    Gone,
+    LengthRequired,
+    PreconditionFailed,
+    ContentTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    UnprocessableContent,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported,
+    InsufficientStorage,
+];
+
+impl StatusCode {
+    // the codes we don't know are None, not a made up reason phrase
+    fn from_code(code: u16) -> Option<Self> {
+        ALL.into_iter().find(|status| status.code() == code)
+    }
+
+    // RFC 9110 section 15: the codes we don't know are understood as the x00
+    // of their class, e.g. 418 as 400
+    pub fn from_code_or_class(code: u16) -> Option<Self> {
+        Self::from_code(code).or_else(|| Self::from_code(code / 100 * 100))
+    }
+
+    pub fn code(self) -> u16 {
+        match self {
+            SwitchingProtocols => 101,
+            EarlyHints => 103,
+            Ok => 200,
+            Created => 201,
+            Accepted => 202,
+            NoContent => 204,
+            PartialContent => 206,
+            MultiStatus => 207,
+            MultipleChoices => 300,
+            MovedPermanently => 301,
+            Found => 302,
+            SeeOther => 303,
+            NotModified => 304,
+            TemporaryRedirect => 307,
+            PermanentRedirect => 308,
+            BadRequest => 400,
+            Unauthorized => 401,
+            Forbidden => 403,
+            NotFound => 404,
+            MethodNotAllowed => 405,
+            NotAcceptable => 406,
+            RequestTimeout => 408,
+            Conflict => 409,
+            Gone => 410,
+            LengthRequired => 411,
+            PreconditionFailed => 412,
+            ContentTooLarge => 413,
+            UriTooLong => 414,
+            UnsupportedMediaType => 415,
+            RangeNotSatisfiable => 416,
+            ExpectationFailed => 417,
+            UnprocessableContent => 422,
+            TooManyRequests => 429,
+            RequestHeaderFieldsTooLarge => 431,
+            InternalServerError => 500,
+            NotImplemented => 501,
+            BadGateway => 502,
+            ServiceUnavailable => 503,
+            GatewayTimeout => 504,
+            HttpVersionNotSupported => 505,
+            InsufficientStorage => 507,
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            SwitchingProtocols => "Switching Protocols",
+            EarlyHints => "Early Hints",
+            Ok => "OK",
+            Created => "Created",
+            Accepted => "Accepted",
+            NoContent => "No Content",
+            PartialContent => "Partial Content",
+            MultiStatus => "Multi-Status",
+            MultipleChoices => "Multiple Choices",
+            MovedPermanently => "Moved Permanently",
+            Found => "Found",
+            SeeOther => "See Other",
+            NotModified => "Not Modified",
+            TemporaryRedirect => "Temporary Redirect",
+            PermanentRedirect => "Permanent Redirect",
+            BadRequest => "Bad Request",
+            Unauthorized => "Unauthorized",
+            Forbidden => "Forbidden",
+            NotFound => "Not Found",
+            MethodNotAllowed => "Method Not Allowed",
+            NotAcceptable => "Not Acceptable",
+            RequestTimeout => "Request Timeout",
+            Conflict => "Conflict",
+            Gone => "Gone",
+            LengthRequired => "Length Required",
+            PreconditionFailed => "Precondition Failed",
+            ContentTooLarge => "Content Too Large",
+            UriTooLong => "URI Too Long",
+            UnsupportedMediaType => "Unsupported Media Type",
+            RangeNotSatisfiable => "Range Not Satisfiable",
+            ExpectationFailed => "Expectation Failed",
+            UnprocessableContent => "Unprocessable Content",
+            TooManyRequests => "Too Many Requests",
+            RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            InternalServerError => "Internal Server Error",
+            NotImplemented => "Not Implemented",
+            BadGateway => "Bad Gateway",
+            ServiceUnavailable => "Service Unavailable",
+            GatewayTimeout => "Gateway Timeout",
+            HttpVersionNotSupported => "HTTP Version Not Supported",
+            InsufficientStorage => "Insufficient Storage",
+        }
+    }
+
+    pub fn is_success(self) -> bool {
+        (200..300).contains(&self.code())
+    }
+
+    // the status of a response's status line, e.g. "HTTP/1.1 200 OK"
+    pub fn from_status_line(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(3, ' ');
+        if !parts.next()?.starts_with("HTTP/") {
+            return None;
+        }
+        let code = parts.next()?;
+        if code.len() != 3 || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Self::from_code(code.parse().ok()?)
+    }
+}
+
+// "404 Not Found", as it goes after the version in a status line
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+#[test]
+fn test_status_code() {
+    assert_eq!(NotFound.to_string(), "404 Not Found");
+    assert_eq!(
+        HttpVersionNotSupported.to_string(),
+        "505 HTTP Version Not Supported"
+    );
+    for status in ALL {
+        assert_eq!(StatusCode::from_code(status.code()), Some(status));
+    }
+    assert_eq!(StatusCode::from_code(418), None);
+    assert_eq!(StatusCode::from_code_or_class(418), Some(BadRequest));
+    assert_eq!(StatusCode::from_code_or_class(299), Some(Ok));
+    assert_eq!(StatusCode::from_code_or_class(199), None);
+    assert_eq!(StatusCode::from_status_line("HTTP/1.0 200 OK"), Some(Ok));
+    assert_eq!(StatusCode::from_status_line("HTTP/1.1 2000 OK"), None);
+    assert_eq!(StatusCode::from_status_line("garbage"), None);
+}
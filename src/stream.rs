@@ -0,0 +1,99 @@
+// What connections are read from and written to: TCP sockets, or TLS sessions
+// over them with --cert and --key, or memory for Server::handle. Both sides of
+// a connection can be used from two threads at once, through clones.
+
+use std::{
+    io::{self, Cursor, Read, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+pub trait Stream: Read + Write + Send {
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    // None to wait forever
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    // Another handle to the same connection
+    fn try_clone(&self) -> io::Result<Connection>;
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+}
+
+pub type Connection = Box<dyn Stream>;
+
+impl Stream for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn try_clone(&self) -> io::Result<Connection> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+}
+
+// A connection to no one, which reads `input` and keeps what's written to it
+#[derive(Clone)]
+pub struct Memory {
+    input: Arc<Mutex<Cursor<Vec<u8>>>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Memory {
+    pub fn new(input: Vec<u8>) -> Self {
+        Memory {
+            input: Arc::new(Mutex::new(Cursor::new(input))),
+            output: Arc::default(),
+        }
+    }
+
+    pub fn output(&self) -> Vec<u8> {
+        self.output
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+}
+
+impl Read for Memory {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut input = self.input.lock().unwrap_or_else(|err| err.into_inner());
+        input.read(buf)
+    }
+}
+
+impl Write for Memory {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut output = self.output.lock().unwrap_or_else(|err| err.into_inner());
+        output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for Memory {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+    }
+
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn try_clone(&self) -> io::Result<Connection> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn shutdown(&self, _how: Shutdown) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+// --tenant: sites served from directories below the served one, each picked
+// by its hostname or a URL prefix, with an authentication realm, a request
+// rate per client and a bandwidth of its own. Requests are mapped to their
+// tenant's directory before anything else, and what's in a tenant's directory
+// is only served through it. The /_ endpoints belong to the main site.
+
+use crate::{is_below, normalize_path, quota};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+// clients are forgotten once their window is over, past this many
+const MAX_CLIENTS: usize = 1024;
+
+pub enum Selector {
+    // lowercase, without a port
+    Host(String),
+    // normalized
+    Prefix(String),
+}
+
+pub struct Tenant {
+    pub selector: Selector,
+    // normalized, below the served directory
+    pub root: String,
+    pub realm: Option<String>,
+    pub rate: Option<RateLimit>,
+    // bytes per second, for all clients
+    pub bandwidth: Option<Arc<Bandwidth>>,
+    pub quota: Option<u64>,
+}
+
+// "host=dir" or "/prefix=dir", then ",realm=name", ",rate=requests per
+// minute", ",bandwidth=size per second" or ",quota=size"
+pub fn parse(value: &str) -> Result<Tenant, String> {
+    let mut options = value.split(',');
+    let Some((selector, root)) = options.next().and_then(|site| site.split_once('=')) else {
+        return Err("'--tenant' value must be 'host=dir' or '/prefix=dir'".to_owned());
+    };
+    let selector = if selector.starts_with('/') {
+        let prefix = normalize_path(selector.to_owned());
+        if prefix.is_empty() {
+            return Err("'--tenant' prefix can't be /, the main site's".to_owned());
+        }
+        Selector::Prefix(prefix)
+    } else if !selector.is_empty() {
+        Selector::Host(selector.to_ascii_lowercase())
+    } else {
+        return Err("'--tenant' needs a hostname or a /prefix".to_owned());
+    };
+    let root = normalize_path(root.to_owned());
+    if root.is_empty() || root == "." {
+        return Err("'--tenant' directory must be below the served directory".to_owned());
+    }
+    let mut tenant = Tenant {
+        selector,
+        root,
+        realm: None,
+        rate: None,
+        bandwidth: None,
+        quota: None,
+    };
+    for option in options {
+        let size = |value: &str| {
+            quota::parse_size(value)
+                .filter(|size| *size > 0)
+                .ok_or_else(|| format!("'--tenant' {option} isn't a size, e.g. 1M"))
+        };
+        match option.split_once('=') {
+            Some(("realm", realm)) if !realm.is_empty() && !realm.contains('"') => {
+                tenant.realm = Some(realm.to_owned());
+            }
+            Some(("rate", rate)) => match rate.parse() {
+                Ok(rate) if rate > 0 => tenant.rate = Some(RateLimit::new(rate)),
+                _ => {
+                    return Err(format!(
+                        "'--tenant' rate must be requests per minute: {rate}"
+                    ));
+                }
+            },
+            Some(("bandwidth", bandwidth)) => {
+                tenant.bandwidth = Some(Arc::new(Bandwidth::new(size(bandwidth)?)));
+            }
+            Some(("quota", quota)) => tenant.quota = Some(size(quota)?),
+            _ => return Err(format!("unknown '--tenant' option: {option}")),
+        }
+    }
+    Ok(tenant)
+}
+
+impl Tenant {
+    // e.g. "example.org" or "/docs"
+    pub fn name(&self) -> String {
+        match &self.selector {
+            Selector::Host(host) => host.clone(),
+            Selector::Prefix(prefix) => format!("/{prefix}"),
+        }
+    }
+
+    // `path` (normalized) as below the tenant's directory
+    pub fn map(&self, path: &str) -> String {
+        let rest = match &self.selector {
+            Selector::Host(_) => path,
+            Selector::Prefix(prefix) => path[prefix.len()..].trim_start_matches('/'),
+        };
+        match rest {
+            "" | "." => self.root.clone(),
+            rest => format!("{}/{rest}", self.root),
+        }
+    }
+
+    // None when `client` may make another request, or else in how many
+    // seconds it may again
+    pub fn limit(&self, client: IpAddr) -> Option<u64> {
+        self.rate.as_ref()?.limit(client)
+    }
+}
+
+// Requests per minute and client, for tenants and --agent
+pub struct RateLimit {
+    pub rate: u64,
+    // the requests of each client in the current window, and when it started
+    clients: Mutex<HashMap<IpAddr, (Instant, u64)>>,
+}
+
+impl RateLimit {
+    pub fn new(rate: u64) -> Self {
+        RateLimit {
+            rate,
+            clients: Mutex::default(),
+        }
+    }
+
+    // None when `client` may make another request, or else in how many
+    // seconds it may again
+    pub fn limit(&self, client: IpAddr) -> Option<u64> {
+        let rate = self.rate;
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap_or_else(|err| err.into_inner());
+        if clients.len() >= MAX_CLIENTS {
+            clients.retain(|_, (start, _)| now.duration_since(*start) < RATE_WINDOW);
+        }
+        let (start, count) = clients.entry(client).or_insert((now, 0));
+        if now.duration_since(*start) >= RATE_WINDOW {
+            (*start, *count) = (now, 0);
+        }
+        if *count >= rate {
+            let left = RATE_WINDOW.saturating_sub(now.duration_since(*start));
+            return Some(left.as_secs().max(1));
+        }
+        *count += 1;
+        None
+    }
+}
+
+// --rate-limit: requests per second and client, which may come in bursts of
+// up to `burst` at once (token buckets, refilled at `rate`)
+pub struct TokenBuckets {
+    pub rate: f64,
+    pub burst: f64,
+    // the tokens of each client, and when they were counted
+    clients: Mutex<HashMap<IpAddr, (Instant, f64)>>,
+}
+
+impl TokenBuckets {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        TokenBuckets {
+            rate,
+            burst,
+            clients: Mutex::default(),
+        }
+    }
+
+    // None when `client` may make another request, or else in how many
+    // seconds it may again
+    pub fn limit(&self, client: IpAddr) -> Option<u64> {
+        self.limit_at(client, Instant::now())
+    }
+
+    fn limit_at(&self, client: IpAddr, now: Instant) -> Option<u64> {
+        let tokens_at = |(counted, tokens): (Instant, f64)| {
+            let refill = now.saturating_duration_since(counted).as_secs_f64() * self.rate;
+            (tokens + refill).min(self.burst)
+        };
+        let mut clients = self.clients.lock().unwrap_or_else(|err| err.into_inner());
+        if clients.len() >= MAX_CLIENTS {
+            // those with full buckets are as good as new
+            clients.retain(|_, bucket| tokens_at(*bucket) < self.burst);
+        }
+        let bucket = clients.entry(client).or_insert((now, self.burst));
+        let tokens = tokens_at(*bucket);
+        if tokens < 1.0 {
+            *bucket = (now, tokens);
+            return Some(((1.0 - tokens) / self.rate).ceil().max(1.0) as u64);
+        }
+        *bucket = (now, tokens - 1.0);
+        None
+    }
+}
+
+// The tenant of a request, by its Host header first and then the longest
+// prefix of its (normalized) path
+pub fn resolve<'a>(tenants: &'a [Tenant], host: Option<&str>, path: &str) -> Option<&'a Tenant> {
+    let host = host.map(host_name);
+    let by_host = tenants.iter().find(
+        |tenant| matches!(&tenant.selector, Selector::Host(name) if Some(name) == host.as_ref()),
+    );
+    by_host.or_else(|| {
+        tenants
+            .iter()
+            .filter_map(|tenant| match &tenant.selector {
+                Selector::Prefix(prefix) if is_below(path, prefix) => Some((tenant, prefix.len())),
+                _ => None,
+            })
+            .max_by_key(|(_, len)| *len)
+            .map(|(tenant, _)| tenant)
+    })
+}
+
+// A Host header as the hostnames of --tenant and --vhost are
+pub fn host_name(host: &str) -> String {
+    // without the port, which comes after the brackets of IPv6 addresses
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|byte| byte.is_ascii_digit()) => name,
+        _ => host,
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+// The directory of the tenant which `path` (normalized, below the served
+// directory) belongs to, None for the main site
+pub fn owner<'a>(tenants: &'a [Tenant], path: &str) -> Option<&'a str> {
+    tenants
+        .iter()
+        .map(|tenant| tenant.root.as_str())
+        .filter(|root| is_below(path, root))
+        .max_by_key(|root| root.len())
+}
+
+// Shared by the responses of a tenant, which wait for their turn
+pub struct Bandwidth {
+    bytes_per_second: u64,
+    // when what was sent so far is done at that rate
+    done: Mutex<Instant>,
+}
+
+impl Bandwidth {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Bandwidth {
+            bytes_per_second,
+            done: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    // Waits for as long as sending `bytes` takes at the rate, after what the
+    // others sent
+    pub fn take(&self, bytes: u64) {
+        let duration = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        let done = {
+            let mut done = self.done.lock().unwrap_or_else(|err| err.into_inner());
+            *done = (*done).max(Instant::now()) + duration;
+            *done
+        };
+        std::thread::sleep(done.saturating_duration_since(Instant::now()));
+    }
+}
+
+#[test]
+fn test_token_buckets() {
+    let buckets = TokenBuckets::new(2.0, 3.0);
+    let (client, other) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+    let start = Instant::now();
+    for _ in 0..3 {
+        assert_eq!(buckets.limit_at(client, start), None);
+    }
+    assert_eq!(buckets.limit_at(client, start), Some(1));
+    assert_eq!(buckets.limit_at(other, start), None);
+    // a token every half second
+    let later = start + Duration::from_millis(500);
+    assert_eq!(buckets.limit_at(client, later), None);
+    assert_eq!(buckets.limit_at(client, later), Some(1));
+    let slow = TokenBuckets::new(0.1, 1.0);
+    assert_eq!(slow.limit_at(client, start), None);
+    assert_eq!(slow.limit_at(client, start), Some(10));
+}
+
+#[test]
+fn test_resolve() {
+    let tenants = [
+        parse("Blog.example.org=sites/blog,realm=Blog").unwrap(),
+        parse("/docs=sites/docs,rate=2,bandwidth=1M,quota=10M").unwrap(),
+        parse("/docs/api=sites/api").unwrap(),
+    ];
+    assert!(parse("=sites/a").is_err());
+    assert!(parse("/a=.").is_err());
+    assert!(parse("/a=b,rate=0").is_err());
+    assert!(parse("/a=b,color=red").is_err());
+
+    let name = |host: Option<&str>, path: &str| resolve(&tenants, host, path).map(Tenant::name);
+    assert_eq!(
+        name(Some("blog.example.org:8080"), "docs").as_deref(),
+        Some("blog.example.org")
+    );
+    assert_eq!(
+        name(Some("BLOG.example.org."), ".").as_deref(),
+        Some("blog.example.org")
+    );
+    assert_eq!(name(Some("other"), "docs/a").as_deref(), Some("/docs"));
+    assert_eq!(name(None, "docs/api/v1").as_deref(), Some("/docs/api"));
+    assert_eq!(name(None, "docsx"), None);
+    assert_eq!(name(Some("[::1]:8080"), "."), None);
+
+    assert_eq!(tenants[0].map("."), "sites/blog");
+    assert_eq!(tenants[0].map("a/b"), "sites/blog/a/b");
+    assert_eq!(tenants[1].map("docs"), "sites/docs");
+    assert_eq!(tenants[1].map("docs/a"), "sites/docs/a");
+    assert_eq!(tenants[0].realm.as_deref(), Some("Blog"));
+    assert_eq!(tenants[1].quota, Some(10 << 20));
+
+    assert_eq!(owner(&tenants, "sites/blog/a"), Some("sites/blog"));
+    assert_eq!(owner(&tenants, "sites"), None);
+    assert_eq!(owner(&tenants, "a"), None);
+
+    let client = IpAddr::from([127, 0, 0, 1]);
+    assert_eq!(tenants[1].limit(client), None);
+    assert_eq!(tenants[1].limit(client), None);
+    assert!(tenants[1].limit(client).is_some_and(|secs| secs <= 60));
+    assert_eq!(tenants[1].limit(IpAddr::from([127, 0, 0, 2])), None);
+    assert_eq!(tenants[0].limit(client), None);
+}
@@ -0,0 +1,322 @@
+// HTTPS with --cert and --key: accepted connections are wrapped in a TLS
+// session of OpenSSL (TLS 1.2 and up). Only built with the `tls` feature, as it
+// links to libssl and libcrypto.
+
+use crate::stream::{Connection, Stream};
+use std::{
+    ffi::{CString, c_char, c_int, c_long, c_ulong, c_void},
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    os::fd::AsRawFd,
+    ptr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// clients which connect and say nothing don't hold a worker for long
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SSL_FILETYPE_PEM: c_int = 1;
+const SSL_CTRL_SET_MIN_PROTO_VERSION: c_int = 123;
+const TLS1_2_VERSION: c_long = 0x0303;
+// a client closing without close_notify is just gone, as with TCP
+const SSL_OP_IGNORE_UNEXPECTED_EOF: u64 = 1 << 7;
+// both sides of a connection can't be used at once during a renegotiation
+const SSL_OP_NO_RENEGOTIATION: u64 = 1 << 30;
+const SSL_ERROR_WANT_READ: c_int = 2;
+const SSL_ERROR_WANT_WRITE: c_int = 3;
+const SSL_ERROR_SYSCALL: c_int = 5;
+const SSL_ERROR_ZERO_RETURN: c_int = 6;
+
+#[link(name = "ssl")]
+unsafe extern "C" {
+    fn TLS_server_method() -> *const c_void;
+    fn SSL_CTX_new(method: *const c_void) -> *mut c_void;
+    fn SSL_CTX_free(ctx: *mut c_void);
+    fn SSL_CTX_use_certificate_chain_file(ctx: *mut c_void, file: *const c_char) -> c_int;
+    fn SSL_CTX_use_PrivateKey_file(ctx: *mut c_void, file: *const c_char, kind: c_int) -> c_int;
+    fn SSL_CTX_check_private_key(ctx: *const c_void) -> c_int;
+    fn SSL_CTX_set_options(ctx: *mut c_void, options: u64) -> u64;
+    fn SSL_CTX_ctrl(ctx: *mut c_void, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn SSL_CTX_get0_certificate(ctx: *const c_void) -> *mut c_void;
+    fn SSL_new(ctx: *mut c_void) -> *mut c_void;
+    fn SSL_free(ssl: *mut c_void);
+    fn SSL_set_fd(ssl: *mut c_void, fd: c_int) -> c_int;
+    fn SSL_accept(ssl: *mut c_void) -> c_int;
+    fn SSL_read(ssl: *mut c_void, buf: *mut c_void, num: c_int) -> c_int;
+    fn SSL_write(ssl: *mut c_void, buf: *const c_void, num: c_int) -> c_int;
+    fn SSL_pending(ssl: *const c_void) -> c_int;
+    fn SSL_get_error(ssl: *const c_void, ret: c_int) -> c_int;
+    fn SSL_shutdown(ssl: *mut c_void) -> c_int;
+}
+
+#[link(name = "crypto")]
+unsafe extern "C" {
+    fn ERR_get_error() -> c_ulong;
+    fn ERR_clear_error();
+    fn ERR_error_string_n(err: c_ulong, buf: *mut c_char, len: usize);
+    fn X509_get0_notAfter(x509: *const c_void) -> *const c_void;
+    fn ASN1_TIME_diff(
+        days: *mut c_int,
+        seconds: *mut c_int,
+        from: *const c_void,
+        to: *const c_void,
+    ) -> c_int;
+}
+
+// OpenSSL's queue of errors, for this thread
+fn openssl_error(what: &str) -> io::Error {
+    let mut reasons = Vec::new();
+    loop {
+        let err = unsafe { ERR_get_error() };
+        if err == 0 {
+            break;
+        }
+        let mut buf = [0 as c_char; 256];
+        unsafe { ERR_error_string_n(err, buf.as_mut_ptr(), buf.len()) };
+        let reason = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+        reasons.push(reason.to_string_lossy().into_owned());
+    }
+    let message = match reasons.is_empty() {
+        true => what.to_owned(),
+        false => format!("{what}: {}", reasons.join("; ")),
+    };
+    io::Error::other(message)
+}
+
+pub struct Tls {
+    ctx: *mut c_void,
+    cert: String,
+}
+
+// contexts are safe to share once set up
+unsafe impl Send for Tls {}
+unsafe impl Sync for Tls {}
+
+impl Tls {
+    // `cert` holds the certificate, then the intermediate ones
+    pub fn new(cert: &str, key: &str) -> io::Result<Self> {
+        let path =
+            |path: &str| CString::new(path).map_err(|_| io::Error::from(ErrorKind::InvalidInput));
+        let (cert_path, key_path) = (path(cert)?, path(key)?);
+        unsafe { ERR_clear_error() };
+        let ctx = unsafe { SSL_CTX_new(TLS_server_method()) };
+        if ctx.is_null() {
+            return Err(openssl_error("TLS setup failed"));
+        }
+        // freed on errors from here
+        let tls = Tls {
+            ctx,
+            cert: cert.to_owned(),
+        };
+        unsafe {
+            SSL_CTX_set_options(ctx, SSL_OP_IGNORE_UNEXPECTED_EOF | SSL_OP_NO_RENEGOTIATION);
+            SSL_CTX_ctrl(
+                ctx,
+                SSL_CTRL_SET_MIN_PROTO_VERSION,
+                TLS1_2_VERSION,
+                ptr::null_mut(),
+            );
+            if SSL_CTX_use_certificate_chain_file(ctx, cert_path.as_ptr()) != 1 {
+                return Err(openssl_error(&format!("invalid certificate '{cert}'")));
+            }
+            if SSL_CTX_use_PrivateKey_file(ctx, key_path.as_ptr(), SSL_FILETYPE_PEM) != 1 {
+                return Err(openssl_error(&format!("invalid private key '{key}'")));
+            }
+            if SSL_CTX_check_private_key(ctx) != 1 {
+                return Err(openssl_error(&format!(
+                    "'{key}' is not the key of '{cert}'"
+                )));
+            }
+        }
+        Ok(tls)
+    }
+
+    pub fn cert(&self) -> &str {
+        &self.cert
+    }
+
+    // Seconds until the certificate expires, negative once it has
+    pub fn expires_in(&self) -> Option<i64> {
+        let (mut days, mut seconds) = (0, 0);
+        let ok = unsafe {
+            let x509 = SSL_CTX_get0_certificate(self.ctx);
+            if x509.is_null() {
+                return None;
+            }
+            // from now
+            ASN1_TIME_diff(
+                &mut days,
+                &mut seconds,
+                ptr::null(),
+                X509_get0_notAfter(x509),
+            )
+        };
+        (ok == 1).then(|| i64::from(days) * 86400 + i64::from(seconds))
+    }
+
+    // The handshake, on the thread which then serves the connection
+    pub fn accept(&self, tcp_stream: TcpStream) -> io::Result<TlsStream> {
+        tcp_stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let ssl = unsafe { SSL_new(self.ctx) };
+        if ssl.is_null() {
+            return Err(openssl_error("TLS session failed"));
+        }
+        // the session owns the socket OpenSSL uses, which must outlive it
+        let mut session = Session {
+            ssl,
+            tcp_stream: tcp_stream.try_clone()?,
+            broken: false,
+        };
+        unsafe {
+            ERR_clear_error();
+            if SSL_set_fd(ssl, session.tcp_stream.as_raw_fd()) != 1 {
+                session.broken = true;
+                return Err(openssl_error("TLS session failed"));
+            }
+            let ret = SSL_accept(ssl);
+            if ret != 1 {
+                return Err(session.error(ret, "TLS handshake failed"));
+            }
+        }
+        tcp_stream.set_read_timeout(None)?;
+        Ok(TlsStream {
+            tcp_stream,
+            session: Arc::new(Mutex::new(session)),
+        })
+    }
+}
+
+impl Drop for Tls {
+    fn drop(&mut self) {
+        unsafe { SSL_CTX_free(self.ctx) };
+    }
+}
+
+struct Session {
+    ssl: *mut c_void,
+    tcp_stream: TcpStream,
+    // after fatal errors, the session can't even be shut down
+    broken: bool,
+}
+
+// only used behind a mutex
+unsafe impl Send for Session {}
+
+impl Session {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        unsafe { ERR_clear_error() };
+        let ret = unsafe { SSL_read(self.ssl, buf.as_mut_ptr().cast(), len) };
+        if ret > 0 {
+            return Ok(ret as usize);
+        }
+        if unsafe { SSL_get_error(self.ssl, ret) } == SSL_ERROR_ZERO_RETURN {
+            return Ok(0);
+        }
+        Err(self.error(ret, "TLS read failed"))
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(c_int::MAX as usize) as c_int;
+        unsafe { ERR_clear_error() };
+        let ret = unsafe { SSL_write(self.ssl, buf.as_ptr().cast(), len) };
+        if ret > 0 {
+            return Ok(ret as usize);
+        }
+        Err(self.error(ret, "TLS write failed"))
+    }
+
+    // Right after the call which returned `ret`
+    fn error(&mut self, ret: c_int, what: &str) -> io::Error {
+        let os_error = io::Error::last_os_error();
+        match unsafe { SSL_get_error(self.ssl, ret) } {
+            // timeouts of the socket
+            SSL_ERROR_WANT_READ | SSL_ERROR_WANT_WRITE => io::Error::from(ErrorKind::WouldBlock),
+            SSL_ERROR_SYSCALL => {
+                self.broken = true;
+                match os_error.raw_os_error() {
+                    Some(0) | None => io::Error::from(ErrorKind::UnexpectedEof),
+                    Some(_) => os_error,
+                }
+            }
+            _ => {
+                self.broken = true;
+                openssl_error(what)
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.broken {
+                // close_notify, without waiting for the client's
+                SSL_shutdown(self.ssl);
+            }
+            SSL_free(self.ssl);
+        }
+    }
+}
+
+pub struct TlsStream {
+    // for waiting, timeouts and addresses, a clone of the session's
+    tcp_stream: TcpStream,
+    session: Arc<Mutex<Session>>,
+}
+
+impl TlsStream {
+    fn session(&self) -> std::sync::MutexGuard<'_, Session> {
+        self.session.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        {
+            let mut session = self.session();
+            if unsafe { SSL_pending(session.ssl) } > 0 {
+                return session.read(buf);
+            }
+        }
+        // waits for the client without the session, which another thread may
+        // be writing to; a partial record is then waited for with it
+        self.tcp_stream.peek(&mut [0])?;
+        self.session().read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.session().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for TlsStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp_stream.peer_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp_stream.set_read_timeout(timeout)
+    }
+
+    fn try_clone(&self) -> io::Result<Connection> {
+        Ok(Box::new(TlsStream {
+            tcp_stream: self.tcp_stream.try_clone()?,
+            session: Arc::clone(&self.session),
+        }))
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.tcp_stream.shutdown(how)
+    }
+}
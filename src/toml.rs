@@ -0,0 +1,303 @@
+// A small TOML parser, enough for --config files: tables, bare and quoted
+// keys, strings, integers, booleans and arrays of them. No dates, inline
+// tables or arrays of tables.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+// A key of the document, in the order it came, with the table it's in ("" for
+// the top level)
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub table: String,
+    pub key: String,
+    pub value: Value,
+}
+
+// The error is the line it's on, and what's wrong
+pub fn parse(input: &str) -> Result<Vec<Entry>, (usize, String)> {
+    let mut parser = Parser {
+        input: input.as_bytes(),
+        pos: 0,
+        line: 1,
+    };
+    let mut res: Vec<Entry> = Vec::new();
+    let mut table = String::new();
+    let mut tables = Vec::new();
+    loop {
+        parser.skip_blank();
+        let Some(byte) = parser.peek() else {
+            return Ok(res);
+        };
+        let line = parser.line;
+        if byte == b'[' {
+            parser.pos += 1;
+            parser.skip_spaces();
+            table = parser.key().map_err(|err| (line, err))?;
+            parser.skip_spaces();
+            parser.expect(b']').map_err(|err| (line, err))?;
+            if tables.contains(&table) {
+                return Err((line, format!("table '{table}' defined twice")));
+            }
+            tables.push(table.clone());
+        } else {
+            let key = parser.key().map_err(|err| (line, err))?;
+            parser.skip_spaces();
+            parser.expect(b'=').map_err(|err| (line, err))?;
+            parser.skip_spaces();
+            let value = parser.value().map_err(|err| (parser.line, err))?;
+            if res
+                .iter()
+                .any(|entry| entry.table == table && entry.key == key)
+            {
+                return Err((line, format!("key '{key}' defined twice")));
+            }
+            res.push(Entry {
+                table: table.clone(),
+                key,
+                value,
+            });
+        }
+        parser.skip_spaces();
+        parser.skip_comment();
+        match parser.peek() {
+            None | Some(b'\n') => (),
+            Some(b'\r') if parser.input.get(parser.pos + 1) == Some(&b'\n') => (),
+            Some(_) => return Err((parser.line, "expected the end of the line".to_owned())),
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), String> {
+        if self.peek() != Some(expected) {
+            return Err(format!("expected '{}'", expected as char));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some(b'#') {
+            while !matches!(self.peek(), None | Some(b'\n')) {
+                self.pos += 1;
+            }
+        }
+    }
+
+    // Spaces, comments and line breaks
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            match self.peek() {
+                Some(b'\n') => self.line += 1,
+                Some(b'\r') => (),
+                _ => return,
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(b'"') => self.basic_string(),
+            Some(b'\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+                while self.peek().is_some_and(|byte| {
+                    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-'
+                }) {
+                    self.pos += 1;
+                }
+                if start == self.pos {
+                    return Err("expected a key".to_owned());
+                }
+                Ok(String::from_utf8_lossy(&self.input[start..self.pos]).into_owned())
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(b'"') => self.basic_string().map(Value::String),
+            Some(b'\'') => self.literal_string().map(Value::String),
+            Some(b'[') => self.array(),
+            Some(b't' | b'f') => {
+                for (word, value) in [("true", true), ("false", false)] {
+                    if self.input[self.pos..].starts_with(word.as_bytes()) {
+                        self.pos += word.len();
+                        return Ok(Value::Bool(value));
+                    }
+                }
+                Err("expected a value".to_owned())
+            }
+            Some(byte) if byte.is_ascii_digit() || byte == b'-' || byte == b'+' => {
+                let start = self.pos;
+                self.pos += 1;
+                while self
+                    .peek()
+                    .is_some_and(|byte| byte.is_ascii_digit() || byte == b'_')
+                {
+                    self.pos += 1;
+                }
+                let digits: String = String::from_utf8_lossy(&self.input[start..self.pos])
+                    .chars()
+                    .filter(|char| *char != '_')
+                    .collect();
+                digits
+                    .parse()
+                    .map(Value::Integer)
+                    .map_err(|_| "invalid integer".to_owned())
+            }
+            _ => Err("expected a value".to_owned()),
+        }
+    }
+
+    // Across lines, with an optional trailing comma
+    fn array(&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut res = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(b']') {
+                self.pos += 1;
+                return Ok(Value::Array(res));
+            }
+            res.push(self.value()?);
+            self.skip_blank();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => (),
+                _ => return Err("expected ',' or ']'".to_owned()),
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut res = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(b'\n') => return Err("unterminated string".to_owned()),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(res).map_err(|_| "invalid UTF-8".to_owned());
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'n') => '\n',
+                        Some(b't') => '\t',
+                        Some(b'r') => '\r',
+                        Some(b'u') => {
+                            let hex = self
+                                .input
+                                .get(self.pos + 1..self.pos + 5)
+                                .and_then(|hex| std::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(char::from_u32)
+                                .ok_or("invalid \\u escape")?;
+                            self.pos += 4;
+                            hex
+                        }
+                        _ => return Err("invalid escape".to_owned()),
+                    };
+                    self.pos += 1;
+                    let mut buf = [0; 4];
+                    res.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(byte) => {
+                    self.pos += 1;
+                    res.push(byte);
+                }
+            }
+        }
+    }
+
+    // 'as is', without escapes
+    fn literal_string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                None | Some(b'\n') => return Err("unterminated string".to_owned()),
+                Some(b'\'') => {
+                    let res = String::from_utf8(self.input[start..self.pos].to_vec())
+                        .map_err(|_| "invalid UTF-8".to_owned());
+                    self.pos += 1;
+                    return res;
+                }
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    let entries = parse(
+        "# a comment
+port = 8_080
+dir = \"/srv/www\" # another
+upnp = true
+deny = [
+  '.git',
+  \"node\\u005fmodules\",
+]
+
+[headers]
+\"X-Frame-Options\" = 'DENY'
+",
+    )
+    .unwrap();
+    let entry = |table: &str, key: &str, value| Entry {
+        table: table.to_owned(),
+        key: key.to_owned(),
+        value,
+    };
+    let string = |value: &str| Value::String(value.to_owned());
+    assert_eq!(
+        entries,
+        [
+            entry("", "port", Value::Integer(8080)),
+            entry("", "dir", string("/srv/www")),
+            entry("", "upnp", Value::Bool(true)),
+            entry(
+                "",
+                "deny",
+                Value::Array(vec![string(".git"), string("node_modules")])
+            ),
+            entry("headers", "X-Frame-Options", string("DENY")),
+        ]
+    );
+
+    assert_eq!(parse("a = 1\nb = \"x").unwrap_err().0, 2);
+    assert_eq!(parse("a = 1 b").unwrap_err().0, 1);
+    assert!(parse("a = 1\na = 2").is_err());
+    assert!(parse("[t]\n[t]").is_err());
+    assert!(parse("a = yes").is_err());
+}
@@ -0,0 +1,264 @@
+// --debug-routes: requests with an X-Debug-Route header get how their path
+// would be resolved, as JSON, instead of the response. The steps are those of
+// respond, in the same order, without doing anything:
+//
+// {"method": "GET", "target": "/docs/", "decoded": "/docs/", "path": "docs",
+//  "rules": ["hidden: no", "mode read-only: GET allowed", ...],
+//  "tried": ["docs", "docs/index.html", "docs/index.htm"],
+//  "handler": "listing", "status": 200}
+
+use crate::{
+    Config, Mode, Request, check_access, check_token, checksum, crypto, fastcgi, is_below,
+    is_cross_site, json, map, media, metrics, mime_type, normalize_path, proxy, root, search,
+    split_zip_path, tenant, tree, upgrade, url_decode, vhost, viewer,
+};
+
+pub const HEADER: &str = "X-Debug-Route";
+
+pub fn route(request: &Request, config: &Config) -> String {
+    let request_path = request.path.split('?').next().unwrap_or_default();
+    // respond answered 400 to paths which don't decode
+    let decoded = url_decode(request_path).unwrap_or_default();
+    let mut path = normalize_path(decoded.clone());
+    if path.is_empty() {
+        path.push('.');
+    }
+    let mut rules = Vec::new();
+    let mut tried = Vec::new();
+    // respond has set the root of the site asked
+    let vhost = vhost::resolve(
+        &config.vhosts,
+        config.vhost_default.as_deref(),
+        request.header("Host"),
+    );
+    if let Some(vhost) = vhost {
+        rules.push(format!("vhost: {} in {}", vhost.host, vhost.root.display()));
+    }
+    let vhost = vhost.is_some();
+    let tenant = tenant::resolve(&config.tenants, request.header("Host"), &path).filter(|_| !vhost);
+    if let Some(tenant) = tenant {
+        rules.push(format!("tenant: {} in /{}", tenant.name(), tenant.root));
+        path = tenant.map(&path);
+    }
+    let map = map::resolve(&config.maps, &path).filter(|_| !vhost && tenant.is_none());
+    if let Some(map) = map {
+        rules.push(format!("map: {}", map.describe()));
+    }
+    let (handler, status) = if root::is_main()
+        && tenant::owner(&config.tenants, &path) != tenant.map(|tenant| tenant.root.as_str())
+    {
+        rules.push("tenant directory: only through its tenant".to_owned());
+        ("refused", 404)
+    } else if let Some(route) =
+        proxy::resolve(&config.proxies, &path).filter(|_| root::is_main() && tenant.is_none())
+    {
+        rules.push(format!("proxy: /{} to {}", route.prefix, route.url));
+        match check_token(config, request, &path) {
+            Some(_) => ("refused", 403),
+            None => ("proxy", 200),
+        }
+    } else if let Some((script, path_info)) = config
+        .fastcgi
+        .as_ref()
+        .and_then(|_| fastcgi::script(&path, request_path.ends_with('/')))
+    {
+        rules.push(format!("fastcgi: /{script}, path info {path_info:?}"));
+        match check_access(config, "GET", &script).or_else(|| check_token(config, request, &script))
+        {
+            Some(status) => ("refused", status.code()),
+            None => ("fastcgi", 200),
+        }
+    } else {
+        let listing = map.is_none_or(|map| map.listing);
+        resolve(
+            request,
+            config,
+            request_path,
+            &path,
+            listing,
+            &mut rules,
+            &mut tried,
+        )
+    };
+
+    let list = |items: &[String]| {
+        let items: Vec<_> = items.iter().map(|item| json::quote(item)).collect();
+        format!("[{}]", items.join(","))
+    };
+    format!(
+        "{{\"method\":{},\"target\":{},\"decoded\":{},\"path\":{},\"rules\":{},\"tried\":{},\
+         \"handler\":{},\"status\":{status}}}\n",
+        json::quote(&request.method),
+        json::quote(&request.path),
+        json::quote(&decoded),
+        json::quote(&path),
+        list(&rules),
+        list(&tried),
+        json::quote(handler),
+    )
+}
+
+fn resolve(
+    request: &Request,
+    config: &Config,
+    request_path: &str,
+    path: &str,
+    // whether directories are listed, see --map
+    listing: bool,
+    rules: &mut Vec<String>,
+    tried: &mut Vec<String>,
+) -> (&'static str, u16) {
+    let method = request.method.as_str();
+    let yes_no = |value: bool| if value { "yes" } else { "no" };
+
+    let hidden = config.hidden.matches(path);
+    rules.push(format!("hidden: {}", yes_no(hidden)));
+    if hidden {
+        return ("refused", 404);
+    }
+    let allowed = check_access(config, method, path).is_none();
+    let verdict = if allowed { "allowed" } else { "not allowed" };
+    rules.push(format!(
+        "mode {}: {method} {verdict}",
+        config.site_mode().name()
+    ));
+    if !allowed {
+        return ("refused", 405);
+    }
+    // tokens are never shown, only which rule applies
+    let prefix = config
+        .tokens
+        .iter()
+        .filter(|rule| is_below(path, &rule.prefix))
+        .max_by_key(|rule| rule.prefix.len())
+        .map(|rule| &rule.prefix);
+    match prefix {
+        Some(prefix) => {
+            let valid = check_token(config, request, path).is_none();
+            rules.push(format!("token for /{prefix}: {}", yes_no(valid)));
+            if !valid {
+                return ("refused", 403);
+            }
+        }
+        None => rules.push("token: none needed".to_owned()),
+    }
+    let is_get = matches!(method, "GET" | "HEAD");
+    let changes_state = !is_get || request.header("Upgrade").is_some();
+    let cross_site = changes_state && is_cross_site(request);
+    rules.push(format!("cross-site change: {}", yes_no(cross_site)));
+    if cross_site {
+        return ("refused", 403);
+    }
+
+    // the /_ endpoints are the main site's
+    let main_site = root::is_main();
+    if config.metrics.is_some() && is_get && main_site && path == metrics::PATH {
+        return ("metrics", 200);
+    }
+    if let Some((revision, _)) = &config.git_ref
+        && is_get
+        && main_site
+    {
+        rules.push(format!("git ref: {revision}"));
+        return ("git", 200);
+    }
+    if is_get && main_site && path == tree::PATH && config.site_mode() != Mode::UploadOnly {
+        return ("tree", 200);
+    }
+    if config.search.is_some()
+        && is_get
+        && main_site
+        && path == search::PATH
+        && config.site_mode() != Mode::UploadOnly
+    {
+        return ("search", 200);
+    }
+    if method == "OPTIONS" {
+        return ("options", 200);
+    }
+    if method == "PROPFIND" {
+        return ("webdav", 207);
+    }
+    if upgrade::requested(request, "websocket") && main_site {
+        return ("live listing", 101);
+    }
+    if method == "PUT" {
+        return ("upload", 201);
+    }
+    if method == "POST" {
+        return ("manage", 303);
+    }
+    if config.zip
+        && let Some((archive, member)) = split_zip_path(path, request_path.ends_with('/'))
+    {
+        rules.push(format!("zip: member {member:?} of {archive}"));
+        return ("zip member", 200);
+    }
+
+    for candidate in [
+        path.to_owned(),
+        format!("{path}/index.html"),
+        format!("{path}/index.htm"),
+    ] {
+        tried.push(candidate.clone());
+        if config.site_mode() != Mode::UploadOnly && root::path(&candidate).is_file() {
+            if config.viewer
+                && request.has_query_param(viewer::PARAM)
+                && viewer::is_viewable(&mime_type(&candidate))
+            {
+                return ("viewer", 200);
+            }
+            return ("file", 200);
+        }
+    }
+    if config.fingerprints
+        && config.site_mode() != Mode::UploadOnly
+        && let Some((original, fingerprint)) = checksum::strip_fingerprint(path)
+    {
+        tried.push(original.clone());
+        if check_access(config, "GET", &original).is_none()
+            && check_token(config, request, &original).is_none()
+            && root::path(&original).is_file()
+        {
+            let matches = config
+                .checksums
+                .sha256(&root::path(&original))
+                .is_ok_and(|digest| crypto::hex(&digest).starts_with(&fingerprint));
+            rules.push(format!("fingerprint {fingerprint}: {}", yes_no(matches)));
+            if matches {
+                return ("fingerprinted file", 200);
+            }
+        }
+    }
+    if root::path(path).is_dir() {
+        if !request_path.ends_with('/') {
+            return ("redirect", 301);
+        }
+        if !listing {
+            rules.push("listing: off".to_owned());
+            return ("refused", 403);
+        }
+        if config.media && config.site_mode() != Mode::UploadOnly {
+            if request.has_query_param(media::PLAYLIST_PARAM) {
+                return ("playlist", 200);
+            }
+            if request.has_query_param(media::PLAYER_PARAM) {
+                return ("player", 200);
+            }
+        }
+        return ("listing", 200);
+    }
+    if config.checksum_headers
+        && let Some(file) = path.strip_suffix(checksum::SIDECAR_EXTENSION)
+    {
+        tried.push(file.to_owned());
+        if config.site_mode() != Mode::UploadOnly
+            && check_access(config, "GET", file).is_none()
+            && check_token(config, request, file).is_none()
+            && root::path(file).is_file()
+        {
+            return ("checksum", 200);
+        }
+    }
+    ("not found", 404)
+}
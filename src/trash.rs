@@ -0,0 +1,168 @@
+// --trash: deleted files and directories are moved to the trash directory
+// rather than unlinked, and can be restored until they're older than the
+// retention period. Each one gets an entry there:
+//
+//   <deleted at, unix seconds>-<n>/origin  the path it was deleted from
+//   <deleted at, unix seconds>-<n>/item    the file or directory itself
+//
+// Both it and the paths are relative to the served directory of the request,
+// or of the background thread, see release::enter.
+
+use crate::{date, root};
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+// how often expired entries are looked for, at most
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub struct Trash {
+    pub directory: PathBuf,
+    pub retention: Duration,
+}
+
+pub struct Entry {
+    pub id: String,
+    // normalized
+    pub origin: String,
+    // unix seconds
+    pub deleted: u64,
+}
+
+impl Trash {
+    // Moves `path` (normalized) to the trash, which has to be on the same
+    // file system
+    pub fn put(&self, path: &str) -> io::Result<String> {
+        let directory = self.directory();
+        fs::create_dir_all(&directory)?;
+        let deleted = date::now();
+        let mut count = 0;
+        let id = loop {
+            let id = format!("{deleted}-{count}");
+            match fs::create_dir(directory.join(&id)) {
+                Ok(()) => break id,
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => count += 1,
+                Err(err) => return Err(err),
+            }
+        };
+        let entry = directory.join(&id);
+        let res = fs::write(entry.join("origin"), path)
+            .and_then(|()| fs::rename(root::path(path), entry.join("item")));
+        if let Err(err) = res {
+            let _ = fs::remove_dir_all(&entry);
+            return Err(err);
+        }
+        Ok(id)
+    }
+
+    // Newest first, skipping what isn't an entry
+    pub fn entries(&self) -> Vec<Entry> {
+        let mut res: Vec<_> = fs::read_dir(self.directory())
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|dir_entry| {
+                let id = dir_entry.file_name().into_string().ok()?;
+                let deleted = parse_id(&id)?;
+                let origin = self.origin(&id).ok()?;
+                Some(Entry {
+                    id,
+                    origin,
+                    deleted,
+                })
+            })
+            .collect();
+        res.sort_by(|a, b| b.deleted.cmp(&a.deleted).then_with(|| b.id.cmp(&a.id)));
+        res
+    }
+
+    pub fn origin(&self, id: &str) -> io::Result<String> {
+        if parse_id(id).is_none() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        fs::read_to_string(self.directory().join(id).join("origin"))
+    }
+
+    // Moves the entry `id` back where it was deleted from, unless something
+    // took its place since
+    pub fn restore(&self, id: &str) -> io::Result<()> {
+        let origin = root::path(&self.origin(id)?);
+        if fs::symlink_metadata(&origin).is_ok() {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+        if let Some(parent) = origin
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let entry = self.directory().join(id);
+        fs::rename(entry.join("item"), origin)?;
+        fs::remove_dir_all(entry)
+    }
+
+    // Removes the entries older than the retention period, returns how many
+    pub fn purge(&self, now: SystemTime) -> usize {
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.entries()
+            .into_iter()
+            .filter(|entry| entry.deleted.saturating_add(self.retention.as_secs()) < now)
+            .filter(|entry| fs::remove_dir_all(self.directory().join(&entry.id)).is_ok())
+            .count()
+    }
+
+    fn directory(&self) -> PathBuf {
+        root::served(&self.directory)
+    }
+}
+
+// The deletion time of "<unix seconds>-<n>", None for anything else
+fn parse_id(id: &str) -> Option<u64> {
+    let (deleted, count) = id.split_once('-')?;
+    let is_number = |digits: &str| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit());
+    if !is_number(deleted) || !is_number(count) {
+        return None;
+    }
+    deleted.parse().ok()
+}
+
+#[test]
+fn test_trash() {
+    let root = std::env::temp_dir().join(format!("trash-test-{}", std::process::id()));
+    fs::create_dir_all(root.join("a/b")).unwrap();
+    fs::write(root.join("a/b/x"), "x").unwrap();
+    fs::write(root.join("a/y"), "y").unwrap();
+    let trash = Trash {
+        directory: root.join(".trash"),
+        retention: Duration::from_secs(60),
+    };
+    let path = |name: &str| root.join(name).to_str().unwrap().to_owned();
+
+    let b = trash.put(&path("a/b")).unwrap();
+    let y = trash.put(&path("a/y")).unwrap();
+    assert_ne!(b, y);
+    assert!(!root.join("a/b").exists() && !root.join("a/y").exists());
+    let entries = trash.entries();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].origin, path("a/y"));
+    assert!(trash.origin("../a").is_err());
+
+    // something took its place
+    fs::write(root.join("a/y"), "new").unwrap();
+    let err = trash.restore(&y).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    // the parent went away too
+    fs::remove_dir_all(root.join("a")).unwrap();
+    trash.restore(&b).unwrap();
+    assert_eq!(fs::read_to_string(root.join("a/b/x")).unwrap(), "x");
+    assert_eq!(trash.entries().len(), 1);
+
+    assert_eq!(trash.purge(SystemTime::now()), 0);
+    assert_eq!(trash.purge(SystemTime::now() + Duration::from_secs(120)), 1);
+    assert!(trash.entries().is_empty());
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,146 @@
+// The served tree as nested JSON, for scripts and dashboards which would
+// rather not scrape listings:
+//
+// {"path": "/docs", "type": "directory", "children": [
+//   {"name": "a.txt", "type": "file", "size": 12, "modified": 1700000000},
+//   {"name": "img", "type": "directory", "modified": 1700000000, "children": []}]}
+//
+// Directories deeper than asked for come without children. Symbolic links
+// are listed as such and not followed.
+
+use crate::{json, root};
+use std::{
+    fs,
+    io::{self, Write},
+    time::UNIX_EPOCH,
+};
+
+pub const PATH: &str = "_api/tree";
+pub const DEFAULT_DEPTH: u32 = 1;
+// keeps responses for the whole tree of a big directory bounded
+pub const MAX_DEPTH: u32 = 32;
+
+// `directory` is normalized ("." for the root), `visible` says whether the
+// client may see a path below it
+pub fn write(
+    res: &mut impl Write,
+    directory: &str,
+    depth: u32,
+    visible: &dyn Fn(&str) -> bool,
+) -> io::Result<()> {
+    let path = if directory == "." {
+        "/".to_owned()
+    } else {
+        format!("/{directory}")
+    };
+    write!(
+        res,
+        "{{\"path\":{},\"type\":\"directory\"",
+        json::quote(&path)
+    )?;
+    write_children(res, directory, depth, visible)?;
+    writeln!(res, "}}")
+}
+
+fn write_children(
+    res: &mut impl Write,
+    directory: &str,
+    depth: u32,
+    visible: &dyn Fn(&str) -> bool,
+) -> io::Result<()> {
+    if depth == 0 {
+        return Ok(());
+    }
+    let mut entries = Vec::new();
+    // an unreadable directory looks empty rather than failing the whole tree
+    for entry in fs::read_dir(root::path(directory))
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        // JSON strings can't hold names which aren't UTF-8
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let path = if directory == "." {
+            name.clone()
+        } else {
+            format!("{directory}/{name}")
+        };
+        if let Ok(metadata) = entry.metadata()
+            && visible(&path)
+        {
+            entries.push((name, path, metadata));
+        }
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    write!(res, ",\"children\":[")?;
+    for (idx, (name, path, metadata)) in entries.iter().enumerate() {
+        if idx > 0 {
+            write!(res, ",")?;
+        }
+        write!(res, "{{\"name\":{}", json::quote(name))?;
+        if let Ok(modified) = metadata.modified()
+            && let Ok(modified) = modified.duration_since(UNIX_EPOCH)
+        {
+            write!(res, ",\"modified\":{}", modified.as_secs())?;
+        }
+        if metadata.is_dir() {
+            write!(res, ",\"type\":\"directory\"")?;
+            write_children(res, path, depth - 1, visible)?;
+        } else if metadata.is_symlink() {
+            write!(res, ",\"type\":\"symlink\"")?;
+        } else {
+            write!(res, ",\"type\":\"file\",\"size\":{}", metadata.len())?;
+        }
+        write!(res, "}}")?;
+    }
+    write!(res, "]")
+}
+
+#[test]
+fn test_write() {
+    let root = std::env::temp_dir().join(format!("tree-test-{}", std::process::id()));
+    fs::create_dir_all(root.join("sub/deeper")).unwrap();
+    fs::create_dir_all(root.join("secret")).unwrap();
+    fs::write(root.join("sub/a \"b\""), "hello").unwrap();
+    let directory = root.to_str().unwrap();
+    let visible = |path: &str| !path.ends_with("/secret");
+
+    let tree = |depth| {
+        let mut res = Vec::new();
+        write(&mut res, directory, depth, &visible).unwrap();
+        let res = String::from_utf8(res).unwrap();
+        json::parse(&res).unwrap()
+    };
+    let top = tree(1);
+    assert_eq!(
+        top.get("path").and_then(json::Value::as_str),
+        Some(format!("/{directory}").as_str())
+    );
+    let children = top.get("children").and_then(json::Value::as_array).unwrap();
+    assert_eq!(children.len(), 1);
+    assert_eq!(
+        children[0].get("name").and_then(json::Value::as_str),
+        Some("sub")
+    );
+    assert_eq!(children[0].get("children"), None);
+
+    let all = tree(MAX_DEPTH);
+    let sub = &all.get("children").and_then(json::Value::as_array).unwrap()[0];
+    let names: Vec<_> = sub
+        .get("children")
+        .and_then(json::Value::as_array)
+        .unwrap()
+        .iter()
+        .map(|child| {
+            (
+                child.get("name").and_then(json::Value::as_str).unwrap(),
+                child.get("size").and_then(json::Value::as_f64),
+            )
+        })
+        .collect();
+    assert_eq!(names, [("a \"b\"", Some(5.0)), ("deeper", None)]);
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,326 @@
+// --upstream-proxy: outbound connections (--proxy routes, webhooks, identity
+// providers) go through a proxy, as corporate networks may require: an HTTP
+// one, asked with CONNECT, or a SOCKS5 one (RFC 1928), both with optional
+// credentials, which SOCKS5 sends as in RFC 1929. Either way the connection
+// is a tunnel to the server, so what goes through it is unchanged, and
+// upgraded connections work. Connections to loopback addresses are always
+// direct, no proxy could reach them.
+
+use crate::base64;
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+    sync::OnceLock,
+    time::Duration,
+};
+
+// longest response to CONNECT we read
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+static DEFAULT: OnceLock<Upstream> = OnceLock::new();
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Kind {
+    Http,
+    Socks5,
+}
+
+#[derive(Clone)]
+pub struct Upstream {
+    kind: Kind,
+    // host:port
+    address: String,
+    credentials: Option<(String, String)>,
+}
+
+// How a connection is made
+pub enum Via {
+    // through --upstream-proxy, if there's one
+    Default,
+    Direct,
+    Proxy(Upstream),
+}
+
+impl Upstream {
+    // "http://[user:password@]host[:port]" or "socks5://...", ports 8080 and
+    // 1080 by default
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let (kind, rest, port) = if let Some(rest) = url.strip_prefix("http://") {
+            (Kind::Http, rest, 8080)
+        } else if let Some(rest) = url.strip_prefix("socks5://") {
+            (Kind::Socks5, rest, 1080)
+        } else {
+            return Err(format!(
+                "unsupported proxy URL: {url} (only http:// and socks5://)"
+            ));
+        };
+        let rest = rest.trim_end_matches('/');
+        let (credentials, host) = match rest.rsplit_once('@') {
+            // passwords can have any character but '/', as the last @ ends
+            // them
+            Some((userinfo, host)) => {
+                let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                (Some((user.to_owned(), password.to_owned())), host)
+            }
+            None => (None, rest),
+        };
+        if host.is_empty() || host.contains('/') {
+            return Err(format!("invalid proxy URL: {url}"));
+        }
+        let address = match host.rsplit_once(':') {
+            Some((_, port)) if !port.ends_with(']') => host.to_owned(),
+            _ => format!("{host}:{port}"),
+        };
+        if let Some((user, password)) = &credentials
+            && kind == Kind::Socks5
+            && (user.len() > 255 || password.len() > 255)
+        {
+            return Err("SOCKS5 credentials can't be longer than 255 bytes".to_owned());
+        }
+        Ok(Upstream {
+            kind,
+            address,
+            credentials,
+        })
+    }
+
+    // A connection to `target` (host:port) through the proxy
+    fn connect(&self, target: &str, timeout: Duration) -> io::Result<TcpStream> {
+        let stream = connect_direct(&self.address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        match self.kind {
+            Kind::Http => self.http_connect(&stream, target)?,
+            Kind::Socks5 => self.socks5_connect(&stream, target)?,
+        }
+        Ok(stream)
+    }
+
+    fn http_connect(&self, mut stream: &TcpStream, target: &str) -> io::Result<()> {
+        let mut head = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+        if let Some((user, password)) = &self.credentials {
+            let credentials = base64::encode(format!("{user}:{password}").as_bytes());
+            head.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+
+        // a byte at a time, as what follows the head is the server's
+        let mut response = Vec::new();
+        let mut byte = [0];
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() > MAX_HEAD_SIZE || stream.read(&mut byte)? == 0 {
+                return Err(proxy_error("closed the connection"));
+            }
+            response.push(byte[0]);
+        }
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response.lines().next().unwrap_or_default();
+        let code = status_line.split(' ').nth(1).unwrap_or_default();
+        if !code.starts_with('2') {
+            return Err(proxy_error(&format!("answered '{status_line}'")));
+        }
+        Ok(())
+    }
+
+    fn socks5_connect(&self, mut stream: &TcpStream, target: &str) -> io::Result<()> {
+        let method = match self.credentials {
+            Some(_) => 2,
+            None => 0,
+        };
+        stream.write_all(&[5, 1, method])?;
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply)?;
+        if reply != [5, method] {
+            return Err(proxy_error("refused the authentication method"));
+        }
+        if let Some((user, password)) = &self.credentials {
+            let mut request = vec![1, user.len() as u8];
+            request.extend_from_slice(user.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request)?;
+            stream.read_exact(&mut reply)?;
+            if reply[1] != 0 {
+                return Err(proxy_error("refused the credentials"));
+            }
+        }
+
+        stream.write_all(&socks5_request(target)?)?;
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply)?;
+        if reply[1] != 0 {
+            return Err(proxy_error(&format!(
+                "couldn't connect to {target} (reply {})",
+                reply[1]
+            )));
+        }
+        // the address the proxy connected from, which we don't need
+        let length = match reply[3] {
+            1 => 4,
+            4 => 16,
+            3 => {
+                let mut length = [0];
+                stream.read_exact(&mut length)?;
+                usize::from(length[0])
+            }
+            _ => return Err(proxy_error("sent an invalid reply")),
+        };
+        let mut bound = vec![0; length + 2];
+        stream.read_exact(&mut bound)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Upstream {
+    // without the password
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scheme = match self.kind {
+            Kind::Http => "http",
+            Kind::Socks5 => "socks5",
+        };
+        match &self.credentials {
+            Some((user, _)) => write!(f, "{scheme}://{user}@{}", self.address),
+            None => write!(f, "{scheme}://{}", self.address),
+        }
+    }
+}
+
+impl Via {
+    // "direct", or a proxy URL
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "direct" => Ok(Via::Direct),
+            url => Upstream::parse(url).map(Via::Proxy),
+        }
+    }
+}
+
+// For the outbound connections of the whole server
+pub fn set_default(upstream: Upstream) {
+    let _ = DEFAULT.set(upstream);
+}
+
+// A connection to `target` (host:port)
+pub fn connect(target: &str, via: &Via, timeout: Duration) -> io::Result<TcpStream> {
+    let upstream = match via {
+        Via::Default => DEFAULT.get(),
+        Via::Direct => None,
+        Via::Proxy(upstream) => Some(upstream),
+    };
+    match upstream {
+        Some(upstream) if !is_loopback(target) => upstream.connect(target, timeout),
+        _ => connect_direct(target, timeout),
+    }
+}
+
+fn connect_direct(address: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+    TcpStream::connect_timeout(&address, timeout)
+}
+
+fn is_loopback(target: &str) -> bool {
+    let host = target.rsplit_once(':').map_or(target, |(host, _)| host);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+// CONNECT to `target` (host:port), by name so the proxy resolves it
+fn socks5_request(target: &str) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid {target}"));
+    let (host, port) = target.rsplit_once(':').ok_or_else(invalid)?;
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    let mut res = vec![5, 1, 0];
+    match format!("{host}:{port}")
+        .parse::<SocketAddr>()
+        .map(|a| a.ip())
+    {
+        Ok(IpAddr::V4(ip)) => {
+            res.push(1);
+            res.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            res.push(4);
+            res.extend_from_slice(&ip.octets());
+        }
+        Err(_) if host.len() <= 255 => {
+            res.extend([3, host.len() as u8]);
+            res.extend_from_slice(host.as_bytes());
+        }
+        Err(_) => return Err(invalid()),
+    }
+    res.extend_from_slice(&port.to_be_bytes());
+    Ok(res)
+}
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::other(format!("upstream proxy {message}"))
+}
+
+#[test]
+fn test_upstream() {
+    let upstream = Upstream::parse("socks5://me:p@s:s@proxy.corp").unwrap();
+    assert_eq!(upstream.kind, Kind::Socks5);
+    assert_eq!(upstream.address, "proxy.corp:1080");
+    assert_eq!(
+        upstream.credentials,
+        Some(("me".to_owned(), "p@s:s".to_owned()))
+    );
+    assert_eq!(upstream.to_string(), "socks5://me@proxy.corp:1080");
+    let upstream = Upstream::parse("http://[::1]:3128/").unwrap();
+    assert_eq!(upstream.address, "[::1]:3128");
+    assert!(Upstream::parse("https://proxy.corp").is_err());
+    assert!(Upstream::parse("http://").is_err());
+    assert!(matches!(Via::parse("direct"), Ok(Via::Direct)));
+
+    assert!(is_loopback("127.0.0.1:3000") && is_loopback("[::1]:80"));
+    assert!(is_loopback("localhost:80") && !is_loopback("example.com:80"));
+    assert_eq!(
+        socks5_request("example.com:80").unwrap(),
+        b"\x05\x01\x00\x03\x0bexample.com\x00\x50"
+    );
+    assert_eq!(socks5_request("[::1]:443").unwrap()[3..5], [4, 0]);
+    assert_eq!(
+        socks5_request("10.0.0.1:8080").unwrap(),
+        [5, 1, 0, 1, 10, 0, 0, 1, 0x1f, 0x90]
+    );
+}
+
+// The tunnel through a CONNECT proxy, against one answering on a socket
+#[test]
+fn test_http_connect() {
+    use std::{io::BufRead, net::TcpListener, thread};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let proxy = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = io::BufReader::new(&stream);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        (&stream)
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nhello")
+            .unwrap();
+        head
+    });
+    let upstream = Upstream::parse(&format!("http://a:b@{address}")).unwrap();
+    let mut stream = connect(
+        "example.com:80",
+        &Via::Proxy(upstream),
+        Duration::from_secs(5),
+    )
+    .unwrap();
+    let mut greeting = String::new();
+    stream.read_to_string(&mut greeting).unwrap();
+    assert_eq!(greeting, "hello");
+    let head = proxy.join().unwrap();
+    assert!(head.starts_with("CONNECT example.com:80 HTTP/1.1\r\n"));
+    assert!(head.contains("Proxy-Authorization: Basic YTpi\r\n"));
+}
@@ -0,0 +1,71 @@
+// Connections switching protocols (RFC 9110 section 7.8): after the 101
+// response a handler owns the raw socket, starting with whatever the request
+// reader had already buffered past the request head.
+
+use crate::{Request, response, status::StatusCode, stream::Connection};
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+pub struct Upgraded {
+    buffered: Vec<u8>,
+    stream: Connection,
+}
+
+impl Upgraded {
+    // For a second thread writing to the connection
+    pub fn try_clone_stream(&self) -> io::Result<Connection> {
+        self.stream.try_clone()
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffered.is_empty() {
+            return self.stream.read(buf);
+        }
+        let len = buf.len().min(self.buffered.len());
+        buf[..len].copy_from_slice(&self.buffered[..len]);
+        self.buffered.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+pub fn requested(request: &Request, protocol: &str) -> bool {
+    let lists = |name: &str, token: &str| {
+        request.header(name).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|item| item.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    lists("Connection", "upgrade") && lists("Upgrade", protocol)
+}
+
+// Sends the 101 response and hands the connection over
+pub fn switch(
+    buf_reader: &mut BufReader<Connection>,
+    protocol: &str,
+    headers: &[(&str, &str)],
+) -> io::Result<Upgraded> {
+    // buffer() doesn't read more from the socket, unlike fill_buf()
+    let buffered = buf_reader.buffer().to_vec();
+    buf_reader.consume(buffered.len());
+    let mut stream = buf_reader.get_ref().try_clone()?;
+    // idle connections are up to the protocol now, not --read-timeout
+    stream.set_read_timeout(None)?;
+
+    let mut head = vec![("Connection", "Upgrade"), ("Upgrade", protocol)];
+    head.extend_from_slice(headers);
+    response::write_head(&mut stream, StatusCode::SwitchingProtocols, &head)?;
+
+    Ok(Upgraded { buffered, stream })
+}
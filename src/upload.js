@@ -0,0 +1,96 @@
+// Drag-and-drop uploads for the directory listing. Every file is PUT next to
+// the listing, big files in chunks carrying a Content-Range header.
+const CHUNK_SIZE = 8 * 1024 * 1024;
+const PARALLEL_UPLOADS = 3;
+
+const dropZone = document.getElementById("drop-zone");
+const fileInput = document.getElementById("file-input");
+const uploads = document.getElementById("uploads");
+const queue = [];
+let running = 0;
+let failed = false;
+
+function putChunk(url, blob, range, onProgress) {
+  return new Promise((resolve, reject) => {
+    const xhr = new XMLHttpRequest();
+    xhr.open("PUT", url);
+    if (range) {
+      xhr.setRequestHeader("Content-Range", range);
+    }
+    xhr.upload.onprogress = (event) => onProgress(event.loaded);
+    xhr.onload = () =>
+      xhr.status >= 200 && xhr.status < 300
+        ? resolve()
+        : reject(new Error(xhr.status + " " + xhr.statusText));
+    xhr.onerror = () => reject(new Error("network error"));
+    xhr.send(blob);
+  });
+}
+
+async function upload(file, progress) {
+  const url = location.pathname + encodeURIComponent(file.name);
+  if (file.size <= CHUNK_SIZE) {
+    await putChunk(url, file, null, (loaded) => (progress.value = loaded));
+    return;
+  }
+  for (let start = 0; start < file.size; start += CHUNK_SIZE) {
+    const end = Math.min(start + CHUNK_SIZE, file.size);
+    const range = "bytes " + start + "-" + (end - 1) + "/" + file.size;
+    await putChunk(url, file.slice(start, end), range, (loaded) => {
+      progress.value = start + loaded;
+    });
+  }
+}
+
+function next() {
+  while (running < PARALLEL_UPLOADS && queue.length > 0) {
+    const { file, progress, status } = queue.shift();
+    running++;
+    upload(file, progress)
+      .then(() => {
+        progress.value = progress.max;
+        status.textContent = "done";
+      })
+      .catch((err) => {
+        failed = true;
+        status.textContent = "failed: " + err.message;
+      })
+      .finally(() => {
+        running--;
+        if (running === 0 && queue.length === 0 && !failed) {
+          location.reload();
+        } else {
+          next();
+        }
+      });
+  }
+}
+
+function enqueue(files) {
+  for (const file of files) {
+    const item = document.createElement("li");
+    const progress = document.createElement("progress");
+    const status = document.createElement("span");
+    progress.max = Math.max(file.size, 1);
+    progress.value = 0;
+    item.append(file.name + " ", progress, " ", status);
+    uploads.append(item);
+    queue.push({ file, progress, status });
+  }
+  next();
+}
+
+dropZone.addEventListener("dragover", (event) => {
+  event.preventDefault();
+  dropZone.classList.add("dragging");
+});
+dropZone.addEventListener("dragleave", () => dropZone.classList.remove("dragging"));
+dropZone.addEventListener("drop", (event) => {
+  event.preventDefault();
+  dropZone.classList.remove("dragging");
+  enqueue(event.dataTransfer.files);
+});
+fileInput.addEventListener("change", () => {
+  enqueue(fileInput.files);
+  fileInput.value = "";
+});
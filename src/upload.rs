@@ -0,0 +1,226 @@
+// Whole file uploads go to a temporary file next to their target, which is
+// renamed over it once complete: readers never see half a file, and an
+// interrupted upload leaves the previous version in place. Uploads in parts,
+// each a PUT with a Content-Range, gather theirs in such a file too, renamed
+// once every byte came.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+// Uploads in parts left without a new one for this long are given up, and
+// their temporary file removed
+const PARTS_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+// What happens to a PUT while another one to the same path is running
+#[derive(Clone, Copy, PartialEq)]
+pub enum Conflict {
+    // both complete, the last one renamed stays
+    LastWriterWins,
+    // the second one gets a 409
+    Reject,
+}
+
+impl Conflict {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "last-writer-wins" => Some(Conflict::LastWriterWins),
+            "reject" => Some(Conflict::Reject),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Conflict::LastWriterWins => "last-writer-wins",
+            Conflict::Reject => "reject",
+        }
+    }
+}
+
+// The paths being uploaded to
+#[derive(Default)]
+pub struct Uploads {
+    paths: Mutex<HashSet<String>>,
+    // by path, the uploads in parts not complete yet
+    parts: Mutex<HashMap<String, Parts>>,
+}
+
+// An upload in parts, which can come in any order or in parallel
+struct Parts {
+    temp: PathBuf,
+    total: u64,
+    // the ranges written, sorted and merged, their ends excluded
+    received: Vec<(u64, u64)>,
+    touched: Instant,
+}
+
+impl Uploads {
+    // None when `path` is already being uploaded to
+    pub fn start(&self, path: &str) -> Option<Upload<'_>> {
+        let mut paths = self.paths.lock().unwrap_or_else(|err| err.into_inner());
+        paths.insert(path.to_owned()).then(|| Upload {
+            uploads: self,
+            path: path.to_owned(),
+        })
+    }
+
+    // The size of the file whose parts are arriving at `path`, if any
+    pub fn in_parts(&self, path: &str) -> Option<u64> {
+        let parts = self.parts.lock().unwrap_or_else(|err| err.into_inner());
+        parts.get(path).map(|upload| upload.total)
+    }
+
+    // The temporary file of the upload in parts of `total` bytes to `path`,
+    // created with that size unless it was. One of another size starts over.
+    pub fn part_file(&self, path: &str, target: &Path, total: u64) -> std::io::Result<PathBuf> {
+        let mut parts = self.parts.lock().unwrap_or_else(|err| err.into_inner());
+        parts.retain(|_, upload| {
+            let expired = upload.touched.elapsed() > PARTS_EXPIRY;
+            if expired {
+                let _ = std::fs::remove_file(&upload.temp);
+            }
+            !expired
+        });
+        if let Some(upload) = parts.get_mut(path) {
+            if upload.total == total {
+                upload.touched = Instant::now();
+                return Ok(upload.temp.clone());
+            }
+            let _ = std::fs::remove_file(&upload.temp);
+        }
+        let temp = temp_path(target);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?
+            .set_len(total)?;
+        parts.insert(
+            path.to_owned(),
+            Parts {
+                temp: temp.clone(),
+                total,
+                received: Vec::new(),
+                touched: Instant::now(),
+            },
+        );
+        Ok(temp)
+    }
+
+    // Records that bytes `start..end` of `path` were written to `temp`. Returns
+    // true, forgetting the upload, once that makes all of them: the caller
+    // then renames the file over its target.
+    pub fn part_written(&self, path: &str, temp: &Path, start: u64, end: u64) -> bool {
+        let mut parts = self.parts.lock().unwrap_or_else(|err| err.into_inner());
+        // started over or given up on while this part was written
+        let Some(upload) = parts.get_mut(path).filter(|upload| upload.temp == temp) else {
+            return false;
+        };
+        upload.touched = Instant::now();
+        let mut received = Vec::with_capacity(upload.received.len() + 1);
+        let (mut start, mut end) = (start, end);
+        for &(from, to) in &upload.received {
+            if to < start || from > end {
+                received.push((from, to));
+            } else {
+                start = start.min(from);
+                end = end.max(to);
+            }
+        }
+        received.push((start, end));
+        received.sort_unstable();
+        let complete = received == [(0, upload.total)];
+        upload.received = received;
+        if complete {
+            parts.remove(path);
+        }
+        complete
+    }
+}
+
+// Until dropped, the upload to `path` is in progress
+pub struct Upload<'a> {
+    uploads: &'a Uploads,
+    path: String,
+}
+
+impl Drop for Upload<'_> {
+    fn drop(&mut self) {
+        let mut paths = self
+            .uploads
+            .paths
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        paths.remove(&self.path);
+    }
+}
+
+// ".name.upload-<pid>-<n>" in the target's directory, so the rename doesn't
+// cross file systems. A dotfile, which --hide-dotfiles keeps from clients.
+pub fn temp_path(target: &Path) -> PathBuf {
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    target.with_file_name(format!(
+        ".{name}.upload-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+#[test]
+fn test_uploads() {
+    let uploads = Uploads::default();
+    let upload = uploads.start("a/b");
+    assert!(upload.is_some());
+    assert!(uploads.start("a/b").is_none());
+    assert!(uploads.start("a/c").is_some());
+    drop(upload);
+    assert!(uploads.start("a/b").is_some());
+
+    let temp = temp_path(Path::new("a/b.txt"));
+    assert_eq!(temp.parent(), Some(Path::new("a")));
+    assert!(
+        temp.to_str()
+            .unwrap()
+            .starts_with(&format!("a/.b.txt.upload-{}-", std::process::id()))
+    );
+    assert_ne!(temp, temp_path(Path::new("a/b.txt")));
+}
+
+#[test]
+fn test_upload_parts() {
+    let dir = std::env::temp_dir().join(format!("upload-parts-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let target = dir.join("big.bin");
+    let uploads = Uploads::default();
+    assert_eq!(uploads.in_parts("big.bin"), None);
+    let temp = uploads.part_file("big.bin", &target, 30).unwrap();
+    assert_eq!(std::fs::metadata(&temp).unwrap().len(), 30);
+    assert_eq!(uploads.in_parts("big.bin"), Some(30));
+    assert_eq!(uploads.part_file("big.bin", &target, 30).unwrap(), temp);
+    assert!(!uploads.part_written("big.bin", &temp, 20, 30));
+    assert!(!uploads.part_written("big.bin", &temp, 0, 10));
+    // sent again, after a retry
+    assert!(!uploads.part_written("big.bin", &temp, 0, 10));
+    assert!(uploads.part_written("big.bin", &temp, 10, 20));
+    assert_eq!(uploads.in_parts("big.bin"), None);
+
+    // another size starts over, and parts of the first are ignored
+    let first = uploads.part_file("big.bin", &target, 30).unwrap();
+    let second = uploads.part_file("big.bin", &target, 40).unwrap();
+    assert_ne!(first, second);
+    assert!(!first.exists());
+    assert!(!uploads.part_written("big.bin", &first, 0, 30));
+    assert!(uploads.part_written("big.bin", &second, 0, 40));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,364 @@
+// Ask the local router to forward our port, using NAT-PMP (RFC 6886) when the
+// gateway speaks it and falling back to UPnP IGD otherwise.
+
+use crate::{
+    client::{self, Url},
+    log,
+    status::StatusCode,
+    tunnel,
+};
+use std::{
+    error::Error,
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        Mutex,
+        mpsc::{self, RecvTimeoutError, Sender},
+    },
+    time::Duration,
+};
+
+const NATPMP_PORT: u16 = 5351;
+// NAT-PMP mappings expire, they get renewed at half their lifetime
+const NATPMP_LIFETIME: u32 = 3600;
+const SSDP_ADDRESS: &str = "239.255.255.250:1900";
+const IGD_SERVICES: [&str; 2] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAPPING_DESCRIPTION: &str = "rust-std-web-server";
+
+enum Protocol {
+    NatPmp {
+        gateway: Ipv4Addr,
+        // dropping this stops the renewal thread
+        _renewal: Sender<()>,
+    },
+    Upnp {
+        control: Url,
+        service: &'static str,
+    },
+}
+
+pub struct PortMapping {
+    protocol: Protocol,
+    internal_port: u16,
+    external_ip: Ipv4Addr,
+    external_port: u16,
+    removed: Mutex<bool>,
+}
+
+impl PortMapping {
+    pub fn new(port: u16) -> Result<Self, Box<dyn Error>> {
+        let natpmp_err = match default_gateway() {
+            Ok(gateway) => match natpmp_map(gateway, port) {
+                Ok(mapping) => return Ok(mapping),
+                Err(err) => err,
+            },
+            Err(err) => err,
+        };
+        upnp_map(port).map_err(|err| format!("NAT-PMP: {natpmp_err}, UPnP: {err}").into())
+    }
+
+    pub fn external_addr(&self) -> SocketAddr {
+        SocketAddr::from((self.external_ip, self.external_port))
+    }
+
+    pub fn protocol_name(&self) -> &'static str {
+        match self.protocol {
+            Protocol::NatPmp { .. } => "NAT-PMP",
+            Protocol::Upnp { .. } => "UPnP",
+        }
+    }
+
+    // Safe to call several times (the mapping is also removed on drop)
+    pub fn remove(&self) {
+        let mut removed = self.removed.lock().unwrap_or_else(|err| err.into_inner());
+        if *removed {
+            return;
+        }
+        *removed = true;
+
+        let res = match &self.protocol {
+            Protocol::NatPmp { gateway, .. } => {
+                natpmp_request(*gateway, &natpmp_map_request(self.internal_port, 0, 0)).map(|_| ())
+            }
+            Protocol::Upnp { control, service } => soap_call(
+                control,
+                service,
+                "DeletePortMapping",
+                &format!(
+                    "<NewRemoteHost></NewRemoteHost>\
+                     <NewExternalPort>{}</NewExternalPort>\
+                     <NewProtocol>TCP</NewProtocol>",
+                    self.external_port
+                ),
+            )
+            .map(|_| ()),
+        };
+        match res {
+            Ok(()) => log::info(&format!("removed {} port mapping", self.protocol_name())),
+            Err(err) => log::error(&format!(
+                "failed to remove {} port mapping: {err}",
+                self.protocol_name()
+            )),
+        }
+    }
+}
+
+impl Drop for PortMapping {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn default_gateway() -> Result<Ipv4Addr, Box<dyn Error>> {
+    parse_route_table(&std::fs::read_to_string("/proc/net/route")?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_gateway() -> Result<Ipv4Addr, Box<dyn Error>> {
+    Err("cannot find the default gateway on this platform".into())
+}
+
+fn parse_route_table(routes: &str) -> Result<Ipv4Addr, Box<dyn Error>> {
+    // Iface  Destination  Gateway  Flags ...
+    // eth0   00000000     0102A8C0 0003  ...
+    for line in routes.lines().skip(1) {
+        let mut fields = line.split_whitespace().skip(1);
+        if let (Some("00000000"), Some(gateway)) = (fields.next(), fields.next()) {
+            // addresses are stored in host byte order (little endian)
+            let gateway = u32::from_str_radix(gateway, 16)?;
+            return Ok(Ipv4Addr::from(gateway.to_le_bytes()));
+        }
+    }
+    Err("no default route".into())
+}
+
+fn natpmp_map_request(internal_port: u16, external_port: u16, lifetime: u32) -> Vec<u8> {
+    // version 0, opcode 2 (map TCP), 2 reserved bytes
+    let mut request = vec![0, 2, 0, 0];
+    request.extend_from_slice(&internal_port.to_be_bytes());
+    request.extend_from_slice(&external_port.to_be_bytes());
+    request.extend_from_slice(&lifetime.to_be_bytes());
+    request
+}
+
+fn natpmp_request(gateway: Ipv4Addr, request: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect((gateway, NATPMP_PORT))?;
+
+    // the RFC asks for 250ms doubling on every retry
+    let mut timeout = Duration::from_millis(250);
+    let mut buffer = [0; 16];
+    for _ in 0..4 {
+        socket.send(request)?;
+        socket.set_read_timeout(Some(timeout))?;
+        match socket.recv(&mut buffer) {
+            Ok(len) if len >= 4 && buffer[1] == request[1] + 128 => {
+                let result = u16::from_be_bytes([buffer[2], buffer[3]]);
+                if result != 0 {
+                    return Err(format!("gateway returned result code {result}").into());
+                }
+                return Ok(buffer[..len].to_vec());
+            }
+            Ok(_) => return Err("invalid response from gateway".into()),
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                timeout *= 2;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Err(format!("no answer from {gateway}").into())
+}
+
+fn natpmp_map(gateway: Ipv4Addr, port: u16) -> Result<PortMapping, Box<dyn Error>> {
+    let response = natpmp_request(gateway, &[0, 0])?;
+    let [_, _, _, _, _, _, _, _, a, b, c, d] = response[..] else {
+        return Err("invalid external address response".into());
+    };
+    let external_ip = Ipv4Addr::new(a, b, c, d);
+
+    let request = natpmp_map_request(port, port, NATPMP_LIFETIME);
+    let response = natpmp_request(gateway, &request)?;
+    if response.len() < 16 {
+        return Err("invalid mapping response".into());
+    }
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+    let lifetime = u32::from_be_bytes([response[12], response[13], response[14], response[15]]);
+
+    let (renewal, stop) = mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        let renew_every = Duration::from_secs(u64::from(lifetime.max(2) / 2));
+        while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(renew_every) {
+            if let Err(err) = natpmp_request(gateway, &request) {
+                log::error(&format!("failed to renew NAT-PMP port mapping: {err}"));
+            }
+        }
+    });
+
+    Ok(PortMapping {
+        protocol: Protocol::NatPmp {
+            gateway,
+            _renewal: renewal,
+        },
+        internal_port: port,
+        external_ip,
+        external_port,
+        removed: Mutex::new(false),
+    })
+}
+
+fn upnp_map(port: u16) -> Result<PortMapping, Box<dyn Error>> {
+    let location = Url::parse(&ssdp_discover()?)?;
+    let (description, local_ip) = http_request(&location, "GET", &[], "")?;
+    let (control, service) = find_control_url(&location, &description)?;
+
+    soap_call(
+        &control,
+        service,
+        "AddPortMapping",
+        &format!(
+            "<NewRemoteHost></NewRemoteHost>\
+             <NewExternalPort>{port}</NewExternalPort>\
+             <NewProtocol>TCP</NewProtocol>\
+             <NewInternalPort>{port}</NewInternalPort>\
+             <NewInternalClient>{local_ip}</NewInternalClient>\
+             <NewEnabled>1</NewEnabled>\
+             <NewPortMappingDescription>{MAPPING_DESCRIPTION}</NewPortMappingDescription>\
+             <NewLeaseDuration>0</NewLeaseDuration>"
+        ),
+    )?;
+    let response = soap_call(&control, service, "GetExternalIPAddress", "")?;
+    let external_ip = xml_value(&response, "NewExternalIPAddress")
+        .ok_or("no external address in response")?
+        .parse()?;
+
+    Ok(PortMapping {
+        protocol: Protocol::Upnp { control, service },
+        internal_port: port,
+        external_ip,
+        external_port: port,
+        removed: Mutex::new(false),
+    })
+}
+
+fn ssdp_discover() -> Result<String, Box<dyn Error>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_read_timeout(Some(Duration::from_secs(3)))?;
+    socket.send_to(
+        format!(
+            "M-SEARCH * HTTP/1.1\r\n\
+             HOST: {SSDP_ADDRESS}\r\n\
+             MAN: \"ssdp:discover\"\r\n\
+             MX: 2\r\n\
+             ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n"
+        )
+        .as_bytes(),
+        SSDP_ADDRESS,
+    )?;
+
+    let mut buffer = [0; 2048];
+    let len = socket
+        .recv(&mut buffer)
+        .map_err(|_| "no UPnP gateway answered")?;
+    String::from_utf8_lossy(&buffer[..len])
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.eq_ignore_ascii_case("location"))
+        .map(|(_, value)| value.trim().to_owned())
+        .ok_or_else(|| "no location in SSDP response".into())
+}
+
+fn find_control_url(
+    location: &Url,
+    description: &str,
+) -> Result<(Url, &'static str), Box<dyn Error>> {
+    for service in IGD_SERVICES {
+        let Some(start) = description.find(&format!("<serviceType>{service}</serviceType>")) else {
+            continue;
+        };
+        let Some(control_url) = xml_value(&description[start..], "controlURL") else {
+            continue;
+        };
+        let control = if control_url.starts_with("http://") {
+            Url::parse(control_url)?
+        } else {
+            Url {
+                host: location.host.clone(),
+                path: format!("/{}", control_url.trim_start_matches('/')),
+            }
+        };
+        return Ok((control, service));
+    }
+    Err("gateway doesn't offer a WAN connection service".into())
+}
+
+fn soap_call(
+    control: &Url,
+    service: &str,
+    action: &str,
+    arguments: &str,
+) -> Result<String, Box<dyn Error>> {
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:{action} xmlns:u=\"{service}\">{arguments}</u:{action}></s:Body>\
+         </s:Envelope>"
+    );
+    let soap_action = format!("\"{service}#{action}\"");
+    let (response, _) = http_request(
+        control,
+        "POST",
+        &[
+            ("Content-Type", "text/xml; charset=\"utf-8\""),
+            ("SOAPAction", &soap_action),
+        ],
+        &body,
+    )?;
+    Ok(response)
+}
+
+fn xml_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let len = xml[start..].find(&format!("</{tag}>"))?;
+    Some(xml[start..start + len].trim())
+}
+
+// Returns the body and our own address on the router's network
+fn http_request(
+    url: &Url,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> Result<(String, Ipv4Addr), Box<dyn Error>> {
+    // the gateway is on our network, never behind --upstream-proxy
+    let via = &tunnel::Via::Direct;
+    let response = client::request_via(url, via, method, headers, body.as_bytes(), TIMEOUT)?;
+    if response.status != Some(StatusCode::Ok) {
+        return Err(format!("gateway answered '{}'", response.status_line).into());
+    }
+
+    let local_ip = match response.local_addr {
+        Some(SocketAddr::V4(addr)) => *addr.ip(),
+        _ => return Err("UPnP over IPv6 is not supported".into()),
+    };
+    Ok((String::from_utf8(response.body)?, local_ip))
+}
+
+#[test]
+fn test_parse_route_table() {
+    let routes =
+        "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t0002A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0
+eth0\t00000000\t0102A8C0\t0003\t0\t0\t0\t00000000\t0\t0\t0
+";
+    assert_eq!(
+        parse_route_table(routes).unwrap(),
+        Ipv4Addr::new(192, 168, 2, 1)
+    );
+    assert!(parse_route_table("Iface\tDestination\tGateway\n").is_err());
+}
@@ -0,0 +1,70 @@
+// --vhost: sites of their own, anywhere on the disk, picked by the Host header
+// of requests, e.g. example.com:/srv/example. Their files are served
+// read-only from their directory, see root; uploads, management and the /_
+// endpoints are the main site's. Requests for other hosts get the main site,
+// or the --vhost-default one.
+
+use crate::tenant;
+use std::path::PathBuf;
+
+pub struct Vhost {
+    // lowercase, without a port
+    pub host: String,
+    // absolute, as we move to the served directory
+    pub root: PathBuf,
+}
+
+// "host:dir"
+pub fn parse(value: &str) -> Result<Vhost, String> {
+    let Some((host, root)) = value.split_once(':') else {
+        return Err(
+            "'--vhost' value must be 'host:dir', e.g. 'example.com:/srv/example'".to_owned(),
+        );
+    };
+    if host.is_empty() || root.is_empty() {
+        return Err("'--vhost' needs a hostname and a directory".to_owned());
+    }
+    let root =
+        std::path::absolute(root).map_err(|_| format!("invalid '--vhost' directory: {root}"))?;
+    Ok(Vhost {
+        host: tenant::host_name(host),
+        root,
+    })
+}
+
+// The site of a request by its Host header, else the default one if any
+pub fn resolve<'a>(
+    vhosts: &'a [Vhost],
+    default: Option<&str>,
+    host: Option<&str>,
+) -> Option<&'a Vhost> {
+    let find = |host: &str| vhosts.iter().find(|vhost| vhost.host == host);
+    host.map(tenant::host_name)
+        .and_then(|host| find(&host))
+        .or_else(|| default.and_then(find))
+}
+
+#[test]
+fn test_vhost() {
+    let vhosts = [
+        parse("Example.com:/srv/example").unwrap(),
+        parse("blog.example.com:/srv/blog").unwrap(),
+    ];
+    assert!(parse("example.com").is_err());
+    assert!(parse(":/srv").is_err());
+    assert!(parse("example.com:").is_err());
+
+    let host = |default, host| resolve(&vhosts, default, host).map(|vhost| vhost.host.as_str());
+    assert_eq!(host(None, Some("example.com:8080")), Some("example.com"));
+    assert_eq!(
+        host(None, Some("BLOG.example.com.")),
+        Some("blog.example.com")
+    );
+    assert_eq!(host(None, Some("other.com")), None);
+    assert_eq!(host(None, None), None);
+    assert_eq!(
+        host(Some("example.com"), Some("other.com")),
+        Some("example.com")
+    );
+    assert_eq!(host(Some("example.com"), None), Some("example.com"));
+}
@@ -0,0 +1,85 @@
+// --viewer: documents get a page of their own (doc.pdf?view) instead of
+// being downloaded. PDFs go to the browser's viewer, EPUBs to a small reader
+// unzipping the book in the browser and showing a chapter at a time.
+
+use crate::{html_encode, url_encode};
+
+pub const PARAM: &str = "view";
+const EPUB_SCRIPT: &str = include_str!("epub.js");
+
+pub fn is_viewable(mime_type: &str) -> bool {
+    matches!(mime_type, "application/pdf" | "application/epub+zip")
+}
+
+// The page for the file `name` of type `mime_type`, `query` being what its
+// URL needs after it, e.g. "?token=..."
+pub fn page(name: &str, mime_type: &str, query: &str) -> String {
+    let src = format!("{}{query}", url_encode(name));
+    let (controls, frame, script) = if mime_type == "application/epub+zip" {
+        (
+            "\n  <button id=\"previous\">◀</button>
+  <select id=\"chapters\"></select>
+  <button id=\"next\">▶</button>",
+            // the book's scripts don't run, its pages are only read
+            format!(
+                "<iframe id=\"book\" data-src=\"{src}\" sandbox=\"allow-same-origin\" title=\"book\"></iframe>"
+            ),
+            format!("<script>\n{EPUB_SCRIPT}</script>\n"),
+        )
+    } else {
+        (
+            "",
+            format!("<iframe src=\"{src}\" title=\"document\"></iframe>"),
+            String::new(),
+        )
+    };
+    format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+  <meta charset=\"utf-8\">
+  <title>{name}</title>
+  <style>
+  body {{
+    background-color: Canvas;
+    color: CanvasText;
+    color-scheme: light dark;
+    display: flex;
+    flex-direction: column;
+    height: 100vh;
+    margin: 0;
+  }}
+  nav {{
+    padding: 0.5em;
+  }}
+  iframe {{
+    border: none;
+    flex: 1;
+  }}
+  #book {{
+    background-color: white;
+  }}
+  </style>
+</head>
+<nav>
+  <a href=\"./{query}\">Back to the listing</a> · <a href=\"{src}\" download>Download</a>{controls}
+</nav>
+<p id=\"error\" hidden></p>
+{frame}
+{script}</html>
+",
+        name = html_encode(name.to_owned()),
+    )
+}
+
+#[test]
+fn test_page() {
+    let pdf = page("a&b.pdf", "application/pdf", "?token=t");
+    assert!(pdf.contains("<title>a&amp;b.pdf</title>"));
+    assert!(pdf.contains("<iframe src=\"a%26b.pdf?token=t\" title=\"document\">"));
+    assert!(!pdf.contains("<script>"));
+    let epub = page("book.epub", "application/epub+zip", "");
+    assert!(epub.contains("data-src=\"book.epub\""));
+    assert!(epub.contains("<script>"));
+    assert!(is_viewable("application/pdf") && !is_viewable("text/plain"));
+}
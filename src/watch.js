@@ -0,0 +1,8 @@
+// Reloads the listing when something changes in its directory, unless
+// uploads are running (upload.js reloads once they are done).
+const changes = new WebSocket(location.origin.replace(/^http/, "ws") + location.pathname);
+changes.onmessage = () => {
+  if (!document.querySelector("#uploads li")) {
+    location.reload();
+  }
+};
@@ -0,0 +1,77 @@
+// Live listings: browsers keep a WebSocket open on the directory they show
+// and are told when something in it changes.
+
+use crate::{normalize_path, upgrade::Upgraded, websocket};
+use std::{
+    io,
+    net::Shutdown,
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    time::Duration,
+};
+
+// pings find out about browsers gone without closing
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+pub struct Changes {
+    watchers: Mutex<Vec<(String, Sender<()>)>>,
+}
+
+impl Changes {
+    pub fn subscribe(&self, directory: &str) -> Receiver<()> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watchers = self.watchers.lock().unwrap_or_else(|err| err.into_inner());
+        watchers.push((normalize_path(directory.to_owned()), sender));
+        receiver
+    }
+
+    pub fn notify(&self, directory: &str) {
+        let directory = normalize_path(directory.to_owned());
+        let mut watchers = self.watchers.lock().unwrap_or_else(|err| err.into_inner());
+        // also forgets the watchers which went away
+        watchers.retain(|(watched, sender)| *watched != directory || sender.send(()).is_ok());
+    }
+}
+
+// Runs until the browser goes away: a second thread pushes the changes while
+// this one answers the browser's frames.
+pub fn serve(mut upgraded: Upgraded, changes: Receiver<()>) -> io::Result<()> {
+    let mut writer = upgraded.try_clone_stream()?;
+    std::thread::spawn(move || {
+        loop {
+            let res = match changes.recv_timeout(PING_INTERVAL) {
+                Ok(()) => websocket::write_frame(&mut writer, websocket::TEXT, b"changed"),
+                Err(RecvTimeoutError::Timeout) => {
+                    websocket::write_frame(&mut writer, websocket::PING, b"")
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            if res.is_err() {
+                let _ = writer.shutdown(Shutdown::Both);
+                return;
+            }
+        }
+    });
+
+    let res = answer_frames(&mut upgraded);
+    // makes the next write of the other thread fail
+    let _ = upgraded.try_clone_stream()?.shutdown(Shutdown::Both);
+    res
+}
+
+fn answer_frames(upgraded: &mut Upgraded) -> io::Result<()> {
+    loop {
+        match websocket::read_frame(upgraded)? {
+            (websocket::CLOSE, payload) => {
+                return websocket::write_frame(upgraded, websocket::CLOSE, &payload);
+            }
+            (websocket::PING, payload) => {
+                websocket::write_frame(upgraded, websocket::PONG, &payload)?
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// --webdav: the read-only part of WebDAV (RFC 4918), class 1, for the served
+// directory to be mounted as a drive by file managers such as macOS Finder
+// and Windows Explorer. PROPFIND answers with the size, modification date and
+// type of a file, or of a directory and its entries, in a multistatus
+// document; the requested properties are ignored, all of these are sent.
+
+use crate::{Request, date};
+
+// directories are only listed one level deep, as clients browse them
+pub const MAX_DEPTH: u32 = 1;
+
+pub struct Resource {
+    // percent-encoded, with a slash at the end for directories
+    pub href: String,
+    pub name: String,
+    pub directory: bool,
+    pub size: u64,
+    // unix seconds
+    pub modified: Option<u64>,
+    pub content_type: String,
+    pub etag: Option<String>,
+}
+
+// The Depth header, 0 or 1, and None for "infinity", which we don't do
+// (section 9.1). Its absence means infinity too, but is answered as 1, what
+// clients browsing want.
+pub fn depth(request: &Request) -> Option<u32> {
+    match request.header("Depth").map(str::trim) {
+        Some("0") => Some(0),
+        Some("1") | None => Some(MAX_DEPTH),
+        _ => None,
+    }
+}
+
+// The 207 body for `resources`, each found (section 9.1.3)
+pub fn multistatus(resources: &[Resource]) -> String {
+    let mut res = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    for resource in resources {
+        let mut props = format!("<D:displayname>{}</D:displayname>", escape(&resource.name));
+        if resource.directory {
+            props.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+        } else {
+            props.push_str(&format!(
+                "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+                 <D:getcontenttype>{}</D:getcontenttype>",
+                resource.size,
+                escape(&resource.content_type)
+            ));
+        }
+        if let Some(modified) = resource.modified {
+            props.push_str(&format!(
+                "<D:getlastmodified>{}</D:getlastmodified>",
+                date::http_date(modified)
+            ));
+        }
+        if let Some(etag) = &resource.etag {
+            props.push_str(&format!("<D:getetag>{}</D:getetag>", escape(etag)));
+        }
+        res.push_str(&format!(
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop>{props}</D:prop>\
+             <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+            escape(&resource.href)
+        ));
+    }
+    res.push_str("</D:multistatus>\n");
+    res
+}
+
+// The body of a 403 for a Depth of infinity (section 9.1)
+pub fn finite_depth_error() -> &'static str {
+    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+     <D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n"
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[test]
+fn test_multistatus() {
+    let resources = [
+        Resource {
+            href: "/docs/".to_owned(),
+            name: "docs".to_owned(),
+            directory: true,
+            size: 0,
+            modified: Some(0),
+            content_type: String::new(),
+            etag: None,
+        },
+        Resource {
+            href: "/docs/a%26b.txt".to_owned(),
+            name: "a&b.txt".to_owned(),
+            directory: false,
+            size: 3,
+            modified: None,
+            content_type: "text/plain".to_owned(),
+            etag: Some("\"1-3-0\"".to_owned()),
+        },
+    ];
+    let body = multistatus(&resources);
+    assert!(body.starts_with("<?xml"));
+    assert_eq!(body.matches("<D:response>").count(), 2);
+    assert!(body.contains(
+        "<D:href>/docs/</D:href><D:propstat><D:prop><D:displayname>docs</D:displayname>\
+         <D:resourcetype><D:collection/></D:resourcetype>\
+         <D:getlastmodified>Thu, 01 Jan 1970 00:00:00 GMT</D:getlastmodified>"
+    ));
+    assert!(body.contains("<D:displayname>a&amp;b.txt</D:displayname>"));
+    assert!(body.contains("<D:getcontentlength>3</D:getcontentlength>"));
+    assert!(body.contains("<D:getetag>\"1-3-0\"</D:getetag>"));
+
+    let request = |depth: &str| {
+        Request::parse(format!("PROPFIND / HTTP/1.1\r\nDepth: {depth}\r\n\r\n").as_bytes()).unwrap()
+    };
+    assert_eq!(depth(&request("0")), Some(0));
+    assert_eq!(depth(&request("1")), Some(1));
+    assert_eq!(depth(&request("infinity")), None);
+}
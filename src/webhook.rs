@@ -0,0 +1,141 @@
+// --webhook: changes made through the server (uploads and management actions)
+// are POSTed as JSON to the webhook URLs, one event at a time, in order:
+//
+// {"event": "rename", "path": "/docs/a.txt", "to": "/docs/b.txt", "time": 1700000000}
+//
+// Events are sent from a thread of their own so requests don't wait, and
+// retried with a growing delay while the receiver fails. With a secret,
+// X-Webhook-Signature is "sha256=" and the hex HMAC-SHA256 of the body.
+
+use crate::{client, crypto, date, json, log, status::StatusCode};
+use std::{
+    error::Error,
+    sync::{
+        OnceLock,
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+    },
+    thread,
+    time::Duration,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+// 1s, 2s, 4s... between them
+const ATTEMPTS: u32 = 6;
+// events beyond this are dropped while receivers are down
+const MAX_QUEUED: usize = 1000;
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+pub struct Webhooks {
+    urls: Vec<String>,
+    secret: Option<String>,
+    // started with the first event
+    queue: OnceLock<SyncSender<String>>,
+}
+
+impl Webhooks {
+    pub fn new(urls: Vec<String>, secret: Option<String>) -> Result<Self, Box<dyn Error>> {
+        for url in &urls {
+            client::Url::parse(url)?;
+        }
+        Ok(Webhooks {
+            urls,
+            secret,
+            queue: OnceLock::new(),
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        let signed = if self.secret.is_some() {
+            ", signed"
+        } else {
+            ""
+        };
+        format!("{}{signed}", self.urls.join(" "))
+    }
+
+    // `path` and `to` are normalized
+    pub fn send(&self, event: &str, path: &str, to: Option<&str>) {
+        let time = date::now();
+        let body = event_body(event, path, to, time);
+        let queue = self.queue.get_or_init(|| {
+            let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED);
+            let urls = self.urls.clone();
+            let secret = self.secret.clone();
+            thread::spawn(move || deliver_all(&urls, secret.as_deref(), &receiver));
+            sender
+        });
+        if let Err(TrySendError::Full(_)) = queue.try_send(body) {
+            log::warning(&format!(
+                "webhook queue full, {event} event for /{path} dropped"
+            ));
+        }
+    }
+}
+
+fn event_body(event: &str, path: &str, to: Option<&str>, time: u64) -> String {
+    let mut res = format!(
+        "{{\"event\":{},\"path\":{}",
+        json::quote(event),
+        json::quote(&format!("/{path}"))
+    );
+    if let Some(to) = to {
+        res.push_str(&format!(",\"to\":{}", json::quote(&format!("/{to}"))));
+    }
+    res.push_str(&format!(",\"time\":{time}}}"));
+    res
+}
+
+fn signature(secret: &str, body: &str) -> String {
+    let mac = crypto::hmac::hmac_sha256(secret.as_bytes(), body.as_bytes());
+    format!("sha256={}", crypto::hex(&mac))
+}
+
+fn deliver_all(urls: &[String], secret: Option<&str>, events: &Receiver<String>) {
+    for body in events {
+        let signature = secret.map(|secret| signature(secret, &body));
+        let mut headers = vec![("Content-Type", "application/json")];
+        if let Some(signature) = &signature {
+            headers.push((SIGNATURE_HEADER, signature));
+        }
+        for url in urls {
+            deliver(url, &headers, &body);
+        }
+    }
+}
+
+fn deliver(url: &str, headers: &[(&str, &str)], body: &str) {
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=ATTEMPTS {
+        let res = client::Url::parse(url)
+            .and_then(|url| client::request(&url, "POST", headers, body.as_bytes(), TIMEOUT));
+        let err = match res {
+            Ok(response) if response.status.is_some_and(StatusCode::is_success) => return,
+            Ok(response) => response.status_line,
+            Err(err) => err.to_string(),
+        };
+        if attempt == ATTEMPTS {
+            log::error(&format!("webhook {url} failed, event dropped: {err}"));
+            return;
+        }
+        log::warning(&format!("webhook {url} failed (attempt {attempt}): {err}"));
+        thread::sleep(delay);
+        delay *= 2;
+    }
+}
+
+#[test]
+fn test_event_body() {
+    assert_eq!(
+        event_body("rename", "docs/a \"1\"", Some("docs/b"), 1700000000),
+        "{\"event\":\"rename\",\"path\":\"/docs/a \\\"1\\\"\",\"to\":\"/docs/b\",\"time\":1700000000}"
+    );
+    assert_eq!(
+        event_body("upload", "a", None, 0),
+        "{\"event\":\"upload\",\"path\":\"/a\",\"time\":0}"
+    );
+    // printf "{}" | openssl dgst -sha256 -hmac key
+    assert_eq!(
+        signature("key", "{}"),
+        "sha256=a777724d943eb48dc69bca8a4a6d57a04db3f9ec7e1de4e581e860265bdf3032"
+    );
+}
@@ -0,0 +1,97 @@
+// Just enough of WebSocket (RFC 6455) for the server to push messages to
+// browsers: the handshake and unfragmented frames.
+
+use crate::{
+    base64,
+    crypto::{Digest, sha1::Sha1},
+};
+use std::io::{self, Read, Write};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// clients only send us control frames, which are small
+const MAX_PAYLOAD: u64 = 64 * 1024;
+
+pub const TEXT: u8 = 0x1;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xa;
+
+// The Sec-WebSocket-Accept value for a Sec-WebSocket-Key
+pub fn accept_key(key: &str) -> String {
+    base64::encode(&Sha1::digest(format!("{key}{GUID}").as_bytes()))
+}
+
+// Server frames are never masked
+pub fn write_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+// Returns the opcode and the unmasked payload
+pub fn read_frame(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut head = [0; 2];
+    stream.read_exact(&mut head)?;
+    // client frames must be masked
+    if head[1] & 0x80 == 0 {
+        return Err(invalid("unmasked client frame"));
+    }
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len = [0; 8];
+            stream.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => u64::from(len),
+    };
+    if len > MAX_PAYLOAD {
+        return Err(invalid("frame too big"));
+    }
+
+    let mut mask = [0; 4];
+    stream.read_exact(&mut mask)?;
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((head[0] & 0x0f, payload))
+}
+
+#[test]
+fn test_websocket() {
+    // RFC 6455 section 1.3
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+    // RFC 6455 section 5.7, a masked "Hello"
+    let frame = [
+        0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+    ];
+    assert_eq!(
+        read_frame(&mut &frame[..]).unwrap(),
+        (TEXT, b"Hello".to_vec())
+    );
+    let mut written = Vec::new();
+    write_frame(&mut written, TEXT, b"Hello").unwrap();
+    assert_eq!(written, [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+}
@@ -0,0 +1,198 @@
+// Reading members of zip files (PKWARE APPNOTE), stored or deflated, from
+// the central directory at the end of the file. Neither ZIP64 nor encryption
+// are supported.
+
+use crate::inflate::inflate;
+use std::io::{self, Read, Seek, SeekFrom};
+
+const END_SIGNATURE: u32 = 0x06054b50;
+const CENTRAL_SIGNATURE: u32 = 0x02014b50;
+const LOCAL_SIGNATURE: u32 = 0x04034b50;
+const END_SIZE: u64 = 22;
+const CENTRAL_SIZE: usize = 46;
+const LOCAL_SIZE: usize = 30;
+const MAX_COMMENT_SIZE: u64 = 0xffff;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+const ENCRYPTED: u16 = 1;
+// members are decompressed in memory
+pub const MAX_MEMBER_SIZE: u64 = 64 * 1024 * 1024;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("zip: {message}"))
+}
+
+fn u16_at(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+#[derive(Clone)]
+struct Entry {
+    name: String,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    offset: u64,
+}
+
+pub struct Archive<R> {
+    reader: R,
+    entries: Vec<Entry>,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        // the end record is followed by a comment of up to 64 KiB
+        let length = reader.seek(SeekFrom::End(0))?;
+        let tail_size = length.min(END_SIZE + MAX_COMMENT_SIZE);
+        reader.seek(SeekFrom::Start(length - tail_size))?;
+        let mut tail = vec![0; tail_size as usize];
+        reader.read_exact(&mut tail)?;
+        let end = (0..tail.len().saturating_sub(END_SIZE as usize - 1))
+            .rev()
+            .find(|pos| {
+                u32_at(&tail, *pos) == END_SIGNATURE
+                    && pos + END_SIZE as usize + usize::from(u16_at(&tail, pos + 20)) == tail.len()
+            })
+            .ok_or_else(|| invalid("no end of central directory"))?;
+        let count = u16_at(&tail, end + 10);
+        let directory_size = u32_at(&tail, end + 12);
+        let directory_offset = u32_at(&tail, end + 16);
+        if count == 0xffff || directory_offset == 0xffffffff {
+            return Err(invalid("ZIP64 isn't supported"));
+        }
+
+        let mut directory = vec![0; directory_size as usize];
+        reader.seek(SeekFrom::Start(directory_offset.into()))?;
+        reader.read_exact(&mut directory)?;
+        let mut entries = Vec::with_capacity(count.into());
+        let mut pos = 0;
+        for _ in 0..count {
+            let header = directory
+                .get(pos..pos + CENTRAL_SIZE)
+                .filter(|header| u32_at(header, 0) == CENTRAL_SIGNATURE)
+                .ok_or_else(|| invalid("invalid central directory"))?;
+            let name_size = usize::from(u16_at(header, 28));
+            let extra_size = usize::from(u16_at(header, 30));
+            let comment_size = usize::from(u16_at(header, 32));
+            let name = directory
+                .get(pos + CENTRAL_SIZE..pos + CENTRAL_SIZE + name_size)
+                .ok_or_else(|| invalid("invalid central directory"))?;
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                flags: u16_at(header, 8),
+                method: u16_at(header, 10),
+                crc: u32_at(header, 16),
+                compressed_size: u32_at(header, 20).into(),
+                size: u32_at(header, 24).into(),
+                offset: u32_at(header, 42).into(),
+            });
+            pos += CENTRAL_SIZE + name_size + extra_size + comment_size;
+        }
+        Ok(Archive { reader, entries })
+    }
+
+    // Directories need not have entries of their own, "a/b" is enough for "a"
+    pub fn is_dir(&self, name: &str) -> bool {
+        name.is_empty()
+            || self.entries.iter().any(|entry| {
+                entry
+                    .name
+                    .strip_prefix(name)
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+    }
+
+    // The content of the file `name`, None if there's no such member
+    pub fn read(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let Some(entry) = self
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        if entry.flags & ENCRYPTED != 0 {
+            return Err(invalid("encrypted members aren't supported"));
+        }
+        if entry.size > MAX_MEMBER_SIZE || entry.compressed_size > MAX_MEMBER_SIZE {
+            return Err(invalid("member too large"));
+        }
+
+        let mut header = [0; LOCAL_SIZE];
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        self.reader.read_exact(&mut header)?;
+        if u32_at(&header, 0) != LOCAL_SIGNATURE {
+            return Err(invalid("invalid local header"));
+        }
+        // the local extra field can differ from the central one
+        let skip = i64::from(u16_at(&header, 26)) + i64::from(u16_at(&header, 28));
+        self.reader.seek(SeekFrom::Current(skip))?;
+        let mut data = vec![0; entry.compressed_size as usize];
+        self.reader.read_exact(&mut data)?;
+
+        let data = match entry.method {
+            STORED => data,
+            DEFLATED => inflate(&data, entry.size as usize)?,
+            method => return Err(invalid(&format!("compression method {method}"))),
+        };
+        if data.len() as u64 != entry.size || crc32(&data) != entry.crc {
+            return Err(invalid("corrupted member"));
+        }
+        Ok(Some(data))
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[test]
+fn test_archive() {
+    // made by Python's zipfile, d/i.html is deflated and a stored
+    let zip = b"\x50\x4b\x03\x04\x14\x00\x00\x00\x08\x00\xad\x02\x50\x5d\x80\x88\xf9\xe5\x0a\x00\
+        \x00\x00\x11\x00\x00\x00\x08\x00\x00\x00\x64\x2f\x69\x2e\x68\x74\x6d\x6c\xcb\x48\
+        \xcd\xc9\xc9\x57\xc8\x40\x90\x00\x50\x4b\x03\x04\x14\x00\x00\x00\x00\x00\xad\x02\
+        \x50\x5d\x0b\xf9\x43\x56\x06\x00\x00\x00\x06\x00\x00\x00\x01\x00\x00\x00\x61\x73\
+        \x74\x6f\x72\x65\x64\x50\x4b\x01\x02\x14\x03\x14\x00\x00\x00\x08\x00\xad\x02\x50\
+        \x5d\x80\x88\xf9\xe5\x0a\x00\x00\x00\x11\x00\x00\x00\x08\x00\x00\x00\x00\x00\x00\
+        \x00\x00\x00\x00\x00\x80\x01\x00\x00\x00\x00\x64\x2f\x69\x2e\x68\x74\x6d\x6c\x50\
+        \x4b\x01\x02\x14\x03\x14\x00\x00\x00\x00\x00\xad\x02\x50\x5d\x0b\xf9\x43\x56\x06\
+        \x00\x00\x00\x06\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80\
+        \x01\x30\x00\x00\x00\x61\x50\x4b\x05\x06\x00\x00\x00\x00\x02\x00\x02\x00\x65\x00\
+        \x00\x00\x55\x00\x00\x00\x00\x00";
+    let mut archive = Archive::new(io::Cursor::new(&zip[..])).unwrap();
+    assert_eq!(archive.read("a").unwrap().unwrap(), b"stored");
+    assert_eq!(
+        archive.read("d/i.html").unwrap().unwrap(),
+        b"hello hello hello"
+    );
+    assert_eq!(archive.read("d").unwrap(), None);
+    assert!(archive.is_dir("d") && archive.is_dir(""));
+    assert!(!archive.is_dir("a") && !archive.is_dir("d/i"));
+
+    let mut corrupted = zip.to_vec();
+    corrupted[80] ^= 1;
+    let mut archive = Archive::new(io::Cursor::new(corrupted)).unwrap();
+    assert!(archive.read("a").is_err());
+    assert!(Archive::new(io::Cursor::new(&zip[..100])).is_err());
+    assert_eq!(crc32(b"123456789"), 0xcbf43926);
+}